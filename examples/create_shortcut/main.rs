@@ -8,7 +8,7 @@ fn main() {
         Ok(output) => {
             println!("{}", output);
             println!("Press ENTER to continue...");
-            stdin().read(&mut [0]).unwrap();
+            stdin().read_exact(&mut [0]).unwrap();
         }
 
         Err(e) => {
@@ -0,0 +1,40 @@
+//! Build time syntax checking using PowerShell's own parser.
+
+use std::{path::Path, process::Command};
+
+/// The PowerShell executables to try, in order.
+const CANDIDATES: &[&str] = &["pwsh", "powershell"];
+
+/// Parses the script at `path` and returns the syntax errors PowerShell
+/// found. Succeeds without checking if PowerShell isn't installed.
+pub(crate) fn syntax(path: &Path) -> Result<(), String> {
+    let command = format!(
+        "$errors = $null; $null = [System.Management.Automation.Language.Parser]::ParseFile('{}', [ref]$null, [ref]$errors); foreach ($e in $errors) {{ '{{0}}:{{1}}: {{2}}' -f $e.Extent.StartLineNumber, $e.Extent.StartColumnNumber, $e.Message }}",
+        path.to_string_lossy().replace('\'', "''")
+    );
+
+    for program in CANDIDATES {
+        let output = match Command::new(program)
+            .args(["-NoProfile", "-NonInteractive", "-Command", &command])
+            .output()
+        {
+            Ok(output) => output,
+            Err(_) => continue,
+        };
+
+        let errors = String::from_utf8_lossy(&output.stdout);
+        let errors = errors.trim();
+        if !output.status.success() {
+            return Err(format!(
+                "failed to check the syntax of {}: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        if errors.is_empty() {
+            return Ok(());
+        }
+        return Err(format!("syntax errors in {}:\n{}", path.display(), errors));
+    }
+    Ok(())
+}
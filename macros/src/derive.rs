@@ -0,0 +1,156 @@
+//! `#[derive(FromPs)]`, parsed by hand since the crate has no dependencies.
+
+use proc_macro::{Delimiter, Literal, TokenStream, TokenTree};
+
+use crate::lit;
+
+struct Field {
+    ident: String,
+    property: String,
+}
+
+pub(crate) fn from_ps(input: TokenStream) -> Result<TokenStream, String> {
+    let mut tokens = input.into_iter().peekable();
+    let mut name = None;
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Ident(ident) if ident.to_string() == "struct" => {
+                name = match tokens.next() {
+                    Some(TokenTree::Ident(ident)) => Some(ident.to_string()),
+                    _ => None,
+                };
+                break;
+            }
+            TokenTree::Ident(ident) if ident.to_string() == "enum" => {
+                return Err("FromPs can only be derived for structs with named fields".into())
+            }
+            _ => {}
+        }
+    }
+    let name = name.ok_or("FromPs can only be derived for structs with named fields")?;
+
+    let body = match tokens.next() {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => group.stream(),
+        Some(TokenTree::Punct(p)) if p.as_char() == '<' => {
+            return Err("FromPs can't be derived for generic structs".into())
+        }
+        _ => return Err("FromPs can only be derived for structs with named fields".into()),
+    };
+
+    let fields = fields(body)?;
+    let mut init = String::new();
+    for field in &fields {
+        let ident = field.ident.trim_start_matches("r#");
+        init.push_str(&format!(
+            "{}: ::powershell_script::__field(&value, {}, {})?,",
+            field.ident,
+            Literal::string(&field.property),
+            Literal::string(ident)
+        ));
+    }
+
+    let tokens = format!(
+        "impl ::powershell_script::FromPsValue for {name} {{
+            fn from_ps_value(value: ::powershell_script::PsValue) -> ::std::result::Result<Self, ::powershell_script::PsError> {{
+                ::std::result::Result::Ok({name} {{ {init} }})
+            }}
+        }}",
+        name = name,
+        init = init
+    );
+    Ok(tokens.parse().expect("generated tokens are valid"))
+}
+
+/// Parses the named fields in the body of a struct.
+fn fields(body: TokenStream) -> Result<Vec<Field>, String> {
+    let mut fields = Vec::new();
+    let mut rename = None;
+    let mut tokens = body.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        match token {
+            // Attributes
+            TokenTree::Punct(p) if p.as_char() == '#' => {
+                if let Some(TokenTree::Group(group)) = tokens.next() {
+                    if let Some(name) = ps_rename(group.stream())? {
+                        rename = Some(name);
+                    }
+                }
+            }
+            TokenTree::Ident(ident) if ident.to_string() == "pub" => {
+                // Skip `pub(crate)` and friends
+                if let Some(TokenTree::Group(group)) = tokens.peek() {
+                    if group.delimiter() == Delimiter::Parenthesis {
+                        tokens.next();
+                    }
+                }
+            }
+            TokenTree::Ident(ident) => {
+                let ident = ident.to_string();
+                match tokens.next() {
+                    Some(TokenTree::Punct(p)) if p.as_char() == ':' => {}
+                    _ => {
+                        return Err(
+                            "FromPs can only be derived for structs with named fields".into()
+                        )
+                    }
+                }
+                // Skip the type, which ends at the first comma outside of
+                // angle brackets
+                let mut depth = 0;
+                for token in tokens.by_ref() {
+                    if let TokenTree::Punct(p) = token {
+                        match p.as_char() {
+                            '<' => depth += 1,
+                            '>' => depth -= 1,
+                            ',' if depth == 0 => break,
+                            _ => {}
+                        }
+                    }
+                }
+                let property = rename
+                    .take()
+                    .unwrap_or_else(|| pascal_case(ident.trim_start_matches("r#")));
+                fields.push(Field { ident, property });
+            }
+            _ => {}
+        }
+    }
+    Ok(fields)
+}
+
+/// Returns the name in `ps(rename = "Name")`, or `None` for other attributes.
+fn ps_rename(attr: TokenStream) -> Result<Option<String>, String> {
+    let mut tokens = attr.into_iter();
+    match tokens.next() {
+        Some(TokenTree::Ident(ident)) if ident.to_string() == "ps" => {}
+        _ => return Ok(None),
+    }
+    let args: Vec<TokenTree> = match tokens.next() {
+        Some(TokenTree::Group(group)) => group.stream().into_iter().collect(),
+        _ => Vec::new(),
+    };
+    match args.as_slice() {
+        [TokenTree::Ident(key), TokenTree::Punct(eq), TokenTree::Literal(value)]
+            if key.to_string() == "rename" && eq.as_char() == '=' =>
+        {
+            lit::parse_str(&value.to_string())
+                .map(Some)
+                .ok_or_else(|| "expected a string in #[ps(rename = \"...\")]".to_string())
+        }
+        _ => Err("the only attribute FromPs supports is #[ps(rename = \"...\")]".into()),
+    }
+}
+
+/// `display_name` -> `DisplayName`
+fn pascal_case(ident: &str) -> String {
+    ident
+        .split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,171 @@
+//! Procedural macros for [powershell_script](https://docs.rs/powershell_script).
+//! Use them through the `macros` feature of that crate rather than depending
+//! on this crate directly.
+//!
+//! The macros only use the compiler's `proc_macro` API, so the crate has no
+//! dependencies.
+
+extern crate proc_macro;
+
+mod check;
+mod derive;
+mod lit;
+mod validate;
+
+use proc_macro::{Literal, Span, TokenStream, TokenTree};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Embeds a PowerShell script file as a `&'static str`, like `include_str!`.
+///
+/// The path is relative to the file the macro is invoked from. The contents
+/// are normalized so they run the same regardless of how the file was
+/// checked out: a UTF-8 byte order mark is removed and line endings are
+/// converted to `\n`.
+///
+/// Pass `check` as a second argument to parse the script with PowerShell at
+/// build time and fail compilation on syntax errors. The check is skipped on
+/// machines where neither `pwsh` nor `powershell` can be found.
+///
+/// ```rust, ignore
+/// let deploy = include_ps!("scripts/deploy.ps1", check);
+/// powershell_script::run(deploy).unwrap();
+/// ```
+#[proc_macro]
+pub fn include_ps(input: TokenStream) -> TokenStream {
+    match include_ps_impl(input) {
+        Ok(tokens) => tokens,
+        Err(msg) => compile_error(&msg),
+    }
+}
+
+/// Derives `powershell_script::FromPsValue` for a struct with named fields,
+/// reading each field from the property with the PascalCase version of its
+/// name. Use `#[ps(rename = "Name")]` on a field to read it from another
+/// property.
+#[proc_macro_derive(FromPs, attributes(ps))]
+pub fn derive_from_ps(input: TokenStream) -> TokenStream {
+    match derive::from_ps(input) {
+        Ok(tokens) => tokens,
+        Err(msg) => compile_error(&msg),
+    }
+}
+
+/// An inline PowerShell script, checked at compile time.
+///
+/// Takes a single string literal (a raw string is usually the most
+/// convenient) and evaluates to a `powershell_script::Script` which can be
+/// passed to all the run methods. Compilation fails if quotes, brackets,
+/// here-strings or block comments aren't balanced, or if a double quoted
+/// string contains a `{name}` placeholder, since the macro never interpolates
+/// Rust values into the script. Pass values as arguments instead, with
+/// `PsScript::run_with_args`.
+///
+/// ```rust, ignore
+/// let script = ps!(r#"
+///     param($Name)
+///     "Hello $Name"
+/// "#);
+/// ps.run_with_args(&script, &["world"]).unwrap();
+/// ```
+#[proc_macro]
+pub fn ps(input: TokenStream) -> TokenStream {
+    match ps_impl(input) {
+        Ok(tokens) => tokens,
+        Err(msg) => compile_error(&msg),
+    }
+}
+
+fn ps_impl(input: TokenStream) -> Result<TokenStream, String> {
+    let mut tokens = input.into_iter();
+    let script = match (tokens.next(), tokens.next()) {
+        (Some(TokenTree::Literal(lit)), None) => lit::parse_str(&lit.to_string()),
+        _ => None,
+    }
+    .ok_or("ps! expects a single string literal")?;
+
+    validate::script(&script).map_err(|e| format!("invalid script: {}", e))?;
+
+    let tokens = format!(
+        "::powershell_script::Script::from_static({})",
+        Literal::string(&script)
+    );
+    Ok(tokens.parse().expect("generated tokens are valid"))
+}
+
+fn include_ps_impl(input: TokenStream) -> Result<TokenStream, String> {
+    let mut tokens = input.into_iter();
+    let path = match tokens.next() {
+        Some(TokenTree::Literal(lit)) => lit::parse_str(&lit.to_string())
+            .ok_or("include_ps! expects a string literal with the path to the script")?,
+        _ => return Err("include_ps! expects a string literal with the path to the script".into()),
+    };
+
+    let mut check = false;
+    match tokens.next() {
+        None => {}
+        Some(TokenTree::Punct(p)) if p.as_char() == ',' => match tokens.next() {
+            Some(TokenTree::Ident(ident)) if ident.to_string() == "check" => check = true,
+            None => {}
+            _ => return Err("the only option supported by include_ps! is `check`".into()),
+        },
+        _ => return Err("unexpected tokens after the path in include_ps!".into()),
+    }
+    match tokens.next() {
+        None => {}
+        Some(TokenTree::Punct(p)) if p.as_char() == ',' && tokens.next().is_none() => {}
+        _ => return Err("unexpected tokens in include_ps!".into()),
+    }
+
+    let path = resolve(Path::new(&path));
+    let bytes = fs::read(&path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+    let script =
+        String::from_utf8(bytes).map_err(|_| format!("{} is not valid UTF-8", path.display()))?;
+    let script = normalize(&script);
+
+    if check {
+        check::syntax(&path)?;
+    }
+
+    // `include_bytes!` makes cargo rebuild the crate when the script changes
+    let tokens = format!(
+        "{{ const _: &[u8] = include_bytes!({}); {} }}",
+        Literal::string(&path.to_string_lossy()),
+        Literal::string(&script)
+    );
+    Ok(tokens.parse().expect("generated tokens are valid"))
+}
+
+/// Resolves `path` relative to the file invoking the macro, falling back to
+/// the root of the crate being compiled. The result is absolute since it's
+/// passed on to `include_bytes!`, which resolves relative paths differently.
+fn resolve(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        return path.to_path_buf();
+    }
+    let dir = Span::call_site()
+        .local_file()
+        .and_then(|file| file.parent().map(Path::to_path_buf))
+        .or_else(|| std::env::var_os("CARGO_MANIFEST_DIR").map(PathBuf::from))
+        .unwrap_or_default();
+    // `local_file` is relative to the directory the compiler runs in
+    let dir = match std::env::current_dir() {
+        Ok(cwd) => cwd.join(dir),
+        Err(_) => dir,
+    };
+    dir.join(path)
+}
+
+/// Removes a byte order mark and converts line endings to `\n`.
+fn normalize(script: &str) -> String {
+    let script = script.strip_prefix('\u{feff}').unwrap_or(script);
+    script.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+fn compile_error(msg: &str) -> TokenStream {
+    format!("compile_error!({})", Literal::string(msg))
+        .parse()
+        .expect("generated tokens are valid")
+}
@@ -0,0 +1,50 @@
+//! Parsing of Rust string literals, since the `proc_macro` API only gives us
+//! their source text.
+
+/// Returns the value of the string literal `src`, or `None` if it isn't one.
+/// Handles regular and raw strings.
+pub(crate) fn parse_str(src: &str) -> Option<String> {
+    if let Some(raw) = src.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        let body = raw[hashes..].strip_prefix('"')?;
+        let body = body.strip_suffix(&raw[..hashes])?.strip_suffix('"')?;
+        return Some(body.to_string());
+    }
+
+    let body = src.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = String::with_capacity(body.len());
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => out.push('\n'),
+            'r' => out.push('\r'),
+            't' => out.push('\t'),
+            '0' => out.push('\0'),
+            '\\' => out.push('\\'),
+            '\'' => out.push('\''),
+            '"' => out.push('"'),
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                out.push(u8::from_str_radix(&hex, 16).ok()? as char);
+            }
+            'u' => {
+                let rest = chars.as_str().strip_prefix('{')?;
+                let end = rest.find('}')?;
+                let code = u32::from_str_radix(&rest[..end].replace('_', ""), 16).ok()?;
+                out.push(char::from_u32(code)?);
+                chars = rest[end + 1..].chars();
+            }
+            // A line continuation skips the line break and leading whitespace
+            '\n' => {
+                let rest = chars.as_str().trim_start();
+                chars = rest.chars();
+            }
+            _ => return None,
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1,156 @@
+//! Lexical validation of inline scripts. This isn't a PowerShell parser, it
+//! only catches the mistakes which are easy to make when writing PowerShell
+//! inside a Rust string.
+
+/// Where we are in the script.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Context {
+    Code,
+    SingleQuoted,
+    DoubleQuoted,
+    HereString(char),
+}
+
+/// Checks that quotes and brackets in `script` are balanced and that double
+/// quoted strings don't contain what looks like Rust `format!` interpolation.
+pub(crate) fn script(script: &str) -> Result<(), String> {
+    // Contexts we'll return to, with the line they started on
+    let mut contexts: Vec<(Context, usize)> = vec![(Context::Code, 1)];
+    // Open brackets, the line they're on and whether they started a
+    // subexpression inside a string
+    let mut brackets: Vec<(char, usize, bool)> = Vec::new();
+    let mut block_comment = None;
+
+    for (index, line) in script.lines().enumerate() {
+        let line_no = index + 1;
+        let (context, _) = *contexts.last().expect("code context is never popped");
+
+        if let Context::HereString(quote) = context {
+            if line.starts_with(quote) && line[1..].starts_with('@') {
+                contexts.pop();
+            }
+            continue;
+        }
+
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let next = chars.get(i + 1).copied();
+            i += 1;
+
+            if block_comment.is_some() {
+                if c == '#' && next == Some('>') {
+                    block_comment = None;
+                    i += 1;
+                }
+                continue;
+            }
+
+            let (context, _) = *contexts.last().expect("code context is never popped");
+            match context {
+                Context::Code => match c {
+                    '#' => break,
+                    '<' if next == Some('#') => {
+                        block_comment = Some(line_no);
+                        i += 1;
+                    }
+                    '`' => i += 1,
+                    '@' if matches!(next, Some('"' | '\'')) && i + 1 == chars.len() => {
+                        contexts.push((Context::HereString(next.unwrap()), line_no));
+                        i += 1;
+                    }
+                    '\'' => contexts.push((Context::SingleQuoted, line_no)),
+                    '"' => contexts.push((Context::DoubleQuoted, line_no)),
+                    '(' | '{' | '[' => brackets.push((c, line_no, false)),
+                    ')' | '}' | ']' => {
+                        let (open, _, subexpression) = brackets
+                            .pop()
+                            .ok_or_else(|| format!("unexpected `{}` on line {}", c, line_no))?;
+                        if closing(open) != c {
+                            return Err(format!(
+                                "`{}` on line {} doesn't match `{}`",
+                                c, line_no, open
+                            ));
+                        }
+                        if subexpression {
+                            contexts.pop();
+                        }
+                    }
+                    _ => {}
+                },
+                Context::SingleQuoted => {
+                    if c == '\'' {
+                        if next == Some('\'') {
+                            i += 1;
+                        } else {
+                            contexts.pop();
+                        }
+                    }
+                }
+                Context::DoubleQuoted => match c {
+                    '`' => i += 1,
+                    '"' if next == Some('"') => i += 1,
+                    '"' => {
+                        contexts.pop();
+                    }
+                    '$' if next == Some('(') => {
+                        brackets.push(('(', line_no, true));
+                        contexts.push((Context::Code, line_no));
+                        i += 1;
+                    }
+                    '{' if chars.get(i.wrapping_sub(2)) != Some(&'$') => {
+                        let rest: String = chars[i..].iter().collect();
+                        if let Some(name) = format_placeholder(&rest) {
+                            return Err(format!(
+                                "`{{{}}}` on line {} looks like Rust string interpolation, which ps! doesn't do; pass values to the script as arguments instead",
+                                name, line_no
+                            ));
+                        }
+                    }
+                    _ => {}
+                },
+                Context::HereString(_) => unreachable!("here-strings are handled per line"),
+            }
+        }
+    }
+
+    if let Some(line) = block_comment {
+        return Err(format!(
+            "unterminated block comment starting on line {}",
+            line
+        ));
+    }
+    if let Some((context, line)) = contexts.last() {
+        if *context != Context::Code || contexts.len() > 1 {
+            return Err(format!("unterminated string starting on line {}", line));
+        }
+    }
+    if let Some((open, line, _)) = brackets.last() {
+        return Err(format!("unclosed `{}` on line {}", open, line));
+    }
+    Ok(())
+}
+
+fn closing(open: char) -> char {
+    match open {
+        '(' => ')',
+        '{' => '}',
+        _ => ']',
+    }
+}
+
+/// Returns the name in `rest` if it starts with `name}`, `name:...}` or `}`,
+/// like a `format!` placeholder.
+fn format_placeholder(rest: &str) -> Option<&str> {
+    let end = rest.find('}')?;
+    let inner = &rest[..end];
+    let name = inner.split(':').next().unwrap_or("");
+    let is_ident = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with(|c: char| c.is_ascii_digit());
+    if is_ident && (name.len() == inner.len() || !name.is_empty()) {
+        Some(inner)
+    } else {
+        None
+    }
+}
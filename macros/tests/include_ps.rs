@@ -0,0 +1,19 @@
+use powershell_script_macros::include_ps;
+
+#[test]
+fn normalizes_bom_and_line_endings() {
+    let script = include_ps!("scripts/crlf.ps1");
+    assert_eq!(script, "Write-Output \"one\"\nWrite-Output \"two\"\n");
+}
+
+#[test]
+fn accepts_raw_strings() {
+    let script: &'static str = include_ps!(r"scripts/crlf.ps1");
+    assert!(script.starts_with("Write-Output"));
+}
+
+#[test]
+fn check_accepts_valid_script() {
+    let script = include_ps!("scripts/crlf.ps1", check);
+    assert!(script.ends_with("\"two\"\n"));
+}
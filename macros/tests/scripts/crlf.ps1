@@ -0,0 +1,2 @@
+﻿Write-Output "one"
+Write-Output "two"
@@ -0,0 +1,206 @@
+//! Linting scripts with
+//! [PSScriptAnalyzer](https://github.com/PowerShell/PSScriptAnalyzer), for
+//! checking user-provided scripts before running them.
+//!
+//! ## Example
+//!
+//! ```rust, no_run
+//! use powershell_script::{analyzer::{self, Options, Severity}, PsScriptBuilder};
+//!
+//! let ps = PsScriptBuilder::new().build();
+//! let options = Options::new().install_if_missing(true);
+//! let diagnostics = analyzer::analyze(&ps, "Invoke-Expression $userInput", &options).unwrap();
+//! if diagnostics.iter().any(|d| d.severity >= Severity::Warning) {
+//!     for diagnostic in &diagnostics {
+//!         println!("{}", diagnostic);
+//!     }
+//! }
+//! ```
+
+use std::{fmt, path::Path};
+
+use crate::{
+    error::PsError,
+    value::{FromPsValue, PsValue},
+    wrap, PsScript, Result,
+};
+
+/// Configures the analysis.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    install: bool,
+    exclude_rules: Vec<String>,
+    severities: Vec<Severity>,
+}
+
+impl Options {
+    /// Runs all the default rules, without installing PSScriptAnalyzer.
+    pub fn new() -> Self {
+        Options::default()
+    }
+
+    /// If set to `true`, PSScriptAnalyzer is installed for the current user
+    /// from the PowerShell Gallery if it isn't installed already. Otherwise
+    /// the analysis fails if it's missing.
+    pub fn install_if_missing(mut self, flag: bool) -> Self {
+        self.install = flag;
+        self
+    }
+
+    /// Skips the rule called `rule`, like `PSAvoidUsingWriteHost`.
+    pub fn exclude_rule(mut self, rule: impl Into<String>) -> Self {
+        self.exclude_rules.push(rule.into());
+        self
+    }
+
+    /// Only reports diagnostics of `severity`. Can be called more than once
+    /// to report several severities.
+    pub fn severity(mut self, severity: Severity) -> Self {
+        self.severities.push(severity);
+        self
+    }
+
+    fn script(&self, input: &str) -> String {
+        // A missing module mustn't look like a script without problems
+        let mut script = String::from("$ErrorActionPreference = 'Stop'\n");
+        if self.install {
+            script.push_str("if (-not (Get-Module -ListAvailable -Name PSScriptAnalyzer)) { Install-Module -Name PSScriptAnalyzer -Scope CurrentUser -Force -ErrorAction Stop }\n");
+        }
+        let mut invocation = format!("Invoke-ScriptAnalyzer {}", input);
+        if !self.exclude_rules.is_empty() {
+            let rules: Vec<String> = self.exclude_rules.iter().map(|r| wrap::quote(r)).collect();
+            invocation.push_str(&format!(" -ExcludeRule @({})", rules.join(", ")));
+        }
+        if !self.severities.is_empty() {
+            let severities: Vec<String> = self
+                .severities
+                .iter()
+                .map(|s| wrap::quote(s.name()))
+                .collect();
+            invocation.push_str(&format!(" -Severity @({})", severities.join(", ")));
+        }
+        script.push_str(&format!("$__ps_diagnostics = @({})\n", invocation));
+        script.push_str(&wrap::emit_block("analyzer", "(ConvertTo-Json -Depth 2 -Compress -InputObject @($__ps_diagnostics | ForEach-Object { [ordered]@{ Rule = $_.RuleName; Severity = [string]$_.Severity; Line = $_.Line; Column = $_.Column; Message = $_.Message; File = $_.ScriptPath } }))"));
+        script.push('\n');
+        script
+    }
+}
+
+/// How serious a diagnostic is, from least to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Information,
+    Warning,
+    Error,
+    /// The script can't be parsed.
+    ParseError,
+}
+
+impl Severity {
+    fn name(self) -> &'static str {
+        match self {
+            Severity::Information => "Information",
+            Severity::Warning => "Warning",
+            Severity::Error => "Error",
+            Severity::ParseError => "ParseError",
+        }
+    }
+}
+
+/// A problem found by a rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The name of the rule, like `PSAvoidUsingInvokeExpression`.
+    pub rule: String,
+    pub severity: Severity,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub message: String,
+    /// The file the problem was found in, when analyzing files.
+    pub file: Option<String>,
+}
+
+impl FromPsValue for Diagnostic {
+    fn from_ps_value(value: PsValue) -> Result<Self> {
+        let string = |key: &str| {
+            value
+                .get(key)
+                .and_then(PsValue::as_str)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+        };
+        let number = |key: &str| -> Result<Option<u32>> {
+            match value.get(key).cloned() {
+                Some(n) => Ok(Option::<u32>::from_ps_value(n)?.filter(|n| *n > 0)),
+                None => Ok(None),
+            }
+        };
+        let severity = match string("Severity").as_deref() {
+            Some("Information") => Severity::Information,
+            Some("Warning") => Severity::Warning,
+            Some("ParseError") => Severity::ParseError,
+            _ => Severity::Error,
+        };
+        Ok(Diagnostic {
+            rule: string("Rule").unwrap_or_default(),
+            severity,
+            line: number("Line")?,
+            column: number("Column")?,
+            message: string("Message").unwrap_or_default(),
+            file: string("File"),
+        })
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        if let Some(line) = self.line {
+            write!(f, "{}:", line)?;
+            if let Some(column) = self.column {
+                write!(f, "{}:", column)?;
+            }
+        }
+        if self.file.is_some() || self.line.is_some() {
+            write!(f, " ")?;
+        }
+        write!(
+            f,
+            "{} {}: {}",
+            self.severity.name(),
+            self.rule,
+            self.message
+        )
+    }
+}
+
+/// Analyzes the source of a script.
+pub fn analyze(ps: &PsScript, script: &str, options: &Options) -> Result<Vec<Diagnostic>> {
+    let input = format!("-ScriptDefinition {}", wrap::quote(script));
+    run(ps, &options.script(&input))
+}
+
+/// Analyzes the script file at `path`, or the scripts in the directory at
+/// `path`.
+pub fn analyze_file<P: AsRef<Path>>(
+    ps: &PsScript,
+    path: P,
+    options: &Options,
+) -> Result<Vec<Diagnostic>> {
+    let path = path.as_ref();
+    let mut input = format!("-Path {}", wrap::quote(&path.to_string_lossy()));
+    if path.is_dir() {
+        input.push_str(" -Recurse");
+    }
+    run(ps, &options.script(&input))
+}
+
+fn run(ps: &PsScript, script: &str) -> Result<Vec<Diagnostic>> {
+    let output = ps.run_with_args(script, std::iter::empty::<&str>())?;
+    let json = output.block("analyzer").ok_or_else(|| {
+        PsError::Deserialize("PSScriptAnalyzer didn't return any diagnostics".to_string())
+    })?;
+    Vec::from_ps_value(PsValue::from_json(json)?)
+}
@@ -0,0 +1,196 @@
+//! Handling of the ANSI escape sequences PowerShell 7.2+ writes to style its
+//! output.
+
+use std::borrow::Cow;
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+
+/// Returns `bytes` with all escape sequences removed.
+pub(crate) fn strip(bytes: &[u8]) -> Cow<'_, [u8]> {
+    if !bytes.contains(&ESC) {
+        return Cow::Borrowed(bytes);
+    }
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == ESC {
+            i = sequence_end(bytes, i);
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// Returns the index after the escape sequence starting at `start`.
+pub(crate) fn sequence_end(bytes: &[u8], start: usize) -> usize {
+    let mut i = start + 1;
+    match bytes.get(i) {
+        // CSI: parameters and intermediates, ended by a byte in 0x40..=0x7e
+        Some(b'[') => {
+            i += 1;
+            while i < bytes.len() && !(0x40..=0x7e).contains(&bytes[i]) {
+                i += 1;
+            }
+            (i + 1).min(bytes.len())
+        }
+        // OSC (used for hyperlinks and window titles): ended by BEL or ST
+        Some(b']') => {
+            while i < bytes.len() {
+                if bytes[i] == BEL {
+                    return i + 1;
+                }
+                if bytes[i] == ESC && bytes.get(i + 1) == Some(&b'\\') {
+                    return i + 2;
+                }
+                i += 1;
+            }
+            i
+        }
+        Some(_) => i + 1,
+        None => i,
+    }
+}
+
+/// A color set by an escape sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Color {
+    /// A color from the 256 color palette. 0-7 are the standard colors (black,
+    /// red, green, yellow, blue, magenta, cyan, white) and 8-15 their bright
+    /// versions.
+    Indexed(u8),
+    /// A 24-bit color.
+    Rgb(u8, u8, u8),
+}
+
+/// The style of a piece of text. The default is unstyled text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Style {
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub blink: bool,
+    pub reverse: bool,
+    pub hidden: bool,
+    pub strikethrough: bool,
+}
+
+impl Style {
+    /// Applies the parameters of an SGR (`ESC [ ... m`) sequence.
+    fn apply(&mut self, params: &str) {
+        let mut params = params
+            .split([';', ':'])
+            .map(|p| p.parse::<u16>().unwrap_or(0));
+        // An empty sequence is a reset
+        let mut next = Some(params.next().unwrap_or(0));
+        while let Some(code) = next {
+            match code {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                5 | 6 => self.blink = true,
+                7 => self.reverse = true,
+                8 => self.hidden = true,
+                9 => self.strikethrough = true,
+                22 => {
+                    self.bold = false;
+                    self.dim = false;
+                }
+                23 => self.italic = false,
+                24 => self.underline = false,
+                25 => self.blink = false,
+                27 => self.reverse = false,
+                28 => self.hidden = false,
+                29 => self.strikethrough = false,
+                30..=37 => self.foreground = Some(Color::Indexed((code - 30) as u8)),
+                38 => self.foreground = extended_color(&mut params),
+                39 => self.foreground = None,
+                40..=47 => self.background = Some(Color::Indexed((code - 40) as u8)),
+                48 => self.background = extended_color(&mut params),
+                49 => self.background = None,
+                90..=97 => self.foreground = Some(Color::Indexed((code - 90 + 8) as u8)),
+                100..=107 => self.background = Some(Color::Indexed((code - 100 + 8) as u8)),
+                _ => {}
+            }
+            next = params.next();
+        }
+    }
+}
+
+/// Reads the rest of a `38;5;n` or `38;2;r;g;b` color.
+fn extended_color(params: &mut impl Iterator<Item = u16>) -> Option<Color> {
+    let byte = |p: Option<u16>| p.map(|p| p.min(255) as u8);
+    match params.next()? {
+        5 => byte(params.next()).map(Color::Indexed),
+        2 => Some(Color::Rgb(
+            byte(params.next())?,
+            byte(params.next())?,
+            byte(params.next())?,
+        )),
+        _ => None,
+    }
+}
+
+/// A piece of text written with the same style.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StyledSpan {
+    pub text: String,
+    pub style: Style,
+}
+
+/// Splits output containing ANSI escape sequences into spans of text with the
+/// style they're written in, so it can be rendered in a GUI. Escape sequences
+/// other than the ones setting colors and text attributes are dropped.
+///
+/// ## Example
+///
+/// ```rust
+/// use powershell_script::{parse_ansi, Color};
+///
+/// let spans = parse_ansi(b"\x1b[32;1mOK\x1b[0m done");
+/// assert_eq!(spans[0].text, "OK");
+/// assert_eq!(spans[0].style.foreground, Some(Color::Indexed(2)));
+/// assert!(spans[0].style.bold);
+/// assert_eq!(spans[1].text, " done");
+/// ```
+pub fn parse_ansi(bytes: &[u8]) -> Vec<StyledSpan> {
+    let mut spans: Vec<StyledSpan> = Vec::new();
+    let mut style = Style::default();
+    let mut text = Vec::new();
+    let mut flush = |text: &mut Vec<u8>, style: Style| {
+        if text.is_empty() {
+            return;
+        }
+        let s = String::from_utf8_lossy(text).into_owned();
+        text.clear();
+        match spans.last_mut() {
+            Some(last) if last.style == style => last.text.push_str(&s),
+            _ => spans.push(StyledSpan { text: s, style }),
+        }
+    };
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != ESC {
+            text.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let end = sequence_end(bytes, i);
+        let sequence = &bytes[i..end];
+        if sequence.len() >= 3 && sequence[1] == b'[' && sequence[sequence.len() - 1] == b'm' {
+            flush(&mut text, style);
+            style.apply(&String::from_utf8_lossy(&sequence[2..sequence.len() - 1]));
+        }
+        i = end;
+    }
+    flush(&mut text, style);
+    spans
+}
@@ -1,15 +1,217 @@
 use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 
-use crate::PsScript;
+use crate::{
+    callback::HostCallback,
+    credential::{Credential, CredentialProvider, CredentialRequest},
+    error::{BuildError, PsError},
+    events::Progress,
+    heartbeat::StallCallback,
+    metrics::{Metrics, MetricsMiddleware},
+    middleware::Middleware,
+    requires::ModuleSpec,
+    script::FailureHook,
+    session::RecyclePolicy,
+    side_channel::{HostMessage, MessageHandler},
+    target,
+    value::PsValue,
+    ChildTracker, ExecutionQuota, PsPool, PsScript, PsSession,
+};
+
+/// The parameters of the PowerShell executable along with their documented
+/// aliases, used to recognize them in raw arguments. Any other unambiguous
+/// prefix of a name is accepted by PowerShell as well.
+const POWERSHELL_PARAMETERS: &[(&str, &[&str])] = &[
+    ("-Command", &["-c"]),
+    ("-ConfigurationName", &["-config"]),
+    ("-CustomPipeName", &[]),
+    ("-EncodedCommand", &["-e", "-ec"]),
+    ("-ExecutionPolicy", &["-ep", "-ex"]),
+    ("-File", &["-f"]),
+    ("-InputFormat", &["-if", "-inp"]),
+    ("-Interactive", &["-i"]),
+    ("-Login", &["-l"]),
+    ("-MTA", &[]),
+    ("-NoExit", &["-noe"]),
+    ("-NoLogo", &["-nol"]),
+    ("-NonInteractive", &["-noni"]),
+    ("-NoProfile", &["-nop"]),
+    ("-OutputFormat", &["-o", "-of"]),
+    ("-SettingsFile", &["-settings"]),
+    ("-STA", &[]),
+    ("-Version", &["-v"]),
+    ("-WindowStyle", &["-w"]),
+    ("-WorkingDirectory", &["-wd"]),
+];
+
+/// Default size of the chunks scripts are written to PowerShell's `stdin` in.
+const DEFAULT_STDIN_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Decides how a script is handed over to PowerShell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionMode {
+    /// Pipes the script to PowerShell's `stdin` line by line. Each line runs
+    /// as a separate command. This is the default.
+    Stdin,
+    /// Wraps the script in `& { <script> }` and runs it as a single script
+    /// block. This enables `param()` blocks and `return` semantics for inline
+    /// scripts without writing them to a file first.
+    CallOperator,
+    /// Pipes the script to PowerShell's `stdin` exactly as it was passed,
+    /// without splitting it into lines or normalizing line endings, so
+    /// here-strings keep their content byte for byte. Options which rewrite
+    /// the script, like `abort_on_error` and `record_timeline`, can't be
+    /// used with it.
+    /// A line ending is added if the script doesn't end with one.
+    Raw,
+    /// Passes the script as the `-EncodedCommand` argument, base64 of its
+    /// UTF-16LE encoding, so PowerShell parses it as a whole like a script
+    /// file instead of reading it from `stdin` command by command. None of
+    /// the quoting and line ending pitfalls of `stdin` apply, but the script
+    /// is subject to the platform's command line length limit, which is
+    /// 32767 characters on Windows, or about 12000 characters of script.
+    /// The options for writing to `stdin`, `stdin_buffer_size`,
+    /// `split_lines` and `stdin_encoding`, can't be used with it.
+    Encoded,
+}
+
+/// The encoding the program is written to PowerShell's `stdin` in. It has to
+/// match what PowerShell reads its input as, which is decided by
+/// `[Console]::InputEncoding` and depends on the platform and version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StdinEncoding {
+    /// UTF-8 without a byte order mark. This is the default.
+    Utf8,
+    /// UTF-8 starting with a byte order mark.
+    Utf8Bom,
+    /// UTF-16, little endian, starting with a byte order mark.
+    Utf16Le,
+}
+
+/// When cmdlets ask for confirmation before making changes, set with
+/// `PsScriptBuilder::confirm`. Cmdlets ask when their impact is at least the
+/// level of `$ConfirmPreference`. Since scripts can't answer prompts when
+/// run non-interactively, asking fails the cmdlet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmPolicy {
+    /// Leaves `$ConfirmPreference` as it is, which asks for high impact
+    /// changes unless the profile changes it. This is the default.
+    Default,
+    /// Never asks, like passing `-Confirm:$false` to every cmdlet.
+    None,
+    /// Asks for changes with a low, medium or high impact.
+    Low,
+    /// Asks for changes with a medium or high impact.
+    Medium,
+    /// Asks for changes with a high impact.
+    High,
+}
+
+impl ConfirmPolicy {
+    /// The value of `$ConfirmPreference` for the policy.
+    pub(crate) fn preference(self) -> Option<&'static str> {
+        match self {
+            ConfirmPolicy::Default => None,
+            ConfirmPolicy::None => Some("None"),
+            ConfirmPolicy::Low => Some("Low"),
+            ConfirmPolicy::Medium => Some("Medium"),
+            ConfirmPolicy::High => Some("High"),
+        }
+    }
+}
+
+/// The execution policy PowerShell runs with, set with
+/// `PsScriptBuilder::execution_policy`. It only applies to the process
+/// running the script and has no effect on platforms other than Windows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionPolicy {
+    /// Only runs scripts signed by a trusted publisher.
+    AllSigned,
+    /// Runs everything without warnings or prompts.
+    Bypass,
+    /// The default policy of the platform.
+    Default,
+    /// Requires scripts downloaded from the internet to be signed.
+    RemoteSigned,
+    /// Doesn't run any scripts, only single commands.
+    Restricted,
+    /// Removes the policy set for the process, so the next scope's applies.
+    Undefined,
+    /// Runs everything, warning about scripts downloaded from the internet.
+    Unrestricted,
+}
+
+impl ExecutionPolicy {
+    /// The value of `-ExecutionPolicy` for the policy.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            ExecutionPolicy::AllSigned => "AllSigned",
+            ExecutionPolicy::Bypass => "Bypass",
+            ExecutionPolicy::Default => "Default",
+            ExecutionPolicy::RemoteSigned => "RemoteSigned",
+            ExecutionPolicy::Restricted => "Restricted",
+            ExecutionPolicy::Undefined => "Undefined",
+            ExecutionPolicy::Unrestricted => "Unrestricted",
+        }
+    }
+}
 
 /// Builds a `PsScript` instance with configurable options for running your
 /// script.
 pub struct PsScriptBuilder {
     args: VecDeque<&'static str>,
+    raw_args: Vec<OsString>,
+    env: Vec<(OsString, OsString)>,
+    current_dir: Option<PathBuf>,
     no_profile: bool,
     non_interactive: bool,
+    execution_policy: Option<ExecutionPolicy>,
     hidden: bool,
     print_commands: bool,
+    mode: ExecutionMode,
+    capture_return: bool,
+    capture_host: bool,
+    capture_streams: bool,
+    capture_env: bool,
+    capture_location: bool,
+    capture_errors: bool,
+    capture_changes: bool,
+    required_modules: Vec<ModuleSpec>,
+    libraries: Vec<(String, String)>,
+    what_if: bool,
+    confirm: ConfirmPolicy,
+    temp_workspace: bool,
+    cancellation_sentinel: bool,
+    abort_on_error: bool,
+    stdin_buffer_size: usize,
+    split_lines: bool,
+    stdin_encoding: StdinEncoding,
+    console_codepage: Option<u32>,
+    culture: Option<String>,
+    record_timeline: bool,
+    strip_ansi: bool,
+    deterministic_output: bool,
+    progress: Progress,
+    credential_provider: Option<CredentialProvider>,
+    callbacks: Vec<(String, HostCallback)>,
+    message_handler: Option<MessageHandler>,
+    probe_paths: Vec<PathBuf>,
+    executable: Option<PathBuf>,
+    exit_codes: Vec<(i32, String)>,
+    failure_hooks: Vec<FailureHook>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    metrics: Vec<Arc<dyn Metrics>>,
+    tracker: Option<ChildTracker>,
+    quota: Option<ExecutionQuota>,
+    recycle: RecyclePolicy,
+    warm_up: Option<Arc<str>>,
+    health_check: Option<Arc<str>>,
+    input_timeout: Option<Duration>,
+    stall_timeout: Option<Duration>,
+    on_stall: Option<StallCallback>,
 }
 
 impl PsScriptBuilder {
@@ -27,11 +229,115 @@ impl PsScriptBuilder {
 
     /// Runs the script in non-interactive mode, which does not present an
     /// interactive prompt to the user. See [NonInteractive flag](https://docs.microsoft.com/en-us/powershell/module/microsoft.powershell.core/about/about_powershell_exe?view=powershell-5.1#-noninteractive)
+    ///
+    /// Can't be combined with `input_timeout`, which turns it off.
     pub fn non_interactive(mut self, flag: bool) -> Self {
         self.non_interactive = flag;
         self
     }
 
+    /// Runs PowerShell with `-ExecutionPolicy <policy>`, which overrides the
+    /// machine and user policies for this process only. Use
+    /// `ExecutionPolicy::Bypass` to run scripts on machines whose policy
+    /// would otherwise refuse them. Has no effect on platforms other than
+    /// Windows.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::{ExecutionPolicy, PsScriptBuilder};
+    ///
+    /// let ps = PsScriptBuilder::new()
+    ///     .execution_policy(ExecutionPolicy::Bypass)
+    ///     .build();
+    /// ps.run("& ./install.ps1").unwrap();
+    /// ```
+    pub fn execution_policy(mut self, policy: ExecutionPolicy) -> Self {
+        self.execution_policy = Some(policy);
+        self
+    }
+
+    /// Fails runs which wait for input at a prompt for longer than `timeout`
+    /// with `PsError::WaitingForInput`, instead of hanging until somebody
+    /// notices. Scripts can't be answered when run by this crate, so a
+    /// prompt only ever ends when it times out.
+    ///
+    /// The prompts detected are those of `Read-Host`, and of `Get-Credential`
+    /// unless a `credential_provider` answers it. A prompt which hasn't
+    /// written any output for `timeout` is considered hung, and PowerShell
+    /// is killed.
+    ///
+    /// With `non_interactive` set these prompts fail right away, so this
+    /// turns `non_interactive` off, which is on by default. Turning it back
+    /// on afterwards makes `try_build` fail with
+    /// `BuildError::ConflictingOptions`.
+    ///
+    /// This only applies to `run`, `run_with_args` and `invoke_function`,
+    /// and can't be used with `record_timeline`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use std::time::Duration;
+    /// use powershell_script::{PsError, PsScriptBuilder};
+    ///
+    /// let ps = PsScriptBuilder::new()
+    ///     .input_timeout(Duration::from_secs(10))
+    ///     .build();
+    /// match ps.run("$name = Read-Host 'Name'") {
+    ///     Err(PsError::WaitingForInput(prompt)) => eprintln!("hung at {}", prompt),
+    ///     result => println!("{:?}", result),
+    /// }
+    /// ```
+    pub fn input_timeout(mut self, timeout: Duration) -> Self {
+        self.input_timeout = Some(timeout);
+        self.non_interactive = false;
+        self
+    }
+
+    /// Kills PowerShell if the script makes no progress for `window`, and
+    /// fails the run with `PsError::Stalled`. A script makes progress when it
+    /// writes output, or when the heartbeat the prelude sends over a side
+    /// channel arrives. The heartbeat is only sent between the commands of
+    /// the script, so it stops while the script is stuck in a call which
+    /// doesn't return, like a network request to an unresponsive server.
+    /// Use `on_stall` to be notified instead.
+    ///
+    /// This only applies to `run`, `run_with_args` and `invoke_function`,
+    /// and can't be used with `record_timeline`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use std::time::Duration;
+    /// use powershell_script::{PsError, PsScriptBuilder};
+    ///
+    /// let ps = PsScriptBuilder::new()
+    ///     .stall_timeout(Duration::from_secs(60))
+    ///     .build();
+    /// match ps.run("Invoke-RestMethod https://inventory.internal/api/hosts") {
+    ///     Err(PsError::Stalled(silent)) => eprintln!("no progress for {:?}", silent),
+    ///     result => println!("{:?}", result),
+    /// }
+    /// ```
+    pub fn stall_timeout(mut self, window: Duration) -> Self {
+        self.stall_timeout = Some(window);
+        self
+    }
+
+    /// Calls `callback` with how long the script has been silent when it
+    /// stalls, instead of killing it. See `stall_timeout`, which decides
+    /// when the script has stalled; without it `try_build` fails with
+    /// `BuildError::MissingOption`. The callback is called once per stall,
+    /// and again if the script stalls after having made progress.
+    pub fn on_stall<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(Duration) + Send + Sync + 'static,
+    {
+        self.on_stall = Some(Arc::new(callback));
+        self
+    }
+
     /// Prevents PowerShell window from being shown by creating a console
     /// window with the CREATE_NO_WINDOW flag set. See [creation flags](https://docs.microsoft.com/en-us/windows/win32/procthread/process-creation-flags)
     ///
@@ -49,8 +355,825 @@ impl PsScriptBuilder {
         self
     }
 
+    /// If set to `true` the crate records when each command of the script
+    /// starts and how much output it writes, available through
+    /// `Output::timeline`. Combined with `print_commands` the timeline is
+    /// printed to `stderr` when a script fails, which shows which command of
+    /// a long script failed or hung.
+    ///
+    /// This only applies to `run`, `run_with_args` and `invoke_function`, and
+    /// can't be used with `ExecutionMode::Raw`, `input_timeout` or
+    /// `stall_timeout`.
+    pub fn record_timeline(mut self, flag: bool) -> Self {
+        self.record_timeline = flag;
+        self
+    }
+
+    /// If set to `true` the ANSI escape sequences PowerShell 7.2+ may use to
+    /// color its output are removed from `stdout` and `stderr`. The styled
+    /// output is still available through `Output::styled_stdout` and
+    /// `Output::styled_stderr`. Defaults to `false`, leaving the output as
+    /// PowerShell wrote it.
+    pub fn strip_ansi(mut self, flag: bool) -> Self {
+        self.strip_ansi = flag;
+        self
+    }
+
+    /// If set to `true` the output of a script is the same on every machine
+    /// it runs on, for comparing it to golden files in tests. This
+    ///
+    /// - runs the script with the invariant culture, unless `culture` is set,
+    /// - suppresses progress if it's passed through,
+    /// - strips ANSI escape sequences, regardless of `strip_ansi`,
+    /// - passes `-NoLogo` to PowerShell, and
+    /// - replaces `\r\n` line endings in the output with `\n`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().deterministic_output(true).build();
+    /// let output = ps.run("Get-Date -Date '2024-03-01' | Format-List").unwrap();
+    /// let expected = std::fs::read_to_string("tests/golden/date.txt").unwrap();
+    /// assert_eq!(output.stdout().unwrap(), expected);
+    /// ```
+    pub fn deterministic_output(mut self, flag: bool) -> Self {
+        self.deterministic_output = flag;
+        self
+    }
+
+    /// Sets how calls to `Write-Progress` are handled. Defaults to
+    /// [`Progress::Passthrough`], leaving it to PowerShell like before this
+    /// option existed. Use [`Progress::Suppress`] to keep progress from
+    /// ending up in the captured output.
+    ///
+    /// `run_with_events` and friends always deliver progress as events.
+    pub fn progress(mut self, progress: Progress) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Answers the script's calls to `Get-Credential` with `provider` instead
+    /// of prompting on the console, which isn't possible for a script run by
+    /// this crate. The provider can show a dialog, read a secret store or
+    /// return `None` to decline, in which case `Get-Credential` returns
+    /// `$null` like when its prompt is cancelled.
+    ///
+    /// The credentials are passed to PowerShell over a connection on the
+    /// loopback interface and turned into a `PSCredential` inside the
+    /// session.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::{Credential, PsScriptBuilder};
+    ///
+    /// let ps = PsScriptBuilder::new()
+    ///     .credential_provider(|request| {
+    ///         println!("{}", request.message.as_deref().unwrap_or("Credentials needed"));
+    ///         Some(Credential::new("CORP\\deploy", "hunter2"))
+    ///     })
+    ///     .build();
+    /// ps.run("$cred = Get-Credential; New-PSDrive -Name X -PSProvider FileSystem -Root \\\\srv\\c$ -Credential $cred").unwrap();
+    /// ```
+    pub fn credential_provider<F>(mut self, provider: F) -> Self
+    where
+        F: Fn(&CredentialRequest) -> Option<Credential> + Send + Sync + 'static,
+    {
+        self.credential_provider = Some(Arc::new(provider));
+        self
+    }
+
+    /// Registers `callback` as a function of the host application which the
+    /// script can call by `name` with `Invoke-HostCallback`, for plugin
+    /// scripts which need to ask the application for something while they
+    /// run. `-Data` is passed to the callback as JSON, and what the callback
+    /// returns is returned by `Invoke-HostCallback`. If the callback returns
+    /// an `Err`, `Invoke-HostCallback` throws its message. Names are
+    /// compared case-insensitively, and registering a `name` which is
+    /// already taken replaces its callback.
+    ///
+    /// Calls are passed between PowerShell and the host over a connection on
+    /// the loopback interface, one at a time.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::{PsScriptBuilder, PsValue};
+    ///
+    /// let ps = PsScriptBuilder::new()
+    ///     .host_callback("GetSetting", |data| match data.as_str() {
+    ///         Some("region") => Ok(PsValue::from("eu-west-1")),
+    ///         _ => Err(format!("unknown setting {}", data)),
+    ///     })
+    ///     .build();
+    /// ps.run("$region = Invoke-HostCallback GetSetting -Data 'region'; \"Deploying to $region\"").unwrap();
+    /// ```
+    pub fn host_callback<F>(mut self, name: impl Into<String>, callback: F) -> Self
+    where
+        F: Fn(&PsValue) -> std::result::Result<PsValue, String> + Send + Sync + 'static,
+    {
+        let name = name.into();
+        let callback: HostCallback = Arc::new(callback);
+        match self
+            .callbacks
+            .iter_mut()
+            .find(|(n, _)| n.eq_ignore_ascii_case(&name))
+        {
+            Some(registered) => registered.1 = callback,
+            None => self.callbacks.push((name, callback)),
+        }
+        self
+    }
+
+    /// Passes the messages the script sends with `Send-HostMessage` to
+    /// `handler` as they arrive, for structured results and progress which
+    /// shouldn't be mixed into the output. `-Data` is passed to the handler
+    /// converted through JSON. All messages have been handled by the time
+    /// the run returns.
+    ///
+    /// Messages are sent over a named pipe, or a Unix domain socket on Linux
+    /// and macOS, created for each run.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new()
+    ///     .on_message(|message| {
+    ///         if message.kind == "deployed" {
+    ///             println!("deployed {}", message.data);
+    ///         }
+    ///     })
+    ///     .build();
+    /// ps.run("foreach ($site in 'a', 'b') { Send-HostMessage deployed -Data $site }").unwrap();
+    /// ```
+    pub fn on_message<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&HostMessage) + Send + Sync + 'static,
+    {
+        self.message_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Adds an argument for PowerShell itself, like `-ExecutionPolicy Bypass`
+    /// or `-WorkingDirectory "C:\Build Output"`, placed before the
+    /// arguments telling it which commands to run.
+    ///
+    /// On Windows the argument is written to the command line exactly as
+    /// given instead of being quoted by the standard library, so it must be
+    /// quoted the way PowerShell expects already. This avoids quotes and
+    /// carets in pre-quoted arguments being escaped a second time. On other
+    /// platforms it's passed as a single argument.
+    ///
+    /// `try_build` fails if a parameter is passed more than once, or if it's
+    /// one the builder sets itself, like `-Command` or `-NoProfile` when
+    /// `no_profile` is set.
+    pub fn raw_arg(mut self, arg: impl Into<OsString>) -> Self {
+        self.raw_args.push(arg.into());
+        self
+    }
+
+    /// Sets the environment variable `key` to `value` for PowerShell, on top
+    /// of the environment inherited from this process. Scripts read it with
+    /// `$env:<key>`, which keeps secrets and configuration out of the script
+    /// itself, where they'd show up in `print_commands` and error messages.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let token = std::env::var("DEPLOY_TOKEN").unwrap();
+    /// let ps = PsScriptBuilder::new()
+    ///     .env("DEPLOY_TOKEN", token)
+    ///     .env("DEPLOY_TARGET", "staging")
+    ///     .build();
+    /// ps.run("./deploy.ps1 -Target $env:DEPLOY_TARGET -Token $env:DEPLOY_TOKEN").unwrap();
+    /// ```
+    pub fn env(mut self, key: impl Into<OsString>, value: impl Into<OsString>) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Sets several environment variables for PowerShell, like calling
+    /// [`env`](Self::env) for each of them.
+    pub fn envs<I, K, V>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<OsString>,
+        V: Into<OsString>,
+    {
+        self.env.extend(
+            vars.into_iter()
+                .map(|(key, value)| (key.into(), value.into())),
+        );
+        self
+    }
+
+    /// Starts PowerShell in `dir` instead of the working directory of this
+    /// process, so relative paths in scripts resolve against it. A relative
+    /// `dir` is itself relative to the working directory of this process.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new()
+    ///     .current_dir("/srv/app")
+    ///     .build();
+    /// ps.run("Get-Content ./config/settings.json").unwrap();
+    /// ```
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// Sets how the script is handed over to PowerShell. Defaults to
+    /// [`ExecutionMode::Stdin`].
+    pub fn execution_mode(mut self, mode: ExecutionMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// If set to `true` the script stops at the first command that fails
+    /// instead of running the rest of it against whatever state the failure
+    /// left behind. The line of the failed command is available through
+    /// `Output::failed_line`.
+    ///
+    /// This only applies to scripts run with `ExecutionMode::Stdin` or
+    /// `ExecutionMode::Encoded`, where PowerShell carries on after errors,
+    /// and can't be combined with the options capturing the return value,
+    /// host output, streams, environment, location or errors, which run the
+    /// script as a single script block. Only top level commands are checked;
+    /// a failure inside a loop or function is seen when the statement
+    /// calling it is done.
+    pub fn abort_on_error(mut self, flag: bool) -> Self {
+        self.abort_on_error = flag;
+        self
+    }
+
+    /// Sets the size in bytes of the chunks the script is written to
+    /// PowerShell's `stdin` in. Defaults to 64 KiB. Scripts larger than this
+    /// are written from a background thread so PowerShell can't block on a
+    /// full output pipe while it's still being fed the script.
+    ///
+    /// Can't be used with `ExecutionMode::Encoded`, which doesn't write the
+    /// script to `stdin`.
+    pub fn stdin_buffer_size(mut self, bytes: usize) -> Self {
+        self.stdin_buffer_size = bytes;
+        self
+    }
+
+    /// If set to `false` scripts run with `ExecutionMode::Stdin` are written
+    /// to `stdin` as they are instead of being split into lines first, which
+    /// saves an allocation per line for large generated scripts. Defaults to
+    /// `true`, which normalizes `\r\n` line endings to `\n`.
+    ///
+    /// Can't be set to `false` with `ExecutionMode::Encoded`, which doesn't
+    /// write the script to `stdin`.
+    pub fn split_lines(mut self, flag: bool) -> Self {
+        self.split_lines = flag;
+        self
+    }
+
+    /// Sets the encoding the program is written to PowerShell's `stdin` in.
+    /// Defaults to [`StdinEncoding::Utf8`].
+    ///
+    /// Can't be used with `ExecutionMode::Encoded`, which doesn't write the
+    /// script to `stdin`.
+    pub fn stdin_encoding(mut self, encoding: StdinEncoding) -> Self {
+        self.stdin_encoding = encoding;
+        self
+    }
+
+    /// Sets the console code page of PowerShell before the script runs,
+    /// like `chcp` does. Native tools called by the script write their
+    /// output in this code page, and PowerShell decodes it with the same
+    /// one. `$OutputEncoding`, which is used for input piped to native
+    /// tools, is set to match.
+    ///
+    /// The crate decodes PowerShell's output as UTF-8, so `65001` is the
+    /// code page which keeps text outside of ASCII intact. Legacy tools
+    /// which can only write in the ANSI or OEM code page show up garbled
+    /// either way, unless the script converts their output itself.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().console_codepage(65001).build();
+    /// let output = ps.run("ipconfig /all").unwrap();
+    /// ```
+    pub fn console_codepage(mut self, codepage: u32) -> Self {
+        self.console_codepage = Some(codepage);
+        self
+    }
+
+    /// Runs the script with the culture named `culture`, like `"en-US"`,
+    /// instead of the one of the machine, so numbers and dates are formatted
+    /// the same everywhere and the output can be parsed. An empty name
+    /// selects the invariant culture.
+    ///
+    /// This sets the culture and UI culture of the thread running the script
+    /// and the default for threads it starts. An unknown name fails the
+    /// script.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().culture("en-US").build();
+    /// let output = ps.run("1234.5").unwrap();
+    /// assert_eq!(output.stdout().unwrap().trim(), "1234.5");
+    /// ```
+    pub fn culture(mut self, culture: impl Into<String>) -> Self {
+        self.culture = Some(culture.into());
+        self
+    }
+
+    /// Captures the script's return value separately from the rest of its
+    /// output. The last object the script writes to the pipeline (which is
+    /// what `return $value` does) is serialized as JSON and made available
+    /// through `Output::return_value`, while anything written before it is
+    /// passed through to `stdout` as usual.
+    ///
+    /// This runs the script as a script block, the same way as
+    /// `ExecutionMode::CallOperator` does.
+    pub fn capture_return_value(mut self, flag: bool) -> Self {
+        self.capture_return = flag;
+        self
+    }
+
+    /// Captures output written with `Write-Host` (the information stream)
+    /// separately instead of mixing it into `stdout`. The captured messages
+    /// are available through `Output::host_output`.
+    ///
+    /// This runs the script as a script block, the same way as
+    /// `ExecutionMode::CallOperator` does.
+    pub fn capture_host_output(mut self, flag: bool) -> Self {
+        self.capture_host = flag;
+        self
+    }
+
+    /// Captures the verbose, warning, debug and information streams
+    /// separately instead of mixing them into `stdout` and `stderr`. The
+    /// messages are available through `Output::verbose`, `Output::warnings`,
+    /// `Output::debug` and `Output::information`.
+    ///
+    /// Verbose and debug messages are only written if the script sets
+    /// `$VerbosePreference` or `$DebugPreference`, or passes `-Verbose` or
+    /// `-Debug`. With `capture_host_output` set as well, messages written
+    /// with `Write-Host` go to `Output::host_output` instead.
+    ///
+    /// This runs the script as a script block, the same way as
+    /// `ExecutionMode::CallOperator` does.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().capture_streams(true).build();
+    /// let output = ps.run("Write-Warning 'disk almost full'; Get-PSDrive C").unwrap();
+    /// for warning in output.warnings() {
+    ///     eprintln!("warning: {}", warning);
+    /// }
+    /// println!("{}", output);
+    /// ```
+    pub fn capture_streams(mut self, flag: bool) -> Self {
+        self.capture_streams = flag;
+        self
+    }
+
+    /// Records the environment variables the script adds, changes or
+    /// removes, available through `Output::env_delta`.
+    ///
+    /// This runs the script as a script block, the same way as
+    /// `ExecutionMode::CallOperator` does.
+    pub fn capture_env_delta(mut self, flag: bool) -> Self {
+        self.capture_env = flag;
+        self
+    }
+
+    /// Records the script's working directory when it finishes, available
+    /// through `Output::final_location`.
+    ///
+    /// This runs the script as a script block, the same way as
+    /// `ExecutionMode::CallOperator` does.
+    pub fn capture_final_location(mut self, flag: bool) -> Self {
+        self.capture_location = flag;
+        self
+    }
+
+    /// Records the errors the script runs into as structured records,
+    /// available through `Output::errors`, each tagged with where it came
+    /// from: `Write-Error`, a `throw` statement, a native program and so on.
+    ///
+    /// This runs the script as a script block, the same way as
+    /// `ExecutionMode::CallOperator` does.
+    pub fn capture_errors(mut self, flag: bool) -> Self {
+        self.capture_errors = flag;
+        self
+    }
+
+    /// Defines `Write-PsChange <action> <target> [<details>]` for the script
+    /// to report the changes it makes with, available through
+    /// `Output::changes`. A script which reports no changes left the system
+    /// as it was, which is what makes a configuration script idempotent.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().capture_changes(true).build();
+    /// let script = r#"
+    /// if (-not (Test-Path C:\app)) {
+    ///     New-Item -ItemType Directory C:\app | Out-Null
+    ///     Write-PsChange Created C:\app
+    /// }
+    /// if ((Get-Service W3SVC).Status -ne 'Running') {
+    ///     Start-Service W3SVC
+    ///     Write-PsChange Started W3SVC
+    /// }
+    /// "#;
+    /// let output = ps.run(script).unwrap();
+    /// for change in output.changes() {
+    ///     println!("changed: {}", change);
+    /// }
+    /// ```
+    pub fn capture_changes(mut self, flag: bool) -> Self {
+        self.capture_changes = flag;
+        self
+    }
+
+    /// Checks that `module` is installed before the script runs, like
+    /// `#Requires -Modules` does. If any of the required modules is missing
+    /// the script doesn't run and fails with `PsError::MissingModules`
+    /// listing all of them, instead of failing halfway through with a
+    /// command which isn't found.
+    ///
+    /// Runs reporting events exit with code 1 and write the missing modules
+    /// to `stderr` instead.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::{ModuleSpec, PsError, PsScriptBuilder};
+    ///
+    /// let ps = PsScriptBuilder::new()
+    ///     .requires(ModuleSpec::new("Az.Accounts").min_version("2.10"))
+    ///     .requires(ModuleSpec::new("Az.Storage"))
+    ///     .build();
+    /// match ps.run("Get-AzStorageAccount") {
+    ///     Err(PsError::MissingModules(modules)) => {
+    ///         for module in modules {
+    ///             eprintln!("please install {}", module);
+    ///         }
+    ///     }
+    ///     result => println!("{:?}", result),
+    /// }
+    /// ```
+    pub fn requires(mut self, module: ModuleSpec) -> Self {
+        self.required_modules.push(module);
+        self
+    }
+
+    /// Registers a library of PowerShell functions which is defined before
+    /// every script runs, so an application can offer its script authors
+    /// helpers like logging functions without them having to define or
+    /// import them. A [`PsSession`] defines its libraries once, when it
+    /// starts.
+    ///
+    /// Libraries are defined in the order they're registered. Registering a
+    /// library under a `name` which is already taken replaces it.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let logging = r#"
+    /// function Write-AppLog {
+    ///     param([string] $Message)
+    ///     [Console]::Error.WriteLine("[app] $Message")
+    /// }
+    /// "#;
+    /// let ps = PsScriptBuilder::new().library("logging", logging).build();
+    /// ps.run("Write-AppLog 'starting'; Get-Date").unwrap();
+    /// ```
+    pub fn library(mut self, name: impl Into<String>, source: impl Into<String>) -> Self {
+        let (name, source) = (name.into(), source.into());
+        match self.libraries.iter_mut().find(|(n, _)| *n == name) {
+            Some(library) => library.1 = source,
+            None => self.libraries.push((name, source)),
+        }
+        self
+    }
+
+    /// Previews what the script would do without doing it, by setting
+    /// `$WhatIfPreference`. Cmdlets which support `-WhatIf` report the
+    /// changes they would make instead of making them. Functions called
+    /// with `invoke_function` get `-WhatIf` passed explicitly if they
+    /// support it, since preference variables don't reach into modules.
+    ///
+    /// Commands which don't support `-WhatIf`, including native programs,
+    /// still run as usual.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let preview = PsScriptBuilder::new().what_if(true).build();
+    /// // "What if: Performing the operation "Remove Directory" on target ..."
+    /// let output = preview.run("Remove-Item -Recurse ./build").unwrap();
+    /// println!("{}", output);
+    /// ```
+    pub fn what_if(mut self, flag: bool) -> Self {
+        self.what_if = flag;
+        self
+    }
+
+    /// Sets when cmdlets ask for confirmation, see [`ConfirmPolicy`]. With
+    /// `ConfirmPolicy::None`, functions called with `invoke_function` get
+    /// `-Confirm:$false` passed explicitly if they support it.
+    pub fn confirm(mut self, policy: ConfirmPolicy) -> Self {
+        self.confirm = policy;
+        self
+    }
+
+    /// Creates a new temporary directory for every run, which the script
+    /// finds in `$env:PS_WORKSPACE`, for intermediate files that shouldn't
+    /// outlive the run or be seen by other runs. The directory and its
+    /// contents are removed once the run is over, retrying for a moment if
+    /// files are still locked.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().temp_workspace(true).build();
+    /// let script = r#"
+    /// $archive = Join-Path $env:PS_WORKSPACE 'logs.zip'
+    /// Compress-Archive -Path ./logs -DestinationPath $archive
+    /// (Get-Item $archive).Length
+    /// "#;
+    /// let output = ps.run(script).unwrap();
+    /// ```
+    pub fn temp_workspace(mut self, flag: bool) -> Self {
+        self.temp_workspace = flag;
+        self
+    }
+
+    /// If set to `true` scripts started with `spawn` can tell that
+    /// [`PsChild::cancel`](crate::PsChild::cancel) was called by checking
+    /// `Test-HostCancelled`, so they can stop cleanly at a safe point
+    /// instead of being killed in the middle of something.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use std::time::Duration;
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().cancellation_sentinel(true).build();
+    /// let mut child = ps
+    ///     .spawn("foreach ($file in Get-ChildItem ./inbox) { if (Test-HostCancelled) { break }; ./process.ps1 $file }")
+    ///     .unwrap();
+    /// // The user clicked cancel
+    /// let stopped = child.cancel(Duration::from_secs(10)).unwrap();
+    /// ```
+    pub fn cancellation_sentinel(mut self, flag: bool) -> Self {
+        self.cancellation_sentinel = flag;
+        self
+    }
+
+    /// Adds a location to look for the PowerShell executable in if it isn't
+    /// found on `PATH`. Locations are tried in the order they're added and
+    /// before the default install locations.
+    pub fn probe_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.probe_paths.push(path.into());
+        self
+    }
+
+    /// Runs PowerShell from `path` instead of looking for it, like a
+    /// portable `pwsh` bundled with the application or a particular version
+    /// among several installed ones. `probe_path` and `PATH` aren't searched
+    /// then, and runs fail with `PsError::Io` if there's no executable at
+    /// `path`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new()
+    ///     .executable(r"C:\Program Files\PowerShell\7\pwsh.exe")
+    ///     .build();
+    /// ps.run("$PSVersionTable.PSVersion").unwrap();
+    /// ```
+    pub fn executable(mut self, path: impl Into<PathBuf>) -> Self {
+        self.executable = Some(path.into());
+        self
+    }
+
+    /// Explains what the exit code `code` means, like `3010` meaning a
+    /// reboot is required for an installer. The meaning is shown along with
+    /// the output when a script exits with the code, including in the
+    /// message of the `PsError`, and is available through
+    /// `Output::exit_meaning`. Registering a code again replaces its meaning.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new()
+    ///     .exit_code_meaning(3010, "reboot required")
+    ///     .exit_code_meaning(1618, "another installation is in progress")
+    ///     .build();
+    /// if let Err(e) = ps.run("Start-Process msiexec '/i app.msi /qn' -Wait -PassThru | % { exit $_.ExitCode }") {
+    ///     // "... Exit code 3010: reboot required"
+    ///     eprintln!("{}", e);
+    /// }
+    /// ```
+    pub fn exit_code_meaning(mut self, code: i32, meaning: impl Into<String>) -> Self {
+        self.exit_codes.retain(|(c, _)| *c != code);
+        self.exit_codes.push((code, meaning.into()));
+        self
+    }
+
+    /// Registers a cleanup script which runs in a new PowerShell process when
+    /// a script run with `run`, `run_with_args` or `invoke_function` fails,
+    /// for example to undo partially applied changes. The cleanup script's
+    /// own result is ignored, the original error is what's returned.
+    ///
+    /// Hooks run in the order they're registered.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new()
+    ///     .on_failure_run("Remove-SmbShare -Name Staging -Force -ErrorAction SilentlyContinue")
+    ///     .build();
+    /// let result = ps.run("New-SmbShare -Name Staging -Path C:\\staging; ./deploy.ps1");
+    /// ```
+    pub fn on_failure_run(mut self, cleanup: impl Into<String>) -> Self {
+        self.failure_hooks.push(FailureHook::Script(cleanup.into()));
+        self
+    }
+
+    /// Registers a closure which is called with the error when a script run
+    /// with `run`, `run_with_args` or `invoke_function` fails.
+    pub fn on_failure<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&PsError) + Send + Sync + 'static,
+    {
+        self.failure_hooks
+            .push(FailureHook::Callback(Arc::new(callback)));
+        self
+    }
+
+    /// Adds a [`Middleware`] which runs around every script. Middleware run
+    /// in the order they're added.
+    pub fn middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Reports the duration and outcome of every run to `metrics`, and how
+    /// long scripts waited for a worker of a [`PsPool`]. Pass an `Arc` if
+    /// you need to read the numbers back, like with
+    /// [`InMemoryMetrics`](crate::InMemoryMetrics).
+    pub fn metrics(mut self, metrics: impl Metrics + 'static) -> Self {
+        let metrics: Arc<dyn Metrics> = Arc::new(metrics);
+        self.metrics.push(metrics.clone());
+        self.middleware(MetricsMiddleware(metrics))
+    }
+
+    /// Records the PowerShell processes started by the `PsScript` in
+    /// `tracker`, so they can be stopped with
+    /// [`ChildTracker::shutdown_all`] when the application exits.
+    pub fn child_tracker(mut self, tracker: ChildTracker) -> Self {
+        self.tracker = Some(tracker);
+        self
+    }
+
+    /// Counts the PowerShell processes started by the `PsScript`, and by the
+    /// sessions built from it, against `quota`. Runs which would exceed it
+    /// wait for room or fail with `PsError::QuotaExceeded`, see
+    /// [`ExecutionQuota`].
+    pub fn execution_quota(mut self, quota: ExecutionQuota) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    /// Sets when the sessions built from this configuration replace their
+    /// PowerShell process with a fresh one, see [`RecyclePolicy`]. Doesn't
+    /// apply to the `PsScript`, which starts a new process for every run.
+    pub fn recycle(mut self, policy: RecyclePolicy) -> Self {
+        self.recycle = policy;
+        self
+    }
+
+    /// Runs `script` in every PowerShell process a session or pool starts,
+    /// before it runs any other script, to import modules, define functions
+    /// or open connections the scripts rely on. Since it runs again when a
+    /// process is recycled, the state it sets up survives recycling.
+    ///
+    /// Starting the session or pool fails with the error of the script if
+    /// it fails. Doesn't apply to the `PsScript`, use
+    /// [`library`](Self::library) to define functions for its runs.
+    pub fn warm_up(mut self, script: &str) -> Self {
+        self.warm_up = Some(script.into());
+        self
+    }
+
+    /// Runs `script` in the PowerShell process of a session or pool worker
+    /// before a script is run on it after an earlier one. If the check fails
+    /// the process is recycled, so one left broken by a script doesn't fail
+    /// the next. Doesn't apply to the `PsScript`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let pool = PsScriptBuilder::new()
+    ///     .warm_up("Import-Module SqlServer; $db = Connect-Db")
+    ///     .health_check("if ($db.State -ne 'Open') { throw 'disconnected' }")
+    ///     .build_pool(4)
+    ///     .unwrap();
+    /// ```
+    pub fn health_check(mut self, script: &str) -> Self {
+        self.health_check = Some(script.into());
+        self
+    }
+
+    /// Builds the `PsScript`.
+    ///
+    /// ## Panics
+    /// If the configuration is invalid. Use `try_build` to handle this as an
+    /// error instead.
     pub fn build(self) -> PsScript {
+        match self.try_build() {
+            Ok(ps) => ps,
+            Err(e) => panic!("invalid PsScriptBuilder configuration: {}", e),
+        }
+    }
+
+    /// Builds the configuration and starts a [`PsSession`] with it, a
+    /// PowerShell process which runs one script after another. Fails with
+    /// `PsError::InvalidConfig` if the configuration is invalid, see
+    /// `try_build`, or sets options a session can't honour, see
+    /// [`PsSession`].
+    pub fn build_session(self) -> Result<PsSession, PsError> {
+        self.validate_session("build_session")?;
+        PsSession::start(self.try_build()?)
+    }
+
+    /// Builds the configuration and starts a [`PsPool`] with `workers`
+    /// sessions, at least one, which run scripts from any number of threads.
+    /// Fails with `PsError::InvalidConfig` if the configuration is invalid,
+    /// see `try_build`, or sets options a session can't honour, see
+    /// [`PsPool`].
+    pub fn build_pool(self, workers: usize) -> Result<PsPool, PsError> {
+        self.validate_session("build_pool")?;
+        PsPool::start(self.try_build()?, workers)
+    }
+
+    /// Builds the `PsScript`, returning an error if the options contradict
+    /// each other instead of leaving it to PowerShell to fail in some less
+    /// obvious way at runtime.
+    pub fn try_build(mut self) -> Result<PsScript, BuildError> {
+        self.validate()?;
+
+        if self.deterministic_output {
+            self.strip_ansi = true;
+            self.culture.get_or_insert_with(String::new);
+            if let Progress::Passthrough = self.progress {
+                self.progress = Progress::Suppress;
+            }
+        }
+
         let mut args = self.args;
+        if self.deterministic_output {
+            args.push_front("-NoLogo");
+        }
+        if let Some(policy) = self.execution_policy {
+            args.push_front(policy.name());
+            args.push_front("-ExecutionPolicy");
+        }
         if self.non_interactive {
             args.push_front("-NonInteractive");
         }
@@ -59,29 +1182,318 @@ impl PsScriptBuilder {
             args.push_front("-NoProfile");
         }
 
-        PsScript {
-            args: args.make_contiguous().to_vec(),
+        Ok(PsScript {
+            args: args.make_contiguous().into(),
+            raw_args: self.raw_args.into(),
+            env: self.env.into(),
+            current_dir: self.current_dir,
             hidden: self.hidden,
             print_commands: self.print_commands,
+            mode: self.mode,
+            capture_return: self.capture_return,
+            capture_host: self.capture_host,
+            capture_streams: self.capture_streams,
+            capture_env: self.capture_env,
+            capture_location: self.capture_location,
+            capture_errors: self.capture_errors,
+            capture_changes: self.capture_changes,
+            required_modules: self.required_modules.into(),
+            libraries: self
+                .libraries
+                .into_iter()
+                .map(|(_, source)| source)
+                .collect(),
+            what_if: self.what_if,
+            confirm: self.confirm,
+            temp_workspace: self.temp_workspace,
+            cancellation_sentinel: self.cancellation_sentinel,
+            abort_on_error: self.abort_on_error,
+            stdin_buffer_size: self.stdin_buffer_size,
+            split_lines: self.split_lines,
+            stdin_encoding: self.stdin_encoding,
+            console_codepage: self.console_codepage,
+            culture: self.culture,
+            record_timeline: self.record_timeline,
+            strip_ansi: self.strip_ansi,
+            normalize_newlines: self.deterministic_output,
+            progress: self.progress,
+            credential_provider: self.credential_provider,
+            callbacks: self.callbacks.into(),
+            message_handler: self.message_handler,
+            probe_paths: self.probe_paths.into(),
+            exit_codes: self.exit_codes.into(),
+            failure_hooks: self.failure_hooks.into(),
+            middleware: self.middleware.into(),
+            metrics: self.metrics.into(),
+            tracker: self.tracker,
+            quota: self.quota,
+            recycle: self.recycle,
+            warm_up: self.warm_up,
+            health_check: self.health_check,
+            input_timeout: self.input_timeout,
+            stall_timeout: self.stall_timeout,
+            on_stall: self.on_stall,
+            executable: Arc::new(
+                self.executable
+                    .map(|path| {
+                        OnceLock::from(target::long_path(&path).into_owned().into_os_string())
+                    })
+                    .unwrap_or_default(),
+            ),
+        })
+    }
+
+    fn validate(&self) -> Result<(), BuildError> {
+        if self.probe_paths.iter().any(|p| p.as_os_str().is_empty()) {
+            return Err(BuildError::EmptyProbePath);
+        }
+        if self
+            .executable
+            .as_ref()
+            .is_some_and(|p| p.as_os_str().is_empty())
+        {
+            return Err(BuildError::EmptyExecutable);
+        }
+
+        // Options which rewrite the script would otherwise be silently
+        // ignored where the script isn't run command by command.
+        if self.abort_on_error {
+            if let ExecutionMode::CallOperator | ExecutionMode::Raw = self.mode {
+                return Err(BuildError::UnsupportedMode("abort_on_error", self.mode));
+            }
+            let wrapping = [
+                ("capture_return_value", self.capture_return),
+                ("capture_host_output", self.capture_host),
+                ("capture_streams", self.capture_streams),
+                ("capture_env_delta", self.capture_env),
+                ("capture_final_location", self.capture_location),
+                ("capture_errors", self.capture_errors),
+            ];
+            if let Some((option, _)) = wrapping.iter().find(|(_, set)| *set) {
+                return Err(BuildError::ConflictingOptions("abort_on_error", option));
+            }
+        }
+        if self.mode == ExecutionMode::Encoded {
+            let stdin_options = [
+                (
+                    "stdin_buffer_size",
+                    self.stdin_buffer_size != DEFAULT_STDIN_BUFFER_SIZE,
+                ),
+                ("split_lines", !self.split_lines),
+                ("stdin_encoding", self.stdin_encoding != StdinEncoding::Utf8),
+            ];
+            if let Some((option, _)) = stdin_options.iter().find(|(_, set)| *set) {
+                return Err(BuildError::UnsupportedMode(option, self.mode));
+            }
+        }
+        if self.record_timeline && self.mode == ExecutionMode::Raw {
+            return Err(BuildError::UnsupportedMode("record_timeline", self.mode));
+        }
+        if self.on_stall.is_some() && self.stall_timeout.is_none() {
+            return Err(BuildError::MissingOption("on_stall", "stall_timeout"));
+        }
+        // Prompts fail right away in non-interactive mode, so they'd never
+        // time out
+        if self.input_timeout.is_some() && self.non_interactive {
+            return Err(BuildError::ConflictingOptions(
+                "input_timeout",
+                "non_interactive",
+            ));
+        }
+        // A timeline is recorded without watching for prompts or stalls
+        if self.record_timeline {
+            let watching = [
+                ("input_timeout", self.input_timeout.is_some()),
+                ("stall_timeout", self.stall_timeout.is_some()),
+            ];
+            if let Some((option, _)) = watching.iter().find(|(_, set)| *set) {
+                return Err(BuildError::ConflictingOptions(option, "record_timeline"));
+            }
+        }
+
+        // The script is always passed with `-Command`, so anything else
+        // telling PowerShell what to run conflicts with it.
+        let mut managed = vec!["-Command", "-EncodedCommand", "-File"];
+        if self.no_profile {
+            managed.push("-NoProfile");
+        }
+        if self.non_interactive {
+            managed.push("-NonInteractive");
+        }
+        if self.deterministic_output {
+            managed.push("-NoLogo");
+        }
+        if self.execution_policy.is_some() {
+            managed.push("-ExecutionPolicy");
+        }
+
+        let mut seen = Vec::new();
+        for arg in &self.raw_args {
+            for name in parameters(&arg.to_string_lossy()) {
+                if managed.contains(&name) {
+                    return Err(BuildError::ConflictingArg(name.to_string()));
+                }
+                if seen.contains(&name) {
+                    return Err(BuildError::DuplicateArg(name.to_string()));
+                }
+                seen.push(name);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Rejects the options a session started by `method` would ignore, since
+    /// its scripts run as script blocks in a running process rather than the
+    /// way `PsScript::run` runs them.
+    fn validate_session(&self, method: &'static str) -> Result<(), BuildError> {
+        if self.mode != ExecutionMode::Stdin {
+            return Err(BuildError::UnsupportedMode(method, self.mode));
+        }
+        let ignored = [
+            ("capture_return_value", self.capture_return),
+            ("capture_host_output", self.capture_host),
+            ("capture_streams", self.capture_streams),
+            ("capture_env_delta", self.capture_env),
+            ("capture_final_location", self.capture_location),
+            ("capture_errors", self.capture_errors),
+            ("capture_changes", self.capture_changes),
+            ("requires", !self.required_modules.is_empty()),
+            ("what_if", self.what_if),
+            ("confirm", self.confirm != ConfirmPolicy::Default),
+            ("temp_workspace", self.temp_workspace),
+            ("cancellation_sentinel", self.cancellation_sentinel),
+            ("abort_on_error", self.abort_on_error),
+            (
+                "stdin_buffer_size",
+                self.stdin_buffer_size != DEFAULT_STDIN_BUFFER_SIZE,
+            ),
+            ("split_lines", !self.split_lines),
+            ("console_codepage", self.console_codepage.is_some()),
+            ("culture", self.culture.is_some()),
+            ("record_timeline", self.record_timeline),
+            ("deterministic_output", self.deterministic_output),
+            ("progress", !matches!(self.progress, Progress::Passthrough)),
+            ("credential_provider", self.credential_provider.is_some()),
+            ("host_callback", !self.callbacks.is_empty()),
+            ("on_message", self.message_handler.is_some()),
+            ("exit_code_meaning", !self.exit_codes.is_empty()),
+            ("on_failure", !self.failure_hooks.is_empty()),
+            // Each of the `metrics` adds a middleware of its own
+            ("middleware", self.middleware.len() > self.metrics.len()),
+            ("input_timeout", self.input_timeout.is_some()),
+            ("stall_timeout", self.stall_timeout.is_some()),
+            // A pool records how long scripts wait for a worker
+            (
+                "metrics",
+                method == "build_session" && !self.metrics.is_empty(),
+            ),
+        ];
+        match ignored.iter().find(|(_, set)| *set) {
+            Some((option, _)) => Err(BuildError::ConflictingOptions(option, method)),
+            None => Ok(()),
         }
     }
 }
 
-impl Default for PsScriptBuilder {
+/// Returns the full names of the PowerShell parameters in a raw argument,
+/// skipping quoted values and parameters it doesn't recognize.
+fn parameters(arg: &str) -> Vec<&'static str> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut quote = None;
+    for c in arg.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c.is_whitespace() => tokens.push(std::mem::take(&mut token)),
+            None => token.push(c),
+        }
+    }
+    tokens.push(token);
+
+    tokens
+        .iter()
+        .filter(|token| token.starts_with('-') && token.len() > 1)
+        .filter_map(|token| parameter(token.split(':').next().unwrap_or_default()))
+        .collect()
+}
 
+/// Resolves `-name` to the full name of the PowerShell parameter it refers
+/// to, if it's an alias or an unambiguous prefix of one.
+fn parameter(name: &str) -> Option<&'static str> {
+    let name = name.to_ascii_lowercase();
+    let exact = POWERSHELL_PARAMETERS.iter().find(|(full, aliases)| {
+        full.eq_ignore_ascii_case(&name) || aliases.contains(&name.as_str())
+    });
+    if let Some((full, _)) = exact {
+        return Some(full);
+    }
+    let mut prefixed = POWERSHELL_PARAMETERS
+        .iter()
+        .filter(|(full, _)| full.to_ascii_lowercase().starts_with(&name));
+    match (prefixed.next(), prefixed.next()) {
+        (Some((full, _)), None) => Some(full),
+        _ => None,
+    }
+}
+
+impl Default for PsScriptBuilder {
     /// Creates a default builder with `no_profile`, `non_interactive` and `hidden`
     /// options set to `true` and `print_commands` set to `false`.
     fn default() -> Self {
-        let mut args = VecDeque::new();
-        args.push_back("-Command");
-        args.push_back("-");
-
         Self {
-            args,
+            args: VecDeque::new(),
+            raw_args: Vec::new(),
+            env: Vec::new(),
+            current_dir: None,
             no_profile: true,
             non_interactive: true,
+            execution_policy: None,
             hidden: true,
             print_commands: false,
+            mode: ExecutionMode::Stdin,
+            capture_return: false,
+            capture_host: false,
+            capture_streams: false,
+            capture_env: false,
+            capture_location: false,
+            capture_errors: false,
+            capture_changes: false,
+            required_modules: Vec::new(),
+            libraries: Vec::new(),
+            what_if: false,
+            confirm: ConfirmPolicy::Default,
+            temp_workspace: false,
+            cancellation_sentinel: false,
+            abort_on_error: false,
+            stdin_buffer_size: DEFAULT_STDIN_BUFFER_SIZE,
+            split_lines: true,
+            stdin_encoding: StdinEncoding::Utf8,
+            console_codepage: None,
+            culture: None,
+            record_timeline: false,
+            strip_ansi: false,
+            deterministic_output: false,
+            progress: Progress::Passthrough,
+            credential_provider: None,
+            callbacks: Vec::new(),
+            message_handler: None,
+            probe_paths: Vec::new(),
+            executable: None,
+            exit_codes: Vec::new(),
+            failure_hooks: Vec::new(),
+            middleware: Vec::new(),
+            metrics: Vec::new(),
+            tracker: None,
+            quota: None,
+            recycle: RecyclePolicy::default(),
+            warm_up: None,
+            health_check: None,
+            input_timeout: None,
+            stall_timeout: None,
+            on_stall: None,
         }
     }
 }
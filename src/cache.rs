@@ -0,0 +1,172 @@
+//! Skipping scripts which already ran successfully, for configuration
+//! scripts which are run over and over until the system converges.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::{context, output::Output, PsScript, Result};
+
+/// Records successful runs and skips running the same script again, with
+/// the same arguments against the same target, for `ttl` after it
+/// succeeded. Failed runs aren't recorded, so they're retried the next time.
+///
+/// The target is whatever the script acts on which isn't part of its
+/// arguments, like the name of the machine it configures. Clones share the
+/// same records.
+///
+/// ## Example
+///
+/// ```rust, no_run
+/// use std::time::Duration;
+/// use powershell_script::{CacheOutcome, PsScriptBuilder, RunCache};
+///
+/// let ps = PsScriptBuilder::new().build();
+/// let cache = RunCache::new(Duration::from_secs(600));
+/// let script = "param($Feature) Enable-WindowsOptionalFeature -Online -FeatureName $Feature -NoRestart";
+/// for _ in 0..3 {
+///     match cache.run(&ps, script, ["IIS-WebServer"], "localhost").unwrap() {
+///         CacheOutcome::Ran(output) => println!("{}", output),
+///         CacheOutcome::Skipped { age } => println!("already done {:?} ago", age),
+///     }
+/// }
+/// ```
+#[derive(Clone)]
+pub struct RunCache {
+    ttl: Duration,
+    runs: Arc<Mutex<HashMap<Key, Instant>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    script_hash: u64,
+    args: Vec<String>,
+    target: String,
+}
+
+impl Key {
+    fn new<I, S>(script: &str, args: I, target: &str) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Key {
+            script_hash: context::hash_script(script),
+            args: args
+                .into_iter()
+                .map(|arg| arg.as_ref().to_string())
+                .collect(),
+            target: target.to_string(),
+        }
+    }
+}
+
+/// Whether `RunCache::run` ran the script.
+#[derive(Debug)]
+pub enum CacheOutcome {
+    /// The script ran successfully.
+    Ran(Output),
+    /// The script had already run successfully `age` ago.
+    Skipped { age: Duration },
+}
+
+impl RunCache {
+    /// Creates an empty cache which remembers successful runs for `ttl`.
+    pub fn new(ttl: Duration) -> Self {
+        RunCache {
+            ttl,
+            runs: Arc::default(),
+        }
+    }
+
+    /// Runs `script` with `ps` like `PsScript::run_with_args`, unless it ran
+    /// successfully with the same `args` against `target` within the TTL.
+    pub fn run<I, S>(
+        &self,
+        ps: &PsScript,
+        script: &str,
+        args: I,
+        target: &str,
+    ) -> Result<CacheOutcome>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let key = Key::new(script, args, target);
+        if let Some(ran_at) = self.lock().get(&key) {
+            let age = ran_at.elapsed();
+            if age < self.ttl {
+                return Ok(CacheOutcome::Skipped { age });
+            }
+        }
+        Ok(CacheOutcome::Ran(self.execute(ps, script, key)?))
+    }
+
+    /// Runs `script` regardless of whether it ran recently, and records the
+    /// run if it succeeds.
+    pub fn force_run<I, S>(
+        &self,
+        ps: &PsScript,
+        script: &str,
+        args: I,
+        target: &str,
+    ) -> Result<Output>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.execute(ps, script, Key::new(script, args, target))
+    }
+
+    /// Whether the script ran successfully with `args` against `target`
+    /// within the TTL.
+    pub fn contains<I, S>(&self, script: &str, args: I, target: &str) -> bool
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.lock()
+            .get(&Key::new(script, args, target))
+            .is_some_and(|ran_at| ran_at.elapsed() < self.ttl)
+    }
+
+    /// Forgets the run of the script with `args` against `target`, so it
+    /// runs again the next time.
+    pub fn invalidate<I, S>(&self, script: &str, args: I, target: &str)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.lock().remove(&Key::new(script, args, target));
+    }
+
+    /// Forgets all runs.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn execute(&self, ps: &PsScript, script: &str, key: Key) -> Result<Output> {
+        let output = ps.run_with_args(script, &key.args)?;
+        let mut runs = self.lock();
+        let ttl = self.ttl;
+        runs.retain(|_, ran_at| ran_at.elapsed() < ttl);
+        runs.insert(key, Instant::now());
+        Ok(output)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<Key, Instant>> {
+        self.runs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for RunCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RunCache")
+            .field("ttl", &self.ttl)
+            .field("runs", &self.lock().len())
+            .finish()
+    }
+}
@@ -0,0 +1,132 @@
+//! Calling back into the host application from a script, see
+//! `PsScriptBuilder::host_callback`.
+//!
+//! Like the credential bridge, `Invoke-HostCallback` sends its calls over a
+//! TCP connection to a listener on the loopback interface which lives as
+//! long as the script runs, since `stdin` carries the script and `stdout`
+//! its output. Calls carry a random token so other processes on the machine
+//! can't make them.
+
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{base64, error::PsError, side_channel, value::PsValue};
+
+/// How often the listener checks whether the script has finished.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A function the script can call with `Invoke-HostCallback`. An `Err` is
+/// thrown in the script with its message.
+pub(crate) type HostCallback =
+    Arc<dyn Fn(&PsValue) -> std::result::Result<PsValue, String> + Send + Sync>;
+
+/// Answers the callback invocations of one run. The listener stops when
+/// this is dropped.
+pub(crate) struct CallbackBridge {
+    port: u16,
+    token: String,
+    stopped: Arc<AtomicBool>,
+}
+
+impl CallbackBridge {
+    pub(crate) fn start(callbacks: Arc<[(String, HostCallback)]>) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        listener.set_nonblocking(true)?;
+        let port = listener.local_addr()?.port();
+        let token = side_channel::token();
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let bridge = CallbackBridge {
+            port,
+            token: token.clone(),
+            stopped: stopped.clone(),
+        };
+        thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    // A misbehaving client only fails its own call
+                    Ok((stream, _)) => {
+                        let _ = answer(stream, &token, &callbacks);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                    Err(_) => return,
+                }
+            }
+        });
+        Ok(bridge)
+    }
+
+    /// Returns the definition of `Invoke-HostCallback`, which sends `-Data`
+    /// as JSON and returns what the callback returned, or throws what it
+    /// failed with.
+    pub(crate) fn function(&self) -> String {
+        format!(
+            "function Invoke-HostCallback {{ param([Parameter(Mandatory = $true, Position = 0)][string]$Name, [Parameter(Position = 1)]$Data) $__ps_client = New-Object System.Net.Sockets.TcpClient('127.0.0.1', {}); try {{ $__ps_stream = $__ps_client.GetStream(); $__ps_writer = New-Object System.IO.StreamWriter($__ps_stream); $__ps_writer.WriteLine((ConvertTo-Json -Compress -InputObject ([ordered]@{{ Token = '{}'; Name = $Name; Data = [string](ConvertTo-Json -Compress -Depth 100 -InputObject $Data) }}))); $__ps_writer.Flush(); $__ps_answer = (New-Object System.IO.StreamReader($__ps_stream)).ReadLine() }} finally {{ $__ps_client.Dispose() }}; if (-not $__ps_answer) {{ throw \"The host callback '$Name' didn't answer\" }}; $__ps_parts = $__ps_answer.Split(' '); $__ps_text = [Text.Encoding]::UTF8.GetString([Convert]::FromBase64String($__ps_parts[1])); if ($__ps_parts[0] -ne 'ok') {{ throw $__ps_text }}; $__ps_result = & ([scriptblock]::Create($__ps_text)); Write-Output -NoEnumerate $__ps_result }}",
+            self.port, self.token
+        )
+    }
+}
+
+impl Drop for CallbackBridge {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Debug for CallbackBridge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CallbackBridge")
+            .field("port", &self.port)
+            .finish()
+    }
+}
+
+/// Reads a call from `stream`, runs the callback and writes its result
+/// back, as `ok` and the result as a PowerShell expression, or `error` and
+/// a message.
+fn answer(stream: TcpStream, token: &str, callbacks: &[(String, HostCallback)]) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let invalid = |e: PsError| io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+    let call = PsValue::from_json(line.trim()).map_err(invalid)?;
+    if call.get("Token").and_then(PsValue::as_str) != Some(token) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "host callback without a valid token",
+        ));
+    }
+
+    let name = call
+        .get("Name")
+        .and_then(PsValue::as_str)
+        .unwrap_or_default();
+    let data = match call.get("Data").and_then(PsValue::as_str) {
+        Some(json) if !json.trim().is_empty() => PsValue::from_json(json).map_err(invalid)?,
+        _ => PsValue::Null,
+    };
+    let result = match callbacks.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+        Some((_, callback)) => callback(&data),
+        None => Err(format!("There's no host callback called '{}'", name)),
+    };
+    let reply = match result {
+        // The leading comma keeps arrays from being unrolled
+        Ok(value @ PsValue::Array(_)) => reply("ok", &format!(",{}", value.to_literal())),
+        Ok(value) => reply("ok", &value.to_literal()),
+        Err(message) => reply("error", &message),
+    };
+    (&stream).write_all(reply.as_bytes())
+}
+
+fn reply(status: &str, text: &str) -> String {
+    format!("{} {}\n", status, base64::encode(text.as_bytes()))
+}
@@ -0,0 +1,52 @@
+//! Telling a script it has been cancelled, so it can stop at a point of its
+//! choosing, see `PsScriptBuilder::cancellation_sentinel`.
+
+use std::{
+    env,
+    fs::{self, File},
+    io,
+    path::PathBuf,
+    process,
+};
+
+use crate::wrap;
+
+/// A file which doesn't exist until the run is cancelled. Scripts check for
+/// it with `Test-HostCancelled`. It's removed when this is dropped.
+#[derive(Debug)]
+pub(crate) struct CancelSentinel {
+    path: PathBuf,
+}
+
+impl CancelSentinel {
+    /// `id` identifies the run, so the names don't collide within the
+    /// process.
+    pub(crate) fn new(id: u64) -> CancelSentinel {
+        let name = format!("ps-cancel-{}-{}", process::id(), id);
+        CancelSentinel {
+            path: env::temp_dir().join(name),
+        }
+    }
+
+    /// Returns the definition of `Test-HostCancelled`. A file left over from
+    /// a crashed process with the same pid is removed first, so it can't
+    /// cancel the run.
+    pub(crate) fn function(&self) -> String {
+        let path = wrap::quote(&self.path.to_string_lossy());
+        format!(
+            "Remove-Item -LiteralPath {0} -Force -ErrorAction Ignore; function Test-HostCancelled {{ Test-Path -LiteralPath {0} }}",
+            path
+        )
+    }
+
+    /// Cancels the run.
+    pub(crate) fn set(&self) -> io::Result<()> {
+        File::create(&self.path).map(drop)
+    }
+}
+
+impl Drop for CancelSentinel {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,58 @@
+//! Scripts reporting the changes they make, see
+//! `PsScriptBuilder::capture_changes`.
+
+use std::fmt;
+
+use crate::{
+    value::{FromPsValue, PsValue},
+    wrap, Result,
+};
+
+/// The tag of the blocks changes are written in.
+pub(crate) const TAG: &str = "change";
+
+/// Returns the definition of the function scripts report changes with.
+pub(crate) fn function() -> String {
+    format!(
+        "function Write-PsChange {{ param([Parameter(Mandatory = $true, Position = 0)] [string] $Action, [Parameter(Mandatory = $true, Position = 1)] [string] $Target, [Parameter(Position = 2)] $Details) {} }}",
+        wrap::emit_block(
+            TAG,
+            "(ConvertTo-Json -Depth 10 -Compress -InputObject ([ordered]@{ Action = $Action; Target = $Target; Details = $Details }))"
+        )
+    )
+}
+
+/// A change a script made, reported with
+/// `Write-PsChange <action> <target> [<details>]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    /// What was done, like `Created` or `Started`.
+    pub action: String,
+    /// What it was done to, like a path or the name of a service.
+    pub target: String,
+    /// Anything else the script reported, `PsValue::Null` if nothing.
+    pub details: PsValue,
+}
+
+impl FromPsValue for Change {
+    fn from_ps_value(value: PsValue) -> Result<Self> {
+        let string = |key: &str| {
+            value
+                .get(key)
+                .and_then(PsValue::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+        Ok(Change {
+            action: string("Action"),
+            target: string("Target"),
+            details: value.get("Details").cloned().unwrap_or(PsValue::Null),
+        })
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.action, self.target)
+    }
+}
@@ -0,0 +1,424 @@
+use std::{
+    io::{self, Read},
+    process::{self, Child, ChildStdin, ExitStatus},
+    sync::{Arc, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    events::{self, Progress},
+    middleware::{Middleware, RunContext},
+    output::{Cleanup, Output},
+    script, target,
+    tracker::{self, Tracked},
+    usage::ResourceUsage,
+    Result,
+};
+
+/// How often we check if the child has exited while waiting for it to stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A handle to a script running in the background. Returned by
+/// `PsScript::spawn`.
+///
+/// The child's `stdout` and `stderr` are drained by background threads, so
+/// it's fine to only poll it with `try_wait` without risking the child
+/// blocking on a full pipe.
+pub struct PsChild {
+    child: Child,
+    tracked: Option<Tracked>,
+    started: Instant,
+    stdout: Option<JoinHandle<io::Result<Vec<u8>>>>,
+    stderr: Option<JoinHandle<io::Result<Vec<u8>>>>,
+    ctx: RunContext,
+    middleware: Arc<[Arc<dyn Middleware>]>,
+    cleanup: Cleanup,
+    /// Set once the child has exited and been reaped while polling it.
+    exited: Option<(ExitStatus, Option<ResourceUsage>)>,
+}
+
+impl PsChild {
+    pub(crate) fn new(
+        mut child: Child,
+        tracked: Option<Tracked>,
+        ctx: RunContext,
+        middleware: Arc<[Arc<dyn Middleware>]>,
+        cleanup: Cleanup,
+        progress: Progress,
+    ) -> Self {
+        let stdout = child.stdout.take().map(|pipe| match progress {
+            Progress::Capture(callback) => {
+                thread::spawn(move || events::filter_progress(pipe, &callback))
+            }
+            _ => thread::spawn(move || read_all(pipe)),
+        });
+        let stderr = child
+            .stderr
+            .take()
+            .map(|pipe| thread::spawn(move || read_all(pipe)));
+        PsChild {
+            child,
+            tracked,
+            started: Instant::now(),
+            stdout,
+            stderr,
+            ctx,
+            middleware,
+            cleanup,
+            exited: None,
+        }
+    }
+
+    /// Returns the OS-assigned process identifier of the PowerShell process.
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Returns a handle for writing to the script's `stdin` if it was started
+    /// with `PsScript::spawn_with_input` and `stdin` hasn't been closed yet.
+    pub fn stdin(&mut self) -> Option<&mut ChildStdin> {
+        self.child.stdin.as_mut()
+    }
+
+    /// Closes the script's `stdin`, signaling that there is no more input.
+    pub fn close_stdin(&mut self) {
+        drop(self.child.stdin.take());
+    }
+
+    /// Returns the exit status if the script has finished, without blocking.
+    /// Use `wait` to collect the output once it has.
+    pub fn try_wait(&mut self) -> Result<Option<ExitStatus>> {
+        Ok(self.poll()?)
+    }
+
+    /// Whether the script is still running. Errors while checking are treated
+    /// as the process not running.
+    pub fn is_running(&mut self) -> bool {
+        matches!(self.poll(), Ok(None))
+    }
+
+    /// Returns the time since the script was started.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Forcibly terminates the PowerShell process. No cleanup code in the
+    /// script gets to run. See `interrupt` for a graceful alternative.
+    pub fn kill(&mut self) -> Result<()> {
+        // Once the child is reaped its pid may be reused, so it must not be
+        // signaled anymore
+        if self.poll()?.is_some() {
+            return Ok(());
+        }
+        Ok(self.child.kill()?)
+    }
+
+    /// Asks the script to stop the same way pressing Ctrl+C would, giving
+    /// `finally` blocks and similar cleanup code a chance to run, and kills
+    /// it if it hasn't exited within `grace`. Returns `true` if the script
+    /// stopped on its own.
+    ///
+    /// On Unix this sends `SIGINT`. On Windows it sends `CTRL_BREAK_EVENT`,
+    /// which only reaches the script if it shares a console with the calling
+    /// process. Scripts started with `hidden(true)` don't, so they're killed
+    /// once the grace period is over.
+    pub fn interrupt(&mut self, grace: Duration) -> Result<bool> {
+        if self.send_interrupt()? {
+            return Ok(true);
+        }
+        self.stop_within(grace)
+    }
+
+    /// Sends the interrupt `interrupt` waits on. Returns `true` if the script
+    /// had already exited.
+    fn send_interrupt(&mut self) -> Result<bool> {
+        if self.poll()?.is_some() {
+            return Ok(true);
+        }
+
+        // If the signal can't be delivered we still want to honor the grace
+        // period before killing the process, so the error is ignored.
+        let _ = target::interrupt(&self.child);
+        Ok(false)
+    }
+
+    /// Asks the script to stop at a point of its choosing and kills it if it
+    /// hasn't exited within `grace`. Returns `true` if the script stopped on
+    /// its own.
+    ///
+    /// Scripts see the request when they call `Test-HostCancelled`, if they
+    /// were started by a `PsScript` built with `cancellation_sentinel` set.
+    /// Otherwise nothing tells them, so they're killed once the grace period
+    /// is over unless they finish on their own.
+    pub fn cancel(&mut self, grace: Duration) -> Result<bool> {
+        if self.request_cancel()? {
+            return Ok(true);
+        }
+        self.stop_within(grace)
+    }
+
+    /// Sets the sentinel `cancel` waits on. Returns `true` if the script had
+    /// already exited.
+    fn request_cancel(&mut self) -> Result<bool> {
+        if self.poll()?.is_some() {
+            return Ok(true);
+        }
+        if let Some(sentinel) = &self.ctx.cancel {
+            sentinel.set()?;
+        }
+        Ok(false)
+    }
+
+    /// Waits for the script to exit for up to `grace` and kills it if it
+    /// hasn't.
+    fn stop_within(&mut self, grace: Duration) -> Result<bool> {
+        let deadline = Instant::now() + grace;
+        while Instant::now() < deadline {
+            if self.poll()?.is_some() {
+                return Ok(true);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        if self.poll()?.is_some() {
+            return Ok(true);
+        }
+        self.kill()?;
+        Ok(false)
+    }
+
+    /// Returns the exit status if the child has exited. It's reaped with
+    /// `tracker::try_wait` rather than `Child::try_wait`, so the resources it
+    /// used are kept for `wait`.
+    fn poll(&mut self) -> io::Result<Option<ExitStatus>> {
+        if self.exited.is_none() {
+            self.exited = tracker::try_wait(&mut self.child, self.tracked.as_mut())?;
+        }
+        Ok(self.exited.as_ref().map(|(status, _)| *status))
+    }
+
+    /// Turns this into a handle which can be shared between threads, for
+    /// cancelling the script while another thread waits for it.
+    pub fn into_handle(self) -> PsHandle {
+        PsHandle::from(self)
+    }
+
+    /// Pauses the PowerShell process until `resume` is called, for example to
+    /// free up the machine for something more important. Processes started
+    /// by the script aren't suspended.
+    ///
+    /// Uses `SIGSTOP` on Unix and `NtSuspendProcess` on Windows. Does nothing
+    /// if the script has already exited.
+    pub fn suspend(&mut self) -> Result<()> {
+        // Like `kill`, a reaped child's pid may belong to another process
+        if self.poll()?.is_some() {
+            return Ok(());
+        }
+        Ok(target::suspend(&self.child)?)
+    }
+
+    /// Resumes a process paused with `suspend`. Does nothing if the script
+    /// has already exited.
+    pub fn resume(&mut self) -> Result<()> {
+        if self.poll()?.is_some() {
+            return Ok(());
+        }
+        Ok(target::resume(&self.child)?)
+    }
+
+    /// Waits for the script to finish and collects its output. Returns a
+    /// `PsError::Powershell(Output)` if the script failed, like `PsScript::run`.
+    pub fn wait(mut self) -> Result<Output> {
+        // Waiting closes stdin so the script isn't left waiting for input
+        let (status, usage) = match self.exited.take() {
+            Some(exited) => exited,
+            None => tracker::wait(&mut self.child, self.tracked.as_mut())?,
+        };
+        let stdout = join(self.stdout.take())?;
+        let stderr = join(self.stderr.take())?;
+        let mut result = script::into_result(
+            process::Output {
+                status,
+                stdout,
+                stderr,
+            },
+            usage,
+            self.cleanup,
+        );
+        script::attach_context(&mut result, &self.ctx);
+        script::after(&self.middleware, &self.ctx, &result);
+        result
+    }
+}
+
+impl Drop for PsChild {
+    fn drop(&mut self) {
+        // A child nobody waits for is left to the tracker to reap
+        if let Ok(None) = self.poll() {
+            if let Some(tracked) = self.tracked.take() {
+                tracked.orphan();
+            }
+        }
+    }
+}
+
+/// A handle to a script running in the background which can be shared
+/// between threads, so one thread can wait for the script while another
+/// cancels it. Returned by `PsScript::spawn_handle` and
+/// `PsChild::into_handle`.
+///
+/// Clones refer to the same script. If several of them wait for it, one gets
+/// the output and the others an error.
+///
+/// ## Example
+///
+/// ```rust, no_run
+/// use std::{thread, time::Duration};
+/// use powershell_script::PsScriptBuilder;
+///
+/// let ps = PsScriptBuilder::new().cancellation_sentinel(true).build();
+/// let handle = ps.spawn_handle("while (-not (Test-HostCancelled)) { Start-Sleep 1 }").unwrap();
+///
+/// let cancel_button = handle.clone();
+/// thread::spawn(move || {
+///     thread::sleep(Duration::from_secs(5));
+///     cancel_button.cancel(Duration::from_secs(2)).unwrap();
+/// });
+///
+/// let output = handle.wait().unwrap();
+/// println!("{}", output);
+/// ```
+#[derive(Clone)]
+pub struct PsHandle {
+    id: u32,
+    child: Arc<Mutex<Option<PsChild>>>,
+}
+
+impl PsHandle {
+    /// Returns the OS-assigned process identifier of the PowerShell process.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Whether the script is still running, see `PsChild::is_running`.
+    pub fn is_running(&self) -> bool {
+        self.lock().as_mut().is_some_and(PsChild::is_running)
+    }
+
+    /// Asks the script to stop and kills it if it hasn't exited within
+    /// `grace`, see `PsChild::cancel`. Returns `true` if the script stopped
+    /// on its own.
+    pub fn cancel(&self, grace: Duration) -> Result<bool> {
+        let exited = match self.lock().as_mut() {
+            Some(child) => child.request_cancel()?,
+            None => true,
+        };
+        if exited {
+            return Ok(true);
+        }
+        self.stop_within(grace)
+    }
+
+    /// Asks the script to stop the same way pressing Ctrl+C would, see
+    /// `PsChild::interrupt`.
+    pub fn interrupt(&self, grace: Duration) -> Result<bool> {
+        let exited = match self.lock().as_mut() {
+            Some(child) => child.send_interrupt()?,
+            None => true,
+        };
+        if exited {
+            return Ok(true);
+        }
+        self.stop_within(grace)
+    }
+
+    /// Like `PsChild::stop_within`, but the lock is only held while polling
+    /// so the other clones can be used during the grace period.
+    fn stop_within(&self, grace: Duration) -> Result<bool> {
+        let deadline = Instant::now() + grace;
+        loop {
+            {
+                let mut child = self.lock();
+                let child = match child.as_mut() {
+                    Some(child) => child,
+                    // Another clone waited for it, so it has exited
+                    None => return Ok(true),
+                };
+                if child.poll()?.is_some() {
+                    return Ok(true);
+                }
+                if Instant::now() >= deadline {
+                    child.kill()?;
+                    return Ok(false);
+                }
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Forcibly terminates the PowerShell process, see `PsChild::kill`.
+    pub fn kill(&self) -> Result<()> {
+        match self.lock().as_mut() {
+            Some(child) => child.kill(),
+            None => Ok(()),
+        }
+    }
+
+    /// Waits for the script to finish and collects its output, like
+    /// `PsChild::wait`. The other methods can be called from other threads
+    /// in the meantime.
+    pub fn wait(self) -> Result<Output> {
+        loop {
+            // The child is taken out with the lock held, but waited for after
+            // it's released, so the other clones aren't blocked while its
+            // output is collected. They see it as already waited for.
+            let exited = {
+                let mut child = self.lock();
+                let exited = match child.as_mut() {
+                    Some(child) => child.try_wait()?.is_some(),
+                    None => {
+                        return Err(io::Error::other("the script was already waited for").into())
+                    }
+                };
+                if exited {
+                    child.take()
+                } else {
+                    None
+                }
+            };
+            if let Some(child) = exited {
+                return child.wait();
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<PsChild>> {
+        self.child.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl From<PsChild> for PsHandle {
+    fn from(child: PsChild) -> Self {
+        PsHandle {
+            id: child.id(),
+            child: Arc::new(Mutex::new(Some(child))),
+        }
+    }
+}
+
+pub(crate) fn read_all(mut pipe: impl Read) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    pipe.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+pub(crate) fn join(reader: Option<JoinHandle<io::Result<Vec<u8>>>>) -> io::Result<Vec<u8>> {
+    match reader {
+        Some(handle) => handle
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("output reader thread panicked"))),
+        None => Ok(Vec::new()),
+    }
+}
@@ -0,0 +1,251 @@
+//! Running a script on every PowerShell installation on the machine and
+//! comparing the results, for authors of scripts which have to work on both
+//! Windows PowerShell 5.1 and PowerShell 7.
+//!
+//! ## Example
+//!
+//! ```rust, no_run
+//! use powershell_script::compat;
+//!
+//! let report = compat::run_on_all("Get-Date -Date '2024-03-01' -Format o").unwrap();
+//! println!("{}", report);
+//! assert!(report.is_consistent(), "{}", report.diff());
+//! ```
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{context::Edition, error::PsError, output::Output, target, PsScript, Result};
+
+/// Returns the PowerShell installations found on this machine: on `PATH`,
+/// in the default install locations and in the probe paths of the default
+/// `PsScript`, see `set_default`.
+pub fn installations() -> Vec<PathBuf> {
+    crate::with_default(|ps| target::installations(&ps.probe_paths))
+}
+
+/// Runs `script` on every installation with the default `PsScript`, see
+/// [`run_on_all_with`].
+pub fn run_on_all(script: &str) -> Result<CompatReport> {
+    crate::with_default(|ps| run_on_all_with(ps, script))
+}
+
+/// Runs `script` like `ps.run` on every PowerShell installation found on
+/// this machine, one after another, including those in the probe paths of
+/// `ps`. Returns `PsError::PowershellNotFound` if there are none.
+pub fn run_on_all_with(ps: &PsScript, script: &str) -> Result<CompatReport> {
+    let installations = target::installations(&ps.probe_paths);
+    if installations.is_empty() {
+        return Err(PsError::PowershellNotFound);
+    }
+    let runs = installations
+        .into_iter()
+        .map(|path| {
+            let ps = ps.with_executable(path.clone().into_os_string());
+            let (edition, version) = identify(&ps);
+            EditionRun {
+                path,
+                edition,
+                version,
+                result: ps.run(script),
+            }
+        })
+        .collect();
+    Ok(CompatReport { runs })
+}
+
+/// Asks the installation `ps` runs for its edition and version. Either is
+/// `None` if it couldn't be told, older versions don't report an edition.
+fn identify(ps: &PsScript) -> (Option<Edition>, Option<String>) {
+    let line = "\"$($PSVersionTable.PSEdition)|$($PSVersionTable.PSVersion)\"".to_string();
+    let stdout = match ps.run_program("", &[line]) {
+        Ok(output) if output.status.success() => output.stdout,
+        _ => return (None, None),
+    };
+    let stdout = String::from_utf8_lossy(&stdout);
+    let (edition, version) = match stdout
+        .lines()
+        .next()
+        .and_then(|line| line.trim().split_once('|'))
+    {
+        Some(parts) => parts,
+        None => return (None, None),
+    };
+    let edition = match edition {
+        "Desktop" => Some(Edition::Desktop),
+        "Core" => Some(Edition::Core),
+        _ => None,
+    };
+    let version = Some(version.to_string()).filter(|v| !v.is_empty());
+    (edition, version)
+}
+
+/// The result of running the script on one installation.
+#[derive(Debug)]
+pub struct EditionRun {
+    path: PathBuf,
+    edition: Option<Edition>,
+    version: Option<String>,
+    result: Result<Output>,
+}
+
+impl EditionRun {
+    /// The PowerShell executable of the installation.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The edition of the installation, if it could be told.
+    pub fn edition(&self) -> Option<Edition> {
+        self.edition
+    }
+
+    /// The version of the installation, like `7.4.1`, if it could be told.
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// What `run` returned on the installation.
+    pub fn result(&self) -> &Result<Output> {
+        &self.result
+    }
+
+    /// Whether the script succeeded, and the lines it wrote to `stdout`,
+    /// which is what runs are compared by.
+    fn outcome(&self) -> (bool, Vec<String>) {
+        match &self.result {
+            Ok(output) | Err(PsError::Powershell(output)) => (
+                output.success(),
+                output
+                    .stdout()
+                    .unwrap_or_default()
+                    .lines()
+                    .map(|line| line.trim_end().to_string())
+                    .collect(),
+            ),
+            Err(e) => (false, vec![e.to_string()]),
+        }
+    }
+}
+
+impl fmt::Display for EditionRun {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let edition = match self.edition {
+            Some(Edition::Desktop) => "Windows PowerShell",
+            Some(Edition::Core) | None => "PowerShell",
+        };
+        match &self.version {
+            Some(version) => write!(f, "{} {}", edition, version)?,
+            None => write!(f, "{}", edition)?,
+        }
+        write!(f, " ({})", self.path.display())
+    }
+}
+
+/// The results of running a script on every installation, see
+/// [`run_on_all`].
+#[derive(Debug)]
+pub struct CompatReport {
+    runs: Vec<EditionRun>,
+}
+
+impl CompatReport {
+    /// The runs, one per installation.
+    pub fn runs(&self) -> &[EditionRun] {
+        &self.runs
+    }
+
+    /// Whether the script succeeded or failed on all installations alike,
+    /// writing the same to `stdout`. Trailing whitespace and line endings
+    /// are ignored, and so is `stderr`, since the editions format errors
+    /// differently.
+    pub fn is_consistent(&self) -> bool {
+        let mut outcomes = self.runs.iter().map(EditionRun::outcome);
+        match outcomes.next() {
+            Some(first) => outcomes.all(|outcome| outcome == first),
+            None => true,
+        }
+    }
+
+    /// Describes how the other runs differ from the first one, as a diff of
+    /// their `stdout`. Empty if the runs are consistent.
+    pub fn diff(&self) -> String {
+        let mut diff = String::new();
+        let (first, others) = match self.runs.split_first() {
+            Some(split) => split,
+            None => return diff,
+        };
+        let (first_success, first_lines) = first.outcome();
+        for run in others {
+            let (success, lines) = run.outcome();
+            if (success, &lines) == (first_success, &first_lines) {
+                continue;
+            }
+            diff.push_str(&format!("--- {}\n+++ {}\n", first, run));
+            if success != first_success {
+                diff.push_str(&format!(
+                    "-{}\n+{}\n",
+                    status(first_success),
+                    status(success)
+                ));
+            }
+            for line in diff_lines(&first_lines, &lines) {
+                diff.push_str(&line);
+                diff.push('\n');
+            }
+        }
+        diff
+    }
+}
+
+impl fmt::Display for CompatReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for run in &self.runs {
+            writeln!(f, "{}: {}", run, status(run.outcome().0))?;
+        }
+        write!(f, "{}", self.diff())
+    }
+}
+
+fn status(success: bool) -> &'static str {
+    if success {
+        "succeeded"
+    } else {
+        "FAILED"
+    }
+}
+
+/// Returns the lines only in `a` prefixed with `-` and those only in `b`
+/// prefixed with `+`, in order, based on their longest common subsequence.
+fn diff_lines(a: &[String], b: &[String]) -> Vec<String> {
+    // common[i][j] is the length of the longest common subsequence of
+    // a[i..] and b[j..]
+    let mut common = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            common[i][j] = if a[i] == b[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(format!("-{}", a[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+{}", b[j]));
+            j += 1;
+        }
+    }
+    lines
+}
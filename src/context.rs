@@ -0,0 +1,69 @@
+//! Identifying a run, so that everything it produces can be correlated.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
+};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The edition of PowerShell a script runs in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Edition {
+    /// Windows PowerShell (`PowerShell.exe`), which ships with Windows.
+    Desktop,
+    /// PowerShell Core (`pwsh`).
+    Core,
+}
+
+impl Edition {
+    /// The edition this crate was compiled to run scripts in.
+    pub(crate) fn current() -> Self {
+        if cfg!(all(windows, not(feature = "core"))) {
+            Edition::Desktop
+        } else {
+            Edition::Core
+        }
+    }
+}
+
+/// Identifies a single run of a script. It's available to middleware through
+/// `RunContext::execution`, is the first event delivered by the streaming
+/// run methods and is attached to the `Output` of the run, including the one
+/// in `PsError::Powershell`, so that concurrent runs can be told apart in
+/// logs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionContext {
+    /// Unique for each run within this process.
+    pub id: u64,
+    /// A hash of the script as it was passed to the run method. Runs of the
+    /// same script have the same hash within this process, but it may
+    /// change between versions of Rust.
+    pub script_hash: u64,
+    /// When the run was started.
+    pub started_at: SystemTime,
+    /// The arguments passed to the script, if any.
+    pub args: Vec<String>,
+    pub edition: Edition,
+}
+
+impl ExecutionContext {
+    pub(crate) fn new(script: &str, args: Vec<String>) -> Self {
+        ExecutionContext {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            script_hash: hash_script(script),
+            started_at: SystemTime::now(),
+            args,
+            edition: Edition::current(),
+        }
+    }
+}
+
+/// Hashes the source of a script, see `ExecutionContext::script_hash`.
+pub(crate) fn hash_script(script: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    script.hash(&mut hasher);
+    hasher.finish()
+}
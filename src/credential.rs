@@ -0,0 +1,162 @@
+//! Answering `Get-Credential` from the host application.
+//!
+//! PowerShell's `stdin` carries the script, so the replacement function asks
+//! for credentials over a TCP connection to a listener on the loopback
+//! interface which lives as long as the script runs. Requests carry a random
+//! token so other processes on the machine can't ask for credentials.
+
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{base64, side_channel, value::PsValue};
+
+/// How often the listener checks whether the script has finished.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The callback answering credential requests.
+pub(crate) type CredentialProvider =
+    Arc<dyn Fn(&CredentialRequest) -> Option<Credential> + Send + Sync>;
+
+/// The arguments of a call to `Get-Credential` made by a script, passed to
+/// the callback registered with `PsScriptBuilder::credential_provider`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CredentialRequest {
+    /// The user name the script suggested, if any.
+    pub user_name: Option<String>,
+    /// The message to show to the user, if any.
+    pub message: Option<String>,
+    /// The title of the prompt, if any.
+    pub title: Option<String>,
+}
+
+/// A user name and password to hand to a script asking for credentials.
+#[derive(Clone)]
+pub struct Credential {
+    pub user_name: String,
+    pub password: String,
+}
+
+impl Credential {
+    pub fn new(user_name: impl Into<String>, password: impl Into<String>) -> Self {
+        Credential {
+            user_name: user_name.into(),
+            password: password.into(),
+        }
+    }
+}
+
+impl fmt::Debug for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Credential")
+            .field("user_name", &self.user_name)
+            .finish()
+    }
+}
+
+/// Answers the credential requests of one run. The listener stops when
+/// this is dropped.
+pub(crate) struct CredentialBridge {
+    port: u16,
+    token: String,
+    stopped: Arc<AtomicBool>,
+}
+
+impl CredentialBridge {
+    pub(crate) fn start(provider: CredentialProvider) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        listener.set_nonblocking(true)?;
+        let port = listener.local_addr()?.port();
+        let token = side_channel::token();
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let bridge = CredentialBridge {
+            port,
+            token: token.clone(),
+            stopped: stopped.clone(),
+        };
+        thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    // A misbehaving client only fails its own request
+                    Ok((stream, _)) => {
+                        let _ = answer(stream, &token, &provider);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                    Err(_) => return,
+                }
+            }
+        });
+        Ok(bridge)
+    }
+
+    /// Returns the definition of the `Get-Credential` replacement, which
+    /// returns `$null` if the host declines the request like the cmdlet does
+    /// when its prompt is cancelled.
+    pub(crate) fn function(&self) -> String {
+        format!(
+            "function Get-Credential {{ param([Parameter(Position = 0)][string]$UserName, [string]$Message, [string]$Title) $__ps_client = New-Object System.Net.Sockets.TcpClient('127.0.0.1', {}); try {{ $__ps_stream = $__ps_client.GetStream(); $__ps_writer = New-Object System.IO.StreamWriter($__ps_stream); $__ps_writer.WriteLine((ConvertTo-Json -Compress -InputObject ([ordered]@{{ Token = '{}'; UserName = $UserName; Message = $Message; Title = $Title }}))); $__ps_writer.Flush(); $__ps_answer = (New-Object System.IO.StreamReader($__ps_stream)).ReadLine() }} finally {{ $__ps_client.Dispose() }}; if (-not $__ps_answer) {{ return $null }}; $__ps_parts = $__ps_answer.Split(' '); $__ps_decode = {{ param($s) [Text.Encoding]::UTF8.GetString([Convert]::FromBase64String($s)) }}; New-Object System.Management.Automation.PSCredential((& $__ps_decode $__ps_parts[0]), (ConvertTo-SecureString (& $__ps_decode $__ps_parts[1]) -AsPlainText -Force)) }}",
+            self.port, self.token
+        )
+    }
+}
+
+impl Drop for CredentialBridge {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Debug for CredentialBridge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CredentialBridge")
+            .field("port", &self.port)
+            .finish()
+    }
+}
+
+/// Reads a request from `stream` and writes the answer back. An empty line
+/// declines the request.
+fn answer(stream: TcpStream, token: &str, provider: &CredentialProvider) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let request = PsValue::from_json(line.trim())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    if request.get("Token").and_then(PsValue::as_str) != Some(token) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "credential request without a valid token",
+        ));
+    }
+
+    let string = |key: &str| {
+        request
+            .get(key)
+            .and_then(PsValue::as_str)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+    };
+    let request = CredentialRequest {
+        user_name: string("UserName"),
+        message: string("Message"),
+        title: string("Title"),
+    };
+    let reply = match provider(&request) {
+        Some(credential) => format!(
+            "{} {}\n",
+            base64::encode(credential.user_name.as_bytes()),
+            base64::encode(credential.password.as_bytes())
+        ),
+        None => "\n".to_string(),
+    };
+    (&stream).write_all(reply.as_bytes())
+}
@@ -0,0 +1,95 @@
+//! Environment changes made by a script, see
+//! `PsScriptBuilder::capture_env_delta`.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::{
+    value::{FromPsValue, PsValue},
+    Result,
+};
+
+/// The environment variables a script added, changed or removed.
+///
+/// A child process can't change the environment of its parent, so scripts
+/// which are meant to set up the caller's environment (like virtualenv's
+/// `Activate.ps1` or the Visual Studio developer shell) have no effect on a
+/// Rust host. With the delta the host can adopt the changes itself.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct EnvDelta {
+    /// Variables the script created, with their values.
+    pub added: BTreeMap<String, String>,
+    /// Variables the script changed, with their new values.
+    pub changed: BTreeMap<String, String>,
+    /// Variables the script removed.
+    pub removed: BTreeSet<String>,
+}
+
+impl EnvDelta {
+    /// Returns the changes from `before` to `after`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use powershell_script::EnvDelta;
+    ///
+    /// let before: HashMap<String, String> = vec![("A".into(), "1".into()), ("B".into(), "2".into())].into_iter().collect();
+    /// let after: HashMap<String, String> = vec![("A".into(), "3".into()), ("C".into(), "4".into())].into_iter().collect();
+    /// let delta = EnvDelta::diff(&before, &after);
+    /// assert_eq!(delta.changed["A"], "3");
+    /// assert_eq!(delta.added["C"], "4");
+    /// assert!(delta.removed.contains("B"));
+    /// ```
+    pub fn diff(before: &HashMap<String, String>, after: &HashMap<String, String>) -> EnvDelta {
+        let mut delta = EnvDelta::default();
+        for (name, value) in after {
+            match before.get(name) {
+                None => {
+                    delta.added.insert(name.clone(), value.clone());
+                }
+                Some(old) if old != value => {
+                    delta.changed.insert(name.clone(), value.clone());
+                }
+                Some(_) => {}
+            }
+        }
+        delta.removed = before
+            .keys()
+            .filter(|name| !after.contains_key(*name))
+            .cloned()
+            .collect();
+        delta
+    }
+
+    /// Whether the script left the environment as it found it.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+
+    /// Applies the changes to the environment of the current process.
+    ///
+    /// Changing the environment affects the whole process, so the same care
+    /// is needed as for `std::env::set_var`.
+    pub fn apply(&self) {
+        for (name, value) in self.added.iter().chain(&self.changed) {
+            std::env::set_var(name, value);
+        }
+        for name in &self.removed {
+            std::env::remove_var(name);
+        }
+    }
+}
+
+/// Reads the `{ Before = ..., After = ... }` snapshots written by the wrapper
+/// code.
+impl FromPsValue for EnvDelta {
+    fn from_ps_value(value: PsValue) -> Result<Self> {
+        let snapshot = |key: &str| -> Result<HashMap<String, String>> {
+            match value.get(key) {
+                Some(PsValue::Null) | None => Ok(HashMap::new()),
+                Some(snapshot) => HashMap::from_ps_value(snapshot.clone()),
+            }
+        };
+        Ok(EnvDelta::diff(&snapshot("Before")?, &snapshot("After")?))
+    }
+}
@@ -0,0 +1,93 @@
+//! A convention for scripts to report their result as a JSON envelope,
+//! separate from whatever else they write to the output, see
+//! `PsScript::run_enveloped`.
+
+use std::fmt;
+
+use crate::{
+    error::PsError,
+    output::Output,
+    value::{FromPsValue, PsValue},
+    wrap, Result,
+};
+
+/// The tag of the blocks the envelopes are written in.
+const TAG: &str = "envelope";
+
+/// The outcome a script reported with `Write-PsResult` or `Write-PsFailure`.
+pub type PsResult<T> = std::result::Result<T, ScriptFailure>;
+
+/// A failure reported by a script with `Write-PsFailure`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptFailure {
+    pub message: String,
+    /// The `-Details` passed along with the message, `PsValue::Null` if
+    /// there were none.
+    pub details: PsValue,
+}
+
+impl fmt::Display for ScriptFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ScriptFailure {}
+
+/// Returns the lines defining the functions scripts report their result
+/// with.
+pub(crate) fn helpers() -> Vec<String> {
+    let emit = |envelope: &str| {
+        wrap::emit_block(
+            TAG,
+            &format!(
+                "(ConvertTo-Json -Depth 10 -Compress -InputObject ([ordered]@{{ {} }}))",
+                envelope
+            ),
+        )
+    };
+    vec![
+        format!(
+            "function Write-PsResult {{ param([Parameter(Position = 0)] $Data) {} }}",
+            emit("ok = $true; data = $Data; error = $null")
+        ),
+        format!(
+            "function Write-PsFailure {{ param([Parameter(Mandatory = $true, Position = 0)] [string] $Message, [Parameter(Position = 1)] $Details) {} }}",
+            emit("ok = $false; data = $null; error = [ordered]@{ message = $Message; details = $Details }")
+        ),
+    ]
+}
+
+/// Runs after a script run with `PsScript::run_enveloped`, turning the
+/// result into what the script reported. A failed run without an envelope
+/// is returned as the error it is.
+pub(crate) fn parse<T: FromPsValue>(result: Result<Output>) -> Result<PsResult<T>> {
+    match result {
+        Ok(output) => output.envelope(),
+        Err(PsError::Powershell(output)) if output.block(TAG).is_some() => output.envelope(),
+        Err(e) => Err(e),
+    }
+}
+
+/// Converts the last envelope written by the script to a `PsResult`.
+pub(crate) fn from_output<T: FromPsValue>(output: &Output) -> Result<PsResult<T>> {
+    let json = output.block(TAG).ok_or_else(|| {
+        PsError::Deserialize(
+            "the script didn't report a result with Write-PsResult or Write-PsFailure".to_string(),
+        )
+    })?;
+    let envelope = PsValue::from_json(json)?;
+    let field = |value: &PsValue, key: &str| value.get(key).cloned().unwrap_or(PsValue::Null);
+    if field(&envelope, "ok") == PsValue::Bool(true) {
+        return Ok(Ok(T::from_ps_value(field(&envelope, "data"))?));
+    }
+    let error = field(&envelope, "error");
+    Ok(Err(ScriptFailure {
+        message: error
+            .get("message")
+            .and_then(PsValue::as_str)
+            .unwrap_or_default()
+            .to_string(),
+        details: field(&error, "details"),
+    }))
+}
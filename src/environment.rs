@@ -0,0 +1,196 @@
+//! Inspecting the PowerShell environment scripts run in, to explain why a
+//! script would be blocked before trying to run it.
+
+use std::fmt;
+
+use crate::{
+    error::PsError,
+    value::{FromPsValue, PsValue},
+    wrap, PsScript, Result,
+};
+
+/// An execution policy, see `about_Execution_Policies`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExecutionPolicy {
+    /// Scripts run without restrictions, with a warning for downloaded ones.
+    Unrestricted,
+    /// Scripts downloaded from the internet must be signed.
+    RemoteSigned,
+    /// All scripts must be signed.
+    AllSigned,
+    /// No script files run, only individual commands.
+    Restricted,
+    /// Nothing is blocked and there are no warnings.
+    Bypass,
+    /// The default policy of the platform.
+    Default,
+    /// No policy is set in the scope.
+    Undefined,
+}
+
+impl ExecutionPolicy {
+    fn parse(name: &str) -> Option<Self> {
+        let policy = match name {
+            "Unrestricted" => ExecutionPolicy::Unrestricted,
+            "RemoteSigned" => ExecutionPolicy::RemoteSigned,
+            "AllSigned" => ExecutionPolicy::AllSigned,
+            "Restricted" => ExecutionPolicy::Restricted,
+            "Bypass" => ExecutionPolicy::Bypass,
+            "Default" => ExecutionPolicy::Default,
+            "Undefined" => ExecutionPolicy::Undefined,
+            _ => return None,
+        };
+        Some(policy)
+    }
+}
+
+/// Where an execution policy is set, from highest to lowest precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    /// Set by a group policy for all users of the computer.
+    MachinePolicy,
+    /// Set by a group policy for the current user.
+    UserPolicy,
+    /// Set for the current PowerShell process only.
+    Process,
+    CurrentUser,
+    LocalMachine,
+}
+
+impl Scope {
+    fn parse(name: &str) -> Option<Self> {
+        let scope = match name {
+            "MachinePolicy" => Scope::MachinePolicy,
+            "UserPolicy" => Scope::UserPolicy,
+            "Process" => Scope::Process,
+            "CurrentUser" => Scope::CurrentUser,
+            "LocalMachine" => Scope::LocalMachine,
+            _ => return None,
+        };
+        Some(scope)
+    }
+}
+
+/// Which parts of the language scripts may use, see
+/// `about_Language_Modes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LanguageMode {
+    /// All of the language is available.
+    FullLanguage,
+    /// Types are restricted, which is typically enforced by application
+    /// control policies like WDAC or AppLocker.
+    ConstrainedLanguage,
+    /// Only a small subset of operators and variables is available.
+    RestrictedLanguage,
+    /// Scripts can't be run at all, only commands through the API.
+    NoLanguage,
+}
+
+/// The execution policies and language mode of the session a `PsScript`
+/// runs scripts in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyReport {
+    /// The policy in effect.
+    pub effective: ExecutionPolicy,
+    /// The policy set in each scope, in order of precedence.
+    pub scopes: Vec<(Scope, ExecutionPolicy)>,
+    pub language_mode: LanguageMode,
+}
+
+impl PolicyReport {
+    /// The scope the effective policy comes from, which is the one to
+    /// change (or ask an administrator to change) if it blocks a script.
+    /// `None` if no scope sets a policy and the platform default applies.
+    pub fn deciding_scope(&self) -> Option<Scope> {
+        self.scopes
+            .iter()
+            .find(|(_, policy)| *policy != ExecutionPolicy::Undefined)
+            .map(|(scope, _)| *scope)
+    }
+
+    /// Whether unsigned script files, like those run with `run_file`, are
+    /// refused. Scripts run through `stdin` aren't subject to the execution
+    /// policy.
+    pub fn blocks_unsigned_scripts(&self) -> bool {
+        matches!(
+            self.effective,
+            ExecutionPolicy::Restricted | ExecutionPolicy::AllSigned
+        )
+    }
+}
+
+impl fmt::Display for PolicyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The execution policy is {:?}", self.effective)?;
+        match self.deciding_scope() {
+            Some(scope) => write!(f, ", set in the {:?} scope", scope)?,
+            None => write!(f, ", the default as no scope sets one")?,
+        }
+        write!(f, ". The language mode is {:?}.", self.language_mode)
+    }
+}
+
+impl FromPsValue for PolicyReport {
+    fn from_ps_value(value: PsValue) -> Result<Self> {
+        let invalid =
+            |what: &str, name: &str| PsError::Deserialize(format!("unknown {} `{}`", what, name));
+        let string = |value: &PsValue, key: &str| {
+            value
+                .get(key)
+                .and_then(PsValue::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+        let policy = |name: String| {
+            ExecutionPolicy::parse(&name).ok_or_else(|| invalid("execution policy", &name))
+        };
+
+        let mut scopes = Vec::new();
+        let list = value.get("Scopes").cloned().unwrap_or(PsValue::Null);
+        for entry in Vec::<PsValue>::from_ps_value(list)? {
+            let name = string(&entry, "Scope");
+            let scope = Scope::parse(&name).ok_or_else(|| invalid("scope", &name))?;
+            scopes.push((scope, policy(string(&entry, "ExecutionPolicy"))?));
+        }
+
+        let language_mode = match string(&value, "LanguageMode").as_str() {
+            "FullLanguage" => LanguageMode::FullLanguage,
+            "ConstrainedLanguage" => LanguageMode::ConstrainedLanguage,
+            "RestrictedLanguage" => LanguageMode::RestrictedLanguage,
+            "NoLanguage" => LanguageMode::NoLanguage,
+            other => return Err(invalid("language mode", other)),
+        };
+
+        Ok(PolicyReport {
+            effective: policy(string(&value, "Effective"))?,
+            scopes,
+            language_mode,
+        })
+    }
+}
+
+/// Returns the execution policies and language mode of the session `ps`
+/// runs scripts in.
+///
+/// ## Example
+///
+/// ```rust, no_run
+/// use powershell_script::{environment, PsScriptBuilder};
+///
+/// let ps = PsScriptBuilder::new().build();
+/// let report = environment::policy_report(&ps).unwrap();
+/// if report.blocks_unsigned_scripts() {
+///     eprintln!("deploy.ps1 won't run: {}", report);
+/// }
+/// ```
+pub fn policy_report(ps: &PsScript) -> Result<PolicyReport> {
+    let script = wrap::emit_block(
+        "policy",
+        "(ConvertTo-Json -Depth 3 -Compress -InputObject ([ordered]@{ Effective = [string](Get-ExecutionPolicy); Scopes = @(Get-ExecutionPolicy -List | ForEach-Object { [ordered]@{ Scope = [string]$_.Scope; ExecutionPolicy = [string]$_.ExecutionPolicy } }); LanguageMode = [string]$ExecutionContext.SessionState.LanguageMode }))",
+    );
+    let output = ps.run_with_args(&script, std::iter::empty::<&str>())?;
+    let json = output
+        .block("policy")
+        .ok_or_else(|| PsError::Deserialize("the script didn't report the policies".to_string()))?;
+    PolicyReport::from_ps_value(PsValue::from_json(json)?)
+}
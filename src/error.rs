@@ -1,9 +1,14 @@
 use std::fmt;
 use std::io;
+use std::process;
+use std::time::Duration;
 
-use crate::output::Output;
+use crate::{
+    builder::ExecutionMode, context::ExecutionContext, output::Output, requires::ModuleSpec,
+};
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum PsError {
     /// An error in the PowerShell script.
     Powershell(Output),
@@ -13,6 +18,74 @@ pub enum PsError {
     PowershellNotFound,
     /// Failed to retrieve a handle to `stdin` for the child process
     ChildStdinNotFound,
+    /// Failed to convert a value returned from the script into the requested type.
+    Deserialize(String),
+    /// A middleware refused to run the script.
+    Rejected(String),
+    /// No script is registered under the name in the `ScriptRegistry`.
+    UnknownScript(String),
+    /// The parameters passed to a registered script don't match its schema.
+    InvalidParameters(String),
+    /// The script waited for input at a prompt for longer than the
+    /// `input_timeout` and was killed. Holds the prompt.
+    WaitingForInput(String),
+    /// Modules required with `PsScriptBuilder::requires` aren't installed,
+    /// so the script didn't run. Holds the missing modules.
+    MissingModules(Vec<ModuleSpec>),
+    /// The script neither wrote output nor sent a heartbeat for longer than
+    /// the `stall_timeout` and was killed. Holds how long it had been silent.
+    Stalled(Duration),
+    /// The run would exceed the `ExecutionQuota` and wasn't started. Holds
+    /// which limit it would exceed.
+    QuotaExceeded(String),
+    /// The script's deadline passed while it waited for a `PsPool` worker,
+    /// so it wasn't run. Holds how long it waited.
+    DeadlineExceeded(Duration),
+    /// The configuration passed to `PsScriptBuilder::build_session` or
+    /// `build_pool` is invalid.
+    InvalidConfig(BuildError),
+}
+
+impl PsError {
+    /// Returns the context of the run which failed, if the script ran.
+    pub fn context(&self) -> Option<&ExecutionContext> {
+        match self {
+            PsError::Powershell(output) => output.context(),
+            _ => None,
+        }
+    }
+
+    /// Returns the exit code for a program which wraps the script to exit
+    /// with when the run failed. A script which failed by itself reports its
+    /// own code, see [`Output::to_exit_code`]. Other errors use the codes
+    /// shells and `sysexits.h` have for them:
+    ///
+    /// - 127 if PowerShell wasn't found, like a command which isn't found
+    /// - 124 if the script was killed for waiting for input or making no
+    ///   progress, like `timeout` does
+    /// - 64 for unknown scripts and invalid parameters (`EX_USAGE`)
+    /// - 65 if the output couldn't be deserialized (`EX_DATAERR`)
+    /// - 69 for missing modules (`EX_UNAVAILABLE`)
+    /// - 74 for I/O errors (`EX_IOERR`)
+    /// - 75 if the execution quota or a deadline was exceeded (`EX_TEMPFAIL`)
+    /// - 77 if a middleware rejected the script (`EX_NOPERM`)
+    /// - 78 for an invalid configuration (`EX_CONFIG`)
+    pub fn exit_code(&self) -> process::ExitCode {
+        use PsError::*;
+        let code = match self {
+            Powershell(output) => return output.to_exit_code(),
+            PowershellNotFound => 127,
+            WaitingForInput(_) | Stalled(_) => 124,
+            UnknownScript(_) | InvalidParameters(_) => 64,
+            Deserialize(_) => 65,
+            MissingModules(_) => 69,
+            Io(_) | ChildStdinNotFound => 74,
+            QuotaExceeded(_) | DeadlineExceeded(_) => 75,
+            Rejected(_) => 77,
+            InvalidConfig(_) => 78,
+        };
+        process::ExitCode::from(code)
+    }
 }
 
 impl std::error::Error for PsError {}
@@ -25,6 +98,31 @@ impl fmt::Display for PsError {
             Io(e) => write!(f, "{}", e)?,
             PowershellNotFound => write!(f, "Failed to find powershell on this system")?,
             ChildStdinNotFound => write!(f, "Failed to acquire a handle to stdin in the child process.")?,
+            Deserialize(msg) => write!(f, "Failed to deserialize the output of the script: {}", msg)?,
+            Rejected(msg) => write!(f, "The script was rejected: {}", msg)?,
+            UnknownScript(name) => write!(f, "No script is registered as `{}`", name)?,
+            InvalidParameters(msg) => write!(f, "Invalid parameters for {}", msg)?,
+            WaitingForInput(prompt) => write!(
+                f,
+                "The script was killed while waiting for input at the prompt `{}`. Scripts can't be answered when run by this crate, pass the value as a parameter instead",
+                prompt
+            )?,
+            MissingModules(modules) => {
+                let modules: Vec<String> = modules.iter().map(ToString::to_string).collect();
+                write!(f, "The script requires modules which aren't installed: {}", modules.join(", "))?
+            }
+            Stalled(silent) => write!(
+                f,
+                "The script was killed after making no progress for {:.1}s",
+                silent.as_secs_f64()
+            )?,
+            QuotaExceeded(msg) => write!(f, "The script wasn't started as it would exceed the execution quota: {}", msg)?,
+            DeadlineExceeded(waited) => write!(
+                f,
+                "The script wasn't started as its deadline passed after waiting {:.1}s for a worker",
+                waited.as_secs_f64()
+            )?,
+            InvalidConfig(e) => write!(f, "Invalid configuration: {}", e)?,
         }
         Ok(())
     }
@@ -35,3 +133,60 @@ impl From<io::Error> for PsError {
         PsError::Io(io)
     }
 }
+
+impl From<BuildError> for PsError {
+    fn from(e: BuildError) -> PsError {
+        PsError::InvalidConfig(e)
+    }
+}
+
+/// A configuration error detected by `PsScriptBuilder::try_build`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BuildError {
+    /// An empty path was passed to `probe_path`.
+    EmptyProbePath,
+    /// An empty path was passed to `executable`.
+    EmptyExecutable,
+    /// The PowerShell parameter was passed more than once with `raw_arg`.
+    DuplicateArg(String),
+    /// The PowerShell parameter passed with `raw_arg` is one the builder
+    /// already sets, like `-Command`.
+    ConflictingArg(String),
+    /// The option doesn't apply to scripts run with the `ExecutionMode`.
+    UnsupportedMode(&'static str, ExecutionMode),
+    /// The two options can't be used together.
+    ConflictingOptions(&'static str, &'static str),
+    /// The first option only applies when the second one is set.
+    MissingOption(&'static str, &'static str),
+}
+
+impl std::error::Error for BuildError {}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use BuildError::*;
+        match self {
+            EmptyProbePath => write!(f, "`probe_path` was called with an empty path")?,
+            EmptyExecutable => write!(f, "`executable` was called with an empty path")?,
+            DuplicateArg(name) => write!(f, "`raw_arg` passes `{}` more than once", name)?,
+            ConflictingArg(name) => write!(
+                f,
+                "`raw_arg` passes `{}`, which is already set by the builder",
+                name
+            )?,
+            UnsupportedMode(option, mode) => write!(
+                f,
+                "`{}` doesn't apply to `ExecutionMode::{:?}`",
+                option, mode
+            )?,
+            ConflictingOptions(option, other) => {
+                write!(f, "`{}` can't be used together with `{}`", option, other)?
+            }
+            MissingOption(option, required) => {
+                write!(f, "`{}` only applies when `{}` is set", option, required)?
+            }
+        }
+        Ok(())
+    }
+}
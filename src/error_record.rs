@@ -0,0 +1,84 @@
+//! Errors a script ran into, collected from `$Error`, see
+//! `PsScriptBuilder::capture_errors`.
+
+use crate::{
+    value::{FromPsValue, PsValue},
+    Result,
+};
+
+/// An expression evaluating to the records in `$Error`, oldest first, as
+/// JSON.
+pub(crate) const COLLECT_ERRORS: &str = "(& { $records = @($Error | Where-Object { $_ -is [System.Management.Automation.ErrorRecord] } | ForEach-Object { $id = [string]$_.FullyQualifiedErrorId; $origin = if ($_.Exception.WasThrownFromThrowStatement) { 'Throw' } elseif ($_.Exception -is [Microsoft.PowerShell.Commands.WriteErrorException] -or $id -like '*WriteErrorException*') { 'WriteError' } elseif ($id -match '^(NativeCommandError|NativeCommandErrorMessage|ProgramExitedWithNonZeroCode)') { 'NativeCommand' } elseif ($_.InvocationInfo.MyCommand -is [System.Management.Automation.CmdletInfo]) { 'Cmdlet' } else { 'Exception' }; [ordered]@{ Message = [string]$_; Origin = $origin; ErrorId = $id; Category = [string]$_.CategoryInfo.Category; ExceptionType = $_.Exception.GetType().FullName; Command = [string]$_.InvocationInfo.MyCommand.Name; Line = $_.InvocationInfo.ScriptLineNumber } }); [array]::Reverse($records); ConvertTo-Json -InputObject $records -Depth 2 -Compress })";
+
+/// Where an error came from, which usually says something about how to deal
+/// with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorOrigin {
+    /// A non-terminating error written with `Write-Error`. Scripts often use
+    /// these to report that one item of many failed.
+    WriteError,
+    /// A terminating error raised by a `throw` statement, which usually
+    /// means the script hit a case it doesn't handle.
+    Throw,
+    /// Output a native program wrote to `stderr`, or a native program
+    /// exiting with a non-zero exit code when
+    /// `$PSNativeCommandUseErrorActionPreference` is set.
+    NativeCommand,
+    /// A non-terminating error written by a cmdlet other than `Write-Error`,
+    /// like `Get-Item` not finding a file.
+    Cmdlet,
+    /// Any other error, like an exception thrown by a .NET method.
+    Exception,
+}
+
+/// An error recorded while the script ran.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorRecord {
+    /// The error message.
+    pub message: String,
+    pub origin: ErrorOrigin,
+    /// PowerShell's `FullyQualifiedErrorId`, like
+    /// `PathNotFound,Microsoft.PowerShell.Commands.GetItemCommand`.
+    pub error_id: String,
+    /// The error category, like `ObjectNotFound`.
+    pub category: String,
+    /// The full name of the .NET exception type.
+    pub exception_type: String,
+    /// The command which failed, if known.
+    pub command: Option<String>,
+    /// The line of the script the error occurred on, if known.
+    pub line: Option<u32>,
+}
+
+impl FromPsValue for ErrorRecord {
+    fn from_ps_value(value: PsValue) -> Result<Self> {
+        let string = |key: &str| {
+            value
+                .get(key)
+                .and_then(PsValue::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+        let origin = match string("Origin").as_str() {
+            "WriteError" => ErrorOrigin::WriteError,
+            "Throw" => ErrorOrigin::Throw,
+            "NativeCommand" => ErrorOrigin::NativeCommand,
+            "Cmdlet" => ErrorOrigin::Cmdlet,
+            _ => ErrorOrigin::Exception,
+        };
+        let line = match value.get("Line").cloned() {
+            Some(line) => Option::<u32>::from_ps_value(line)?.filter(|line| *line > 0),
+            None => None,
+        };
+        let command = Some(string("Command")).filter(|c| !c.is_empty());
+        Ok(ErrorRecord {
+            message: string("Message"),
+            origin,
+            error_id: string("ErrorId"),
+            category: string("Category"),
+            exception_type: string("ExceptionType"),
+            command,
+            line,
+        })
+    }
+}
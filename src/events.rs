@@ -0,0 +1,377 @@
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Read},
+    process::{Child, ExitStatus},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Instant, SystemTime},
+};
+
+use crate::{
+    child,
+    context::ExecutionContext,
+    tracker::{self, Tracked},
+    value::{FromPsValue, PsValue},
+    wrap, Result,
+};
+
+/// Written to `stdout` by our `Write-Progress` replacement, followed by the
+/// record as JSON.
+const PROGRESS_MARKER: &str = "##ps-progress:";
+/// Written to `stdout` for each line of a verbose message.
+const VERBOSE_MARKER: &str = "##ps-verbose:";
+/// Written to `stdout` for each line of a warning.
+const WARNING_MARKER: &str = "##ps-warning:";
+/// Written to `stdout` for each line of a debug message.
+const DEBUG_MARKER: &str = "##ps-debug:";
+
+/// Replaces `Write-Progress` for the script, since the progress stream can't
+/// be redirected and is dropped when PowerShell isn't attached to a console.
+const PROGRESS_FUNCTION: &str = "function Write-Progress { param([Parameter(Position = 0)][string]$Activity, [Parameter(Position = 1)][string]$Status, [Parameter(Position = 2)][int]$Id = 0, [int]$PercentComplete = -1, [int]$SecondsRemaining = -1, [string]$CurrentOperation, [int]$ParentId = -1, [switch]$Completed) [Console]::Out.WriteLine('##ps-progress:' + (ConvertTo-Json -Compress -InputObject ([ordered]@{ Activity = $Activity; Status = $Status; Id = $Id; PercentComplete = $PercentComplete; SecondsRemaining = $SecondsRemaining; CurrentOperation = $CurrentOperation; ParentId = $ParentId; Completed = [bool]$Completed }))) }";
+
+/// Something the script wrote, or the script exiting, delivered as it
+/// happens.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputEvent {
+    /// The script was started. This is always the first event.
+    Started(ExecutionContext),
+    /// A line written to `stdout`, without the line ending.
+    Stdout(String),
+    /// A line written to `stderr`, without the line ending.
+    Stderr(String),
+    /// A call to `Write-Progress`.
+    Progress(ProgressRecord),
+    /// A line of a message written to the verbose stream. Note that verbose
+    /// messages are only written if the script sets `$VerbosePreference` or
+    /// passes `-Verbose`.
+    Verbose(String),
+    /// A line of a message written to the warning stream, with
+    /// `Write-Warning` for example.
+    Warning(String),
+    /// A line of a message written to the debug stream. Like verbose
+    /// messages, these are only written if the script sets
+    /// `$DebugPreference` or passes `-Debug`.
+    Debug(String),
+    /// The script exited. This is always the last event.
+    Exited(ExitStatus),
+}
+
+/// When an [`OutputEvent`] happened, taken as soon as its line was read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp {
+    /// For measuring the time between events, unaffected by changes to the
+    /// system clock.
+    pub instant: Instant,
+    /// For aligning the output with other logs.
+    pub wall: SystemTime,
+}
+
+impl Timestamp {
+    /// Returns the current time.
+    pub fn now() -> Self {
+        Timestamp {
+            instant: Instant::now(),
+            wall: SystemTime::now(),
+        }
+    }
+}
+
+/// An [`OutputEvent`] along with when it happened. Delivered by channels of
+/// `TimedEvent`s passed to `PsScript::run_with_events`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimedEvent {
+    pub event: OutputEvent,
+    pub at: Timestamp,
+}
+
+/// The parameters of a call to `Write-Progress`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ProgressRecord {
+    pub activity: String,
+    pub status: String,
+    pub id: i32,
+    pub parent_id: Option<i32>,
+    /// Between 0 and 100, if the script reported it.
+    pub percent_complete: Option<i32>,
+    pub seconds_remaining: Option<i32>,
+    pub current_operation: Option<String>,
+    /// Set when the script signals the activity is done.
+    pub completed: bool,
+}
+
+impl FromPsValue for ProgressRecord {
+    fn from_ps_value(value: PsValue) -> Result<Self> {
+        let int = |key: &str| {
+            value
+                .get(key)
+                .cloned()
+                .map(i32::from_ps_value)
+                .transpose()
+                .map(|v| v.filter(|v| *v >= 0))
+        };
+        let string = |key: &str| {
+            value
+                .get(key)
+                .and_then(PsValue::as_str)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+        };
+        Ok(ProgressRecord {
+            activity: string("Activity").unwrap_or_default(),
+            status: string("Status").unwrap_or_default(),
+            id: int("Id")?.unwrap_or(0),
+            parent_id: int("ParentId")?,
+            percent_complete: int("PercentComplete")?,
+            seconds_remaining: int("SecondsRemaining")?,
+            current_operation: string("CurrentOperation"),
+            completed: value.get("Completed") == Some(&PsValue::Bool(true)),
+        })
+    }
+}
+
+/// The callback of `Progress::Capture`.
+pub(crate) type ProgressCallback = Arc<dyn Fn(&ProgressRecord) + Send + Sync>;
+
+/// How calls to `Write-Progress` are handled when a script is run, set with
+/// `PsScriptBuilder::progress`.
+///
+/// PowerShell renders progress through its host, and depending on the
+/// version and platform it's either dropped, drawn with escape sequences or
+/// serialized into the captured output when that isn't a console. None of
+/// which is useful for a script whose output is parsed, but since it's what
+/// PowerShell does it's left to it by default.
+#[derive(Clone, Default)]
+pub enum Progress {
+    /// Sets `$ProgressPreference` to `SilentlyContinue` so no progress is
+    /// written.
+    Suppress,
+    /// Passes the script's `Write-Progress` calls to the callback as they
+    /// happen, keeping them out of the output. Progress reported by cmdlets
+    /// themselves, like `Invoke-WebRequest`, is suppressed.
+    ///
+    /// When a timeline is recorded the records are delivered once the script
+    /// has finished instead.
+    Capture(ProgressCallback),
+    /// Leaves progress to PowerShell, like running the script in a terminal.
+    /// This is the default.
+    #[default]
+    Passthrough,
+}
+
+impl Progress {
+    /// Captures progress, passing each record to `callback`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::{Progress, PsScriptBuilder};
+    ///
+    /// let ps = PsScriptBuilder::new()
+    ///     .progress(Progress::capture(|record| {
+    ///         println!("{}: {:?}%", record.activity, record.percent_complete)
+    ///     }))
+    ///     .build();
+    /// let output = ps.run("1..3 | % { Write-Progress -Activity Copy -PercentComplete ($_ * 33); $_ }").unwrap();
+    /// assert_eq!(output.stdout().unwrap().split_whitespace().count(), 3);
+    /// ```
+    pub fn capture<F>(callback: F) -> Self
+    where
+        F: Fn(&ProgressRecord) + Send + Sync + 'static,
+    {
+        Progress::Capture(Arc::new(callback))
+    }
+
+    /// Returns the commands setting up progress handling before a script
+    /// runs.
+    pub(crate) fn prelude(&self) -> Vec<String> {
+        let suppress = "$ProgressPreference = 'SilentlyContinue'".to_string();
+        match self {
+            Progress::Suppress => vec![suppress],
+            Progress::Capture(_) => vec![suppress, PROGRESS_FUNCTION.to_string()],
+            Progress::Passthrough => Vec::new(),
+        }
+    }
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Progress::Suppress => f.write_str("Suppress"),
+            Progress::Capture(_) => f.write_str("Capture(..)"),
+            Progress::Passthrough => f.write_str("Passthrough"),
+        }
+    }
+}
+
+/// Where [`OutputEvent`]s are delivered, see `PsScript::run_with_events`.
+/// Implemented for the sending half of `std::sync::mpsc` channels; a
+/// bounded `SyncSender` blocks the reader when the consumer falls behind.
+/// Channels of [`TimedEvent`]s receive the time of each event as well.
+pub trait EventSink: Clone + Send + 'static {
+    /// Delivers an event. Returns `false` if the consumer is gone, in which
+    /// case the remaining output is discarded.
+    fn send(&self, event: OutputEvent) -> bool;
+
+    /// Delivers an event which happened `at`. The default implementation
+    /// drops the timestamp and calls `send`.
+    fn send_at(&self, event: OutputEvent, at: Timestamp) -> bool {
+        let _ = at;
+        self.send(event)
+    }
+}
+
+impl EventSink for mpsc::Sender<OutputEvent> {
+    fn send(&self, event: OutputEvent) -> bool {
+        mpsc::Sender::send(self, event).is_ok()
+    }
+}
+
+impl EventSink for mpsc::SyncSender<OutputEvent> {
+    fn send(&self, event: OutputEvent) -> bool {
+        mpsc::SyncSender::send(self, event).is_ok()
+    }
+}
+
+impl EventSink for mpsc::Sender<TimedEvent> {
+    fn send(&self, event: OutputEvent) -> bool {
+        self.send_at(event, Timestamp::now())
+    }
+
+    fn send_at(&self, event: OutputEvent, at: Timestamp) -> bool {
+        mpsc::Sender::send(self, TimedEvent { event, at }).is_ok()
+    }
+}
+
+impl EventSink for mpsc::SyncSender<TimedEvent> {
+    fn send(&self, event: OutputEvent) -> bool {
+        self.send_at(event, Timestamp::now())
+    }
+
+    fn send_at(&self, event: OutputEvent, at: Timestamp) -> bool {
+        mpsc::SyncSender::send(self, TimedEvent { event, at }).is_ok()
+    }
+}
+
+/// Returns the lines to send to PowerShell to run `script` with its output
+/// translated to events.
+pub(crate) fn program(script: &str) -> Vec<String> {
+    let invocation = format!(
+        "{} 3>&1 4>&1 5>&1 | ForEach-Object {{ $__ps_marker = if ($_ -is [System.Management.Automation.VerboseRecord]) {{ '{}' }} elseif ($_ -is [System.Management.Automation.WarningRecord]) {{ '{}' }} elseif ($_ -is [System.Management.Automation.DebugRecord]) {{ '{}' }}; if ($__ps_marker) {{ foreach ($__ps_line in ($_.Message -split \"`r?`n\")) {{ [Console]::Out.WriteLine($__ps_marker + $__ps_line) }} }} else {{ $_ }} }} | Out-Default",
+        wrap::call_operator(script, std::iter::empty::<&str>()),
+        VERBOSE_MARKER,
+        WARNING_MARKER,
+        DEBUG_MARKER
+    );
+    vec![
+        PROGRESS_FUNCTION.to_string(),
+        "$__ps_ok = $true".to_string(),
+        format!(
+            "try {{ {}; $__ps_ok = $? }} catch {{ $__ps_ok = $false; Write-Error -ErrorRecord $_ }}",
+            invocation
+        ),
+        "if (-not $__ps_ok) { exit 1 }".to_string(),
+    ]
+}
+
+/// Reads the output of `child` line by line and delivers it to `sink` until
+/// the child exits.
+pub(crate) fn forward<S: EventSink>(
+    mut child: Child,
+    mut tracked: Option<Tracked>,
+    ctx: ExecutionContext,
+    sink: S,
+) -> io::Result<ExitStatus> {
+    let connected = Arc::new(AtomicBool::new(sink.send(OutputEvent::Started(ctx))));
+    let stdout = child
+        .stdout
+        .take()
+        .map(|pipe| reader(pipe, sink.clone(), connected.clone(), stdout_event));
+    let stderr = child
+        .stderr
+        .take()
+        .map(|pipe| reader(pipe, sink.clone(), connected.clone(), OutputEvent::Stderr));
+
+    let (status, _) = tracker::wait(&mut child, tracked.as_mut())?;
+    child::join(stdout)?;
+    child::join(stderr)?;
+    if connected.load(Ordering::Relaxed) {
+        sink.send(OutputEvent::Exited(status));
+    }
+    Ok(status)
+}
+
+/// Reads `pipe` to the end, passing the records written by our
+/// `Write-Progress` replacement to `callback` as they arrive. Returns the
+/// rest of the output.
+pub(crate) fn filter_progress(pipe: impl Read, callback: &ProgressCallback) -> io::Result<Vec<u8>> {
+    let mut pipe = BufReader::new(pipe);
+    let mut out = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if pipe.read_until(b'\n', &mut line)? == 0 {
+            return Ok(out);
+        }
+        let record = std::str::from_utf8(&line)
+            .ok()
+            .and_then(|text| progress_record(text.trim_end_matches(['\r', '\n'])));
+        match record {
+            Some(record) => callback(&record),
+            None => out.extend_from_slice(&line),
+        }
+    }
+}
+
+pub(crate) fn progress_record(line: &str) -> Option<ProgressRecord> {
+    line.strip_prefix(PROGRESS_MARKER)
+        .and_then(|json| PsValue::from_json(json).ok())
+        .and_then(|value| ProgressRecord::from_ps_value(value).ok())
+}
+
+fn stdout_event(line: String) -> OutputEvent {
+    if let Some(message) = line.strip_prefix(VERBOSE_MARKER) {
+        return OutputEvent::Verbose(message.to_string());
+    }
+    if let Some(message) = line.strip_prefix(WARNING_MARKER) {
+        return OutputEvent::Warning(message.to_string());
+    }
+    if let Some(message) = line.strip_prefix(DEBUG_MARKER) {
+        return OutputEvent::Debug(message.to_string());
+    }
+    match progress_record(&line) {
+        Some(record) => OutputEvent::Progress(record),
+        None => OutputEvent::Stdout(line),
+    }
+}
+
+fn reader<S: EventSink>(
+    pipe: impl Read + Send + 'static,
+    sink: S,
+    connected: Arc<AtomicBool>,
+    to_event: fn(String) -> OutputEvent,
+) -> thread::JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut pipe = BufReader::new(pipe);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if pipe.read_until(b'\n', &mut line)? == 0 {
+                return Ok(Vec::new());
+            }
+            let at = Timestamp::now();
+            // Keep draining the pipe when the consumer is gone so the script
+            // doesn't block writing to it
+            if !connected.load(Ordering::Relaxed) {
+                continue;
+            }
+            let text = String::from_utf8_lossy(&line);
+            let text = text.trim_end_matches(['\r', '\n']).to_string();
+            if !sink.send_at(to_event(text), at) {
+                connected.store(false, Ordering::Relaxed);
+            }
+        }
+    })
+}
@@ -0,0 +1,71 @@
+//! Running scripts from async code on a thread of their own, see
+//! `PsScript::run_async`.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+use crate::{output::Output, PsScript, Result};
+
+#[derive(Debug, Default)]
+struct Slot {
+    result: Option<Result<Output>>,
+    waker: Option<Waker>,
+}
+
+/// The future returned by [`PsScript::run_async`], resolving to what `run`
+/// would have returned.
+///
+/// This isn't integrated with any async runtime, since the crate only
+/// depends on `std`. Instead every call starts a new OS thread which runs
+/// the script with the blocking `run` and wakes the future when it's done,
+/// so it works with any runtime, but costs a thread per running script
+/// like the runtime's `spawn_blocking` would. The script isn't stopped if
+/// the future is dropped before it completes.
+#[derive(Debug)]
+pub struct ThreadedRunFuture {
+    slot: Arc<Mutex<Slot>>,
+}
+
+impl ThreadedRunFuture {
+    /// Starts running `script` with `ps` on a new thread.
+    pub(crate) fn start(ps: PsScript, script: &str) -> ThreadedRunFuture {
+        let slot = Arc::new(Mutex::new(Slot::default()));
+        let script = script.to_string();
+        {
+            let slot = slot.clone();
+            thread::spawn(move || {
+                let result = ps.run(&script);
+                let mut slot = lock(&slot);
+                slot.result = Some(result);
+                if let Some(waker) = slot.waker.take() {
+                    waker.wake();
+                }
+            });
+        }
+        ThreadedRunFuture { slot }
+    }
+}
+
+impl Future for ThreadedRunFuture {
+    type Output = Result<Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = lock(&self.slot);
+        match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+fn lock(slot: &Mutex<Slot>) -> MutexGuard<'_, Slot> {
+    slot.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,83 @@
+//! Detecting scripts which have stopped making progress, see
+//! `PsScriptBuilder::stall_timeout`.
+//!
+//! The prelude starts a timer which sends a heartbeat over the side channel.
+//! Its handler only runs between the commands of the script, so the
+//! heartbeats stop while the script is stuck in a call which doesn't return,
+//! like a network request without a timeout.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::side_channel::{HostMessage, MessageHandler};
+
+/// The kind of the messages the heartbeat is sent as. They're not passed on
+/// to the handler registered with `on_message`.
+const KIND: &str = "__ps_heartbeat";
+/// The shortest interval heartbeats are sent at.
+const MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The callback notified of stalls instead of killing the script.
+pub(crate) type StallCallback = Arc<dyn Fn(Duration) + Send + Sync>;
+
+/// When the script last showed signs of life on the side channel.
+#[derive(Debug)]
+pub(crate) struct Heartbeat {
+    last: Mutex<Instant>,
+}
+
+impl Heartbeat {
+    pub(crate) fn new() -> Heartbeat {
+        Heartbeat {
+            last: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Returns the time since the last heartbeat or message.
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.last
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .elapsed()
+    }
+
+    fn beat(&self) {
+        *self.last.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+    }
+
+    /// Returns a message handler recording heartbeats and passing the other
+    /// messages on to `handler`.
+    pub(crate) fn handler(self: &Arc<Self>, handler: Option<MessageHandler>) -> MessageHandler {
+        let heartbeat = self.clone();
+        Arc::new(move |message: &HostMessage| {
+            heartbeat.beat();
+            if message.kind != KIND {
+                if let Some(handler) = &handler {
+                    handler(message);
+                }
+            }
+        })
+    }
+}
+
+/// Returns a line starting a timer which sends heartbeats often enough to
+/// arrive several times within `window`. `Send-HostMessage` has to be
+/// defined before.
+pub(crate) fn timer(window: Duration) -> String {
+    let interval = (window / 4).max(MIN_INTERVAL);
+    format!(
+        "$__ps_heartbeat = New-Object System.Timers.Timer({}); $null = Register-ObjectEvent -InputObject $__ps_heartbeat -EventName Elapsed -Action {{ Send-HostMessage '{}' }}; $__ps_heartbeat.Start()",
+        interval.as_millis(),
+        KIND
+    )
+}
+
+/// How `prompt::wait` watches for stalls.
+pub(crate) struct Stall {
+    pub(crate) window: Duration,
+    pub(crate) heartbeat: Arc<Heartbeat>,
+    /// Notified instead of killing the script, if set.
+    pub(crate) callback: Option<StallCallback>,
+}
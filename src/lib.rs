@@ -54,19 +54,32 @@
 //! ```
 //! use powershell_script::PsScriptBuilder;
 //!
-//! fn main() {
-//!     let ps = PsScriptBuilder::new()
-//!         .no_profile(true)
-//!         .non_interactive(true)
-//!         .hidden(false)
-//!         .print_commands(false)
-//!         .build();
-//!     let output = ps.run(r#"echo "hello world""#).unwrap();
-//!
-//!     assert!(output.stdout().unwrap().contains("hello world"));
-//! }
+//! let ps = PsScriptBuilder::new()
+//!     .no_profile(true)
+//!     .non_interactive(true)
+//!     .hidden(false)
+//!     .print_commands(false)
+//!     .build();
+//! let output = ps.run(r#"echo "hello world""#).unwrap();
+//!
+//! assert!(output.stdout().unwrap().contains("hello world"));
 //! ```
 //!
+//! Use `powershell_script::set_default` to have the free functions like
+//! `powershell_script::run()` use such a configuration throughout your
+//! application.
+//!
+//! ## Execution modes
+//!
+//! By default the script is piped to PowerShell line by line. Setting
+//! `ExecutionMode::CallOperator` wraps the script in `& { <script> }` instead,
+//! which lets you use `param()` blocks and `return` in inline scripts. Use
+//! `PsScript::run_with_args` to pass arguments to such a script.
+//! `ExecutionMode::Raw` pipes the script exactly as it was passed, for
+//! scripts whose line endings or here-string content must be kept intact.
+//! `ExecutionMode::Encoded` passes the script as `-EncodedCommand` instead of
+//! piping it, so it's parsed as a whole like a script file.
+//!
 //! ## Features and compatability
 //!
 //! On Windows it defaults to using the PowerShell which ships with Windows, but you
@@ -75,11 +88,67 @@
 //!
 //! On all other operating systems it will run scripts using PowerShell core.
 //!
+//! If `pwsh` isn't found on `PATH` we also look in the default install locations
+//! for snap (`/snap/bin/pwsh`), `dotnet tool` (`~/.dotnet/tools/pwsh`) and the
+//! Microsoft packages. Use `PsScriptBuilder::probe_path` to add your own.
+//!
+//! The `macros` feature adds `include_ps!`, which embeds a script file like
+//! `include_str!` while normalizing line endings and removing any byte order mark.
+//! Add `check` to have PowerShell parse the script at build time, which fails
+//! compilation on syntax errors when PowerShell is installed:
+//! `include_ps!("deploy.ps1", check)`.
+//! It also adds `ps!` for inline scripts, which checks that quotes and brackets
+//! are balanced at compile time and evaluates to a `Script`.
+//!
 
+pub mod analyzer;
+mod ansi;
+mod base64;
 mod builder;
+mod cache;
+mod callback;
+mod cancel;
+mod change;
+mod child;
+pub mod compat;
+mod context;
+mod credential;
+mod env;
+mod envelope;
+pub mod environment;
 mod error;
+mod error_record;
+mod events;
+mod future;
+mod heartbeat;
+mod metrics;
+mod middleware;
 mod output;
+pub mod paths;
+pub mod pester;
+mod pool;
+mod prompt;
+mod quota;
+mod registry;
+mod requires;
+mod script;
+mod session;
+mod share;
+mod shutdown;
+mod side_channel;
+mod source;
 mod target;
+mod template;
+mod text;
+mod timeline;
+mod tracker;
+pub mod transfer;
+mod types;
+mod usage;
+mod value;
+mod workflow;
+mod workspace;
+mod wrap;
 
 // Note: PowerShell Core can be isntalled on windows as well so we can't simply
 // discriminate based on target family.
@@ -88,19 +157,62 @@ mod target;
 /// Windows PowerShell
 const POWERSHELL_NAME: &str = "PowerShell.exe";
 
-#[cfg(any(feature = "core", not(windows)))]
+#[cfg(all(feature = "core", windows))]
 /// PowerShell Core
 const POWERSHELL_NAME: &str = "pwsh.exe";
 
+#[cfg(not(windows))]
+/// PowerShell Core
+const POWERSHELL_NAME: &str = "pwsh";
+
 type Result<T> = std::result::Result<T, PsError>;
 
-#[cfg(target_family = "unix")]
-pub use target::unix::PsScript;
+pub use {
+    ansi::{parse_ansi, Color, Style, StyledSpan},
+    builder::{ConfirmPolicy, ExecutionMode, ExecutionPolicy, PsScriptBuilder, StdinEncoding},
+    cache::{CacheOutcome, RunCache},
+    change::Change,
+    child::{PsChild, PsHandle},
+    context::{Edition, ExecutionContext},
+    credential::{Credential, CredentialRequest},
+    env::EnvDelta,
+    envelope::{PsResult, ScriptFailure},
+    error::{BuildError, PsError},
+    error_record::{ErrorOrigin, ErrorRecord},
+    events::{EventSink, OutputEvent, Progress, ProgressRecord, TimedEvent, Timestamp},
+    future::ThreadedRunFuture,
+    metrics::{InMemoryMetrics, Metrics, MetricsSnapshot, DEFAULT_BUCKETS},
+    middleware::{Middleware, RunContext},
+    output::{
+        clixml::{parse_clixml, ClixmlRecord},
+        stderr::{classify_stderr, StderrMessage, StderrSeverity},
+        Output, RESERVED_BLOCK_TAGS,
+    },
+    pool::{JobOptions, PsPool},
+    quota::{ExecutionQuota, QuotaPolicy},
+    registry::{ParamSchema, ParamSpec, ParamType, ScriptRegistry},
+    requires::ModuleSpec,
+    script::PsScript,
+    session::{PsSession, RecyclePolicy},
+    share::NetworkShare,
+    shutdown::ShutdownGuard,
+    side_channel::HostMessage,
+    source::{PsVersion, Script},
+    template::ScriptTemplate,
+    text::{parse_list, parse_table, split_records},
+    timeline::{Timeline, TimelineEntry},
+    tracker::ChildTracker,
+    types::{Bytes, Guid},
+    usage::ResourceUsage,
+    value::{FromPsValue, PsValue},
+    workflow::{FailurePolicy, PsWorkflow, StepResult, StepStatus, WorkflowSummary},
+};
 
-#[cfg(target_family = "windows")]
-pub use target::windows::PsScript;
+#[cfg(feature = "macros")]
+pub use powershell_script_macros::{include_ps, ps, FromPs};
 
-pub use {builder::PsScriptBuilder, error::PsError, output::Output};
+#[doc(hidden)]
+pub use value::__field;
 
 /// Runs a script in PowerShell. Returns an instance of `Output`. In the case of
 /// a failure when running the script it returns an `PsError::Powershell(Output)`
@@ -115,13 +227,67 @@ pub use {builder::PsScriptBuilder, error::PsError, output::Output};
 /// ## Example
 ///
 /// ```rust
-/// fn main() {
-///     let script = r#"echo "hello world""#;
-///     let output = powershell_script::run(script).unwrap();
-///     assert_eq!(output.stdout().unwrap().trim(), "hello world");
-/// }
+/// let script = r#"echo "hello world""#;
+/// let output = powershell_script::run(script).unwrap();
+/// assert_eq!(output.stdout().unwrap().trim(), "hello world");
 /// ```
 ///
 pub fn run(script: &str) -> Result<Output> {
-    PsScriptBuilder::default().build().run(script)
+    with_default(|ps| ps.run(script))
+}
+
+/// Runs a script in PowerShell like [`run`] on a new OS thread, without
+/// blocking the thread the returned future is awaited on. See
+/// `PsScript::run_async`.
+pub fn run_async(script: &str) -> ThreadedRunFuture {
+    with_default(|ps| ps.run_async(script))
+}
+
+/// Runs the script file at `path` in PowerShell, like `PsScript::run_file`,
+/// using the same configuration as [`run`].
+pub fn run_file<P: AsRef<std::path::Path>>(path: P) -> Result<Output> {
+    with_default(|ps| ps.run_file(path))
+}
+
+/// Reads the script file at `path` and runs it, like
+/// `PsScript::run_from_path`, using the same configuration as [`run`].
+///
+/// ## Example
+///
+/// ```rust, no_run
+/// let output = powershell_script::run_from_path("./script.ps1").unwrap();
+/// println!("{}", output);
+/// ```
+pub fn run_from_path<P: AsRef<std::path::Path>>(path: P) -> Result<Output> {
+    with_default(|ps| ps.run_from_path(path))
+}
+
+/// Sets the `PsScript` the free functions [`run`], [`run_file`] and
+/// [`run_from_path`] use, so an application calling them from many places
+/// can configure them once. Without it they use `PsScriptBuilder::default()`.
+///
+/// The default can only be set once. Returns `false` if it was already set,
+/// in which case `ps` is dropped.
+///
+/// ## Example
+///
+/// ```rust
+/// use powershell_script::PsScriptBuilder;
+///
+/// let ps = PsScriptBuilder::new().capture_errors(true).build();
+/// assert!(powershell_script::set_default(ps));
+/// let ps = PsScriptBuilder::new().build();
+/// assert!(!powershell_script::set_default(ps));
+/// ```
+pub fn set_default(ps: PsScript) -> bool {
+    DEFAULT.set(ps).is_ok()
+}
+
+static DEFAULT: std::sync::OnceLock<PsScript> = std::sync::OnceLock::new();
+
+fn with_default<T>(f: impl FnOnce(&PsScript) -> T) -> T {
+    match DEFAULT.get() {
+        Some(ps) => f(ps),
+        None => f(&PsScriptBuilder::default().build()),
+    }
 }
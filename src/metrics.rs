@@ -0,0 +1,141 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    middleware::{Middleware, RunContext},
+    output::Output,
+};
+
+/// Upper bounds of the buckets used by [`InMemoryMetrics`], chosen to match
+/// the default buckets of the Prometheus client libraries.
+pub const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Receives a measurement for every script run by a `PsScript`. Register an
+/// implementation with `PsScriptBuilder::metrics` to forward the numbers to
+/// your metrics system of choice.
+///
+/// Only runs where PowerShell was started are recorded. Scripts run in a
+/// `PsSession` or `PsPool` don't run the middleware, so they aren't
+/// recorded, but a pool reports how long its scripts waited for a worker.
+pub trait Metrics: Send + Sync {
+    /// Called once for every finished run.
+    fn record(&self, duration: Duration, success: bool);
+
+    /// Called once for every script run on a `PsPool`, with how long it
+    /// waited for a worker, including scripts which missed their deadline
+    /// while waiting. The default implementation ignores it.
+    fn record_queue_wait(&self, waited: Duration) {
+        let _ = waited;
+    }
+}
+
+/// A point in time copy of the numbers collected by [`InMemoryMetrics`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    /// Number of finished runs.
+    pub invocations: u64,
+    /// Number of runs which failed.
+    pub failures: u64,
+    /// Sum of the duration of all runs, in seconds.
+    pub duration_sum: f64,
+    /// Cumulative histogram of run durations as `(upper bound in seconds,
+    /// count)` pairs, in the same format as a Prometheus histogram. Runs
+    /// slower than the last bound are only counted in `invocations`.
+    pub buckets: Vec<(f64, u64)>,
+    /// Number of scripts which waited for a `PsPool` worker.
+    pub queued: u64,
+    /// Sum of the time scripts waited for a `PsPool` worker, in seconds.
+    pub queue_wait_sum: f64,
+}
+
+/// A [`Metrics`] implementation which keeps counters and a duration
+/// histogram in memory.
+///
+/// ## Example
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use powershell_script::{InMemoryMetrics, PsScriptBuilder};
+///
+/// let metrics = Arc::new(InMemoryMetrics::default());
+/// let ps = PsScriptBuilder::new().metrics(metrics.clone()).build();
+/// // ... run some scripts with `ps`
+/// let snapshot = metrics.snapshot();
+/// println!("{} runs, {} failed", snapshot.invocations, snapshot.failures);
+/// ```
+#[derive(Debug)]
+pub struct InMemoryMetrics {
+    inner: Mutex<MetricsSnapshot>,
+}
+
+impl InMemoryMetrics {
+    /// Creates a collector with custom bucket bounds, in seconds. The bounds
+    /// are sorted for you.
+    pub fn with_buckets(bounds: &[f64]) -> Self {
+        let mut bounds = bounds.to_vec();
+        bounds.sort_by(|a, b| a.total_cmp(b));
+        InMemoryMetrics {
+            inner: Mutex::new(MetricsSnapshot {
+                buckets: bounds.into_iter().map(|bound| (bound, 0)).collect(),
+                ..MetricsSnapshot::default()
+            }),
+        }
+    }
+
+    /// Returns a copy of the numbers collected so far.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+impl Default for InMemoryMetrics {
+    fn default() -> Self {
+        Self::with_buckets(DEFAULT_BUCKETS)
+    }
+}
+
+impl Metrics for InMemoryMetrics {
+    fn record(&self, duration: Duration, success: bool) {
+        let secs = duration.as_secs_f64();
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.invocations += 1;
+        if !success {
+            inner.failures += 1;
+        }
+        inner.duration_sum += secs;
+        for (bound, count) in inner.buckets.iter_mut() {
+            if secs <= *bound {
+                *count += 1;
+            }
+        }
+    }
+
+    fn record_queue_wait(&self, waited: Duration) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.queued += 1;
+        inner.queue_wait_sum += waited.as_secs_f64();
+    }
+}
+
+impl<M: Metrics + ?Sized> Metrics for Arc<M> {
+    fn record(&self, duration: Duration, success: bool) {
+        (**self).record(duration, success)
+    }
+
+    fn record_queue_wait(&self, waited: Duration) {
+        (**self).record_queue_wait(waited)
+    }
+}
+
+/// Adapts a [`Metrics`] implementation to the middleware hooks.
+pub(crate) struct MetricsMiddleware<M>(pub(crate) M);
+
+impl<M: Metrics> Middleware for MetricsMiddleware<M> {
+    fn after(&self, ctx: &RunContext, output: &Output) {
+        self.0.record(ctx.elapsed(), output.success());
+    }
+}
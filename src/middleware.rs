@@ -0,0 +1,132 @@
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    callback::CallbackBridge, cancel::CancelSentinel, context::ExecutionContext,
+    credential::CredentialBridge, heartbeat::Heartbeat, output::Output, quota::QuotaPermit,
+    side_channel::SideChannel, workspace::Workspace, Result,
+};
+
+/// Information about a script about to run (or which has just finished),
+/// passed to [`Middleware`] hooks.
+#[derive(Debug, Clone)]
+pub struct RunContext {
+    script: String,
+    prelude: Vec<String>,
+    started: Instant,
+    execution: ExecutionContext,
+    /// Kept here so it answers requests for as long as the run lasts.
+    pub(crate) credentials: Option<Arc<CredentialBridge>>,
+    /// Kept here so it answers callbacks for as long as the run lasts.
+    pub(crate) callbacks: Option<Arc<CallbackBridge>>,
+    /// Dropped once the run is over, after the messages have been handled.
+    pub(crate) side_channel: Option<Arc<SideChannel>>,
+    /// When the script last sent a heartbeat, if `stall_timeout` is set.
+    pub(crate) heartbeat: Option<Arc<Heartbeat>>,
+    /// Set by `PsChild::cancel`, removed once the run is over.
+    pub(crate) cancel: Option<Arc<CancelSentinel>>,
+    /// Removed once the run is over and the context is dropped.
+    pub(crate) workspace: Option<Arc<Workspace>>,
+    /// The room the run takes up in the `ExecutionQuota`, freed once the
+    /// context is dropped.
+    pub(crate) permit: Option<Arc<QuotaPermit>>,
+    /// The meanings of exit codes registered on the builder.
+    pub(crate) exit_codes: Arc<[(i32, String)]>,
+}
+
+impl RunContext {
+    pub(crate) fn new(script: &str, args: Vec<String>) -> Self {
+        RunContext {
+            script: script.to_string(),
+            prelude: Vec::new(),
+            started: Instant::now(),
+            execution: ExecutionContext::new(script, args),
+            credentials: None,
+            callbacks: None,
+            side_channel: None,
+            heartbeat: None,
+            cancel: None,
+            workspace: None,
+            permit: None,
+            exit_codes: Arc::new([]),
+        }
+    }
+
+    /// The script as it was passed to the run method, before any wrapping.
+    pub fn script(&self) -> &str {
+        &self.script
+    }
+
+    /// Identifies the run, for correlating it with its output and errors.
+    pub fn execution(&self) -> &ExecutionContext {
+        &self.execution
+    }
+
+    /// The temporary directory created for the run, if the `PsScript` was
+    /// built with `temp_workspace` set.
+    pub fn workspace(&self) -> Option<&Path> {
+        self.workspace.as_deref().map(Workspace::path)
+    }
+
+    /// Commands which run before the script. Each entry is sent as a separate
+    /// line so it should be a complete statement.
+    pub fn prelude(&self) -> &[String] {
+        &self.prelude
+    }
+
+    /// Time since the run was started. In `after` this is the duration of
+    /// the run.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Adds a command which runs before the script.
+    pub fn add_prelude(&mut self, line: impl Into<String>) {
+        self.prelude.push(line.into());
+    }
+
+    /// Returns `lines` with the prelude in front.
+    pub(crate) fn apply_prelude(&self, lines: Vec<String>) -> Vec<String> {
+        if self.prelude.is_empty() {
+            return lines;
+        }
+        self.prelude.iter().cloned().chain(lines).collect()
+    }
+}
+
+/// Hooks which run around every script executed by a `PsScript`, for
+/// cross-cutting concerns like auditing, metrics or policy enforcement.
+/// Register them with `PsScriptBuilder::middleware`; they run in the order
+/// they're registered.
+///
+/// ## Example
+///
+/// ```rust
+/// use powershell_script::{Middleware, PsError, RunContext};
+///
+/// struct DenyRemoting;
+///
+/// impl Middleware for DenyRemoting {
+///     fn before(&self, ctx: &mut RunContext) -> Result<(), PsError> {
+///         if ctx.script().contains("Invoke-Command") {
+///             return Err(PsError::Rejected("remoting is not allowed".into()));
+///         }
+///         ctx.add_prelude("$ErrorActionPreference = 'Stop'");
+///         Ok(())
+///     }
+/// }
+/// ```
+pub trait Middleware: Send + Sync {
+    /// Called before PowerShell is started. Returning an error aborts the
+    /// run and the error is returned to the caller.
+    fn before(&self, _ctx: &mut RunContext) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when the script has finished, whether it succeeded or not.
+    /// Not called if PowerShell couldn't be started.
+    fn after(&self, _ctx: &RunContext, _output: &Output) {}
+}
@@ -1,4 +1,49 @@
-use std::{process, fmt};
+pub mod clixml;
+pub mod stderr;
+
+use std::{fmt, path::PathBuf, process};
+
+use crate::{
+    ansi, base64,
+    change::{self, Change},
+    context::ExecutionContext,
+    env::EnvDelta,
+    envelope::{self, PsResult},
+    error::PsError,
+    error_record::ErrorRecord,
+    target, text,
+    timeline::Timeline,
+    usage::ResourceUsage,
+    value::{FromPsValue, PsValue},
+    Result,
+};
+
+/// Marks the start of a block of data the crate's wrapper code writes to
+/// `stdout`, followed by the block's tag.
+pub(crate) const BLOCK_BEGIN: &str = "##ps-block-begin:";
+/// Marks the end of a block of data, followed by the block's tag.
+pub(crate) const BLOCK_END: &str = "##ps-block-end:";
+
+/// The block tags the crate's wrapper code uses, which scripts shouldn't use
+/// for their own blocks, see `Output::decode_base64_block`.
+pub const RESERVED_BLOCK_TAGS: &[&str] = &[
+    "analyzer",
+    "change",
+    "env",
+    "envelope",
+    "errors",
+    "failed-line",
+    "host",
+    "json",
+    "location",
+    "missing-modules",
+    "parallel",
+    "params",
+    "pester",
+    "policy",
+    "return",
+    "streams",
+];
 
 /// A convenient wrapper around `process::Output` which indicates if the
 /// script ran successfully or not and gives easy access to both the utf-8
@@ -7,6 +52,40 @@ use std::{process, fmt};
 pub struct Output {
     inner: process::Output,
     pub(crate) success: bool,
+    blocks: Vec<(String, String)>,
+    // The optional parts are boxed to keep `PsError` small
+    pub(crate) timeline: Option<Box<Timeline>>,
+    /// `stdout` and `stderr` before escape sequences were stripped, if
+    /// there were any.
+    styled: Option<Box<(Vec<u8>, Vec<u8>)>>,
+    pub(crate) run: Option<Box<RunInfo>>,
+    pub(crate) usage: Option<Box<ResourceUsage>>,
+}
+
+/// How the output of a finished run is cleaned up before it's returned.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Cleanup {
+    pub(crate) strip_ansi: bool,
+    pub(crate) normalize_newlines: bool,
+}
+
+impl Cleanup {
+    pub(crate) fn apply(self, output: &mut Output) {
+        if self.strip_ansi {
+            output.strip_ansi();
+        }
+        if self.normalize_newlines {
+            output.normalize_newlines();
+        }
+    }
+}
+
+/// What is known about the run which produced an `Output`.
+#[derive(Debug, Clone)]
+pub(crate) struct RunInfo {
+    pub(crate) context: ExecutionContext,
+    /// The meaning registered for the exit code.
+    pub(crate) exit_meaning: Option<String>,
 }
 
 impl Output {
@@ -28,7 +107,74 @@ impl Output {
         }
     }
 
-    /// Returns the raw `process::Output` type
+    /// Returns `stdout` including the ANSI escape sequences PowerShell uses
+    /// to style its output, which are removed from `stdout()` when
+    /// `strip_ansi` is turned on on the builder.
+    pub fn styled_stdout(&self) -> &[u8] {
+        match self.styled.as_deref() {
+            Some((stdout, _)) => stdout,
+            None => &self.inner.stdout,
+        }
+    }
+
+    /// Returns `stderr` including ANSI escape sequences, see `styled_stdout`.
+    pub fn styled_stderr(&self) -> &[u8] {
+        match self.styled.as_deref() {
+            Some((_, stderr)) => stderr,
+            None => &self.inner.stderr,
+        }
+    }
+
+    /// Returns `stdout` split into spans of text with the style PowerShell
+    /// wrote them in, see [`parse_ansi`](crate::parse_ansi).
+    pub fn stdout_spans(&self) -> Vec<ansi::StyledSpan> {
+        ansi::parse_ansi(self.styled_stdout())
+    }
+
+    /// Returns `stderr` split into styled spans, see `stdout_spans`.
+    pub fn stderr_spans(&self) -> Vec<ansi::StyledSpan> {
+        ansi::parse_ansi(self.styled_stderr())
+    }
+
+    /// Splits `stdout` into the records the script separated with NUL
+    /// characters, see [`split_records`](crate::split_records).
+    pub fn records(&self) -> Vec<String> {
+        self.records_by("\0")
+    }
+
+    /// Splits `stdout` into the records the script separated with
+    /// `separator`, see [`split_records`](crate::split_records).
+    pub fn records_by(&self, separator: &str) -> Vec<String> {
+        text::split_records(&self.stdout().unwrap_or_default(), separator)
+    }
+
+    /// Consumes the output and returns the lines of `stdout`, without their
+    /// line endings. The captured bytes are reused when they're valid UTF-8
+    /// rather than copied into a new string first.
+    pub fn into_lines(self) -> impl Iterator<Item = String> {
+        let text = String::from_utf8(self.inner.stdout)
+            .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned());
+        let mut pos = 0;
+        std::iter::from_fn(move || {
+            let rest = &text[pos..];
+            if rest.is_empty() {
+                return None;
+            }
+            let end = rest.find('\n').map(|i| i + 1).unwrap_or(rest.len());
+            pos += end;
+            let line = rest[..end].trim_end_matches('\n');
+            Some(line.strip_suffix('\r').unwrap_or(line).to_string())
+        })
+    }
+
+    /// Returns the `process::Output` the other methods read from.
+    ///
+    /// This isn't exactly what PowerShell wrote: the blocks the crate's
+    /// wrapper code writes to `stdout` (see [`RESERVED_BLOCK_TAGS`]) are
+    /// removed, and so are ANSI escape sequences if `strip_ansi` is on and
+    /// `\r\n` line endings if `deterministic_output` is on. Use
+    /// `styled_stdout` and `styled_stderr` for the output before the last
+    /// two.
     pub fn into_inner(self) -> process::Output {
         self.inner
     }
@@ -37,14 +183,472 @@ impl Output {
     pub fn success(&self) -> bool {
         self.success
     }
+
+    /// Returns the exit code of PowerShell, or `None` if it was terminated
+    /// by a signal. Scripts set it with `exit`, which is how they can report
+    /// outcomes other than success and failure.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::{PsError, PsScriptBuilder};
+    ///
+    /// let ps = PsScriptBuilder::new().build();
+    /// let output = match ps.run("./Install-Updates.ps1") {
+    ///     Ok(output) | Err(PsError::Powershell(output)) => output,
+    ///     Err(e) => panic!("{}", e),
+    /// };
+    /// match output.exit_code() {
+    ///     Some(0) => println!("up to date"),
+    ///     Some(2) => println!("some updates failed"),
+    ///     Some(3) => println!("reboot required"),
+    ///     other => println!("failed with {:?}", other),
+    /// }
+    /// ```
+    pub fn exit_code(&self) -> Option<i32> {
+        self.inner.status.code()
+    }
+
+    /// Alias for [`exit_code`](Self::exit_code), named after
+    /// `ExitStatus::code`.
+    pub fn code(&self) -> Option<i32> {
+        self.exit_code()
+    }
+
+    /// Returns the exit status of the PowerShell process, for what
+    /// `exit_code` doesn't tell, like the signal which terminated it on
+    /// Unix.
+    pub fn status(&self) -> &process::ExitStatus {
+        &self.inner.status
+    }
+
+    /// Returns the exit code for a program which wraps the script to exit
+    /// with, so it reports the same status as the script did.
+    ///
+    /// Exit codes only keep their lowest byte on Unix, which is what a code
+    /// the Windows `exit` produces is cut down to as well: `exit -3` (which
+    /// Windows reports as `0xFFFFFFFD`) becomes 253. Codes whose lowest
+    /// byte is zero, like 256, become 1 so they're never mistaken for
+    /// success. A process terminated by a signal exits with 128 plus the
+    /// signal's number, like shells report it.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use std::process::ExitCode;
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// fn main() -> ExitCode {
+    ///     let ps = PsScriptBuilder::new().build();
+    ///     match ps.run("./Deploy.ps1") {
+    ///         Ok(output) => {
+    ///             print!("{}", output);
+    ///             output.to_exit_code()
+    ///         }
+    ///         Err(e) => {
+    ///             eprintln!("{}", e);
+    ///             e.exit_code()
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn to_exit_code(&self) -> process::ExitCode {
+        let code = match self.inner.status.code() {
+            Some(0) if self.success => return process::ExitCode::SUCCESS,
+            // Keeps the lowest byte, also of negative codes
+            Some(code) => code as u8,
+            None => target::exit_signal(&self.inner.status)
+                .map_or(0, |signal| 128u8.wrapping_add(signal as u8)),
+        };
+        if code == 0 {
+            process::ExitCode::FAILURE
+        } else {
+            process::ExitCode::from(code)
+        }
+    }
+
+    /// Returns what the exit code means, if a meaning was registered for it
+    /// with `PsScriptBuilder::exit_code_meaning`.
+    pub fn exit_meaning(&self) -> Option<&str> {
+        self.run.as_ref()?.exit_meaning.as_deref()
+    }
+
+    /// Returns the memory, CPU time and I/O the PowerShell process used, see
+    /// [`ResourceUsage`]. Only known for runs which waited for the process
+    /// to exit themselves, not for runs in a `PsSession` or whose output was
+    /// streamed.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().build();
+    /// let output = ps.run("Get-ChildItem -Recurse C:\\logs | Measure-Object").unwrap();
+    /// if let Some(usage) = output.resource_usage() {
+    ///     println!("{} MB, {:?} CPU", usage.peak_memory / 1_000_000, usage.cpu_time());
+    /// }
+    /// ```
+    pub fn resource_usage(&self) -> Option<ResourceUsage> {
+        self.usage.as_deref().copied()
+    }
+
+    /// Returns the script's return value converted to `T`. The return value
+    /// is only captured when running with `capture_return_value` set on the
+    /// builder.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().capture_return_value(true).build();
+    /// let output = ps.run("Write-Output 'noise'; return 40 + 2").unwrap();
+    /// assert_eq!(output.return_value::<i32>().unwrap(), 42);
+    /// assert_eq!(output.stdout().unwrap().trim(), "noise");
+    /// ```
+    pub fn return_value<T: FromPsValue>(&self) -> Result<T> {
+        let json = self.block("return").ok_or_else(|| {
+            PsError::Deserialize(
+                "no return value was captured, see `PsScriptBuilder::capture_return_value`"
+                    .to_string(),
+            )
+        })?;
+        T::from_ps_value(PsValue::from_json(json)?)
+    }
+
+    /// Returns the result the script reported with `Write-PsResult` or
+    /// `Write-PsFailure`, see [`PsScript::run_enveloped`]. Fails if the
+    /// script didn't report one.
+    ///
+    /// [`PsScript::run_enveloped`]: crate::PsScript::run_enveloped
+    pub fn envelope<T: FromPsValue>(&self) -> Result<PsResult<T>> {
+        envelope::from_output(self)
+    }
+
+    /// Returns the changes the script reported with `Write-PsChange`, in the
+    /// order it made them. These are only captured when running with
+    /// `capture_changes` set on the builder.
+    pub fn changes(&self) -> Vec<Change> {
+        self.blocks
+            .iter()
+            .filter(|(tag, _)| tag == change::TAG)
+            .filter_map(|(_, json)| PsValue::from_json(json).ok())
+            .filter_map(|value| Change::from_ps_value(value).ok())
+            .collect()
+    }
+
+    /// Returns the messages the script wrote with `Write-Host` (or directly
+    /// to the information stream), one per line. These are only captured when
+    /// running with `capture_host_output` set on the builder.
+    pub fn host_output(&self) -> Option<String> {
+        self.block("host")
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+    }
+
+    /// Returns the messages the script wrote to the verbose stream, oldest
+    /// first. These are only captured when running with `capture_streams`
+    /// set on the builder.
+    pub fn verbose(&self) -> Vec<String> {
+        self.stream("Verbose")
+    }
+
+    /// Returns the messages the script wrote to the warning stream, with
+    /// `Write-Warning` for example, oldest first. These are only captured
+    /// when running with `capture_streams` set on the builder, see
+    /// `stderr_warnings` for the warnings PowerShell wrote to `stderr`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().capture_streams(true).build();
+    /// let output = ps.run("./Sync-Mailboxes.ps1").unwrap();
+    /// for warning in output.warnings() {
+    ///     eprintln!("warning: {}", warning);
+    /// }
+    /// ```
+    pub fn warnings(&self) -> Vec<String> {
+        self.stream("Warning")
+    }
+
+    /// Returns the warnings found in `stderr`, also when the script ran
+    /// successfully, see [`classify_stderr`](crate::classify_stderr).
+    pub fn stderr_warnings(&self) -> Vec<String> {
+        self.stderr_messages()
+            .into_iter()
+            .filter(|m| m.severity == stderr::StderrSeverity::Warning)
+            .map(|m| m.message)
+            .collect()
+    }
+
+    /// Returns `stderr` split into messages classified as errors, warnings
+    /// and so on, see [`classify_stderr`](crate::classify_stderr).
+    pub fn stderr_messages(&self) -> Vec<stderr::StderrMessage> {
+        stderr::classify_stderr(&self.stderr().unwrap_or_default())
+    }
+
+    /// Returns the messages the script wrote to the debug stream, oldest
+    /// first. These are only captured when running with `capture_streams`
+    /// set on the builder.
+    pub fn debug(&self) -> Vec<String> {
+        self.stream("Debug")
+    }
+
+    /// Returns the messages the script wrote to the information stream, with
+    /// `Write-Information` or `Write-Host` for example, oldest first. These
+    /// are only captured when running with `capture_streams` set on the
+    /// builder.
+    pub fn information(&self) -> Vec<String> {
+        self.stream("Information")
+    }
+
+    /// Returns the messages captured from the stream `name`.
+    fn stream(&self, name: &str) -> Vec<String> {
+        self.block("streams")
+            .and_then(|json| PsValue::from_json(json).ok())
+            .and_then(|value| value.get(name).cloned())
+            .and_then(|messages| Vec::<String>::from_ps_value(messages).ok())
+            .unwrap_or_default()
+    }
+
+    /// Returns the errors the script ran into, oldest first. These are only
+    /// captured when running with `capture_errors` set on the builder.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::{ErrorOrigin, PsScriptBuilder};
+    ///
+    /// let ps = PsScriptBuilder::new().capture_errors(true).build();
+    /// let result = ps.run("Get-ChildItem C:\\servers | % { Test-Connection $_.Name -Count 1 -ErrorAction Continue }");
+    /// let output = match result {
+    ///     Ok(output) | Err(powershell_script::PsError::Powershell(output)) => output,
+    ///     Err(e) => panic!("{}", e),
+    /// };
+    /// for error in output.errors() {
+    ///     match error.origin {
+    ///         ErrorOrigin::Throw | ErrorOrigin::Exception => eprintln!("bug: {}", error.message),
+    ///         _ => println!("skipped: {}", error.message),
+    ///     }
+    /// }
+    /// ```
+    pub fn errors(&self) -> Vec<ErrorRecord> {
+        self.block("errors")
+            .and_then(|json| PsValue::from_json(json).ok())
+            .and_then(|value| Vec::<ErrorRecord>::from_ps_value(value).ok())
+            .unwrap_or_default()
+    }
+
+    /// Returns the errors in `stderr` when PowerShell wrote it as CLIXML
+    /// (see [`parse_clixml`](crate::parse_clixml)), which happens when it's
+    /// started with `-OutputFormat XML` or thinks another PowerShell is
+    /// reading its output. Empty if `stderr` is plain text.
+    pub fn error_records(&self) -> Vec<clixml::ClixmlRecord> {
+        let stderr = self.stderr().unwrap_or_default();
+        if !clixml::contains(&stderr) {
+            return Vec::new();
+        }
+        clixml::parse_clixml(&stderr)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(clixml::ClixmlRecord::is_error)
+            .collect()
+    }
+
+    /// Returns the line number (starting at 1) of the command which failed
+    /// and stopped the script when running with `abort_on_error` set on the
+    /// builder.
+    pub fn failed_line(&self) -> Option<usize> {
+        self.block("failed-line")
+            .and_then(|s| s.trim().parse().ok())
+    }
+
+    /// Decodes the base64 block tagged `tag`, for scripts returning binary
+    /// data like a downloaded file or an encrypted blob, which doesn't survive
+    /// being written as text.
+    ///
+    /// The script writes the block to `stdout` between two marker lines, and
+    /// it's removed from the rest of the output:
+    ///
+    /// ```powershell
+    /// '##ps-block-begin:<tag>'
+    /// [Convert]::ToBase64String($bytes, 'InsertLineBreaks')
+    /// '##ps-block-end:<tag>'
+    /// ```
+    ///
+    /// The tags in [`RESERVED_BLOCK_TAGS`] are used by the crate itself. If
+    /// the script writes several blocks with the same tag, the last one is
+    /// decoded.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().build();
+    /// let script = r#"
+    /// $bytes = [IO.File]::ReadAllBytes('C:\report.pdf')
+    /// '##ps-block-begin:report'
+    /// [Convert]::ToBase64String($bytes, 'InsertLineBreaks')
+    /// '##ps-block-end:report'
+    /// "#;
+    /// let output = ps.run(script).unwrap();
+    /// let pdf = output.decode_base64_block("report").unwrap();
+    /// assert!(pdf.starts_with(b"%PDF"));
+    /// ```
+    pub fn decode_base64_block(&self, tag: &str) -> Result<Vec<u8>> {
+        let block = self.block(tag).ok_or_else(|| {
+            PsError::Deserialize(format!("the script didn't write a block tagged `{}`", tag))
+        })?;
+        base64::decode(block).ok_or_else(|| {
+            PsError::Deserialize(format!("the block tagged `{}` isn't valid base64", tag))
+        })
+    }
+
+    /// Returns the changes the script made to its environment variables.
+    /// These are only captured when running with `capture_env_delta` set on
+    /// the builder.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().capture_env_delta(true).build();
+    /// let output = ps.run(". ./venv/Scripts/Activate.ps1").unwrap();
+    /// if let Some(delta) = output.env_delta() {
+    ///     delta.apply();
+    /// }
+    /// ```
+    pub fn env_delta(&self) -> Option<EnvDelta> {
+        self.block("env")
+            .and_then(|json| PsValue::from_json(json).ok())
+            .and_then(|value| EnvDelta::from_ps_value(value).ok())
+    }
+
+    /// Returns the file system location the script was in when it finished,
+    /// so a shell wrapping PowerShell can follow its `Set-Location` calls.
+    /// This is only captured when running with `capture_final_location` set
+    /// on the builder.
+    ///
+    /// Locations set through a PSDrive are resolved to the directory they
+    /// point to. If the script moved to another provider, like the
+    /// registry, the last file system location is returned.
+    pub fn final_location(&self) -> Option<PathBuf> {
+        self.block("location")
+            .filter(|s| !s.is_empty())
+            .map(PathBuf::from)
+    }
+
+    /// Removes ANSI escape sequences from `stdout` and `stderr`, keeping the
+    /// styled output around for `styled_stdout` and `styled_stderr`.
+    pub(crate) fn strip_ansi(&mut self) {
+        let stdout = ansi::strip(&self.inner.stdout).into_owned();
+        let stderr = ansi::strip(&self.inner.stderr).into_owned();
+        if stdout.len() != self.inner.stdout.len() || stderr.len() != self.inner.stderr.len() {
+            let styled_stdout = std::mem::replace(&mut self.inner.stdout, stdout);
+            let styled_stderr = std::mem::replace(&mut self.inner.stderr, stderr);
+            self.styled = Some(Box::new((styled_stdout, styled_stderr)));
+        }
+    }
+
+    /// Replaces `\r\n` line endings in `stdout` and `stderr` with `\n`.
+    pub(crate) fn normalize_newlines(&mut self) {
+        for stream in [&mut self.inner.stdout, &mut self.inner.stderr] {
+            if stream.windows(2).any(|pair| pair == b"\r\n") {
+                let mut normalized = Vec::with_capacity(stream.len());
+                let mut bytes = stream.iter().copied().peekable();
+                while let Some(byte) = bytes.next() {
+                    if byte != b'\r' || bytes.peek() != Some(&b'\n') {
+                        normalized.push(byte);
+                    }
+                }
+                *stream = normalized;
+            }
+        }
+    }
+
+    /// Returns the timeline of the commands the script ran. It's only
+    /// recorded when running with `record_timeline` set on the builder.
+    pub fn timeline(&self) -> Option<&Timeline> {
+        self.timeline.as_deref()
+    }
+
+    /// Returns the context identifying the run which produced this output.
+    /// It's set for the output of all the run methods returning one.
+    pub fn context(&self) -> Option<&ExecutionContext> {
+        self.run.as_ref().map(|run| &run.context)
+    }
+
+    /// Returns the content of the last block tagged `tag`, if any. The
+    /// wrapper code writes its blocks after the script has run, so taking
+    /// the last one keeps a script from shadowing them with blocks of its
+    /// own, and blocks the script may write more than once are read from
+    /// their latest version.
+    pub(crate) fn block(&self, tag: &str) -> Option<&str> {
+        self.blocks
+            .iter()
+            .rev()
+            .find(|(t, _)| t == tag)
+            .map(|(_, content)| content.as_str())
+    }
+}
+
+/// Removes the blocks written by the wrapper code from `stdout`, returning
+/// the remaining output and the blocks as `(tag, content)` pairs.
+fn extract_blocks(stdout: &[u8]) -> (Vec<u8>, Vec<(String, String)>) {
+    let marker = BLOCK_BEGIN.as_bytes();
+    if !stdout.windows(marker.len()).any(|w| w == marker) {
+        return (stdout.to_vec(), Vec::new());
+    }
+
+    let mut rest = Vec::with_capacity(stdout.len());
+    let mut blocks = Vec::new();
+    let mut current: Option<(String, Vec<String>)> = None;
+    for line in stdout.split_inclusive(|b| *b == b'\n') {
+        let text = String::from_utf8_lossy(line);
+        let trimmed = text.trim_end_matches(['\r', '\n']);
+        match current.take() {
+            None => match trimmed.strip_prefix(BLOCK_BEGIN) {
+                Some(tag) => current = Some((tag.to_string(), Vec::new())),
+                None => rest.extend_from_slice(line),
+            },
+            Some((tag, mut lines)) => {
+                if trimmed.strip_prefix(BLOCK_END) == Some(tag.as_str()) {
+                    blocks.push((tag, lines.join("\n")));
+                } else {
+                    lines.push(trimmed.to_string());
+                    current = Some((tag, lines));
+                }
+            }
+        }
+    }
+
+    // An unterminated block is most likely a script that was killed halfway
+    // through writing it so we keep what we got.
+    if let Some((tag, lines)) = current {
+        blocks.push((tag, lines.join("\n")));
+    }
+
+    (rest, blocks)
 }
 
 impl From<process::Output> for Output {
-    fn from(proc_output: process::Output) -> Output {
+    fn from(mut proc_output: process::Output) -> Output {
         let success = proc_output.status.success();
+        let (stdout, blocks) = extract_blocks(&proc_output.stdout);
+        proc_output.stdout = stdout;
         Output {
             inner: proc_output,
             success,
+            blocks,
+            timeline: None,
+            styled: None,
+            run: None,
+            usage: None,
         }
     }
 }
@@ -58,6 +662,10 @@ impl fmt::Display for Output {
         if let Some(stderr) = self.stderr() {
             write!(f, "{}", stderr)?;
         }
+
+        if let (Some(code), Some(meaning)) = (self.exit_code(), self.exit_meaning()) {
+            write!(f, "Exit code {}: {}", code, meaning)?;
+        }
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,548 @@
+//! Parsing of CLIXML, the XML serialization PowerShell writes to `stderr`
+//! instead of plain text when it thinks another PowerShell is reading it,
+//! like when it's started with `-OutputFormat XML` or over remoting.
+//!
+//! A document starts with a `#< CLIXML` line, followed by an `<Objs>`
+//! element with one child per record. Text written to the error, warning
+//! and other streams arrives as `<S>` fragments of a line or less, which are
+//! joined up again, and objects like error and progress records as `<Obj>`.
+
+use std::collections::HashMap;
+
+use crate::{error::PsError, value::PsValue, Result};
+
+/// The line each CLIXML document starts with.
+const MARKER: &str = "#< CLIXML";
+
+/// A record read from CLIXML output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClixmlRecord {
+    /// The stream the record was written to, like `Error`, `Warning` or
+    /// `progress`, as PowerShell spells it.
+    pub stream: String,
+    /// The text of the record, or what the object converts to as a string.
+    pub message: String,
+    /// The error category, like `ObjectNotFound`, for errors which have one.
+    pub category: Option<String>,
+    /// PowerShell's `FullyQualifiedErrorId`, for errors which have one.
+    pub error_id: Option<String>,
+    /// Where the error occurred: the script stack trace of a serialized
+    /// error record, or the position PowerShell printed with a formatted
+    /// one.
+    pub stack_trace: Option<String>,
+    /// The serialized object with its properties, `PsValue::Null` for text.
+    pub object: PsValue,
+}
+
+impl ClixmlRecord {
+    /// Whether the record was written to the error stream.
+    pub fn is_error(&self) -> bool {
+        self.stream.eq_ignore_ascii_case("error")
+    }
+
+    fn text(stream: &str, message: String) -> ClixmlRecord {
+        ClixmlRecord {
+            stream: stream.to_string(),
+            message,
+            category: None,
+            error_id: None,
+            stack_trace: None,
+            object: PsValue::Null,
+        }
+    }
+}
+
+/// Parses the CLIXML documents in `text` into the records they contain, in
+/// the order they were written. Text outside of the documents, like output
+/// written before PowerShell switched to CLIXML, is skipped.
+///
+/// ## Example
+///
+/// ```rust
+/// let stderr = "#< CLIXML\r\n<Objs Version=\"1.1.0.1\" xmlns=\"http://schemas.microsoft.com/powershell/2004/04\"><S S=\"Error\">boom_x000D__x000A_</S><S S=\"Error\">    + CategoryInfo          : NotSpecified: (:) [Write-Error], WriteErrorException_x000D__x000A_</S><S S=\"Error\">    + FullyQualifiedErrorId : Microsoft.PowerShell.Commands.WriteErrorException_x000D__x000A_</S></Objs>";
+/// let records = powershell_script::parse_clixml(stderr).unwrap();
+/// assert_eq!(records[0].message, "boom");
+/// assert_eq!(records[0].category.as_deref(), Some("NotSpecified"));
+/// ```
+pub fn parse_clixml(text: &str) -> Result<Vec<ClixmlRecord>> {
+    let mut records = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(MARKER) {
+        let mut parser = Parser {
+            text: &rest[start + MARKER.len()..],
+            pos: 0,
+        };
+        let root = parser.document()?;
+        rest = &parser.text[parser.pos..];
+        records.extend(Records::default().read(&root));
+    }
+    Ok(records)
+}
+
+/// Returns whether `text` contains CLIXML.
+pub(crate) fn contains(text: &str) -> bool {
+    text.contains(MARKER)
+}
+
+fn invalid(what: &str) -> PsError {
+    PsError::Deserialize(format!("invalid CLIXML: {}", what))
+}
+
+/// An XML element, which is all CLIXML consists of apart from text.
+#[derive(Debug)]
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+}
+
+/// Just enough of an XML parser for what PowerShell writes.
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// Parses the `<Objs>` element following the marker.
+    fn document(&mut self) -> Result<Element> {
+        loop {
+            self.skip_whitespace();
+            if self.eat("<?") {
+                self.skip_past("?>")?;
+            } else if self.eat("<!--") {
+                self.skip_past("-->")?;
+            } else {
+                break;
+            }
+        }
+        let root = self.element()?;
+        if root.name != "Objs" {
+            return Err(invalid(&format!("expected <Objs>, found <{}>", root.name)));
+        }
+        Ok(root)
+    }
+
+    fn element(&mut self) -> Result<Element> {
+        if !self.eat("<") {
+            return Err(invalid("expected an element"));
+        }
+        let name = self.name()?;
+        let mut element = Element {
+            name,
+            attributes: Vec::new(),
+            children: Vec::new(),
+            text: String::new(),
+        };
+        loop {
+            self.skip_whitespace();
+            if self.eat("/>") {
+                return Ok(element);
+            }
+            if self.eat(">") {
+                break;
+            }
+            let attribute = self.name()?;
+            self.skip_whitespace();
+            if !self.eat("=") {
+                return Err(invalid("expected `=` after an attribute name"));
+            }
+            self.skip_whitespace();
+            let quote = match self.rest().chars().next() {
+                Some(q @ '"') | Some(q @ '\'') => q,
+                _ => return Err(invalid("expected a quoted attribute value")),
+            };
+            self.pos += 1;
+            let end = self
+                .rest()
+                .find(quote)
+                .ok_or_else(|| invalid("unterminated attribute"))?;
+            let value = unescape(&self.rest()[..end]);
+            self.pos += end + 1;
+            element.attributes.push((attribute, value));
+        }
+
+        let mut text = String::new();
+        loop {
+            let end = self
+                .rest()
+                .find('<')
+                .ok_or_else(|| invalid("unterminated element"))?;
+            text.push_str(&self.rest()[..end]);
+            self.pos += end;
+            if self.eat("</") {
+                let name = self.name()?;
+                if name != element.name {
+                    return Err(invalid(&format!(
+                        "<{}> is closed by </{}>",
+                        element.name, name
+                    )));
+                }
+                self.skip_whitespace();
+                if !self.eat(">") {
+                    return Err(invalid("expected `>`"));
+                }
+                break;
+            } else if self.eat("<!--") {
+                self.skip_past("-->")?;
+            } else {
+                element.children.push(self.element()?);
+            }
+        }
+        element.text = decode(&unescape(&text));
+        Ok(element)
+    }
+
+    fn name(&mut self) -> Result<String> {
+        let len = self
+            .rest()
+            .find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':')))
+            .unwrap_or_else(|| self.rest().len());
+        if len == 0 {
+            return Err(invalid("expected a name"));
+        }
+        let name = self.rest()[..len].to_string();
+        self.pos += len;
+        Ok(name)
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        let found = self.rest().starts_with(token);
+        if found {
+            self.pos += token.len();
+        }
+        found
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn skip_past(&mut self, token: &str) -> Result<()> {
+        let end = self
+            .rest()
+            .find(token)
+            .ok_or_else(|| invalid(&format!("expected `{}`", token)))?;
+        self.pos += end + token.len();
+        Ok(())
+    }
+}
+
+/// Replaces the XML entities in `text`.
+fn unescape(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let entity = &rest[1..end];
+        let c = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => {
+                let code = match entity.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => entity.strip_prefix('#').and_then(|dec| dec.parse().ok()),
+                };
+                code.and_then(char::from_u32)
+            }
+        };
+        match c {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Decodes the `_xHHHH_` escapes PowerShell uses for characters XML can't
+/// hold, like line breaks, and for `_x` itself.
+fn decode(text: &str) -> String {
+    if !text.contains("_x") {
+        return text.to_string();
+    }
+    let mut units: Vec<u16> = Vec::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("_x") {
+        units.extend(rest[..start].encode_utf16());
+        let escape = rest[start..].get(..7).filter(|e| e.ends_with('_'));
+        match escape.and_then(|e| u16::from_str_radix(&e[2..6], 16).ok()) {
+            Some(unit) => {
+                units.push(unit);
+                rest = &rest[start + 7..];
+            }
+            None => {
+                units.extend("_x".encode_utf16());
+                rest = &rest[start + 2..];
+            }
+        }
+    }
+    units.extend(rest.encode_utf16());
+    String::from_utf16_lossy(&units)
+}
+
+/// Converts the children of `<Objs>` into records, remembering the objects
+/// later ones refer back to.
+#[derive(Default)]
+struct Records {
+    objects: HashMap<String, (PsValue, Option<String>)>,
+}
+
+impl Records {
+    fn read(mut self, root: &Element) -> Vec<ClixmlRecord> {
+        let mut records = Vec::new();
+        // Text fragments of the same stream waiting to be joined up
+        let mut pending: Option<(String, String)> = None;
+        for child in &root.children {
+            let stream = child.attribute("S").unwrap_or("Output");
+            if child.name == "S" {
+                match &mut pending {
+                    Some((s, text)) if s == stream => text.push_str(&child.text),
+                    _ => {
+                        if let Some((s, text)) = pending.take() {
+                            records.extend(text_records(&s, &text));
+                        }
+                        pending = Some((stream.to_string(), child.text.clone()));
+                    }
+                }
+                continue;
+            }
+            if let Some((s, text)) = pending.take() {
+                records.extend(text_records(&s, &text));
+            }
+            let (object, to_string) = self.value(child);
+            records.push(object_record(stream, object, to_string));
+        }
+        if let Some((s, text)) = pending {
+            records.extend(text_records(&s, &text));
+        }
+        records
+    }
+
+    /// Converts a serialized value, returning what it converts to as a
+    /// string as well for objects which have been serialized with it.
+    fn value(&mut self, element: &Element) -> (PsValue, Option<String>) {
+        let value = match element.name.as_str() {
+            "Obj" => {
+                let object = self.object(element);
+                if let Some(id) = element.attribute("RefId") {
+                    self.objects.insert(id.to_string(), object.clone());
+                }
+                return object;
+            }
+            "Ref" => {
+                return element
+                    .attribute("RefId")
+                    .and_then(|id| self.objects.get(id).cloned())
+                    .unwrap_or((PsValue::Null, None))
+            }
+            "Nil" => PsValue::Null,
+            "B" => PsValue::Bool(element.text.trim() == "true"),
+            "I16" | "I32" | "I64" | "SB" | "By" | "U16" | "U32" | "U64" => {
+                match element.text.trim().parse() {
+                    Ok(n) => PsValue::Int(n),
+                    Err(_) => PsValue::String(element.text.clone()),
+                }
+            }
+            "Sg" | "Db" | "D" => match element.text.trim().parse() {
+                Ok(f) => PsValue::Float(f),
+                Err(_) => PsValue::String(element.text.clone()),
+            },
+            _ => PsValue::String(element.text.clone()),
+        };
+        (value, None)
+    }
+
+    fn object(&mut self, element: &Element) -> (PsValue, Option<String>) {
+        let to_string = element.child("ToString").map(|e| e.text.clone());
+        for list in &["LST", "IE", "STK", "QUE"] {
+            if let Some(list) = element.child(list) {
+                let items = list.children.iter().map(|c| self.value(c).0).collect();
+                return (PsValue::Array(items), to_string);
+            }
+        }
+        if let Some(dictionary) = element.child("DCT") {
+            let mut entries = Vec::new();
+            for entry in &dictionary.children {
+                let part = |n: &str| entry.children.iter().find(|c| c.attribute("N") == Some(n));
+                if let (Some(key), Some(value)) = (part("Key"), part("Value")) {
+                    let key = match self.value(key) {
+                        (PsValue::String(s), _) => s,
+                        (other, _) => other.to_string(),
+                    };
+                    entries.push((key, self.value(value).0));
+                }
+            }
+            return (PsValue::Object(entries), to_string);
+        }
+
+        let mut properties = Vec::new();
+        for group in element
+            .children
+            .iter()
+            .filter(|c| c.name == "Props" || c.name == "MS")
+        {
+            for property in &group.children {
+                if let Some(name) = property.attribute("N") {
+                    properties.push((name.to_string(), self.value(property).0));
+                }
+            }
+        }
+        if !properties.is_empty() {
+            return (PsValue::Object(properties), to_string);
+        }
+        // Enums and other objects only serialized as a string and a value
+        let value = match (
+            &to_string,
+            element.children.iter().find(|c| is_primitive(c)),
+        ) {
+            (Some(s), _) => PsValue::String(s.clone()),
+            (None, Some(primitive)) => self.value(primitive).0,
+            (None, None) => PsValue::Null,
+        };
+        (value, to_string)
+    }
+}
+
+fn is_primitive(element: &Element) -> bool {
+    !matches!(
+        element.name.as_str(),
+        "TN" | "TNRef" | "ToString" | "Props" | "MS"
+    )
+}
+
+/// Splits the text written to `stream` into records. Errors PowerShell
+/// formatted end with their `FullyQualifiedErrorId` or an empty line, any
+/// other text is a record as a whole.
+fn text_records(stream: &str, text: &str) -> Vec<ClixmlRecord> {
+    if !stream.eq_ignore_ascii_case("error") {
+        let message = text.trim_end().to_string();
+        return if message.is_empty() {
+            Vec::new()
+        } else {
+            vec![ClixmlRecord::text(stream, message)]
+        };
+    }
+
+    let mut records = Vec::new();
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.lines() {
+        let last = line.trim_start().starts_with("+ FullyQualifiedErrorId");
+        if !line.trim().is_empty() {
+            lines.push(line);
+        }
+        if (last || line.trim().is_empty()) && !lines.is_empty() {
+            records.push(formatted_error(stream, &lines));
+            lines.clear();
+        }
+    }
+    if !lines.is_empty() {
+        records.push(formatted_error(stream, &lines));
+    }
+    records
+}
+
+/// Reads an error PowerShell formatted like
+///
+/// ```text
+/// Get-Item : Cannot find path 'C:\nope' because it does not exist.
+/// At line:1 char:1
+/// + Get-Item C:\nope
+/// + ~~~~~~~~~~~~~~~~
+///     + CategoryInfo          : ObjectNotFound: (C:\nope:String) [Get-Item], ItemNotFoundException
+///     + FullyQualifiedErrorId : PathNotFound,Microsoft.PowerShell.Commands.GetItemCommand
+/// ```
+fn formatted_error(stream: &str, lines: &[&str]) -> ClixmlRecord {
+    let mut record = ClixmlRecord::text(stream, String::new());
+    let mut message = Vec::new();
+    let mut position = Vec::new();
+    for line in lines {
+        let trimmed = line.trim_start();
+        if let Some(info) = field(trimmed, "+ CategoryInfo") {
+            record.category = category(info);
+        } else if let Some(id) = field(trimmed, "+ FullyQualifiedErrorId") {
+            record.error_id = Some(id.to_string());
+        } else if trimmed.starts_with("At ") || !position.is_empty() {
+            position.push(line.trim_end());
+        } else {
+            message.push(line.trim_end());
+        }
+    }
+    record.message = message.join("\n");
+    if !position.is_empty() {
+        record.stack_trace = Some(position.join("\n"));
+    }
+    record
+}
+
+/// Returns the value of a `+ Name : value` line.
+fn field<'l>(line: &'l str, name: &str) -> Option<&'l str> {
+    let rest = line.strip_prefix(name)?.trim_start();
+    Some(rest.strip_prefix(':')?.trim())
+}
+
+/// Returns the category from the text of a `CategoryInfo`, like
+/// `ObjectNotFound: (C:\nope:String) [Get-Item], ItemNotFoundException`.
+fn category(info: &str) -> Option<String> {
+    let category = info.split(':').next()?.trim();
+    Some(category.to_string()).filter(|c| !c.is_empty())
+}
+
+/// Makes a record of a serialized object, picking out the details of error
+/// records.
+fn object_record(stream: &str, object: PsValue, to_string: Option<String>) -> ClixmlRecord {
+    let string = |name: &str| {
+        object
+            .get(name)
+            .and_then(PsValue::as_str)
+            .map(str::to_string)
+            .filter(|s| !s.is_empty())
+    };
+    let message = to_string
+        .or_else(|| string("Message"))
+        .or_else(|| object.as_str().map(str::to_string))
+        .unwrap_or_default();
+    ClixmlRecord {
+        stream: stream.to_string(),
+        message,
+        category: string("ErrorCategory_Message").and_then(|info| category(&info)),
+        error_id: string("FullyQualifiedErrorId"),
+        stack_trace: string("ErrorDetails_ScriptStackTrace"),
+        object,
+    }
+}
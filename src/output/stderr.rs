@@ -0,0 +1,127 @@
+//! Telling apart the errors, warnings and other messages PowerShell writes
+//! to `stderr`, see [`classify_stderr`].
+
+use super::clixml;
+
+/// How serious a message written to `stderr` is, see [`classify_stderr`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StderrSeverity {
+    Error,
+    Warning,
+    Verbose,
+    Debug,
+    Information,
+}
+
+/// A message read from `stderr` with [`classify_stderr`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StderrMessage {
+    pub severity: StderrSeverity,
+    /// The text of the message without the prefix which marked its
+    /// severity. Messages spanning several lines, like formatted errors,
+    /// keep their line breaks.
+    pub message: String,
+}
+
+/// The prefixes PowerShell writes before messages from the other streams
+/// when they end up in `stderr`.
+const PREFIXES: [(&str, StderrSeverity); 5] = [
+    ("WARNING: ", StderrSeverity::Warning),
+    ("ERROR: ", StderrSeverity::Error),
+    ("VERBOSE: ", StderrSeverity::Verbose),
+    ("DEBUG: ", StderrSeverity::Debug),
+    ("INFO: ", StderrSeverity::Information),
+];
+
+/// Splits `stderr` into messages and classifies them by severity, so
+/// warnings can be told apart from errors even when the run succeeded.
+///
+/// CLIXML (see [`parse_clixml`](crate::parse_clixml)) is classified by the
+/// stream each record was written to, leaving out progress records. Plain
+/// text is classified line by line: lines starting with `WARNING: `,
+/// `ERROR: `, `VERBOSE: `, `DEBUG: ` or `INFO: ` start a message of that
+/// severity, and indented lines continue it. Any other text is an error,
+/// which runs until the next blank or prefixed line, since that's how
+/// PowerShell formats errors with their position and category.
+///
+/// ## Example
+///
+/// ```rust
+/// use powershell_script::{classify_stderr, StderrSeverity};
+///
+/// let stderr = "WARNING: disk almost full\nGet-Item : Cannot find path 'C:\\nope'\nAt line:1 char:1\n";
+/// let messages = classify_stderr(stderr);
+/// assert_eq!(messages[0].severity, StderrSeverity::Warning);
+/// assert_eq!(messages[0].message, "disk almost full");
+/// assert_eq!(messages[1].severity, StderrSeverity::Error);
+/// assert_eq!(messages[1].message, "Get-Item : Cannot find path 'C:\\nope'\nAt line:1 char:1");
+/// ```
+pub fn classify_stderr(stderr: &str) -> Vec<StderrMessage> {
+    if clixml::contains(stderr) {
+        if let Ok(records) = clixml::parse_clixml(stderr) {
+            return records
+                .into_iter()
+                .filter_map(|record| {
+                    Some(StderrMessage {
+                        severity: stream_severity(&record.stream)?,
+                        message: record.message,
+                    })
+                })
+                .collect();
+        }
+    }
+
+    let mut messages: Vec<StderrMessage> = Vec::new();
+    // Whether the last message can still be continued by the next line
+    let mut open = false;
+    for line in stderr.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            open = false;
+            continue;
+        }
+        if let Some((message, severity)) = PREFIXES
+            .iter()
+            .find_map(|(prefix, severity)| Some((line.strip_prefix(prefix)?, *severity)))
+        {
+            messages.push(StderrMessage {
+                severity,
+                message: message.to_string(),
+            });
+            open = true;
+            continue;
+        }
+        match messages.last_mut() {
+            Some(last)
+                if open
+                    && (last.severity == StderrSeverity::Error
+                        || line.starts_with(char::is_whitespace)) =>
+            {
+                last.message.push('\n');
+                last.message.push_str(line);
+            }
+            _ => {
+                messages.push(StderrMessage {
+                    severity: StderrSeverity::Error,
+                    message: line.to_string(),
+                });
+                open = true;
+            }
+        }
+    }
+    messages
+}
+
+/// Returns the severity of messages written to the CLIXML `stream`, or
+/// `None` for progress records.
+fn stream_severity(stream: &str) -> Option<StderrSeverity> {
+    let severity = match stream.to_ascii_lowercase().as_str() {
+        "progress" => return None,
+        "error" => StderrSeverity::Error,
+        "warning" => StderrSeverity::Warning,
+        "verbose" => StderrSeverity::Verbose,
+        "debug" => StderrSeverity::Debug,
+        _ => StderrSeverity::Information,
+    };
+    Some(severity)
+}
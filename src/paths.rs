@@ -0,0 +1,49 @@
+//! Converting paths to and from their 8.3 short names on Windows, for
+//! passing paths with spaces to legacy tools which don't handle quoting
+//! well, like some installers and batch files.
+//!
+//! On other platforms the paths are returned as they are.
+//!
+//! ## Example
+//!
+//! ```rust, no_run
+//! use powershell_script::{paths, PsScriptBuilder};
+//!
+//! let setup = paths::to_short(r"C:\Program Files\Vendor Tool\setup.exe").unwrap();
+//! // `C:\PROGRA~1\VENDOR~1\setup.exe`, no quotes needed
+//! let script = format!("cmd /c {} /quiet", setup.display());
+//! PsScriptBuilder::new().build().run(&script).unwrap();
+//! ```
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::target;
+
+/// Returns the 8.3 short form of `path`, like `C:\PROGRA~1` for
+/// `C:\Program Files`. The path has to exist.
+///
+/// Short names can be disabled per volume, in which case the components
+/// without one are returned in their long form. Use [`has_spaces`] to check
+/// whether the result is safe to pass unquoted.
+pub fn to_short<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
+    target::short_path_name(path.as_ref())
+}
+
+/// Returns the long form of `path`, expanding any 8.3 short names in it,
+/// like a path from `$env:TEMP` which some systems report in its short form.
+/// The path has to exist.
+pub fn to_long<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
+    target::long_path_name(path.as_ref())
+}
+
+/// Whether `path` contains whitespace, and needs to be quoted (or shortened
+/// with [`to_short`]) when passed on a command line.
+pub fn has_spaces<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .to_string_lossy()
+        .chars()
+        .any(char::is_whitespace)
+}
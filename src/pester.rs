@@ -0,0 +1,216 @@
+//! Running [Pester](https://pester.dev) test suites, so a Rust test harness
+//! can run the tests of the PowerShell code it ships.
+//!
+//! Requires Pester 5 or later to be installed where the script runs.
+//!
+//! ## Example
+//!
+//! ```rust, no_run
+//! use powershell_script::{pester, PsScriptBuilder};
+//!
+//! // Called from a Rust test
+//! fn powershell_tests() {
+//!     let ps = PsScriptBuilder::new().build();
+//!     let options = pester::Options::new().exclude_tag("Slow");
+//!     pester::run(&ps, "./scripts/tests", &options).unwrap().assert_passed();
+//! }
+//! ```
+
+use std::{fmt, path::Path, time::Duration};
+
+use crate::{
+    error::PsError,
+    value::{FromPsValue, PsValue},
+    wrap, PsScript, Result,
+};
+
+/// Selects which tests to run.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    tags: Vec<String>,
+    exclude_tags: Vec<String>,
+    full_names: Vec<String>,
+}
+
+impl Options {
+    /// Runs all the tests.
+    pub fn new() -> Self {
+        Options::default()
+    }
+
+    /// Only runs tests tagged `tag`. Can be called more than once to run
+    /// tests with any of the tags.
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    /// Skips tests tagged `tag`.
+    pub fn exclude_tag(mut self, tag: impl Into<String>) -> Self {
+        self.exclude_tags.push(tag.into());
+        self
+    }
+
+    /// Only runs tests whose full name, like `Get-Widget.returns nothing`,
+    /// matches `filter`, which may contain wildcards.
+    pub fn full_name(mut self, filter: impl Into<String>) -> Self {
+        self.full_names.push(filter.into());
+        self
+    }
+
+    fn parameters(&self) -> String {
+        let mut params = String::new();
+        let lists = [
+            ("-TagFilter", &self.tags),
+            ("-ExcludeTagFilter", &self.exclude_tags),
+            ("-FullNameFilter", &self.full_names),
+        ];
+        for (name, values) in lists {
+            if !values.is_empty() {
+                let values: Vec<String> = values.iter().map(|v| wrap::quote(v)).collect();
+                params.push_str(&format!(" {} @({})", name, values.join(", ")));
+            }
+        }
+        params
+    }
+}
+
+/// What became of a test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Outcome {
+    Passed,
+    Failed,
+    /// Skipped with `-Skip` or `Set-ItResult -Skipped`.
+    Skipped,
+    /// Excluded by a filter.
+    NotRun,
+    /// Marked with `Set-ItResult -Inconclusive`.
+    Inconclusive,
+}
+
+/// The result of a single test.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+    /// The name given to `It`.
+    pub name: String,
+    /// The names of the enclosing blocks and the test, separated by dots.
+    pub full_name: String,
+    pub outcome: Outcome,
+    pub duration: Duration,
+    /// The file the test is defined in.
+    pub file: Option<String>,
+    /// The line of `file` the test starts on.
+    pub line: Option<u32>,
+    /// The errors which failed the test.
+    pub errors: Vec<String>,
+}
+
+impl FromPsValue for TestResult {
+    fn from_ps_value(value: PsValue) -> Result<Self> {
+        let string = |key: &str| {
+            value
+                .get(key)
+                .and_then(PsValue::as_str)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+        };
+        let outcome = match string("Result").as_deref() {
+            Some("Passed") => Outcome::Passed,
+            Some("Failed") => Outcome::Failed,
+            Some("Skipped") => Outcome::Skipped,
+            Some("Inconclusive") => Outcome::Inconclusive,
+            _ => Outcome::NotRun,
+        };
+        let field = |key: &str| value.get(key).cloned().unwrap_or(PsValue::Null);
+        let millis = Option::<f64>::from_ps_value(field("DurationMs"))?.unwrap_or(0.0);
+        Ok(TestResult {
+            name: string("Name").unwrap_or_default(),
+            full_name: string("FullName").unwrap_or_default(),
+            outcome,
+            duration: Duration::from_secs_f64(millis.max(0.0) / 1000.0),
+            file: string("File"),
+            line: Option::<u32>::from_ps_value(field("Line"))?.filter(|line| *line > 0),
+            errors: Vec::from_ps_value(field("Errors"))?,
+        })
+    }
+}
+
+impl fmt::Display for TestResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({:?})", self.full_name, self.outcome)?;
+        if let Some(file) = &self.file {
+            write!(f, " at {}", file)?;
+            if let Some(line) = self.line {
+                write!(f, ":{}", line)?;
+            }
+        }
+        for error in &self.errors {
+            write!(f, "\n    {}", error.replace('\n', "\n    "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The results of a test run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestRun {
+    pub tests: Vec<TestResult>,
+}
+
+impl TestRun {
+    /// The tests which passed.
+    pub fn passed(&self) -> impl Iterator<Item = &TestResult> {
+        self.with_outcome(Outcome::Passed)
+    }
+
+    /// The tests which failed.
+    pub fn failed(&self) -> impl Iterator<Item = &TestResult> {
+        self.with_outcome(Outcome::Failed)
+    }
+
+    /// Whether no test failed.
+    pub fn is_success(&self) -> bool {
+        self.failed().next().is_none()
+    }
+
+    /// Panics with the errors of the failed tests if any test failed, which
+    /// fails the Rust test calling it.
+    pub fn assert_passed(&self) {
+        let failed: Vec<String> = self.failed().map(TestResult::to_string).collect();
+        if !failed.is_empty() {
+            panic!(
+                "{} of {} Pester tests failed:\n{}",
+                failed.len(),
+                self.tests.len(),
+                failed.join("\n")
+            );
+        }
+    }
+
+    fn with_outcome(&self, outcome: Outcome) -> impl Iterator<Item = &TestResult> {
+        self.tests
+            .iter()
+            .filter(move |test| test.outcome == outcome)
+    }
+}
+
+/// Runs the Pester tests at `path`, a test file or a directory searched for
+/// `*.Tests.ps1` files, and returns their results.
+///
+/// Failing tests don't fail the run; check the returned `TestRun` for them.
+pub fn run<P: AsRef<Path>>(ps: &PsScript, path: P, options: &Options) -> Result<TestRun> {
+    let path = wrap::quote(&path.as_ref().to_string_lossy());
+    let script = format!(
+        "$__ps_run = Invoke-Pester -Path {}{} -Output Detailed -PassThru\n{}\n",
+        path,
+        options.parameters(),
+        wrap::emit_block("pester", "(ConvertTo-Json -Depth 3 -Compress -InputObject @($__ps_run.Tests | ForEach-Object { [ordered]@{ Name = $_.Name; FullName = $_.ExpandedPath; Result = [string]$_.Result; DurationMs = $_.Duration.TotalMilliseconds; File = $_.ScriptBlock.File; Line = $_.StartLine; Errors = @($_.ErrorRecord | ForEach-Object { [string]$_ }) } }))")
+    );
+    let output = ps.run_with_args(&script, std::iter::empty::<&str>())?;
+    let json = output
+        .block("pester")
+        .ok_or_else(|| PsError::Deserialize("Pester didn't return any results".to_string()))?;
+    Ok(TestRun {
+        tests: Vec::from_ps_value(PsValue::from_json(json)?)?,
+    })
+}
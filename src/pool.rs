@@ -0,0 +1,411 @@
+//! A fixed number of PowerShell sessions sharing the scripts run on them,
+//! see `PsPool`.
+
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::Instant,
+};
+
+use crate::{error::PsError, metrics::Metrics, output::Output, PsScript, PsSession, Result};
+
+/// How a script is scheduled on a [`PsPool`], see
+/// [`PsPool::run_with`].
+///
+/// Scripts waiting for a worker get one in order of their priority, then
+/// their deadline, earliest first, then the order they started waiting in.
+/// A script whose deadline passes while it's waiting fails with
+/// `PsError::DeadlineExceeded` instead of running late. Once a script has
+/// started, the deadline no longer applies.
+///
+/// ## Example
+///
+/// ```rust, no_run
+/// use std::time::{Duration, Instant};
+/// use powershell_script::{JobOptions, PsScriptBuilder};
+///
+/// let pool = PsScriptBuilder::new().build_pool(2).unwrap();
+/// let interactive = JobOptions::new()
+///     .priority(10)
+///     .deadline(Instant::now() + Duration::from_secs(5));
+/// let output = pool.run_with("Get-Service spooler", &interactive).unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JobOptions {
+    priority: i32,
+    deadline: Option<Instant>,
+    key: Option<String>,
+}
+
+impl JobOptions {
+    /// Creates options for a script with priority 0, no deadline and no key.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Scripts with a higher priority get a worker before those with a
+    /// lower one. Defaults to 0.
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Fails the script with `PsError::DeadlineExceeded` if it hasn't got a
+    /// worker by `deadline`.
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Runs the script on the worker `key` is pinned to, like
+    /// [`PsPool::run_keyed`].
+    pub fn key(mut self, key: &str) -> Self {
+        self.key = Some(key.to_string());
+        self
+    }
+}
+
+/// A fixed number of [`PsSession`]s, the workers, which run scripts from
+/// any number of threads. A script runs on whichever worker is idle, or
+/// waits for one to become idle, so PowerShell isn't started for every
+/// script and up to as many scripts as there are workers run at once.
+/// Create one with
+/// [`PsScriptBuilder::build_pool`](crate::PsScriptBuilder::build_pool).
+///
+/// The workers are configured like sessions, and `build_pool` rejects the
+/// same options as `build_session`, except for `metrics`, which are told
+/// how long scripts wait for a worker. They're all started with the
+/// pool, each running the `warm_up` script before it serves any scripts,
+/// and are recycled by the `RecyclePolicy` and the `health_check`. A worker
+/// ended by a script calling `exit` is replaced before the next script.
+///
+/// Since scripts run on any worker, they shouldn't rely on state left
+/// behind by earlier ones beyond what the warm-up sets up. Scripts which
+/// do, like those of a tenant sharing a connection, can be run with
+/// [`run_keyed`](Self::run_keyed) to pin them to a worker.
+///
+/// Clones share the same workers. They're killed when the last clone is
+/// dropped.
+///
+/// ## Example
+///
+/// ```rust, no_run
+/// use std::thread;
+/// use powershell_script::PsScriptBuilder;
+///
+/// let pool = PsScriptBuilder::new()
+///     .warm_up("Import-Module ActiveDirectory")
+///     .build_pool(4)
+///     .unwrap();
+/// let handles: Vec<_> = ["alice", "bob"]
+///     .iter()
+///     .map(|user| {
+///         let pool = pool.clone();
+///         let script = format!("Get-ADUser {}", user);
+///         thread::spawn(move || pool.run(&script))
+///     })
+///     .collect();
+/// for handle in handles {
+///     println!("{}", handle.join().unwrap().unwrap());
+/// }
+/// ```
+#[derive(Clone)]
+pub struct PsPool {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    returned: Condvar,
+    metrics: Arc<[Arc<dyn Metrics>]>,
+}
+
+struct State {
+    /// The workers, `None` while one is running a script.
+    workers: Vec<Option<PsSession>>,
+    /// The worker each key passed to `run_keyed` is pinned to.
+    keys: HashMap<String, usize>,
+    /// The scripts waiting for a worker.
+    waiting: Vec<Waiter>,
+    /// Numbers the next waiter, keeping the order they started waiting in.
+    next_ticket: u64,
+}
+
+/// A script waiting for a worker.
+struct Waiter {
+    ticket: u64,
+    priority: i32,
+    deadline: Option<Instant>,
+    /// The worker the script's key is pinned to.
+    worker: Option<usize>,
+}
+
+impl Waiter {
+    /// Sorts waiters in the order they get a worker, scripts without a
+    /// deadline after those with one.
+    fn rank(&self) -> (Reverse<i32>, bool, Option<Instant>, u64) {
+        (
+            Reverse(self.priority),
+            self.deadline.is_none(),
+            self.deadline,
+            self.ticket,
+        )
+    }
+}
+
+impl State {
+    /// Returns the idle worker the waiter with `ticket` is next in line for,
+    /// if any. Idle workers are handed out to the waiters by rank, so one
+    /// isn't taken by a waiter while a higher ranked one could use it.
+    fn assigned(&self, ticket: u64) -> Option<usize> {
+        let mut waiting: Vec<&Waiter> = self.waiting.iter().collect();
+        waiting.sort_by_key(|waiter| waiter.rank());
+        let mut idle: Vec<bool> = self.workers.iter().map(Option::is_some).collect();
+        for waiter in waiting {
+            let index = match waiter.worker {
+                Some(index) => Some(index).filter(|&index| idle[index]),
+                None => idle.iter().position(|&idle| idle),
+            };
+            if let Some(index) = index {
+                if waiter.ticket == ticket {
+                    return Some(index);
+                }
+                idle[index] = false;
+            }
+        }
+        None
+    }
+
+    /// Returns the worker `key` is pinned to, pinning it to the worker with
+    /// the fewest keys if it's new.
+    fn worker_for(&mut self, key: &str) -> usize {
+        if let Some(&index) = self.keys.get(key) {
+            return index;
+        }
+        let mut pinned = vec![0; self.workers.len()];
+        for &index in self.keys.values() {
+            pinned[index] += 1;
+        }
+        let index = (0..pinned.len()).min_by_key(|&i| pinned[i]).unwrap_or(0);
+        self.keys.insert(key.to_string(), index);
+        index
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl PsPool {
+    /// Starts `size` workers configured by `ps`, at least one.
+    pub(crate) fn start(ps: PsScript, size: usize) -> Result<PsPool> {
+        let workers = (0..size.max(1))
+            .map(|_| PsSession::start(ps.clone()).map(Some))
+            .collect::<Result<_>>()?;
+        Ok(PsPool {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    workers,
+                    keys: HashMap::new(),
+                    waiting: Vec::new(),
+                    next_ticket: 0,
+                }),
+                returned: Condvar::new(),
+                metrics: ps.metrics.clone(),
+            }),
+        })
+    }
+
+    /// Runs `script` on the next idle worker and waits for it to finish.
+    /// Returns the output the script wrote, or `PsError::Powershell` with it
+    /// if the script failed, like [`PsSession::run`].
+    pub fn run(&self, script: &str) -> Result<Output> {
+        self.run_with(script, &JobOptions::new())
+    }
+
+    /// Runs `script` like [`run`](Self::run), scheduled according to
+    /// `options`, see [`JobOptions`].
+    pub fn run_with(&self, script: &str, options: &JobOptions) -> Result<Output> {
+        let mut worker = self.checkout(options)?;
+        worker.session().run(script)
+    }
+
+    /// Runs `script` on the worker `key` is pinned to, waiting for it if
+    /// it's busy, so all scripts with the same key see the variables,
+    /// functions and modules set up by the earlier ones. A new key is pinned
+    /// to the worker with the fewest keys.
+    ///
+    /// The state is lost if the worker is recycled or ended by a script,
+    /// like it is for a [`PsSession`]. Keys stay pinned until they're
+    /// released with [`release_key`](Self::release_key).
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let pool = PsScriptBuilder::new().build_pool(4).unwrap();
+    /// pool.run_keyed("contoso", "$tenant = Connect-Tenant contoso").unwrap();
+    /// pool.run_keyed("contoso", "Get-TenantUsers $tenant").unwrap();
+    /// ```
+    pub fn run_keyed(&self, key: &str, script: &str) -> Result<Output> {
+        self.run_with(script, &JobOptions::new().key(key))
+    }
+
+    /// Unpins `key` from its worker. Returns whether it was pinned.
+    pub fn release_key(&self, key: &str) -> bool {
+        self.shared.lock().keys.remove(key).is_some()
+    }
+
+    /// The number of workers.
+    pub fn size(&self) -> usize {
+        self.shared.lock().workers.len()
+    }
+
+    /// The number of workers which aren't running a script.
+    pub fn idle(&self) -> usize {
+        self.shared
+            .lock()
+            .workers
+            .iter()
+            .filter(|w| w.is_some())
+            .count()
+    }
+
+    /// Calls [`PsSession::recycle_if_due`] on the idle workers, so those
+    /// which have been idle for longer than the `idle_timeout` are replaced
+    /// without waiting for the next script. Returns how many were recycled.
+    pub fn recycle_idle(&self) -> Result<usize> {
+        // Starting PowerShell takes a while, so the workers are taken out of
+        // the pool instead of holding the lock while they're recycled
+        let idle: Vec<Checkout<'_>> = {
+            let mut state = self.shared.lock();
+            state
+                .workers
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(index, worker)| {
+                    Some(Checkout {
+                        shared: &self.shared,
+                        index,
+                        session: Some(worker.take()?),
+                    })
+                })
+                .collect()
+        };
+        let mut recycled = 0;
+        for mut worker in idle {
+            if worker.session().recycle_if_due()? {
+                recycled += 1;
+            }
+        }
+        Ok(recycled)
+    }
+
+    /// Waits for an idle worker, or the one the key is pinned to, in line
+    /// with the other waiting scripts, and takes it out of the pool until
+    /// the returned guard is dropped.
+    fn checkout(&self, options: &JobOptions) -> Result<Checkout<'_>> {
+        let queued = Instant::now();
+        let mut state = self.shared.lock();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        let worker = options.key.as_deref().map(|key| state.worker_for(key));
+        state.waiting.push(Waiter {
+            ticket,
+            priority: options.priority,
+            deadline: options.deadline,
+            worker,
+        });
+
+        let result = loop {
+            if let Some(index) = state.assigned(ticket) {
+                break Ok(index);
+            }
+            let now = Instant::now();
+            state = match options.deadline {
+                Some(deadline) if deadline <= now => {
+                    break Err(PsError::DeadlineExceeded(queued.elapsed()));
+                }
+                Some(deadline) => {
+                    self.shared
+                        .returned
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+                None => self
+                    .shared
+                    .returned
+                    .wait(state)
+                    .unwrap_or_else(|e| e.into_inner()),
+            };
+        };
+
+        state.waiting.retain(|waiter| waiter.ticket != ticket);
+        let checkout = match result {
+            Ok(index) => Ok(Checkout {
+                shared: &self.shared,
+                index,
+                session: state.workers[index].take(),
+            }),
+            Err(e) => {
+                // A worker this script was next in line for goes to the
+                // next waiter instead
+                self.shared.returned.notify_all();
+                Err(e)
+            }
+        };
+        drop(state);
+
+        let waited = queued.elapsed();
+        for metrics in self.shared.metrics.iter() {
+            metrics.record_queue_wait(waited);
+        }
+        checkout
+    }
+}
+
+impl fmt::Debug for PsPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PsPool")
+            .field("size", &self.size())
+            .field("idle", &self.idle())
+            .finish()
+    }
+}
+
+/// A worker taken out of the pool, which is put back when it's dropped,
+/// even if the script panicked.
+struct Checkout<'a> {
+    shared: &'a Shared,
+    index: usize,
+    session: Option<PsSession>,
+}
+
+impl Checkout<'_> {
+    fn session(&mut self) -> &mut PsSession {
+        self.session
+            .as_mut()
+            .expect("the worker is only taken on drop")
+    }
+}
+
+impl Drop for Checkout<'_> {
+    fn drop(&mut self) {
+        if let Some(mut session) = self.session.take() {
+            if session.is_ended() {
+                // If this fails, the next script tries again
+                let _ = session.recycle();
+            }
+            self.shared.lock().workers[self.index] = Some(session);
+            // Which waiter gets the worker depends on all of them, so they
+            // all have to check
+            self.shared.returned.notify_all();
+        }
+    }
+}
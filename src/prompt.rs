@@ -0,0 +1,206 @@
+//! Detecting scripts which wait for input nobody is going to give them, see
+//! `PsScriptBuilder::input_timeout`, and waiting for scripts while watching
+//! for them to hang.
+
+use std::{
+    io::{BufRead, BufReader, Read},
+    process::{self, Child},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    child,
+    error::PsError,
+    events::{self, ProgressCallback},
+    heartbeat::Stall,
+    tracker::{self, Tracked},
+    usage::ResourceUsage,
+    Result,
+};
+
+/// Written to `stdout` with the prompt before a prompt waits for input.
+const PROMPT_MARKER: &str = "##ps-prompt:";
+/// Written to `stdout` once the prompt returns.
+const ANSWERED_MARKER: &str = "##ps-prompt-answered";
+/// How often `wait` checks whether the process exited or a prompt hung.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Returns the lines defining the functions which wrap the prompting
+/// cmdlets, reporting when they wait for input. `Get-Credential` is only
+/// wrapped if `get_credential` is set, since it doesn't prompt when a
+/// credential provider answers it.
+pub(crate) fn functions(get_credential: bool) -> Vec<String> {
+    let wrapper = |name: &str, params: &str, prompt: &str, command: &str| {
+        format!(
+            "function {} {{ param({}) [Console]::Out.WriteLine('{}' + {}); try {{ {} @PSBoundParameters }} finally {{ [Console]::Out.WriteLine('{}') }} }}",
+            name, params, PROMPT_MARKER, prompt, command, ANSWERED_MARKER
+        )
+    };
+    let mut lines = vec![wrapper(
+        "Read-Host",
+        "[Parameter(Position = 0, ValueFromRemainingArguments = $true)] $Prompt, [switch] $AsSecureString, [switch] $MaskInput",
+        "\"$Prompt\"",
+        "Microsoft.PowerShell.Utility\\Read-Host",
+    )];
+    if get_credential {
+        lines.push(wrapper(
+            "Get-Credential",
+            "[Parameter(Position = 0)] $Credential, [string] $Message, [string] $UserName, [string] $Title",
+            "'Get-Credential ' + $Message",
+            "Microsoft.PowerShell.Security\\Get-Credential",
+        ));
+    }
+    lines
+}
+
+/// What the process was last seen doing.
+#[derive(Debug)]
+struct Activity {
+    /// The prompt the script is waiting at, if any.
+    prompt: Option<String>,
+    /// When the process last wrote to `stdout` or `stderr`.
+    at: Instant,
+}
+
+impl Activity {
+    /// Returns the prompt if the script has been waiting at it without
+    /// writing anything for at least `timeout`.
+    fn hung(&self, timeout: Duration) -> Option<&str> {
+        self.prompt
+            .as_deref()
+            .filter(|_| self.at.elapsed() >= timeout)
+    }
+}
+
+/// Waits for `process` to exit and collects its output like
+/// `Child::wait_with_output`. The process is killed if it waits at a prompt
+/// for longer than `input_timeout`, or if it stalls, which `stall` is
+/// notified of instead if it has a callback. Progress records are passed to
+/// `progress` if it's set. Returns the resources the process used as well.
+pub(crate) fn wait(
+    mut process: Child,
+    mut tracked: Option<Tracked>,
+    input_timeout: Option<Duration>,
+    stall: Option<Stall>,
+    progress: Option<ProgressCallback>,
+) -> Result<(process::Output, Option<ResourceUsage>)> {
+    let activity = Arc::new(Mutex::new(Activity {
+        prompt: None,
+        at: Instant::now(),
+    }));
+
+    let stdout = process.stdout.take().map(|pipe| {
+        let activity = activity.clone();
+        thread::spawn(move || read_stdout(pipe, &activity, progress.as_ref()))
+    });
+    let stderr = process.stderr.take().map(|mut pipe| {
+        let activity = activity.clone();
+        thread::spawn(move || {
+            let mut out = Vec::new();
+            let mut buf = [0; 4096];
+            loop {
+                match pipe.read(&mut buf)? {
+                    0 => return Ok(out),
+                    n => {
+                        out.extend_from_slice(&buf[..n]);
+                        lock(&activity).at = Instant::now();
+                    }
+                }
+            }
+        })
+    });
+
+    // Whether the callback has been told about the current stall
+    let mut notified = false;
+    let (status, usage) = loop {
+        if let Some(exited) = tracker::try_wait(&mut process, tracked.as_mut())? {
+            break exited;
+        }
+        let (hung, silent) = {
+            let activity = lock(&activity);
+            let hung = input_timeout.and_then(|timeout| activity.hung(timeout).map(str::to_string));
+            (hung, activity.at.elapsed())
+        };
+        // The readers finish once the pipes are closed, nobody has to wait
+        // for them.
+        if let Some(prompt) = hung {
+            let _ = process.kill();
+            let _ = tracker::wait(&mut process, tracked.as_mut());
+            return Err(PsError::WaitingForInput(prompt));
+        }
+        if let Some(stall) = &stall {
+            let silent = silent.min(stall.heartbeat.elapsed());
+            if silent < stall.window {
+                notified = false;
+            } else if let Some(callback) = &stall.callback {
+                if !notified {
+                    callback(silent);
+                    notified = true;
+                }
+            } else {
+                let _ = process.kill();
+                let _ = tracker::wait(&mut process, tracked.as_mut());
+                return Err(PsError::Stalled(silent));
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    let output = process::Output {
+        status,
+        stdout: child::join(stdout)?,
+        stderr: child::join(stderr)?,
+    };
+    Ok((output, usage))
+}
+
+/// Reads `stdout` line by line, recording the prompts and leaving them and
+/// the progress records out of the output.
+fn read_stdout(
+    pipe: impl Read,
+    activity: &Mutex<Activity>,
+    progress: Option<&ProgressCallback>,
+) -> std::io::Result<Vec<u8>> {
+    let mut pipe = BufReader::new(pipe);
+    let mut out = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if pipe.read_until(b'\n', &mut line)? == 0 {
+            return Ok(out);
+        }
+        let text = std::str::from_utf8(&line)
+            .ok()
+            .map(|text| text.trim_end_matches(['\r', '\n']));
+
+        let mut activity = lock(activity);
+        activity.at = Instant::now();
+        match text {
+            Some(text) if text.starts_with(PROMPT_MARKER) => {
+                activity.prompt = Some(text[PROMPT_MARKER.len()..].to_string());
+                continue;
+            }
+            Some(ANSWERED_MARKER) => {
+                activity.prompt = None;
+                continue;
+            }
+            _ => {}
+        }
+        drop(activity);
+
+        let record = progress.and_then(|callback| {
+            text.and_then(events::progress_record)
+                .map(|record| (callback, record))
+        });
+        match record {
+            Some((callback, record)) => callback(&record),
+            None => out.extend_from_slice(&line),
+        }
+    }
+}
+
+fn lock(activity: &Mutex<Activity>) -> MutexGuard<'_, Activity> {
+    activity.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,256 @@
+//! Limiting how many PowerShell processes run at once across an
+//! application, see [`ExecutionQuota`].
+
+use std::{
+    fmt,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::{error::PsError, Result};
+
+/// What PowerShell is assumed to use per process unless configured with
+/// `memory_per_process`, which is about what an idle `pwsh` uses.
+const DEFAULT_MEMORY_PER_PROCESS: u64 = 150 * 1024 * 1024;
+
+/// What happens to a run which would exceed an [`ExecutionQuota`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaPolicy {
+    /// Wait until enough of the running processes have exited.
+    Wait,
+    /// Wait like `Wait`, but fail with `PsError::QuotaExceeded` if the run
+    /// still doesn't fit after the given time.
+    WaitFor(Duration),
+    /// Fail with `PsError::QuotaExceeded` right away.
+    Reject,
+}
+
+/// Limits the number of PowerShell processes running at once, and the
+/// memory they're expected to use together, for all the `PsScript`s and
+/// `PsSession`s it's given to with
+/// [`PsScriptBuilder::execution_quota`](crate::PsScriptBuilder::execution_quota).
+/// Runs which would exceed the quota wait for others to finish, or fail,
+/// depending on the [`QuotaPolicy`].
+///
+/// Clones share the same quota, and changing the limits on one changes them
+/// for all of them. A process counts against the quota from the time it's
+/// about to be started until its run is over: until `PsScript::run` and the
+/// other run methods return, a `PsChild` is waited for or dropped, or a
+/// `PsSession` is closed, dropped or recycled.
+///
+/// Memory isn't measured while scripts run. Instead each process reserves
+/// `memory_per_process`, so set it to what the scripts typically need, which
+/// [`Output::resource_usage`](crate::Output::resource_usage) reports.
+///
+/// ## Example
+///
+/// ```rust, no_run
+/// use std::time::Duration;
+/// use powershell_script::{ExecutionQuota, PsScriptBuilder, QuotaPolicy};
+///
+/// let quota = ExecutionQuota::new()
+///     .max_processes(4)
+///     .max_memory(2 * 1024 * 1024 * 1024)
+///     .memory_per_process(300 * 1024 * 1024)
+///     .policy(QuotaPolicy::WaitFor(Duration::from_secs(30)));
+///
+/// let reports = PsScriptBuilder::new().execution_quota(quota.clone()).build();
+/// let maintenance = PsScriptBuilder::new().execution_quota(quota.clone()).build();
+/// reports.run("Get-Date").unwrap();
+/// maintenance.run("Get-Date").unwrap();
+/// ```
+#[derive(Clone)]
+pub struct ExecutionQuota {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    released: Condvar,
+}
+
+#[derive(Debug)]
+struct State {
+    max_processes: Option<usize>,
+    max_memory: Option<u64>,
+    memory_per_process: u64,
+    policy: QuotaPolicy,
+    running: usize,
+    reserved: u64,
+}
+
+impl State {
+    /// Returns why a process reserving `memory` doesn't fit right now, if it
+    /// doesn't.
+    fn exceeded(&self, memory: u64) -> Option<String> {
+        match (self.max_processes, self.max_memory) {
+            (Some(max), _) if self.running >= max => {
+                Some(format!("{} of {} processes are running", self.running, max))
+            }
+            (_, Some(max)) if self.reserved + memory > max => Some(format!(
+                "{} of {} bytes of memory are reserved, and a process needs {}",
+                self.reserved, max, memory
+            )),
+            _ => None,
+        }
+    }
+
+    /// Whether a process reserving `memory` would exceed the quota even if
+    /// nothing else was running.
+    fn never_fits(&self, memory: u64) -> bool {
+        self.max_processes == Some(0) || self.max_memory.is_some_and(|max| memory > max)
+    }
+}
+
+impl ExecutionQuota {
+    /// Creates a quota without any limits, which waits for room when a run
+    /// would exceed the limits set later.
+    pub fn new() -> Self {
+        ExecutionQuota {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    max_processes: None,
+                    max_memory: None,
+                    memory_per_process: DEFAULT_MEMORY_PER_PROCESS,
+                    policy: QuotaPolicy::Wait,
+                    running: 0,
+                    reserved: 0,
+                }),
+                released: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Limits how many processes may run at once.
+    pub fn max_processes(self, max: usize) -> Self {
+        self.lock().max_processes = Some(max);
+        self.shared.released.notify_all();
+        self
+    }
+
+    /// Limits the memory, in bytes, the running processes may reserve
+    /// together, see `memory_per_process`.
+    pub fn max_memory(self, bytes: u64) -> Self {
+        self.lock().max_memory = Some(bytes);
+        self.shared.released.notify_all();
+        self
+    }
+
+    /// Sets how much memory, in bytes, each process reserves. Defaults to
+    /// 150 MiB. Processes which are already running keep what they reserved.
+    pub fn memory_per_process(self, bytes: u64) -> Self {
+        self.lock().memory_per_process = bytes;
+        self.shared.released.notify_all();
+        self
+    }
+
+    /// Sets what happens to runs which would exceed the quota. Defaults to
+    /// `QuotaPolicy::Wait`.
+    pub fn policy(self, policy: QuotaPolicy) -> Self {
+        self.lock().policy = policy;
+        self
+    }
+
+    /// Returns the number of processes currently counting against the
+    /// quota.
+    pub fn running(&self) -> usize {
+        self.lock().running
+    }
+
+    /// Returns the memory, in bytes, reserved by the running processes.
+    pub fn reserved_memory(&self) -> u64 {
+        self.lock().reserved
+    }
+
+    /// Reserves room for a process, waiting for it according to the policy.
+    /// The room is freed again when the permit is dropped.
+    pub(crate) fn acquire(&self) -> Result<QuotaPermit> {
+        let mut state = self.lock();
+        let deadline = match state.policy {
+            QuotaPolicy::WaitFor(timeout) => Some(Instant::now() + timeout),
+            _ => None,
+        };
+        loop {
+            let memory = state.memory_per_process;
+            let reason = match state.exceeded(memory) {
+                None => {
+                    state.running += 1;
+                    state.reserved += memory;
+                    return Ok(QuotaPermit {
+                        quota: self.clone(),
+                        memory,
+                    });
+                }
+                Some(reason) => reason,
+            };
+            if state.never_fits(memory) {
+                return Err(PsError::QuotaExceeded(format!(
+                    "a process needs more than the whole quota: {}",
+                    reason
+                )));
+            }
+            state = match (state.policy, deadline) {
+                (QuotaPolicy::Reject, _) => return Err(PsError::QuotaExceeded(reason)),
+                (_, Some(deadline)) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(PsError::QuotaExceeded(reason));
+                    }
+                    self.shared
+                        .released
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+                (_, None) => self
+                    .shared
+                    .released
+                    .wait(state)
+                    .unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.shared.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for ExecutionQuota {
+    fn default() -> Self {
+        ExecutionQuota::new()
+    }
+}
+
+impl fmt::Debug for ExecutionQuota {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.lock();
+        f.debug_struct("ExecutionQuota")
+            .field("max_processes", &state.max_processes)
+            .field("max_memory", &state.max_memory)
+            .field("memory_per_process", &state.memory_per_process)
+            .field("policy", &state.policy)
+            .field("running", &state.running)
+            .field("reserved", &state.reserved)
+            .finish()
+    }
+}
+
+/// The room one process takes up in an [`ExecutionQuota`], freed when this
+/// is dropped.
+#[derive(Debug)]
+pub(crate) struct QuotaPermit {
+    quota: ExecutionQuota,
+    memory: u64,
+}
+
+impl Drop for QuotaPermit {
+    fn drop(&mut self) {
+        {
+            let mut state = self.quota.lock();
+            state.running -= 1;
+            state.reserved -= self.memory;
+        }
+        self.quota.shared.released.notify_all();
+    }
+}
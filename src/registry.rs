@@ -0,0 +1,399 @@
+use std::{collections::HashMap, io, path::Path};
+
+use crate::{
+    error::PsError,
+    output::Output,
+    source::Script,
+    value::{FromPsValue, PsValue},
+    wrap, PsScript, Result,
+};
+
+/// The type a parameter of a registered script expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamType {
+    /// Any value.
+    Any,
+    String,
+    Int,
+    /// A number, which may be an integer.
+    Float,
+    Bool,
+    Array,
+    Object,
+}
+
+impl ParamType {
+    /// Maps the full name of the .NET type a parameter is declared with.
+    fn from_type_name(name: &str) -> Self {
+        if name.ends_with("[]") {
+            return ParamType::Array;
+        }
+        match name {
+            "System.String" | "System.Char" => ParamType::String,
+            "System.Byte" | "System.SByte" | "System.Int16" | "System.UInt16" | "System.Int32"
+            | "System.UInt32" | "System.Int64" | "System.UInt64" => ParamType::Int,
+            "System.Single" | "System.Double" | "System.Decimal" => ParamType::Float,
+            "System.Boolean" | "System.Management.Automation.SwitchParameter" => ParamType::Bool,
+            "System.Collections.Hashtable"
+            | "System.Collections.Specialized.OrderedDictionary"
+            | "System.Management.Automation.PSCustomObject" => ParamType::Object,
+            _ => ParamType::Any,
+        }
+    }
+
+    fn accepts(self, value: &PsValue) -> bool {
+        matches!(
+            (self, value),
+            (ParamType::Any, _)
+                | (ParamType::String, PsValue::String(_))
+                | (ParamType::Int, PsValue::Int(_))
+                | (ParamType::Float, PsValue::Int(_) | PsValue::Float(_))
+                | (ParamType::Bool, PsValue::Bool(_))
+                | (ParamType::Array, PsValue::Array(_))
+                | (ParamType::Object, PsValue::Object(_))
+        )
+    }
+}
+
+/// A parameter a registered script takes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamSpec {
+    pub name: String,
+    pub kind: ParamType,
+    pub mandatory: bool,
+    /// The values the parameter accepts, compared case-insensitively like
+    /// `[ValidateSet()]` does. Empty if it accepts any value of its type.
+    pub allowed: Vec<String>,
+}
+
+impl ParamSpec {
+    fn check(&self, value: &PsValue) -> std::result::Result<(), String> {
+        if *value == PsValue::Null {
+            return Ok(());
+        }
+        if !self.kind.accepts(value) {
+            return Err(format!(
+                "parameter `{}` expects {:?}, got `{}`",
+                self.name,
+                self.kind,
+                value.to_literal()
+            ));
+        }
+        if self.allowed.is_empty() {
+            return Ok(());
+        }
+        let values = match value {
+            PsValue::Array(items) => items.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            let text = match value {
+                PsValue::String(s) => s.clone(),
+                PsValue::Int(i) => i.to_string(),
+                PsValue::Float(f) => f.to_string(),
+                PsValue::Bool(b) => b.to_string(),
+                other => other.to_literal(),
+            };
+            if !self.allowed.iter().any(|a| a.eq_ignore_ascii_case(&text)) {
+                return Err(format!(
+                    "parameter `{}` must be one of {}, got `{}`",
+                    self.name,
+                    self.allowed.join(", "),
+                    text
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromPsValue for ParamSpec {
+    fn from_ps_value(value: PsValue) -> Result<Self> {
+        let field = |key: &str| value.get(key).cloned().unwrap_or(PsValue::Null);
+        Ok(ParamSpec {
+            name: String::from_ps_value(field("Name"))?,
+            kind: ParamType::from_type_name(
+                value
+                    .get("Type")
+                    .and_then(PsValue::as_str)
+                    .unwrap_or_default(),
+            ),
+            mandatory: field("Mandatory") == PsValue::Bool(true),
+            allowed: Vec::from_ps_value(field("ValidateSet"))?,
+        })
+    }
+}
+
+/// The parameters a registered script takes. Parameters passed to the
+/// script are checked against it before PowerShell is started.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParamSchema {
+    params: Vec<ParamSpec>,
+}
+
+impl ParamSchema {
+    /// A script without parameters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a parameter which must be passed.
+    pub fn required(mut self, name: impl Into<String>, kind: ParamType) -> Self {
+        self.params.push(ParamSpec {
+            name: name.into(),
+            kind,
+            mandatory: true,
+            allowed: Vec::new(),
+        });
+        self
+    }
+
+    /// Adds a parameter which may be left out.
+    pub fn optional(mut self, name: impl Into<String>, kind: ParamType) -> Self {
+        self.params.push(ParamSpec {
+            name: name.into(),
+            kind,
+            mandatory: false,
+            allowed: Vec::new(),
+        });
+        self
+    }
+
+    /// Adds a parameter described by `spec`, for constraints the shorthands
+    /// don't cover, like a set of allowed values.
+    pub fn param(mut self, spec: ParamSpec) -> Self {
+        self.params.push(spec);
+        self
+    }
+
+    /// Reads the schema from the `param()` block of `script` using
+    /// PowerShell's own parser, which `ps` runs. The type, `Mandatory` and
+    /// `[ValidateSet()]` of each parameter are taken into account; other
+    /// validation attributes are left for PowerShell to check. Types which
+    /// don't map to a [`ParamType`] accept any value.
+    ///
+    /// Fails with `PsError::InvalidParameters` if the script doesn't parse.
+    pub fn from_script(ps: &PsScript, script: &str) -> Result<Self> {
+        let program = format!(
+            "$__ps_errors = $null\n\
+             $__ps_ast = [System.Management.Automation.Language.Parser]::ParseInput({}, [ref]$null, [ref]$__ps_errors)\n\
+             $__ps_params = @(if ($__ps_ast.ParamBlock) {{ $__ps_ast.ParamBlock.Parameters | ForEach-Object {{ $__ps_attrs = $_.Attributes; [ordered]@{{ Name = $_.Name.VariablePath.UserPath; Type = $_.StaticType.FullName; Mandatory = [bool]@($__ps_attrs | Where-Object {{ $_.TypeName.Name -eq 'Parameter' }} | ForEach-Object {{ $_.NamedArguments }} | Where-Object {{ $_.ArgumentName -eq 'Mandatory' -and ($_.ExpressionOmitted -or $_.Argument.SafeGetValue()) }}); ValidateSet = @($__ps_attrs | Where-Object {{ $_.TypeName.Name -eq 'ValidateSet' }} | ForEach-Object {{ $_.PositionalArguments | ForEach-Object {{ [string]$_.SafeGetValue() }} }}) }} }} }})\n\
+             {}\n",
+            wrap::quote(script),
+            wrap::emit_block("params", "(ConvertTo-Json -Depth 3 -Compress -InputObject ([ordered]@{ Errors = @($__ps_errors | ForEach-Object { 'line ' + $_.Extent.StartLineNumber + ': ' + $_.Message }); Params = $__ps_params }))")
+        );
+        let output = ps.run_with_args(&program, std::iter::empty::<&str>())?;
+        let json = output.block("params").ok_or_else(|| {
+            PsError::Deserialize("the parser didn't return the parameters".to_string())
+        })?;
+        let parsed = PsValue::from_json(json)?;
+        let field = |key: &str| parsed.get(key).cloned().unwrap_or(PsValue::Null);
+        let errors = Vec::<String>::from_ps_value(field("Errors"))?;
+        if !errors.is_empty() {
+            return Err(PsError::InvalidParameters(format!(
+                "the script doesn't parse: {}",
+                errors.join("; ")
+            )));
+        }
+        Ok(ParamSchema {
+            params: Vec::from_ps_value(field("Params"))?,
+        })
+    }
+
+    /// The parameters, in the order they were added.
+    pub fn params(&self) -> &[ParamSpec] {
+        &self.params
+    }
+
+    /// Checks `params` against the schema. Parameter names are compared
+    /// case-insensitively, like PowerShell does, and returned as they're
+    /// spelled in the schema.
+    fn validate(
+        &self,
+        params: Vec<(String, PsValue)>,
+    ) -> std::result::Result<Vec<(String, PsValue)>, String> {
+        let mut validated: Vec<(String, PsValue)> = Vec::with_capacity(params.len());
+        for (name, value) in params {
+            let spec = self
+                .params
+                .iter()
+                .find(|spec| spec.name.eq_ignore_ascii_case(&name))
+                .ok_or_else(|| format!("unknown parameter `{}`", name))?;
+            if validated.iter().any(|(n, _)| *n == spec.name) {
+                return Err(format!(
+                    "parameter `{}` was passed more than once",
+                    spec.name
+                ));
+            }
+            spec.check(&value)?;
+            validated.push((spec.name.clone(), value));
+        }
+        for spec in self.params.iter().filter(|spec| spec.mandatory) {
+            match validated.iter().find(|(n, _)| *n == spec.name) {
+                None | Some((_, PsValue::Null)) => {
+                    return Err(format!("mandatory parameter `{}` is missing", spec.name))
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(validated)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    script: Script,
+    schema: ParamSchema,
+}
+
+/// A set of named scripts, registered once with the parameters they take
+/// and run by name from anywhere in an application.
+///
+/// Scripts registered from files are read and decoded when they're
+/// registered, not every time they run. Parameters are checked against the
+/// schema before PowerShell is started and passed to the script as named
+/// parameters rendered as PowerShell literals, so the script needs a
+/// `param()` block declaring them.
+///
+/// ## Example
+///
+/// ```rust, no_run
+/// use powershell_script::{ParamSchema, ParamType, PsScriptBuilder, PsValue, ScriptRegistry};
+///
+/// let mut registry = ScriptRegistry::new();
+/// registry.register(
+///     "greet",
+///     "param($Name, $Times = 1) 1..$Times | ForEach-Object { \"Hello $Name\" }",
+///     ParamSchema::new()
+///         .required("Name", ParamType::String)
+///         .optional("Times", ParamType::Int),
+/// );
+///
+/// let ps = PsScriptBuilder::new().build();
+/// let output = registry
+///     .run(&ps, "greet", vec![("Name", PsValue::from("world")), ("Times", PsValue::from(2))])
+///     .unwrap();
+/// println!("{}", output);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ScriptRegistry {
+    scripts: HashMap<String, Entry>,
+}
+
+impl ScriptRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `script` as `name`, replacing any script registered under
+    /// the same name.
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        script: impl Into<Script>,
+        schema: ParamSchema,
+    ) -> &mut Self {
+        self.scripts.insert(
+            name.into(),
+            Entry {
+                script: script.into(),
+                schema,
+            },
+        );
+        self
+    }
+
+    /// Reads the script file at `path` and registers it as `name`, see
+    /// `Script::from_file` for how it's decoded.
+    pub fn register_file<P: AsRef<Path>>(
+        &mut self,
+        name: impl Into<String>,
+        path: P,
+        schema: ParamSchema,
+    ) -> io::Result<&mut Self> {
+        let script = Script::from_file(path)?;
+        Ok(self.register(name, script, schema))
+    }
+
+    /// Registers `script` as `name` with the schema read from its `param()`
+    /// block, see `ParamSchema::from_script`.
+    pub fn register_parsed(
+        &mut self,
+        ps: &PsScript,
+        name: impl Into<String>,
+        script: impl Into<Script>,
+    ) -> Result<&mut Self> {
+        let script = script.into();
+        let schema = ParamSchema::from_script(ps, &script)?;
+        Ok(self.register(name, script, schema))
+    }
+
+    /// Reads the script file at `path` and registers it as `name` with the
+    /// schema read from its `param()` block.
+    pub fn register_file_parsed<P: AsRef<Path>>(
+        &mut self,
+        ps: &PsScript,
+        name: impl Into<String>,
+        path: P,
+    ) -> Result<&mut Self> {
+        let script = Script::from_file(path)?;
+        self.register_parsed(ps, name, script)
+    }
+
+    /// Whether a script is registered as `name`.
+    pub fn contains(&self, name: &str) -> bool {
+        self.scripts.contains_key(name)
+    }
+
+    /// The parameters of the script registered as `name`.
+    pub fn schema(&self, name: &str) -> Option<&ParamSchema> {
+        self.scripts.get(name).map(|entry| &entry.schema)
+    }
+
+    /// Checks `params` against the schema of the script registered as
+    /// `name`, without running it.
+    pub fn validate<I, K, V>(&self, name: &str, params: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Into<PsValue>,
+    {
+        self.prepare(name, params).map(|_| ())
+    }
+
+    /// Runs the script registered as `name` with `params` using `ps`.
+    /// Returns `PsError::UnknownScript` if no script is registered as
+    /// `name` and `PsError::InvalidParameters` if `params` don't match its
+    /// schema.
+    pub fn run<I, K, V>(&self, ps: &PsScript, name: &str, params: I) -> Result<Output>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Into<PsValue>,
+    {
+        let (entry, params) = self.prepare(name, params)?;
+        ps.run_with_params(&entry.script, &params)
+    }
+
+    fn prepare<I, K, V>(&self, name: &str, params: I) -> Result<(&Entry, Vec<(String, PsValue)>)>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Into<PsValue>,
+    {
+        let entry = self
+            .scripts
+            .get(name)
+            .ok_or_else(|| PsError::UnknownScript(name.to_string()))?;
+        let params = params
+            .into_iter()
+            .map(|(k, v)| (k.as_ref().to_string(), v.into()))
+            .collect();
+        let params = entry
+            .schema
+            .validate(params)
+            .map_err(|reason| PsError::InvalidParameters(format!("`{}`: {}", name, reason)))?;
+        Ok((entry, params))
+    }
+}
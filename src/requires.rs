@@ -0,0 +1,90 @@
+//! Checking that the modules a script needs are installed before it runs,
+//! see `PsScriptBuilder::requires`.
+
+use std::fmt;
+
+use crate::{output::Output, value::PsValue, wrap};
+
+/// The tag of the block the missing modules are written in.
+const TAG: &str = "missing-modules";
+
+/// A module a script needs, like in `#Requires -Modules`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ModuleSpec {
+    pub name: String,
+    /// The lowest version of the module which will do, like `"2.1"`. Any
+    /// version will do if it's `None`.
+    pub min_version: Option<String>,
+}
+
+impl ModuleSpec {
+    /// Requires any version of the module `name`.
+    pub fn new(name: impl Into<String>) -> Self {
+        ModuleSpec {
+            name: name.into(),
+            min_version: None,
+        }
+    }
+
+    /// Requires at least `version` of the module.
+    pub fn min_version(mut self, version: impl Into<String>) -> Self {
+        self.min_version = Some(version.into());
+        self
+    }
+}
+
+impl fmt::Display for ModuleSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.min_version {
+            Some(version) => write!(f, "{} (>= {})", self.name, version),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// Returns a line which ends the script with exit code 1 if any of
+/// `modules` isn't installed, reporting which ones.
+pub(crate) fn check(modules: &[ModuleSpec]) -> String {
+    let specs: Vec<String> = modules
+        .iter()
+        .map(|module| {
+            format!(
+                "@{{ Name = {}; MinVersion = {} }}",
+                wrap::quote(&module.name),
+                module
+                    .min_version
+                    .as_deref()
+                    .map_or_else(|| "$null".to_string(), wrap::quote)
+            )
+        })
+        .collect();
+    format!(
+        "$__ps_missing = @(foreach ($__ps_module in @({})) {{ if (-not (Get-Module -ListAvailable -Name $__ps_module.Name | Where-Object {{ -not $__ps_module.MinVersion -or $_.Version -ge [version]$__ps_module.MinVersion }})) {{ $__ps_module }} }}); if ($__ps_missing.Count) {{ {}; [Console]::Error.WriteLine('Missing required modules: ' + (($__ps_missing | ForEach-Object {{ $_.Name }}) -join ', ')); exit 1 }}",
+        specs.join(", "),
+        wrap::emit_block(TAG, "(ConvertTo-Json -Compress -InputObject @($__ps_missing))")
+    )
+}
+
+/// Returns the modules the check reported missing, if it failed.
+pub(crate) fn missing(output: &Output) -> Option<Vec<ModuleSpec>> {
+    let value = PsValue::from_json(output.block(TAG)?).ok()?;
+    let modules = match value {
+        PsValue::Array(modules) => modules,
+        module => vec![module],
+    };
+    let string = |module: &PsValue, key: &str| {
+        module
+            .get(key)
+            .and_then(PsValue::as_str)
+            .map(str::to_string)
+    };
+    Some(
+        modules
+            .iter()
+            .map(|module| ModuleSpec {
+                name: string(module, "Name").unwrap_or_default(),
+                min_version: string(module, "MinVersion"),
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,1443 @@
+use std::{
+    ffi::{OsStr, OsString},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    process::{self, ChildStdin, Command, ExitStatus, Stdio},
+    sync::{mpsc, Arc, OnceLock},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    builder::{ConfirmPolicy, ExecutionMode, StdinEncoding},
+    callback::{CallbackBridge, HostCallback},
+    cancel::CancelSentinel,
+    change,
+    child::{self, PsChild, PsHandle},
+    credential::{CredentialBridge, CredentialProvider},
+    envelope::{self, PsResult},
+    error::PsError,
+    error_record,
+    events::{self, EventSink, OutputEvent, Progress},
+    future::ThreadedRunFuture,
+    heartbeat::{self, Heartbeat, Stall, StallCallback},
+    metrics::Metrics,
+    middleware::{Middleware, RunContext},
+    output::{Cleanup, Output, RunInfo},
+    prompt,
+    quota::{ExecutionQuota, QuotaPermit},
+    requires::{self, ModuleSpec},
+    session::RecyclePolicy,
+    share::NetworkShare,
+    side_channel::{MessageHandler, SideChannel},
+    source::Script,
+    target,
+    template::ScriptTemplate,
+    timeline,
+    tracker::{self, ChildTracker, Tracked},
+    usage::ResourceUsage,
+    value::{FromPsValue, PsValue},
+    workspace::Workspace,
+    wrap, Result,
+};
+
+const NO_ARGS: [&str; 0] = [];
+
+/// An expression evaluating to a hashtable of the environment variables.
+const ENV_SNAPSHOT: &str =
+    "$(& { $vars = @{}; Get-ChildItem env: | ForEach-Object { $vars[$_.Name] = $_.Value }; $vars })";
+
+/// An expression creating the lists `capture_streams` collects messages in.
+const STREAM_LISTS: &str = "[ordered]@{ Verbose = [System.Collections.Generic.List[string]]::new(); Warning = [System.Collections.Generic.List[string]]::new(); Debug = [System.Collections.Generic.List[string]]::new(); Information = [System.Collections.Generic.List[string]]::new() }";
+
+/// Adds the stream record in `$_` to its list in `$__ps_streams`, and passes
+/// anything else through.
+const SORT_STREAMS: &str = "if ($_ -is [System.Management.Automation.VerboseRecord]) { $__ps_streams.Verbose.Add($_.Message) } elseif ($_ -is [System.Management.Automation.WarningRecord]) { $__ps_streams.Warning.Add($_.Message) } elseif ($_ -is [System.Management.Automation.DebugRecord]) { $__ps_streams.Debug.Add($_.Message) } elseif ($_ -is [System.Management.Automation.InformationRecord]) { $__ps_streams.Information.Add([string]$_.MessageData) } else { $_ }";
+
+/// A configured PowerShell runner. Create one using [`PsScriptBuilder`](crate::PsScriptBuilder).
+///
+/// The configuration is shared rather than copied, so cloning a `PsScript`
+/// is cheap. It's `Send` and `Sync`, so one instance can also be shared
+/// between threads as it is.
+#[derive(Clone)]
+pub struct PsScript {
+    pub(crate) args: Arc<[&'static str]>,
+    pub(crate) raw_args: Arc<[OsString]>,
+    pub(crate) env: Arc<[(OsString, OsString)]>,
+    pub(crate) current_dir: Option<PathBuf>,
+    pub(crate) hidden: bool,
+    pub(crate) print_commands: bool,
+    pub(crate) mode: ExecutionMode,
+    pub(crate) capture_return: bool,
+    pub(crate) capture_host: bool,
+    pub(crate) capture_streams: bool,
+    pub(crate) capture_env: bool,
+    pub(crate) capture_location: bool,
+    pub(crate) capture_errors: bool,
+    pub(crate) capture_changes: bool,
+    pub(crate) required_modules: Arc<[ModuleSpec]>,
+    pub(crate) libraries: Arc<[String]>,
+    pub(crate) what_if: bool,
+    pub(crate) confirm: ConfirmPolicy,
+    pub(crate) temp_workspace: bool,
+    pub(crate) cancellation_sentinel: bool,
+    pub(crate) abort_on_error: bool,
+    pub(crate) stdin_buffer_size: usize,
+    pub(crate) split_lines: bool,
+    pub(crate) stdin_encoding: StdinEncoding,
+    pub(crate) console_codepage: Option<u32>,
+    pub(crate) culture: Option<String>,
+    pub(crate) record_timeline: bool,
+    pub(crate) strip_ansi: bool,
+    pub(crate) normalize_newlines: bool,
+    pub(crate) progress: Progress,
+    pub(crate) credential_provider: Option<CredentialProvider>,
+    pub(crate) callbacks: Arc<[(String, HostCallback)]>,
+    pub(crate) message_handler: Option<MessageHandler>,
+    pub(crate) probe_paths: Arc<[PathBuf]>,
+    pub(crate) exit_codes: Arc<[(i32, String)]>,
+    pub(crate) failure_hooks: Arc<[FailureHook]>,
+    pub(crate) middleware: Arc<[Arc<dyn Middleware>]>,
+    pub(crate) metrics: Arc<[Arc<dyn Metrics>]>,
+    pub(crate) tracker: Option<ChildTracker>,
+    pub(crate) quota: Option<ExecutionQuota>,
+    pub(crate) recycle: RecyclePolicy,
+    pub(crate) warm_up: Option<Arc<str>>,
+    pub(crate) health_check: Option<Arc<str>>,
+    pub(crate) input_timeout: Option<Duration>,
+    pub(crate) stall_timeout: Option<Duration>,
+    pub(crate) on_stall: Option<StallCallback>,
+    /// The PowerShell executable, found on the first run and shared by
+    /// clones so later runs skip the search.
+    pub(crate) executable: Arc<OnceLock<OsString>>,
+}
+
+/// Something to run when a script fails, see `PsScriptBuilder::on_failure_run`.
+pub(crate) enum FailureHook {
+    Script(String),
+    Callback(Arc<dyn Fn(&PsError) + Send + Sync>),
+}
+
+impl PsScript {
+    /// Runs the script using the configured [`ExecutionMode`]. Returns an
+    /// instance of `Output` if the script ran successfully and a
+    /// `PsError::Powershell(Output)` if it didn't.
+    pub fn run(&self, script: &str) -> Result<Output> {
+        self.execute(script, Vec::new(), |script| self.program(script))
+    }
+
+    /// Starts the script in the background using the configured
+    /// [`ExecutionMode`] and returns a [`PsChild`] handle to it, which can be
+    /// polled without blocking the calling thread.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use std::{thread, time::Duration};
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().build();
+    /// let mut child = ps.spawn("Start-Sleep -Seconds 2; 'done'").unwrap();
+    /// while child.is_running() {
+    ///     println!("still running after {:?}", child.elapsed());
+    ///     thread::sleep(Duration::from_millis(500));
+    /// }
+    /// let output = child.wait().unwrap();
+    /// assert_eq!(output.stdout().unwrap().trim(), "done");
+    /// ```
+    pub fn spawn(&self, script: &str) -> Result<PsChild> {
+        let ctx = self.before(script, Vec::new())?;
+        let process = self.spawn_raw(script, &ctx.apply_prelude(self.program(script)), true)?;
+        let tracked = self.track(&process);
+        Ok(PsChild::new(
+            process,
+            tracked,
+            ctx,
+            self.middleware.clone(),
+            self.cleanup(),
+            self.progress.clone(),
+        ))
+    }
+
+    /// Starts the script in the background like `spawn`, returning a
+    /// [`PsHandle`] to it which can be shared between threads. This lets one
+    /// thread wait for the script while another cancels or kills it, when
+    /// the user clicks Cancel for example.
+    pub fn spawn_handle(&self, script: &str) -> Result<PsHandle> {
+        self.spawn(script).map(PsHandle::from)
+    }
+
+    /// Starts the script in the background with `stdin` left open, so input
+    /// can be written to it as it becomes available through
+    /// [`PsChild::stdin`]. The script reads the input using `$input`, and it
+    /// ends when `stdin` is closed with [`PsChild::close_stdin`].
+    ///
+    /// Since `stdin` is reserved for input, the script is passed to PowerShell
+    /// as the `-Command` argument instead, which means it's subject to the
+    /// platform's command line length limit (32767 characters on Windows).
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use std::io::Write;
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().build();
+    /// let mut child = ps.spawn_with_input("$input | ForEach-Object { $_.ToUpper() }").unwrap();
+    /// for item in ["a", "b", "c"] {
+    ///     writeln!(child.stdin().unwrap(), "{}", item).unwrap();
+    /// }
+    /// child.close_stdin();
+    /// let output = child.wait().unwrap();
+    /// assert_eq!(output.stdout().unwrap().split_whitespace().collect::<Vec<_>>(), ["A", "B", "C"]);
+    /// ```
+    pub fn spawn_with_input(&self, script: &str) -> Result<PsChild> {
+        let program = if self.mode != ExecutionMode::CallOperator && !self.requires_wrapping() {
+            script.to_string()
+        } else {
+            // Inside a script block `$input` refers to the input of the block
+            // itself, so we pipe the process' input to it.
+            let invocation = format!("$input | {}", wrap::call_operator(script, NO_ARGS));
+            self.wrapped(invocation).join("\n")
+        };
+
+        let ctx = self.before(script, Vec::new())?;
+        let program = ctx.apply_prelude(vec![program]).join("\n");
+
+        let mut cmd = self.command(true)?;
+        if self.mode == ExecutionMode::Encoded {
+            cmd.arg("-EncodedCommand");
+            cmd.arg(wrap::encoded_command(&program));
+        } else {
+            cmd.args(["-Command", &program]);
+        }
+        self.print_script(script);
+        let process = cmd.spawn()?;
+        let tracked = self.track(&process);
+        Ok(PsChild::new(
+            process,
+            tracked,
+            ctx,
+            self.middleware.clone(),
+            self.cleanup(),
+            self.progress.clone(),
+        ))
+    }
+
+    /// Runs the script with the contents of `reader` piped to its `stdin`,
+    /// which the script reads line by line through `$input`. The data is
+    /// streamed, so scripts can process more data than fits in memory
+    /// without writing it to a temporary file first.
+    ///
+    /// The script is passed to PowerShell the same way as for
+    /// [`spawn_with_input`](Self::spawn_with_input). If the script exits
+    /// without reading all of the input, the rest is discarded.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use std::fs::File;
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().build();
+    /// let log = File::open("./huge.log").unwrap();
+    /// let output = ps.run_with_stdin_reader("$input | Select-String -Pattern ERROR | Measure-Object | % Count", log).unwrap();
+    /// println!("{} errors", output.stdout().unwrap().trim());
+    /// ```
+    pub fn run_with_stdin_reader<R: Read>(&self, script: &str, mut reader: R) -> Result<Output> {
+        let mut child = self.spawn_with_input(script)?;
+        if let Some(stdin) = child.stdin() {
+            match io::copy(&mut reader, stdin) {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+                Err(e) => {
+                    let _ = child.kill();
+                    return Err(e.into());
+                }
+            }
+        }
+        child.close_stdin();
+
+        let result = child.wait();
+        if let Err(e) = &result {
+            self.run_failure_hooks(e);
+        }
+        result
+    }
+
+    /// Runs the script wrapped in `& { <script> }`, passing `args` as
+    /// positional string arguments. This lets inline scripts declare a
+    /// `param()` block and use `return` without writing them to a file first.
+    /// The call operator is used regardless of the configured [`ExecutionMode`].
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().build();
+    /// let script = r#"
+    /// param($Name)
+    /// return "hello $Name"
+    /// "#;
+    /// let output = ps.run_with_args(script, &["world"]).unwrap();
+    /// assert_eq!(output.stdout().unwrap().trim(), "hello world");
+    /// ```
+    pub fn run_with_args<I, S>(&self, script: &str, args: I) -> Result<Output>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let args: Vec<String> = args
+            .into_iter()
+            .map(|arg| arg.as_ref().to_string())
+            .collect();
+        self.execute(script, args.clone(), |script| {
+            self.wrapped(wrap::call_operator(script, args))
+        })
+    }
+
+    /// Runs the script and returns the result it reports by calling
+    /// `Write-PsResult $data` or `Write-PsFailure 'message' $details`, which
+    /// are defined for it. Anything else the script writes is ignored, so
+    /// its result isn't mixed up with incidental output. If the functions are
+    /// called more than once the last call counts.
+    ///
+    /// The outer `Result` is an error if the script couldn't be run, or
+    /// failed without reporting a result. The inner [`PsResult`] is what the
+    /// script reported.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().build();
+    /// let script = r#"
+    /// Write-Output 'checking disk space'
+    /// $free = (Get-PSDrive C).Free
+    /// if ($free -lt 1GB) { Write-PsFailure 'not enough disk space' $free; return }
+    /// Write-PsResult $free
+    /// "#;
+    /// match ps.run_enveloped::<u64>(script).unwrap() {
+    ///     Ok(free) => println!("{} bytes free", free),
+    ///     Err(failure) => eprintln!("{} ({})", failure, failure.details),
+    /// }
+    /// ```
+    pub fn run_enveloped<T: FromPsValue>(&self, script: &str) -> Result<PsResult<T>> {
+        envelope::parse(self.execute(script, Vec::new(), |script| {
+            let mut lines = envelope::helpers();
+            lines.extend(self.program(script));
+            lines
+        }))
+    }
+
+    /// Renders `template` with `params` and runs the result, see
+    /// [`ScriptTemplate`] for how placeholders are replaced. Fails with
+    /// `PsError::InvalidParameters` without running anything if the
+    /// placeholders and parameters don't match up.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::{PsScriptBuilder, PsValue};
+    ///
+    /// let ps = PsScriptBuilder::new().build();
+    /// let user_input = "'; Remove-Item -Recurse C:\\ #";
+    /// let params = vec![("path", PsValue::from(user_input))];
+    /// // Looks for a file with that odd name rather than deleting anything
+    /// let result = ps.run_template("Test-Path -LiteralPath <path>", params);
+    /// ```
+    pub fn run_template<I, K, V>(
+        &self,
+        template: impl Into<ScriptTemplate>,
+        params: I,
+    ) -> Result<Output>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Into<PsValue>,
+    {
+        let script = template.into().render(params)?;
+        self.run(&script)
+    }
+
+    /// Reads the PowerShell data file (`.psd1`) at `path`, like a module
+    /// manifest or a configuration file, with `Import-PowerShellDataFile`.
+    /// Data files may only contain literals, so reading one never runs code
+    /// from it.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().build();
+    /// let manifest = ps.read_psd1("./MyModule/MyModule.psd1").unwrap();
+    /// println!("version {}", manifest.get("ModuleVersion").unwrap());
+    /// ```
+    pub fn read_psd1(&self, path: impl AsRef<Path>) -> Result<PsValue> {
+        let path = wrap::quote(&target::long_path(path.as_ref()).to_string_lossy());
+        self.run_json(&format!("Import-PowerShellDataFile -LiteralPath {}", path))
+    }
+
+    /// Runs the script and converts what it writes to the pipeline to `T`
+    /// by passing it through `ConvertTo-Json`. A single object is converted
+    /// as is and several as an array, while no output at all is `null`.
+    /// Output from `Write-Host` and the other streams doesn't end up in the
+    /// result, and neither does the return value captured with
+    /// `capture_return_value`.
+    ///
+    /// Fails with `PsError::Deserialize` if the output doesn't fit `T`.
+    ///
+    /// `T` is converted with the crate's [`FromPsValue`], also used by
+    /// `invoke_function`, since the crate doesn't depend on serde. To
+    /// deserialize with serde instead, end the script with `| ConvertTo-Json`
+    /// and pass [`Output::stdout`] to `serde_json::from_str`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use std::collections::BTreeMap;
+    ///
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().build();
+    /// let script = "Get-Process | Select-Object -First 5 Name, Id";
+    /// let processes: Vec<BTreeMap<String, String>> = ps.run_json(script).unwrap();
+    /// for process in processes {
+    ///     println!("{} ({})", process["Name"], process["Id"]);
+    /// }
+    /// ```
+    pub fn run_json<T: FromPsValue>(&self, script: &str) -> Result<T> {
+        let output = self.execute(script, Vec::new(), |script| {
+            self.wrapped_with(
+                format!("$__ps_json = @({})", wrap::call_operator(script, NO_ARGS)),
+                vec![
+                    "$__ps_json = if ($__ps_json.Count -eq 1) { $__ps_json[0] } elseif ($__ps_json.Count -eq 0) { $null } else { $__ps_json }".to_string(),
+                    wrap::emit_block(
+                        "json",
+                        "(ConvertTo-Json -InputObject $__ps_json -Depth 100 -Compress)",
+                    ),
+                ],
+            )
+        })?;
+        let json = output.block("json").ok_or_else(|| {
+            PsError::Deserialize("the script's output wasn't converted to JSON".to_string())
+        })?;
+        T::from_ps_value(PsValue::from_json(json)?)
+    }
+
+    /// Loads `file` and calls `function` with `params` as named parameters,
+    /// returning the function's output. Modules (`.psm1`) are imported with
+    /// `Import-Module` while any other file is dot-sourced. The parameters are
+    /// splatted and rendered as PowerShell literals, so their values are never
+    /// interpreted as code.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::{PsScriptBuilder, PsValue};
+    ///
+    /// let ps = PsScriptBuilder::new().build();
+    /// let params = vec![
+    ///     ("Name", PsValue::from("it's a widget")),
+    ///     ("Count", PsValue::from(3)),
+    ///     ("Force", PsValue::from(true)),
+    /// ];
+    /// let output = ps.invoke_function("./Widgets.psm1", "New-Widget", params).unwrap();
+    /// println!("{}", output);
+    /// ```
+    pub fn invoke_function<P, I, K, V>(&self, file: P, function: &str, params: I) -> Result<Output>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Into<PsValue>,
+    {
+        let file = file.as_ref();
+        let is_module = file
+            .extension()
+            .map(|ext| ext.eq_ignore_ascii_case("psm1"))
+            .unwrap_or(false);
+
+        let path = wrap::quote(&target::long_path(file).to_string_lossy());
+        let mut script = if is_module {
+            format!("Import-Module -Name {} -Force\n", path)
+        } else {
+            format!(". {}\n", path)
+        };
+
+        let params: Vec<String> = params
+            .into_iter()
+            .map(|(k, v)| format!("{} = {}", wrap::quote(k.as_ref()), v.into().to_literal()))
+            .collect();
+        script.push_str(&format!("$__ps_params = @{{{}}}\n", params.join("; ")));
+        // Preference variables don't reach functions in modules, so the
+        // switches are passed to functions which support them
+        let mut switches = Vec::new();
+        if self.what_if {
+            switches.push(("WhatIf", "$true"));
+        }
+        if self.confirm == ConfirmPolicy::None {
+            switches.push(("Confirm", "$false"));
+        }
+        if !switches.is_empty() {
+            script.push_str(&format!(
+                "$__ps_command = Get-Command -Name {}\n",
+                wrap::quote(function)
+            ));
+        }
+        for (name, value) in switches {
+            script.push_str(&format!(
+                "if ($__ps_command.Parameters.ContainsKey('{0}')) {{ $__ps_params['{0}'] = {1} }}\n",
+                name, value
+            ));
+        }
+        script.push_str(&format!("& {} @__ps_params\n", wrap::quote(function)));
+
+        self.execute(&script, Vec::new(), |script| {
+            self.wrapped(wrap::call_operator(script, NO_ARGS))
+        })
+    }
+
+    /// Runs the script file at `path`, which can be a local path or a UNC
+    /// path like `\\server\share\deploy.ps1`. The file is invoked with the
+    /// call operator, so its `param()` block and `return` work as expected.
+    ///
+    /// Note that the execution policy may refuse to run scripts from a
+    /// network location.
+    pub fn run_file<P: AsRef<Path>>(&self, path: P) -> Result<Output> {
+        let path = target::long_path(path.as_ref());
+        let script = format!("& {}", wrap::quote(&path.to_string_lossy()));
+        self.execute(&script, Vec::new(), |script| {
+            self.wrapped(wrap::call_operator(script, NO_ARGS))
+        })
+    }
+
+    /// Reads the script file at `path` and runs it like [`run`](Self::run)
+    /// would run its content, which isn't subject to the execution policy
+    /// like running the file with `run_file` is. See `Script::from_file` for
+    /// how the file is decoded.
+    pub fn run_from_path<P: AsRef<Path>>(&self, path: P) -> Result<Output> {
+        let script = Script::from_file(path)?;
+        self.run(&script)
+    }
+
+    /// Connects to `share`, runs the script `file` (relative to the root of
+    /// the share) and disconnects again. Use this to run scripts from a share
+    /// which requires credentials.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::{NetworkShare, PsScriptBuilder};
+    ///
+    /// let ps = PsScriptBuilder::new().build();
+    /// let share = NetworkShare::new(r"\\fileserver\deploy").with_credentials(r"CORP\svc", "hunter2");
+    /// let output = ps.run_file_from_share(&share, r"app\install.ps1").unwrap();
+    /// println!("{}", output);
+    /// ```
+    pub fn run_file_from_share<P: AsRef<Path>>(
+        &self,
+        share: &NetworkShare,
+        file: P,
+    ) -> Result<Output> {
+        let file = file.as_ref();
+        // The credentials are only part of the program we send to
+        // PowerShell, the middleware and `print_commands` see the plain path
+        let script = format!("& {}", wrap::quote(&share.path_of(file)));
+        self.run_with_secrets(&script, &share.script(file))
+    }
+
+    /// Runs `script_block` for each of `items` in parallel with
+    /// `ForEach-Object -Parallel`, at most `throttle` at a time, and returns
+    /// what it output for each item, in the order of `items`. The item is
+    /// available as `$_` and is rendered as a PowerShell literal, so its
+    /// value is never interpreted as code.
+    ///
+    /// Each item's output is converted with [`FromPsValue`]: a single object
+    /// is converted as it is, more than one as an array and none as `$null`.
+    /// The script fails if the script block fails for any item.
+    ///
+    /// `ForEach-Object -Parallel` requires PowerShell 7 or later, and since
+    /// each item runs in its own runspace, variables and functions of the
+    /// surrounding script aren't available to the script block.
+    ///
+    /// [`FromPsValue`]: crate::FromPsValue
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().build();
+    /// let hosts = ["build-01", "build-02", "build-03"];
+    /// let online: Vec<bool> = ps
+    ///     .parallel_foreach(hosts, "Test-Connection -TargetName $_ -Count 1 -Quiet", 8)
+    ///     .unwrap();
+    /// ```
+    pub fn parallel_foreach<I, V, T>(
+        &self,
+        items: I,
+        script_block: &str,
+        throttle: usize,
+    ) -> Result<Vec<T>>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<PsValue>,
+        T: FromPsValue,
+    {
+        let items: Vec<String> = items
+            .into_iter()
+            .map(|item| item.into().to_literal())
+            .collect();
+        let count = items.len();
+        // Script blocks can't be passed to other runspaces with `$using:`, so
+        // each runspace creates its own from the source
+        let script = format!(
+            "$__ps_items = @({items})\n\
+             $__ps_source = {source}\n\
+             $__ps_results = @(@(for ($__ps_i = 0; $__ps_i -lt $__ps_items.Count; $__ps_i++) {{ [pscustomobject]@{{ Index = $__ps_i; Item = $__ps_items[$__ps_i] }} }}) | ForEach-Object -ThrottleLimit {throttle} -Parallel {{ $ErrorActionPreference = 'Stop'; $__ps_entry = $_; [pscustomobject]@{{ Index = $__ps_entry.Index; Output = @(ForEach-Object -InputObject $__ps_entry.Item -Process ([scriptblock]::Create($using:__ps_source))) }} }})\n\
+             if ($__ps_results.Count -ne $__ps_items.Count) {{ throw \"the script block failed for $($__ps_items.Count - $__ps_results.Count) of $($__ps_items.Count) items\" }}\n\
+             $__ps_outputs = [System.Collections.Generic.List[object]]::new(); foreach ($__ps_result in ($__ps_results | Sort-Object Index)) {{ switch ($__ps_result.Output.Count) {{ 0 {{ $__ps_outputs.Add($null) }} 1 {{ $__ps_outputs.Add($__ps_result.Output[0]) }} default {{ $__ps_outputs.Add($__ps_result.Output) }} }} }}\n\
+             {emit}\n",
+            items = items.join(", "),
+            source = wrap::quote(script_block),
+            throttle = throttle.max(1),
+            emit = wrap::emit_block("parallel", "(ConvertTo-Json -InputObject $__ps_outputs.ToArray() -Depth 10 -Compress)"),
+        );
+
+        let output = self.execute(&script, Vec::new(), |script| {
+            self.wrapped(wrap::call_operator(script, NO_ARGS))
+        })?;
+        let json = output.block("parallel").ok_or_else(|| {
+            PsError::Deserialize("the script didn't write the results of the items".to_string())
+        })?;
+        let results = match PsValue::from_json(json)? {
+            PsValue::Array(results) => results,
+            other => vec![other],
+        };
+        if results.len() != count {
+            return Err(PsError::Deserialize(format!(
+                "expected results for {} items, got {}",
+                count,
+                results.len()
+            )));
+        }
+        results.into_iter().map(T::from_ps_value).collect()
+    }
+
+    /// Runs `script` as a script block with `params` splatted onto it as
+    /// named parameters, rendered as PowerShell literals.
+    pub(crate) fn run_with_params(
+        &self,
+        script: &str,
+        params: &[(String, PsValue)],
+    ) -> Result<Output> {
+        let literals: Vec<String> = params
+            .iter()
+            .map(|(k, v)| format!("{} = {}", wrap::quote(k), v.to_literal()))
+            .collect();
+        let args = params
+            .iter()
+            .map(|(k, v)| format!("-{} {}", k, v.to_literal()))
+            .collect();
+        self.execute(script, args, |script| {
+            let program = format!(
+                "$__ps_params = @{{{}}}\n& ({}) @__ps_params",
+                literals.join("; "),
+                wrap::script_block(script)
+            );
+            self.wrapped(wrap::call_operator(&program, NO_ARGS))
+        })
+    }
+
+    /// Runs `program` as a script block while middleware and
+    /// `print_commands` see `script`, for programs containing secrets.
+    pub(crate) fn run_with_secrets(&self, script: &str, program: &str) -> Result<Output> {
+        self.execute(script, Vec::new(), |_| {
+            self.wrapped(wrap::call_operator(program, NO_ARGS))
+        })
+    }
+
+    /// Runs the script, delivering its output to `sink` as [`OutputEvent`]s
+    /// while it runs instead of collecting it. Lines written with
+    /// `Write-Progress` and to the verbose stream are delivered as their own
+    /// events. Blocks until the script exits and returns its exit status.
+    ///
+    /// The script always runs as a script block and options which capture
+    /// output, like `capture_return_value`, don't apply. Middleware `before`
+    /// hooks run as usual, but since there's no `Output`, `after` hooks and
+    /// failure hooks don't.
+    ///
+    /// Pass a channel of [`TimedEvent`]s to also get the monotonic and
+    /// wall-clock time each line was read at, for aligning the output with
+    /// other logs.
+    ///
+    /// [`OutputEvent`]: crate::OutputEvent
+    /// [`TimedEvent`]: crate::TimedEvent
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use std::{sync::mpsc, thread};
+    /// use powershell_script::{OutputEvent, PsScriptBuilder};
+    ///
+    /// let ps = PsScriptBuilder::new().build();
+    /// let (tx, rx) = mpsc::channel();
+    /// thread::spawn(move || {
+    ///     for event in rx {
+    ///         match event {
+    ///             OutputEvent::Progress(p) => println!("{}: {:?}%", p.activity, p.percent_complete),
+    ///             OutputEvent::Stdout(line) => println!("{}", line),
+    ///             _ => {}
+    ///         }
+    ///     }
+    /// });
+    /// let script = "1..3 | % { Write-Progress -Activity Work -PercentComplete ($_ * 33); $_ }";
+    /// ps.run_with_events(script, tx).unwrap();
+    /// ```
+    pub fn run_with_events<S: EventSink>(&self, script: &str, sink: S) -> Result<ExitStatus> {
+        let (process, tracked, ctx) = self.spawn_events(script)?;
+        Ok(events::forward(
+            process,
+            tracked,
+            ctx.execution().clone(),
+            sink,
+        )?)
+    }
+
+    /// Runs `script` like [`run`](Self::run), calling `on_stdout` and
+    /// `on_stderr` with each line the script writes as soon as it's written,
+    /// without the line ending. The callbacks run on the calling thread.
+    ///
+    /// Verbose, warning and debug messages are passed to `on_stdout`
+    /// prefixed like PowerShell shows them, `VERBOSE: ` for example. The
+    /// output is run like with [`run_with_events`](Self::run_with_events),
+    /// so the capture options don't apply; use that for progress and for
+    /// telling the streams apart.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// let ps = PsScriptBuilder::new().build();
+    /// let output = ps
+    ///     .run_streaming(
+    ///         "1..3 | % { \"step $_\"; Start-Sleep 1 }",
+    ///         |line| println!("{}", line),
+    ///         |line| eprintln!("{}", line),
+    ///     )
+    ///     .unwrap();
+    /// assert!(output.success());
+    /// ```
+    pub fn run_streaming<O, E>(
+        &self,
+        script: &str,
+        mut on_stdout: O,
+        mut on_stderr: E,
+    ) -> Result<Output>
+    where
+        O: FnMut(&str),
+        E: FnMut(&str),
+    {
+        let (process, tracked, ctx) = self.spawn_events(script)?;
+        let (tx, rx) = mpsc::channel();
+        let execution = ctx.execution().clone();
+        let reader = thread::spawn(move || events::forward(process, tracked, execution, tx));
+
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let mut stdout_line = |line: &str| {
+            on_stdout(line);
+            stdout.extend_from_slice(line.as_bytes());
+            stdout.push(b'\n');
+        };
+        for event in rx {
+            match event {
+                OutputEvent::Stdout(line) => stdout_line(&line),
+                OutputEvent::Verbose(line) => stdout_line(&format!("VERBOSE: {}", line)),
+                OutputEvent::Warning(line) => stdout_line(&format!("WARNING: {}", line)),
+                OutputEvent::Debug(line) => stdout_line(&format!("DEBUG: {}", line)),
+                OutputEvent::Stderr(line) => {
+                    on_stderr(&line);
+                    stderr.extend_from_slice(line.as_bytes());
+                    stderr.push(b'\n');
+                }
+                _ => {}
+            }
+        }
+        let status = reader
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("output reader thread panicked")));
+
+        let mut result = into_result(
+            process::Output {
+                status: status?,
+                stdout,
+                stderr,
+            },
+            None,
+            self.cleanup(),
+        );
+        attach_context(&mut result, &ctx);
+        after(&self.middleware, &ctx, &result);
+        if let Err(e) = &result {
+            self.run_failure_hooks(e);
+        }
+        result
+    }
+
+    /// Starts the script and returns a channel receiving its output as
+    /// [`OutputEvent`]s, along with a handle to the thread reading the
+    /// output which returns the exit status. The receiver can be polled with
+    /// `try_recv` from a GUI thread without blocking it. Works like
+    /// [`run_with_events`](Self::run_with_events) otherwise.
+    ///
+    /// [`OutputEvent`]: crate::OutputEvent
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::{OutputEvent, PsScriptBuilder};
+    ///
+    /// let ps = PsScriptBuilder::new().build();
+    /// let (handle, events) = ps.run_channel("1..3 | % { $_; Start-Sleep 1 }").unwrap();
+    /// for event in events {
+    ///     if let OutputEvent::Stdout(line) = event {
+    ///         println!("{}", line);
+    ///     }
+    /// }
+    /// let status = handle.join().unwrap().unwrap();
+    /// assert!(status.success());
+    /// ```
+    pub fn run_channel(
+        &self,
+        script: &str,
+    ) -> Result<(
+        thread::JoinHandle<Result<ExitStatus>>,
+        mpsc::Receiver<OutputEvent>,
+    )> {
+        let (process, tracked, ctx) = self.spawn_events(script)?;
+        let (tx, rx) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            Ok(events::forward(
+                process,
+                tracked,
+                ctx.execution().clone(),
+                tx,
+            )?)
+        });
+        Ok((handle, rx))
+    }
+
+    /// Starts the script and returns a receiver for its output as
+    /// [`OutputEvent`]s. At most `capacity` events are buffered; when the
+    /// consumer falls behind the output isn't read until it catches up.
+    /// Works like [`run_with_events`](Self::run_with_events) otherwise.
+    ///
+    /// The exit status is delivered as `OutputEvent::Exited`. If reading the
+    /// output fails the channel is closed without it.
+    ///
+    /// This crate only depends on `std`, so the receiver is a plain
+    /// [`mpsc::Receiver`] and receiving blocks. Async code should receive on
+    /// a blocking task of its runtime, like tokio's `spawn_blocking`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::{OutputEvent, PsScriptBuilder};
+    ///
+    /// let ps = PsScriptBuilder::new().build();
+    /// let events = ps.run_events("Get-Content ./big.log", 64).unwrap();
+    /// for event in events {
+    ///     if let OutputEvent::Stdout(line) = event {
+    ///         println!("{}", line);
+    ///     }
+    /// }
+    /// ```
+    pub fn run_events(&self, script: &str, capacity: usize) -> Result<mpsc::Receiver<OutputEvent>> {
+        let (process, tracked, ctx) = self.spawn_events(script)?;
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        thread::spawn(move || events::forward(process, tracked, ctx.execution().clone(), tx));
+        Ok(rx)
+    }
+
+    /// Runs `script` like [`run`](Self::run) on a new OS thread, and
+    /// returns a future resolving to the result, so async code can run
+    /// scripts without blocking the thread it runs on. Works with any async
+    /// runtime, and uses the same options as `run`.
+    ///
+    /// The future isn't driven by the runtime: each call costs a thread
+    /// blocked on the script, see [`ThreadedRunFuture`].
+    ///
+    /// ## Example
+    ///
+    /// ```rust, no_run
+    /// use powershell_script::PsScriptBuilder;
+    ///
+    /// async fn uptime() -> String {
+    ///     let ps = PsScriptBuilder::new().build();
+    ///     let output = ps.run_async("(Get-Uptime).TotalHours").await.unwrap();
+    ///     output.stdout().unwrap()
+    /// }
+    /// ```
+    pub fn run_async(&self, script: &str) -> ThreadedRunFuture {
+        ThreadedRunFuture::start(self.clone(), script)
+    }
+
+    /// Spawns PowerShell running `script` with its output translated to
+    /// events. The guard has to be given to `events::forward` with the
+    /// process, and the context has to be kept until it has exited.
+    fn spawn_events(&self, script: &str) -> Result<(process::Child, Option<Tracked>, RunContext)> {
+        let ctx = self.before(script, Vec::new())?;
+        let process = self.spawn_raw(script, &ctx.apply_prelude(events::program(script)), false)?;
+        let tracked = self.track(&process);
+        Ok((process, tracked, ctx))
+    }
+
+    /// Tracks `process` with the configured [`ChildTracker`]. The process
+    /// has to be waited for with `tracker::wait` or `tracker::try_wait` and
+    /// the returned guard, which stop tracking it as it's reaped.
+    pub(crate) fn track(&self, process: &process::Child) -> Option<Tracked> {
+        self.tracker.as_ref().map(|tracker| tracker.track(process))
+    }
+
+    /// Reserves room for a process in the configured [`ExecutionQuota`],
+    /// which is freed when the returned permit is dropped.
+    pub(crate) fn admit(&self) -> Result<Option<QuotaPermit>> {
+        self.quota.as_ref().map(ExecutionQuota::acquire).transpose()
+    }
+
+    /// Returns the lines to send to PowerShell to run `script` using the
+    /// configured [`ExecutionMode`].
+    fn program(&self, script: &str) -> Vec<String> {
+        if self.mode == ExecutionMode::Raw && !self.requires_wrapping() {
+            let script = script.strip_suffix('\n').unwrap_or(script);
+            return vec![script.to_string()];
+        }
+        let stdin_or_encoded = matches!(self.mode, ExecutionMode::Stdin | ExecutionMode::Encoded);
+        if stdin_or_encoded && !self.requires_wrapping() {
+            if self.abort_on_error {
+                let script = timeline::abort_on_error(script);
+                return vec![script.trim_end_matches('\n').to_string()];
+            }
+            if !self.split_lines {
+                return vec![script.to_string()];
+            }
+            script.lines().map(str::to_string).collect()
+        } else {
+            self.wrapped(wrap::call_operator(script, NO_ARGS))
+        }
+    }
+
+    /// Returns the lines which run `invocation` (a script invoked through the
+    /// call operator) along with whatever the configured options need to wrap
+    /// around it.
+    fn wrapped(&self, invocation: String) -> Vec<String> {
+        self.wrapped_with(invocation, Vec::new())
+    }
+
+    /// Like `wrapped`, running the lines of `epilogue` after the script
+    /// before anything the options add.
+    fn wrapped_with(&self, mut invocation: String, mut epilogue: Vec<String>) -> Vec<String> {
+        let mut prelude = Vec::new();
+
+        if self.capture_host {
+            // `Write-Host` writes to the information stream, so we merge it
+            // into the pipeline and pick the records out again.
+            prelude
+                .push("$__ps_host = [System.Collections.Generic.List[string]]::new()".to_string());
+            invocation = format!(
+                "{} 6>&1 | ForEach-Object {{ if ($_ -is [System.Management.Automation.InformationRecord]) {{ $__ps_host.Add([string]$_.MessageData) }} else {{ $_ }} }}",
+                invocation
+            );
+            epilogue.push(wrap::emit_block("host", "($__ps_host -join \"`n\")"));
+        }
+
+        if self.capture_streams {
+            // Like the host output above, but for all the streams that aren't
+            // output or errors. The script runs in its own script block so
+            // the redirections apply to all of it and not just the last
+            // command of a pipeline.
+            prelude.push(format!("$__ps_streams = {}", STREAM_LISTS));
+            invocation = format!(
+                "& {{ {} }} 3>&1 4>&1 5>&1 6>&1 | ForEach-Object {{ {} }}",
+                invocation, SORT_STREAMS
+            );
+            epilogue.push(wrap::emit_block(
+                "streams",
+                "(ConvertTo-Json -Compress -InputObject $__ps_streams)",
+            ));
+        }
+
+        if self.capture_env {
+            prelude.push(format!("$__ps_env = {}", ENV_SNAPSHOT));
+            epilogue.push(wrap::emit_block(
+                "env",
+                &format!(
+                    "(ConvertTo-Json -Compress -InputObject @{{ Before = $__ps_env; After = {} }})",
+                    ENV_SNAPSHOT
+                ),
+            ));
+        }
+
+        if self.capture_location {
+            // The provider path is the real directory even if the location
+            // was set through a PSDrive, or is outside of the file system
+            epilogue.push(wrap::emit_block(
+                "location",
+                "$ExecutionContext.SessionState.Path.CurrentFileSystemLocation.ProviderPath",
+            ));
+        }
+
+        if self.capture_errors {
+            prelude.push("$Error.Clear()".to_string());
+            epilogue.push(wrap::emit_block("errors", error_record::COLLECT_ERRORS));
+        }
+
+        if self.capture_return {
+            // Everything but the last object written to the pipeline is passed
+            // through as regular output, the last one is the return value.
+            invocation = format!("$__ps_result = @({})", invocation);
+            epilogue.push(
+                "if ($__ps_result.Count -gt 1) { $__ps_result[0..($__ps_result.Count - 2)] }"
+                    .to_string(),
+            );
+            epilogue.push(
+                "$__ps_return = if ($__ps_result.Count -gt 0) { $__ps_result[-1] } else { $null }"
+                    .to_string(),
+            );
+            epilogue.push(wrap::emit_block(
+                "return",
+                "(ConvertTo-Json -InputObject $__ps_return -Depth 10 -Compress)",
+            ));
+        }
+
+        if prelude.is_empty() && epilogue.is_empty() {
+            return vec![invocation];
+        }
+
+        // The exit code of PowerShell reflects the last command it ran, so we
+        // need to keep track of how the script itself went.
+        let mut lines = prelude;
+        lines.push("$__ps_ok = $true".to_string());
+        lines.push(format!(
+            "try {{ {}; $__ps_ok = $? }} catch {{ $__ps_ok = $false; Write-Error -ErrorRecord $_ }}",
+            invocation
+        ));
+        lines.extend(epilogue);
+        lines.push("if (-not $__ps_ok) { exit 1 }".to_string());
+        lines
+    }
+
+    /// Whether the configured options require the script to run as a script
+    /// block even when using `ExecutionMode::Stdin`.
+    fn requires_wrapping(&self) -> bool {
+        self.capture_return
+            || self.capture_host
+            || self.capture_streams
+            || self.capture_env
+            || self.capture_location
+            || self.capture_errors
+    }
+
+    /// Runs `script` to completion with the middleware and failure hooks
+    /// applied. `program` generates the lines to send to PowerShell, which
+    /// it's given the instrumented script for if a timeline is recorded.
+    fn execute(
+        &self,
+        script: &str,
+        args: Vec<String>,
+        program: impl FnOnce(&str) -> Vec<String>,
+    ) -> Result<Output> {
+        let ctx = self.before(script, args)?;
+        let mut result = if self.record_timeline && self.mode != ExecutionMode::Raw {
+            let (instrumented, commands) = timeline::instrument(script);
+            let process =
+                self.spawn_raw(script, &ctx.apply_prelude(program(&instrumented)), false)?;
+            let tracked = self.track(&process);
+            let (mut proc_output, timeline, usage) = timeline::collect(process, tracked, commands)?;
+            if let Progress::Capture(callback) = &self.progress {
+                proc_output.stdout = events::filter_progress(&proc_output.stdout[..], callback)?;
+            }
+            let mut result = into_result(proc_output, usage, self.cleanup());
+            if let Err(PsError::Powershell(_)) = &result {
+                if self.print_commands {
+                    eprintln!("Script failed, timeline of the commands run:\n{}", timeline);
+                }
+            }
+            if let Ok(output) | Err(PsError::Powershell(output)) = &mut result {
+                output.timeline = Some(Box::new(timeline));
+            }
+            result
+        } else {
+            let (proc_output, usage) = self.run_raw(
+                script,
+                &ctx.apply_prelude(program(script)),
+                ctx.heartbeat.as_ref(),
+            )?;
+            into_result(proc_output, usage, self.cleanup())
+        };
+        attach_context(&mut result, &ctx);
+        after(&self.middleware, &ctx, &result);
+        if let Err(e) = &result {
+            self.run_failure_hooks(e);
+        }
+        result
+    }
+
+    /// Runs the `before` hook of all middleware.
+    fn before(&self, script: &str, args: Vec<String>) -> Result<RunContext> {
+        // Waiting for room isn't part of the run's duration
+        let permit = self.admit()?;
+        let mut ctx = RunContext::new(script, args);
+        ctx.permit = permit.map(Arc::new);
+        ctx.exit_codes = self.exit_codes.clone();
+        if !self.required_modules.is_empty() {
+            ctx.add_prelude(requires::check(&self.required_modules));
+        }
+        for line in self.progress.prelude() {
+            ctx.add_prelude(line);
+        }
+        if let Some(codepage) = self.console_codepage {
+            // Setting the encoding sets the code page of the console with
+            // `SetConsoleOutputCP` on Windows
+            ctx.add_prelude(format!(
+                "[Console]::OutputEncoding = [Text.Encoding]::GetEncoding({}); $OutputEncoding = [Console]::OutputEncoding",
+                codepage
+            ));
+        }
+        if let Some(culture) = &self.culture {
+            ctx.add_prelude(format!(
+                "$__ps_culture = [Globalization.CultureInfo]::GetCultureInfo({}); [Globalization.CultureInfo]::DefaultThreadCurrentCulture = $__ps_culture; [Globalization.CultureInfo]::DefaultThreadCurrentUICulture = $__ps_culture; [Threading.Thread]::CurrentThread.CurrentCulture = $__ps_culture; [Threading.Thread]::CurrentThread.CurrentUICulture = $__ps_culture",
+                wrap::quote(culture)
+            ));
+        }
+        if let Some(provider) = &self.credential_provider {
+            let bridge = CredentialBridge::start(provider.clone())?;
+            ctx.add_prelude(bridge.function());
+            ctx.credentials = Some(Arc::new(bridge));
+        }
+        if !self.callbacks.is_empty() {
+            let bridge = CallbackBridge::start(self.callbacks.clone())?;
+            ctx.add_prelude(bridge.function());
+            ctx.callbacks = Some(Arc::new(bridge));
+        }
+        let heartbeat = self.stall_timeout.map(|_| Arc::new(Heartbeat::new()));
+        let handler = match &heartbeat {
+            Some(heartbeat) => Some(heartbeat.handler(self.message_handler.clone())),
+            None => self.message_handler.clone(),
+        };
+        if let Some(handler) = handler {
+            let channel = SideChannel::start(handler)?;
+            ctx.add_prelude(channel.function());
+            ctx.side_channel = Some(Arc::new(channel));
+        }
+        if let Some(window) = self.stall_timeout {
+            ctx.add_prelude(heartbeat::timer(window));
+            ctx.heartbeat = heartbeat;
+        }
+        if self.capture_changes {
+            ctx.add_prelude(change::function());
+        }
+        if self.input_timeout.is_some() {
+            for line in prompt::functions(self.credential_provider.is_none()) {
+                ctx.add_prelude(line);
+            }
+        }
+        if self.what_if {
+            ctx.add_prelude("$WhatIfPreference = $true");
+        }
+        if let Some(preference) = self.confirm.preference() {
+            ctx.add_prelude(format!("$ConfirmPreference = '{}'", preference));
+        }
+        if self.cancellation_sentinel {
+            let sentinel = CancelSentinel::new(ctx.execution().id);
+            ctx.add_prelude(sentinel.function());
+            ctx.cancel = Some(Arc::new(sentinel));
+        }
+        if self.temp_workspace {
+            let workspace = Workspace::create(ctx.execution().id)?;
+            let path = workspace.path().to_string_lossy();
+            ctx.add_prelude(format!("$env:PS_WORKSPACE = {}", wrap::quote(&path)));
+            ctx.workspace = Some(Arc::new(workspace));
+        }
+        for line in self.library_lines() {
+            ctx.add_prelude(line);
+        }
+        for middleware in self.middleware.iter() {
+            middleware.before(&mut ctx)?;
+        }
+        Ok(ctx)
+    }
+
+    fn run_failure_hooks(&self, error: &PsError) {
+        for hook in self.failure_hooks.iter() {
+            match hook {
+                FailureHook::Script(cleanup) => {
+                    let lines: Vec<String> = cleanup.lines().map(str::to_string).collect();
+                    // The original error is what the caller needs to see, a
+                    // failing cleanup script doesn't change that.
+                    let _ = self.run_raw(cleanup, &lines, None);
+                }
+                FailureHook::Callback(callback) => callback(error),
+            }
+        }
+    }
+
+    /// Runs `lines` and returns the output regardless of whether PowerShell
+    /// reported success. `script` is what gets printed if `print_commands` is
+    /// set.
+    pub(crate) fn run_program(&self, script: &str, lines: &[String]) -> Result<process::Output> {
+        Ok(self.run_raw(script, lines, None)?.0)
+    }
+
+    /// Runs `lines` like `run_program`, watching for stalls with `heartbeat`
+    /// if `stall_timeout` is set. Returns the resources PowerShell used as
+    /// well.
+    fn run_raw(
+        &self,
+        script: &str,
+        lines: &[String],
+        heartbeat: Option<&Arc<Heartbeat>>,
+    ) -> Result<(process::Output, Option<ResourceUsage>)> {
+        let mut process = self.spawn_raw(script, lines, false)?;
+        let mut tracked = self.track(&process);
+        let callback = match &self.progress {
+            Progress::Capture(callback) => Some(callback.clone()),
+            _ => None,
+        };
+        let stall = self
+            .stall_timeout
+            .zip(heartbeat)
+            .map(|(window, heartbeat)| Stall {
+                window,
+                heartbeat: heartbeat.clone(),
+                callback: self.on_stall.clone(),
+            });
+        if self.input_timeout.is_some() || stall.is_some() {
+            return prompt::wait(process, tracked, self.input_timeout, stall, callback);
+        }
+
+        let stdout = process.stdout.take().map(|pipe| {
+            thread::spawn(move || match callback {
+                Some(callback) => events::filter_progress(pipe, &callback),
+                None => child::read_all(pipe),
+            })
+        });
+        let stderr = process
+            .stderr
+            .take()
+            .map(|pipe| thread::spawn(move || child::read_all(pipe)));
+        let (status, usage) = tracker::wait(&mut process, tracked.as_mut())?;
+        let output = process::Output {
+            status,
+            stdout: child::join(stdout)?,
+            stderr: child::join(stderr)?,
+        };
+        Ok((output, usage))
+    }
+
+    /// Spawns PowerShell and writes `lines` to its `stdin`. `script` is the
+    /// original script which is what gets printed if `print_commands` is set.
+    fn spawn_raw(
+        &self,
+        script: &str,
+        lines: &[String],
+        interruptible: bool,
+    ) -> Result<process::Child> {
+        let mut cmd = self.command(interruptible)?;
+        if self.mode == ExecutionMode::Encoded {
+            cmd.arg("-EncodedCommand");
+            cmd.arg(wrap::encoded_command(&lines.join("\n")));
+            let mut process = cmd.spawn()?;
+            self.print_script(script);
+            // Nothing is written, closing `stdin` keeps the script from
+            // waiting for input
+            drop(process.stdin.take());
+            return Ok(process);
+        }
+        cmd.args(["-Command", "-"]);
+
+        let mut process = cmd.spawn()?;
+        let stdin = process.stdin.take().ok_or(PsError::ChildStdinNotFound)?;
+
+        self.print_script(script);
+
+        let size: usize = lines.iter().map(|line| line.len() + 1).sum();
+        if size <= self.stdin_buffer_size {
+            write_lines(stdin, lines, self.stdin_buffer_size, self.stdin_encoding)?;
+        } else {
+            // PowerShell starts running commands before it has read all of
+            // them. If it fills the output pipe while we're still writing it
+            // stops reading, so large programs are written in the background
+            // while the caller reads the output. If writing fails PowerShell
+            // has exited, and its exit status is what tells the caller why.
+            let lines = lines.to_vec();
+            let (buffer_size, encoding) = (self.stdin_buffer_size, self.stdin_encoding);
+            thread::spawn(move || write_lines(stdin, &lines, buffer_size, encoding));
+        }
+        Ok(process)
+    }
+
+    /// Returns the command to start PowerShell with, without the arguments
+    /// telling it which commands to run.
+    /// `interruptible` is set for processes we hand out a `PsChild` for.
+    pub(crate) fn command(&self, interruptible: bool) -> Result<Command> {
+        let mut cmd = Command::new(self.executable()?);
+
+        cmd.stdin(Stdio::piped());
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+
+        cmd.args(self.args.iter());
+        for arg in self.raw_args.iter() {
+            target::raw_arg(&mut cmd, arg);
+        }
+        cmd.envs(self.env.iter().map(|(key, value)| (key, value)));
+        if let Some(dir) = &self.current_dir {
+            cmd.current_dir(target::long_path(dir));
+        }
+
+        target::configure_command(&mut cmd, self.hidden, interruptible);
+        Ok(cmd)
+    }
+
+    /// Returns the path of the PowerShell executable, searching for it if
+    /// this is the first run. A failed search isn't remembered, so
+    /// installing PowerShell doesn't require a new `PsScript`.
+    fn executable(&self) -> Result<&OsStr> {
+        if let Some(executable) = self.executable.get() {
+            return Ok(executable);
+        }
+        let program = target::get_powershell_path(&self.probe_paths)?;
+        let executable = target::long_path(Path::new(&program)).into_owned();
+        Ok(self.executable.get_or_init(|| executable.into_os_string()))
+    }
+
+    /// Returns a copy running PowerShell from `executable` instead of the
+    /// one it finds itself.
+    pub(crate) fn with_executable(&self, executable: OsString) -> PsScript {
+        PsScript {
+            executable: Arc::new(OnceLock::from(executable)),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the lines defining the registered libraries, dot sourced so
+    /// their functions are defined for the script.
+    pub(crate) fn library_lines(&self) -> impl Iterator<Item = String> + '_ {
+        self.libraries
+            .iter()
+            .map(|source| format!(". ({})", wrap::script_block(source)))
+    }
+
+    /// How the output of runs is cleaned up before it's returned.
+    pub(crate) fn cleanup(&self) -> Cleanup {
+        Cleanup {
+            strip_ansi: self.strip_ansi,
+            normalize_newlines: self.normalize_newlines,
+        }
+    }
+
+    pub(crate) fn print_script(&self, script: &str) {
+        if self.print_commands {
+            for line in script.lines() {
+                println!("{}", line);
+            }
+        }
+    }
+}
+
+/// Writes `lines` to PowerShell's `stdin` in chunks of `buffer_size` bytes
+/// and closes it, which lets PowerShell know there are no more commands
+/// coming.
+fn write_lines(
+    stdin: ChildStdin,
+    lines: &[String],
+    buffer_size: usize,
+    encoding: StdinEncoding,
+) -> io::Result<()> {
+    let mut stdin = BufWriter::with_capacity(buffer_size, stdin);
+    write_bom(&mut stdin, encoding)?;
+    for line in lines {
+        write_line(&mut stdin, line, encoding)?;
+    }
+    stdin.flush()
+}
+
+/// Writes the byte order mark `encoding` starts with, if any.
+pub(crate) fn write_bom(stdin: &mut impl Write, encoding: StdinEncoding) -> io::Result<()> {
+    match encoding {
+        StdinEncoding::Utf8 => Ok(()),
+        StdinEncoding::Utf8Bom => stdin.write_all(&[0xEF, 0xBB, 0xBF]),
+        StdinEncoding::Utf16Le => stdin.write_all(&[0xFF, 0xFE]),
+    }
+}
+
+/// Writes `line` followed by a line ending in `encoding`.
+pub(crate) fn write_line(
+    stdin: &mut impl Write,
+    line: &str,
+    encoding: StdinEncoding,
+) -> io::Result<()> {
+    if encoding == StdinEncoding::Utf16Le {
+        for unit in line.encode_utf16().chain("\n".encode_utf16()) {
+            stdin.write_all(&unit.to_le_bytes())?;
+        }
+        Ok(())
+    } else {
+        stdin.write_all(line.as_bytes())?;
+        stdin.write_all(b"\n")
+    }
+}
+
+/// Attaches the context of the run, and the meaning of the exit code if one
+/// was registered, to its output.
+pub(crate) fn attach_context(result: &mut Result<Output>, ctx: &RunContext) {
+    if let Ok(output) | Err(PsError::Powershell(output)) = result {
+        let exit_meaning = output.exit_code().and_then(|code| {
+            ctx.exit_codes
+                .iter()
+                .find(|(c, _)| *c == code)
+                .map(|(_, meaning)| meaning.clone())
+        });
+        output.run = Some(Box::new(RunInfo {
+            context: ctx.execution().clone(),
+            exit_meaning,
+        }));
+    }
+}
+
+/// Runs the `after` hook of all middleware if the script ran to completion.
+pub(crate) fn after(middleware: &[Arc<dyn Middleware>], ctx: &RunContext, result: &Result<Output>) {
+    let output = match result {
+        Ok(output) | Err(PsError::Powershell(output)) => output,
+        Err(_) => return,
+    };
+    for m in middleware {
+        m.after(ctx, output);
+    }
+}
+
+/// Turns the output of a finished PowerShell process into the result we
+/// return to the user.
+pub(crate) fn into_result(
+    proc_output: process::Output,
+    usage: Option<ResourceUsage>,
+    cleanup: Cleanup,
+) -> Result<Output> {
+    let mut output = Output::from(proc_output);
+    output.usage = usage.map(Box::new);
+    if let Some(modules) = requires::missing(&output) {
+        return Err(PsError::MissingModules(modules));
+    }
+    cleanup.apply(&mut output);
+    if output.success {
+        Ok(output)
+    } else {
+        Err(PsError::Powershell(output))
+    }
+}
@@ -0,0 +1,478 @@
+//! A PowerShell process kept running between scripts, see `PsSession`.
+
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    process::{self, Child, ChildStdin, ExitStatus},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    builder::StdinEncoding,
+    error::PsError,
+    output::Output,
+    quota::QuotaPermit,
+    script::{self, into_result},
+    target,
+    tracker::{self, Tracked},
+    wrap, PsScript, Result,
+};
+
+const RUN_BEGIN: &str = "##ps-session-begin:";
+const RUN_END: &str = "##ps-session-end:";
+/// Writes the working set of the PowerShell process, for `max_memory`.
+const WORKING_SET: &str = "[System.Diagnostics.Process]::GetCurrentProcess().WorkingSet64";
+
+/// When a [`PsSession`] replaces its PowerShell process with a fresh one,
+/// set with [`PsScriptBuilder::recycle`](crate::PsScriptBuilder::recycle).
+///
+/// Some modules leak memory or leave stale connections behind in
+/// long-lived processes. A recycled session starts over with a new process,
+/// which defines the libraries again, but loses the variables, functions
+/// and modules earlier scripts set up.
+///
+/// Recycling is checked before each run, or when
+/// [`PsSession::recycle_if_due`] is called, so an idle session keeps its
+/// process until then. By default sessions are never recycled.
+///
+/// ## Example
+///
+/// ```rust, no_run
+/// use std::time::Duration;
+/// use powershell_script::{PsScriptBuilder, RecyclePolicy};
+///
+/// let policy = RecyclePolicy::new()
+///     .max_runs(500)
+///     .max_lifetime(Duration::from_secs(60 * 60))
+///     .max_memory(1024 * 1024 * 1024);
+/// let mut session = PsScriptBuilder::new().recycle(policy).build_session().unwrap();
+/// session.run("Import-Module ActiveDirectory").unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecyclePolicy {
+    max_runs: Option<usize>,
+    max_lifetime: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    max_memory: Option<u64>,
+}
+
+impl RecyclePolicy {
+    /// Creates a policy which never recycles a session.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Recycles the session after the process has run `runs` scripts.
+    pub fn max_runs(mut self, runs: usize) -> Self {
+        self.max_runs = Some(runs);
+        self
+    }
+
+    /// Recycles the session once the process has been running for
+    /// `lifetime`.
+    pub fn max_lifetime(mut self, lifetime: Duration) -> Self {
+        self.max_lifetime = Some(lifetime);
+        self
+    }
+
+    /// Recycles the session when it hasn't run a script for `timeout`.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Recycles the session when the working set of the process exceeds
+    /// `bytes` after a run. PowerShell is asked for it after every run, so
+    /// this costs a round trip to the process per script.
+    pub fn max_memory(mut self, bytes: u64) -> Self {
+        self.max_memory = Some(bytes);
+        self
+    }
+}
+
+/// A PowerShell process which runs one script after another, so they don't
+/// pay for starting PowerShell each time and can use the variables,
+/// functions and modules set up by earlier ones. Create one with
+/// [`PsScriptBuilder::build_session`](crate::PsScriptBuilder::build_session).
+///
+/// The options deciding how PowerShell is started apply, like the
+/// executable, its arguments, `current_dir`, `hidden`, `stdin_encoding`,
+/// `strip_ansi`, `print_commands`, the `child_tracker` and the
+/// `execution_quota`. The libraries registered with `library` are defined
+/// once, when the session starts, followed by the `warm_up` script. The
+/// process is replaced according to the [`RecyclePolicy`] and the
+/// `health_check`. Each script runs as a script block in the running
+/// process, so the options changing how a script is run, like the
+/// `capture_*` options, middleware and failure hooks, can't be used and
+/// make `build_session` fail with `BuildError::ConflictingOptions`.
+///
+/// A script fails if it throws or writes any errors. A script calling `exit`
+/// ends the session, and later runs fail with `PsError::Io`.
+///
+/// Dropping the session kills PowerShell, use [`close`](Self::close) to let
+/// it exit.
+///
+/// ## Example
+///
+/// ```rust, no_run
+/// use powershell_script::PsScriptBuilder;
+///
+/// let mut session = PsScriptBuilder::new().build_session().unwrap();
+/// session.run("Import-Module ActiveDirectory").unwrap();
+/// session.run("$users = Get-ADUser -Filter *").unwrap();
+/// let output = session.run("$users.Count").unwrap();
+/// println!("{} users", output.stdout().unwrap().trim());
+/// session.close().unwrap();
+/// ```
+pub struct PsSession {
+    ps: PsScript,
+    worker: Worker,
+    recycled: usize,
+}
+
+/// The PowerShell process a session currently runs its scripts in.
+struct Worker {
+    process: Child,
+    stdin: Option<ChildStdin>,
+    stdout: mpsc::Receiver<Vec<u8>>,
+    stderr: mpsc::Receiver<Vec<u8>>,
+    started: Instant,
+    last_used: Instant,
+    /// Numbers the markers of the next script sent to the process.
+    next_id: usize,
+    /// The scripts run by the user, leaving out the warm-up, health checks
+    /// and memory queries.
+    runs: usize,
+    /// Whether a script has run since the last health check.
+    unchecked: bool,
+    /// The working set after the last run, if `max_memory` is set.
+    memory: Option<u64>,
+    /// Set when the process was killed to be replaced, but starting the
+    /// new one failed.
+    stopped: bool,
+    /// The exit status, once the process has been waited for.
+    exited: Option<ExitStatus>,
+    tracked: Option<Tracked>,
+    /// Held for as long as the process lives.
+    _permit: Option<QuotaPermit>,
+}
+
+impl Worker {
+    /// Starts PowerShell as configured by `ps`, reading commands from
+    /// `stdin` until it's closed.
+    fn start(ps: &PsScript) -> Result<Worker> {
+        let permit = ps.admit()?;
+        let mut cmd = ps.command(false)?;
+        cmd.args(["-Command", "-"]);
+        let mut process = cmd.spawn()?;
+        let tracked = ps.track(&process);
+
+        let mut stdin = process.stdin.take().ok_or(PsError::ChildStdinNotFound)?;
+        script::write_bom(&mut stdin, ps.stdin_encoding)?;
+        for line in ps.library_lines() {
+            script::write_line(&mut stdin, &line, ps.stdin_encoding)?;
+        }
+        let stdout = read_lines(process.stdout.take());
+        let stderr = read_lines(process.stderr.take());
+
+        let now = Instant::now();
+        let mut worker = Worker {
+            process,
+            stdin: Some(stdin),
+            stdout,
+            stderr,
+            started: now,
+            last_used: now,
+            next_id: 0,
+            runs: 0,
+            unchecked: false,
+            memory: None,
+            stopped: false,
+            exited: None,
+            tracked,
+            _permit: permit,
+        };
+        if let Some(warm_up) = &ps.warm_up {
+            worker.run(ps, warm_up)?;
+        }
+        Ok(worker)
+    }
+
+    /// Kills the process, freeing its place in the quota so the process
+    /// replacing it can start.
+    fn stop(&mut self) {
+        if self.stdin.take().is_some() {
+            let _ = self.process.kill();
+        }
+        let _ = self.wait();
+        self.stopped = true;
+        self.tracked = None;
+        self._permit = None;
+    }
+
+    /// Waits for the process to exit. It's reaped with `tracker::wait`, so
+    /// the status is kept for waiting again.
+    fn wait(&mut self) -> io::Result<ExitStatus> {
+        if let Some(status) = self.exited {
+            return Ok(status);
+        }
+        let (status, _) = tracker::wait(&mut self.process, self.tracked.as_mut())?;
+        self.exited = Some(status);
+        Ok(status)
+    }
+
+    /// Whether the process should be replaced according to `policy`.
+    fn due(&self, policy: &RecyclePolicy) -> bool {
+        self.stopped
+            || policy.max_runs.is_some_and(|max| self.runs >= max)
+            || policy
+                .max_lifetime
+                .is_some_and(|max| self.started.elapsed() >= max)
+            || policy
+                .idle_timeout
+                .is_some_and(|max| self.last_used.elapsed() >= max)
+            || policy
+                .max_memory
+                .zip(self.memory)
+                .is_some_and(|(max, used)| used > max)
+    }
+
+    /// Runs `script` in the process and waits for it to finish.
+    fn run(&mut self, ps: &PsScript, script: &str) -> Result<Output> {
+        let (stdout, stderr, success) = self.exchange(script, ps.stdin_encoding)?;
+        let status = match success {
+            Some(success) => target::exit_status(if success { 0 } else { 1 }),
+            // The script ended the session
+            None => self.wait()?,
+        };
+        into_result(
+            process::Output {
+                status,
+                stdout,
+                stderr,
+            },
+            None,
+            ps.cleanup(),
+        )
+    }
+
+    /// Sends `script` to the process and collects its output. Returns
+    /// `None` as the success if the script ended the session.
+    fn exchange(
+        &mut self,
+        script: &str,
+        encoding: StdinEncoding,
+    ) -> Result<(Vec<u8>, Vec<u8>, Option<bool>)> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let line = run_line(id, script);
+        let stdin = self.stdin.as_mut().ok_or_else(ended)?;
+        // A failed write means PowerShell has exited
+        if script::write_line(stdin, &line, encoding)
+            .and_then(|()| stdin.flush())
+            .is_err()
+        {
+            self.stdin = None;
+            return Err(ended());
+        }
+
+        let (stdout, stdout_ok) = collect(&self.stdout, id);
+        let (stderr, _) = collect(&self.stderr, id);
+        if stdout_ok.is_none() {
+            self.stdin = None;
+        }
+        Ok((stdout, stderr, stdout_ok))
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        if self.stdin.take().is_some() {
+            let _ = self.process.kill();
+        }
+        let _ = self.wait();
+    }
+}
+
+impl PsSession {
+    pub(crate) fn start(ps: PsScript) -> Result<PsSession> {
+        let worker = Worker::start(&ps)?;
+        Ok(PsSession {
+            ps,
+            worker,
+            recycled: 0,
+        })
+    }
+
+    /// Runs `script` in the session and waits for it to finish. Returns the
+    /// output the script wrote, or `PsError::Powershell` with it if the
+    /// script failed.
+    pub fn run(&mut self, script: &str) -> Result<Output> {
+        self.recycle_if_due()?;
+        self.ps.print_script(script);
+
+        let result = self.worker.run(&self.ps, script);
+        self.worker.runs += 1;
+        self.worker.unchecked = true;
+        if !self.is_ended() && self.ps.recycle.max_memory.is_some() {
+            self.worker.memory = self.working_set();
+        }
+        self.worker.last_used = Instant::now();
+        result
+    }
+
+    /// Replaces the PowerShell process with a fresh one if the
+    /// [`RecyclePolicy`] says it's due or the health check fails, which
+    /// `run` does before each script. Returns whether the session was
+    /// recycled.
+    ///
+    /// Call this while the session is idle to have `idle_timeout` take
+    /// effect before the next script is run.
+    pub fn recycle_if_due(&mut self) -> Result<bool> {
+        // A session ended by a script isn't restarted behind its back
+        if self.is_ended() {
+            return Ok(false);
+        }
+        if !self.worker.due(&self.ps.recycle) && self.healthy() {
+            return Ok(false);
+        }
+        self.recycle()?;
+        Ok(true)
+    }
+
+    /// Replaces the PowerShell process with a fresh one right away, killing
+    /// the current one. If starting the new process fails, the next run
+    /// tries again.
+    pub fn recycle(&mut self) -> Result<()> {
+        self.worker.stop();
+        self.worker = Worker::start(&self.ps)?;
+        self.recycled += 1;
+        Ok(())
+    }
+
+    /// How many times the PowerShell process has been replaced.
+    pub fn recycled(&self) -> usize {
+        self.recycled
+    }
+
+    /// Ends the session, letting PowerShell exit once the running script is
+    /// done, and returns its exit status.
+    pub fn close(mut self) -> Result<ExitStatus> {
+        self.worker.stdin = None;
+        Ok(self.worker.wait()?)
+    }
+
+    /// The process id of PowerShell, which changes when the session is
+    /// recycled.
+    pub fn id(&self) -> u32 {
+        self.worker.process.id()
+    }
+
+    /// Whether a script ended the session by calling `exit`.
+    pub(crate) fn is_ended(&self) -> bool {
+        self.worker.stdin.is_none() && !self.worker.stopped
+    }
+
+    /// Runs the health check if a script ran since the last one. A
+    /// process which doesn't pass it is replaced by the caller.
+    fn healthy(&mut self) -> bool {
+        let check = match &self.ps.health_check {
+            Some(check) if self.worker.unchecked => check.clone(),
+            _ => return true,
+        };
+        self.worker.unchecked = false;
+        self.worker.run(&self.ps, &check).is_ok()
+    }
+
+    /// Asks PowerShell for the working set of its process.
+    fn working_set(&mut self) -> Option<u64> {
+        let (stdout, _, success) = self
+            .worker
+            .exchange(WORKING_SET, self.ps.stdin_encoding)
+            .ok()?;
+        success?;
+        String::from_utf8_lossy(&stdout).trim().parse().ok()
+    }
+}
+
+impl fmt::Debug for PsSession {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PsSession")
+            .field("pid", &self.worker.process.id())
+            .field("runs", &self.worker.runs)
+            .field("recycled", &self.recycled)
+            .field("ended", &self.worker.stdin.is_none())
+            .finish()
+    }
+}
+
+fn ended() -> PsError {
+    PsError::Io(io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "the PowerShell session has ended",
+    ))
+}
+
+/// Returns a single line running `script` as run `id`, dot sourced so what
+/// it defines stays in the session. The output is piped to `Out-Default` so
+/// all of it is written before the end marker.
+fn run_line(id: usize, script: &str) -> String {
+    format!(
+        "[Console]::Out.WriteLine('{begin}{id}'); [Console]::Error.WriteLine('{begin}{id}'); \
+         $__ps_errors = $Error.Count; $__ps_ok = $true; \
+         try {{ . ({sb}) | Out-Default; $__ps_ok = $Error.Count -eq $__ps_errors }} \
+         catch {{ $__ps_ok = $false; Write-Error -ErrorRecord $_ }}; \
+         [Console]::Out.WriteLine(\"{end}{id}:$__ps_ok\"); [Console]::Error.WriteLine(\"{end}{id}:$__ps_ok\")",
+        begin = RUN_BEGIN,
+        end = RUN_END,
+        id = id,
+        sb = wrap::script_block(script),
+    )
+}
+
+/// Forwards the lines read from `pipe` to the returned channel until it's
+/// closed.
+fn read_lines(pipe: Option<impl Read + Send + 'static>) -> mpsc::Receiver<Vec<u8>> {
+    let (tx, rx) = mpsc::channel();
+    if let Some(pipe) = pipe {
+        thread::spawn(move || {
+            let mut pipe = BufReader::new(pipe);
+            loop {
+                let mut line = Vec::new();
+                match pipe.read_until(b'\n', &mut line) {
+                    Ok(0) | Err(_) => return,
+                    Ok(_) => {
+                        if tx.send(line).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+    }
+    rx
+}
+
+/// Collects the output of run `id`, up to its end marker. Returns whether
+/// the run succeeded, or `None` if the output ended before the marker.
+fn collect(lines: &mpsc::Receiver<Vec<u8>>, id: usize) -> (Vec<u8>, Option<bool>) {
+    let begin = format!("{}{}", RUN_BEGIN, id);
+    let end = format!("{}{}:", RUN_END, id);
+    let mut output = Vec::new();
+    let mut started = false;
+    for line in lines.iter() {
+        let text = String::from_utf8_lossy(&line);
+        let trimmed = text.trim_end_matches(['\r', '\n']);
+        if let Some(success) = trimmed.strip_prefix(&end) {
+            return (output, Some(success == "True"));
+        }
+        if trimmed == begin {
+            started = true;
+        } else if started {
+            output.extend_from_slice(&line);
+        }
+    }
+    (output, None)
+}
@@ -0,0 +1,98 @@
+use std::{fmt, path::Path};
+
+use crate::wrap;
+
+/// Name of the drive a [`NetworkShare`] is mapped to while a script runs.
+const DRIVE_NAME: &str = "__ps_share";
+
+/// A network share to connect to before running a script located on it,
+/// see `PsScript::run_file_from_share`.
+///
+/// The connection is made with `New-PSDrive` for the duration of the script
+/// and removed afterwards, even if the script fails. Connecting with
+/// credentials is only supported by Windows PowerShell and PowerShell Core on
+/// Windows.
+#[derive(Clone)]
+pub struct NetworkShare {
+    root: String,
+    credential: Option<(String, String)>,
+}
+
+impl NetworkShare {
+    /// A share like `\\server\share`, connected to as the current user.
+    pub fn new(root: impl Into<String>) -> Self {
+        NetworkShare {
+            root: root.into(),
+            credential: None,
+        }
+    }
+
+    /// Connects to the share as `user`. The password is sent to PowerShell
+    /// over `stdin`, so it doesn't show up in the process list, and is never
+    /// printed by `print_commands`.
+    pub fn with_credentials(
+        mut self,
+        user: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.credential = Some((user.into(), password.into()));
+        self
+    }
+
+    /// The root of the share.
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    /// Full path of `file` on the share.
+    pub(crate) fn path_of(&self, file: &Path) -> String {
+        format!(
+            "{}\\{}",
+            self.root.trim_end_matches(['\\', '/']),
+            file.to_string_lossy().trim_start_matches(['\\', '/'])
+        )
+    }
+
+    /// Returns a script connecting to the share, running `file` from it and
+    /// disconnecting again.
+    pub(crate) fn script(&self, file: &Path) -> String {
+        let mut script = String::new();
+        let credential = match &self.credential {
+            Some((user, password)) => {
+                script.push_str(&format!(
+                    "$__ps_cred = New-Object System.Management.Automation.PSCredential({}, (ConvertTo-SecureString {} -AsPlainText -Force))\n",
+                    wrap::quote(user),
+                    wrap::quote(password)
+                ));
+                " -Credential $__ps_cred"
+            }
+            None => "",
+        };
+        script.push_str(&format!(
+            "New-PSDrive -Name {} -PSProvider FileSystem -Root {}{} -ErrorAction Stop | Out-Null\n",
+            DRIVE_NAME,
+            wrap::quote(&self.root),
+            credential
+        ));
+        let relative = file.to_string_lossy();
+        script.push_str(&format!(
+            "try {{ & {} }} finally {{ Remove-PSDrive -Name {} -Force -ErrorAction SilentlyContinue }}\n",
+            wrap::quote(&format!(
+                "{}:\\{}",
+                DRIVE_NAME,
+                relative.trim_start_matches(['\\', '/'])
+            )),
+            DRIVE_NAME
+        ));
+        script
+    }
+}
+
+impl fmt::Debug for NetworkShare {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NetworkShare")
+            .field("root", &self.root)
+            .field("user", &self.credential.as_ref().map(|(user, _)| user))
+            .finish()
+    }
+}
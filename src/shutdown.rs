@@ -0,0 +1,110 @@
+//! Stopping the PowerShell processes of an application when it's asked to
+//! shut down.
+
+use std::{
+    io, process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{target, ChildTracker};
+
+/// How often the watcher thread checks whether a shutdown was requested.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Whether a guard is installed, since there is only one signal handler.
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Stops the processes in a [`ChildTracker`] when the application is asked to
+/// shut down, and when the guard is dropped.
+///
+/// While the guard is alive, `SIGTERM` on Unix, and the console being closed
+/// or the system shutting down on Windows, no longer terminate the process
+/// right away. Instead the tracked scripts are interrupted, given `grace` to
+/// exit, killed if they haven't, and then the process exits with the code it
+/// would have exited with otherwise. Windows only waits a few seconds for
+/// this before terminating the process regardless, so keep `grace` short
+/// there.
+///
+/// Dropping the guard stops the tracked processes the same way and restores
+/// the default handling of the signals. Only one guard can be installed at a
+/// time.
+///
+/// ## Example
+///
+/// ```rust, no_run
+/// use std::time::Duration;
+/// use powershell_script::{ChildTracker, PsScriptBuilder, ShutdownGuard};
+///
+/// let tracker = ChildTracker::new();
+/// let _guard = ShutdownGuard::install(&tracker, Duration::from_secs(3)).unwrap();
+/// let ps = PsScriptBuilder::new().child_tracker(tracker).build();
+/// // A SIGTERM while this runs stops the script before the service exits
+/// ps.run("./long-running-job.ps1").unwrap();
+/// ```
+pub struct ShutdownGuard {
+    tracker: ChildTracker,
+    grace: Duration,
+    stop: Arc<AtomicBool>,
+    watcher: Option<JoinHandle<()>>,
+}
+
+impl ShutdownGuard {
+    /// Installs the signal handlers and starts a thread waiting for the
+    /// application to be asked to shut down. Returns an error if a guard is
+    /// already installed or the handlers can't be installed.
+    pub fn install(tracker: &ChildTracker, grace: Duration) -> io::Result<ShutdownGuard> {
+        if INSTALLED.swap(true, Ordering::SeqCst) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "a ShutdownGuard is already installed",
+            ));
+        }
+        if let Err(e) = target::catch_shutdown() {
+            INSTALLED.store(false, Ordering::SeqCst);
+            return Err(e);
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let watcher = {
+            let (tracker, stop) = (tracker.clone(), stop.clone());
+            thread::spawn(move || {
+                while !stop.load(Ordering::SeqCst) {
+                    if let Some(code) = target::shutdown_requested() {
+                        tracker.shutdown_all(grace);
+                        process::exit(code);
+                    }
+                    thread::sleep(POLL_INTERVAL);
+                }
+            })
+        };
+
+        Ok(ShutdownGuard {
+            tracker: tracker.clone(),
+            grace,
+            stop,
+            watcher: Some(watcher),
+        })
+    }
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(watcher) = self.watcher.take() {
+            let _ = watcher.join();
+        }
+        target::release_shutdown();
+        INSTALLED.store(false, Ordering::SeqCst);
+        self.tracker.shutdown_all(self.grace);
+        // A signal which arrived after the watcher stopped still has to end
+        // the process
+        if let Some(code) = target::shutdown_requested() {
+            process::exit(code);
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! Passing structured messages from a script to the host application
+//! without going through its output, see `PsScriptBuilder::on_message`.
+//!
+//! Each run gets a named pipe of its own, a Unix domain socket on Linux and
+//! macOS, which `Send-HostMessage` writes a line of JSON to per message.
+//! Messages carry a random token so other processes on the machine can't
+//! send any.
+
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    io::{BufRead, BufReader, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{target::PipeServer, value::PsValue, wrap};
+
+/// How long `Send-HostMessage` waits for the pipe to accept its connection,
+/// in milliseconds.
+const CONNECT_TIMEOUT_MS: u32 = 5000;
+
+/// The callback messages are passed to.
+pub(crate) type MessageHandler = Arc<dyn Fn(&HostMessage) + Send + Sync>;
+
+/// A message a script sent with `Send-HostMessage`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostMessage {
+    /// What the message is about, the `-Type` it was sent with.
+    pub kind: String,
+    /// The `-Data` it was sent with, `PsValue::Null` if there's none.
+    pub data: PsValue,
+}
+
+/// Returns a random token for authenticating requests over the loopback
+/// interface or a pipe.
+pub(crate) fn token() -> String {
+    format!(
+        "{:016x}{:016x}",
+        RandomState::new().build_hasher().finish(),
+        RandomState::new().build_hasher().finish()
+    )
+}
+
+/// The side channel of one run. Dropping it stops accepting connections and
+/// waits until the messages already sent have been handled.
+pub(crate) struct SideChannel {
+    server: Arc<PipeServer>,
+    token: String,
+    stopped: Arc<AtomicBool>,
+    acceptor: Option<JoinHandle<()>>,
+    readers: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl SideChannel {
+    pub(crate) fn start(handler: MessageHandler) -> std::io::Result<Self> {
+        let token = token();
+        let server = Arc::new(PipeServer::bind(&format!("ps-{}", &token[..16]))?);
+        let stopped = Arc::new(AtomicBool::new(false));
+        let readers = Arc::new(Mutex::new(Vec::new()));
+
+        let acceptor = {
+            let (server, token, stopped, readers) = (
+                server.clone(),
+                token.clone(),
+                stopped.clone(),
+                readers.clone(),
+            );
+            thread::spawn(move || loop {
+                let stream = match server.accept() {
+                    Ok(stream) => stream,
+                    Err(_) => return,
+                };
+                let mut stream = BufReader::new(stream);
+                if stopped.load(Ordering::Relaxed) {
+                    // The script has exited, so this is either a connection
+                    // it made just before, which has all its messages
+                    // waiting, or the one telling us to stop.
+                    let mut line = String::new();
+                    let _ = stream.read_line(&mut line);
+                    if line.trim_end() == stop_line(&token) {
+                        return;
+                    }
+                    handle(&line, &token, &handler);
+                    read_messages(stream, &token, &handler);
+                    continue;
+                }
+                let (token, handler) = (token.clone(), handler.clone());
+                let reader = thread::spawn(move || read_messages(stream, &token, &handler));
+                lock(&readers).push(reader);
+            })
+        };
+
+        Ok(SideChannel {
+            server,
+            token,
+            stopped,
+            acceptor: Some(acceptor),
+            readers,
+        })
+    }
+
+    /// Returns the definition of `Send-HostMessage`, which connects to the
+    /// pipe the first time it's called and keeps the connection for the
+    /// rest of the run.
+    pub(crate) fn function(&self) -> String {
+        format!(
+            "function Send-HostMessage {{ param([Parameter(Mandatory = $true, Position = 0)][string]$Type, [Parameter(Position = 1)]$Data) if (-not $global:__ps_channel) {{ $__ps_pipe = New-Object System.IO.Pipes.NamedPipeClientStream('.', {}, [System.IO.Pipes.PipeDirection]::Out); $__ps_pipe.Connect({}); $global:__ps_channel = New-Object System.IO.StreamWriter($__ps_pipe, (New-Object System.Text.UTF8Encoding($false))); $global:__ps_channel.AutoFlush = $true }}; $global:__ps_channel.WriteLine((ConvertTo-Json -Compress -Depth 100 -InputObject ([ordered]@{{ Token = '{}'; Type = $Type; Data = $Data }}))) }}",
+            wrap::quote(&self.server.client_name()),
+            CONNECT_TIMEOUT_MS,
+            self.token
+        )
+    }
+}
+
+impl Drop for SideChannel {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        if let Some(acceptor) = self.acceptor.take() {
+            // Connecting fails while the acceptor is between two instances
+            // of a named pipe, so it's retried for a while.
+            for _ in 0..100 {
+                if let Ok(mut client) = self.server.connect() {
+                    let _ = writeln!(client, "{}", stop_line(&self.token));
+                }
+                if acceptor.is_finished() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+        // The script has exited by now, so the readers reach the end of
+        // their connections
+        let readers = std::mem::take(&mut *lock(&self.readers));
+        for reader in readers {
+            let _ = reader.join();
+        }
+    }
+}
+
+impl fmt::Debug for SideChannel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SideChannel")
+            .field("pipe", &self.server.client_name())
+            .finish()
+    }
+}
+
+/// The line which tells the acceptor to stop, which can't be mistaken for a
+/// message.
+fn stop_line(token: &str) -> String {
+    format!("stop {}", token)
+}
+
+/// Passes the messages read from `stream` to `handler` until the script
+/// closes its end.
+fn read_messages(stream: impl BufRead, token: &str, handler: &MessageHandler) {
+    for line in stream.lines() {
+        match line {
+            Ok(line) => handle(&line, token, handler),
+            Err(_) => return,
+        }
+    }
+}
+
+/// Passes the message on `line` to `handler`. Lines which aren't messages
+/// with the token are skipped.
+fn handle(line: &str, token: &str, handler: &MessageHandler) {
+    let message = match PsValue::from_json(line.trim()) {
+        Ok(message) => message,
+        Err(_) => return,
+    };
+    if message.get("Token").and_then(PsValue::as_str) != Some(token) {
+        return;
+    }
+    handler(&HostMessage {
+        kind: message
+            .get("Type")
+            .and_then(PsValue::as_str)
+            .unwrap_or_default()
+            .to_string(),
+        data: message.get("Data").cloned().unwrap_or(PsValue::Null),
+    });
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,223 @@
+use std::{
+    borrow::Cow,
+    fmt, fs,
+    hash::{Hash, Hasher},
+    io,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+use crate::{context, registry::ParamSchema};
+
+/// A PowerShell script, along with what's known about it. All the run
+/// methods take a `&str`, which a `&Script` coerces to.
+///
+/// Scripts are usually created with the `ps!` macro (enabled by the `macros`
+/// feature), which checks them at compile time.
+///
+/// Two scripts are equal if their source and metadata are. They hash by
+/// their source only.
+///
+/// ## Example
+///
+/// ```rust, no_run
+/// use powershell_script::{ParamSchema, ParamType, PsScriptBuilder, Script};
+///
+/// let script = Script::from_file("./scripts/deploy.ps1")
+///     .unwrap()
+///     .with_params(ParamSchema::new().required("Environment", ParamType::String));
+/// println!("running {} ({:x})", script.name().unwrap(), script.hash());
+/// if let Some(version) = script.required_version() {
+///     println!("requires PowerShell {}", version);
+/// }
+/// PsScriptBuilder::new().build().run(&script).unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Script {
+    source: Cow<'static, str>,
+    name: Option<String>,
+    path: Option<PathBuf>,
+    params: Option<ParamSchema>,
+}
+
+impl Script {
+    /// Creates a script from its source.
+    pub fn new(source: impl Into<String>) -> Self {
+        Script {
+            source: Cow::Owned(source.into()),
+            name: None,
+            path: None,
+            params: None,
+        }
+    }
+
+    /// Creates a script from a string literal without copying it.
+    pub const fn from_static(source: &'static str) -> Self {
+        Script {
+            source: Cow::Borrowed(source),
+            name: None,
+            path: None,
+            params: None,
+        }
+    }
+
+    /// Reads a script from the file at `path`. The script is named after
+    /// the file, without the extension.
+    ///
+    /// Files starting with a UTF-8 or UTF-16 byte order mark are decoded
+    /// accordingly, anything else has to be UTF-8. The byte order mark is
+    /// removed and line endings are converted to `\n`, the same way
+    /// `include_ps!` does.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let bytes = fs::read(path)?;
+        let source = decode(&bytes).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the script isn't valid UTF-8 or UTF-16",
+            )
+        })?;
+        let mut script = Script::new(source.replace("\r\n", "\n").replace('\r', "\n"));
+        script.name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned());
+        script.path = Some(path.to_path_buf());
+        Ok(script)
+    }
+
+    /// Names the script, for logs and error messages.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Describes the parameters the script expects. See
+    /// [`ParamSchema::from_script`] for reading them from its `param()`
+    /// block.
+    pub fn with_params(mut self, params: ParamSchema) -> Self {
+        self.params = Some(params);
+        self
+    }
+
+    /// The source of the script.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The name of the script, if it was read from a file or given one with
+    /// `with_name`.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The file the script was read from, if any.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// The parameters the script expects, if they were described with
+    /// `with_params`.
+    pub fn params(&self) -> Option<&ParamSchema> {
+        self.params.as_ref()
+    }
+
+    /// The PowerShell version the script declares it needs with a
+    /// `#Requires -Version` statement.
+    pub fn required_version(&self) -> Option<PsVersion> {
+        self.source.lines().find_map(requires_version)
+    }
+
+    /// A hash of the source, the same as `ExecutionContext::script_hash` of
+    /// its runs. It's stable within the process, but may change between
+    /// versions of Rust.
+    pub fn hash(&self) -> u64 {
+        context::hash_script(&self.source)
+    }
+}
+
+/// A PowerShell version, as in `#Requires -Version 7.2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PsVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl fmt::Display for PsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+impl Hash for Script {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.source.hash(state);
+    }
+}
+
+impl Deref for Script {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.source
+    }
+}
+
+impl AsRef<str> for Script {
+    fn as_ref(&self) -> &str {
+        &self.source
+    }
+}
+
+impl From<&'static str> for Script {
+    fn from(source: &'static str) -> Self {
+        Script::from_static(source)
+    }
+}
+
+impl From<String> for Script {
+    fn from(source: String) -> Self {
+        Script::new(source)
+    }
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// Decodes a script file according to its byte order mark, defaulting to
+/// UTF-8.
+fn decode(bytes: &[u8]) -> Option<String> {
+    let utf16 = |bytes: &[u8], from: fn([u8; 2]) -> u16| {
+        let chunks = bytes.chunks_exact(2);
+        if !chunks.remainder().is_empty() {
+            return None;
+        }
+        let units: Vec<u16> = chunks.map(|c| from([c[0], c[1]])).collect();
+        String::from_utf16(&units).ok()
+    };
+    match bytes {
+        [0xEF, 0xBB, 0xBF, rest @ ..] => String::from_utf8(rest.to_vec()).ok(),
+        [0xFF, 0xFE, rest @ ..] => utf16(rest, u16::from_le_bytes),
+        [0xFE, 0xFF, rest @ ..] => utf16(rest, u16::from_be_bytes),
+        _ => String::from_utf8(bytes.to_vec()).ok(),
+    }
+}
+
+/// Parses the version out of a `#Requires -Version <major>[.<minor>]`
+/// statement.
+fn requires_version(line: &str) -> Option<PsVersion> {
+    let mut words = line.split_whitespace();
+    if !words.next()?.eq_ignore_ascii_case("#requires") {
+        return None;
+    }
+    words.find(|word| word.eq_ignore_ascii_case("-version"))?;
+    let mut parts = words.next()?.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = match parts.next() {
+        Some(minor) => minor.parse().ok()?,
+        None => 0,
+    };
+    Some(PsVersion { major, minor })
+}
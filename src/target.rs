@@ -1,5 +1,78 @@
+#[cfg(target_family = "unix")]
+pub mod unix;
 #[cfg(target_family = "windows")]
 pub mod windows;
+
 #[cfg(target_family = "unix")]
-pub mod unix;
+pub(crate) use unix::{
+    catch_shutdown, configure_command, exit_signal, exit_status, get_powershell_path,
+    installations, interrupt, interrupt_pid, kill_pid, long_path, long_path_name, raw_arg,
+    release_shutdown, resume, short_path_name, shutdown_requested, suspend, try_reap, try_wait,
+    wait, wait_exited, PipeServer,
+};
+
+#[cfg(target_family = "windows")]
+pub(crate) use windows::{
+    catch_shutdown, configure_command, exit_signal, exit_status, get_powershell_path,
+    installations, interrupt, interrupt_pid, kill_pid, long_path, long_path_name, raw_arg,
+    release_shutdown, resume, short_path_name, shutdown_requested, suspend, try_reap, try_wait,
+    wait, wait_exited, PipeServer,
+};
+
+use std::{env, path::PathBuf};
+
+#[cfg(target_family = "unix")]
+const PATH_SPLITTER: char = ':';
+
+#[cfg(target_family = "windows")]
+const PATH_SPLITTER: char = ';';
+
+/// Check whether there is a program called "program name" on the system path
+fn is_program_on_path(program_name: &str) -> Option<bool> {
+    let system_path = match env::var("PATH") {
+        Ok(x) => x,
+        Err(_e) => return None,
+    };
+
+    for path_dir in system_path.split(PATH_SPLITTER) {
+        let path = std::path::Path::new(path_dir).join(program_name);
+        if path.exists() {
+            return Some(true);
+        }
+    }
+    Some(false)
+}
+
+/// Returns the path of `program_name` in the first directory on the system
+/// path containing it.
+fn find_on_path(program_name: &str) -> Option<PathBuf> {
+    env::var("PATH")
+        .ok()?
+        .split(PATH_SPLITTER)
+        .map(|dir| std::path::Path::new(dir).join(program_name))
+        .find(|path| path.is_file())
+}
+
+/// Returns the `candidates` which exist on this system, leaving out those
+/// which are the same file as an earlier one, like a link on `PATH` to an
+/// install location.
+fn all_existing<I: IntoIterator<Item = PathBuf>>(candidates: I) -> Vec<PathBuf> {
+    let mut seen = Vec::new();
+    let mut found = Vec::new();
+    for path in candidates.into_iter().filter(|path| path.is_file()) {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if !seen.contains(&canonical) {
+            seen.push(canonical);
+            found.push(path);
+        }
+    }
+    found
+}
 
+/// Returns the first of the `candidates` which exists on this system.
+fn first_existing<I: IntoIterator<Item = PathBuf>>(candidates: I) -> Option<String> {
+    candidates
+        .into_iter()
+        .find(|path| path.is_file())
+        .map(|path| path.to_string_lossy().to_string())
+}
@@ -0,0 +1,369 @@
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::{
+    borrow::Cow,
+    env,
+    ffi::OsStr,
+    fs, io,
+    os::raw::c_long,
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus},
+    sync::atomic::{AtomicI32, Ordering},
+    time::Duration,
+};
+
+use super::{all_existing, find_on_path, first_existing, is_program_on_path};
+use crate::{error::PsError, usage::ResourceUsage, Result, POWERSHELL_NAME};
+
+/// Locations PowerShell Core is commonly installed to without being linked
+/// into a directory on `PATH`.
+const DEFAULT_PROBE_PATHS: &[&str] = &[
+    "/snap/bin/pwsh",
+    "/opt/microsoft/powershell/7/pwsh",
+    "/usr/local/microsoft/powershell/7/pwsh",
+];
+
+const SIGINT: i32 = 2;
+const SIGKILL: i32 = 9;
+const SIGTERM: i32 = 15;
+/// The `SIG_DFL` handler, restoring the default action of a signal.
+const SIG_DFL: usize = 0;
+const SIG_ERR: usize = usize::MAX;
+const WNOHANG: i32 = 1;
+/// `waitid` waits for the process with the given id.
+const P_PID: i32 = 1;
+const WEXITED: i32 = 4;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const WNOWAIT: i32 = 0x01000000;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const WNOWAIT: i32 = 0x20;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const SIGSTOP: i32 = 19;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const SIGCONT: i32 = 18;
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const SIGSTOP: i32 = 17;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const SIGCONT: i32 = 19;
+
+#[cfg(target_vendor = "apple")]
+type Suseconds = i32;
+#[cfg(not(target_vendor = "apple"))]
+type Suseconds = c_long;
+
+/// `ru_maxrss` is in kilobytes everywhere but on Apple's systems.
+#[cfg(target_vendor = "apple")]
+const MAXRSS_UNIT: u64 = 1;
+#[cfg(not(target_vendor = "apple"))]
+const MAXRSS_UNIT: u64 = 1024;
+
+#[repr(C)]
+#[derive(Default)]
+struct Timeval {
+    tv_sec: c_long,
+    tv_usec: Suseconds,
+}
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct Rusage {
+    ru_utime: Timeval,
+    ru_stime: Timeval,
+    ru_maxrss: c_long,
+    ru_ixrss: c_long,
+    ru_idrss: c_long,
+    ru_isrss: c_long,
+    ru_minflt: c_long,
+    ru_majflt: c_long,
+    ru_nswap: c_long,
+    ru_inblock: c_long,
+    ru_oublock: c_long,
+    ru_msgsnd: c_long,
+    ru_msgrcv: c_long,
+    ru_nsignals: c_long,
+    ru_nvcsw: c_long,
+    ru_nivcsw: c_long,
+}
+
+extern "C" {
+    fn kill(pid: i32, sig: i32) -> i32;
+    fn waitpid(pid: i32, status: *mut i32, options: i32) -> i32;
+    fn wait4(pid: i32, status: *mut i32, options: i32, rusage: *mut Rusage) -> i32;
+    fn waitid(id_type: i32, id: u32, info: *mut u64, options: i32) -> i32;
+    fn signal(signum: i32, handler: usize) -> usize;
+}
+
+/// The signal which asked us to shut down, or 0.
+static SHUTDOWN_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn on_shutdown_signal(signal: i32) {
+    SHUTDOWN_SIGNAL.store(signal, Ordering::SeqCst);
+}
+
+/// Applies the platform specific options to the command. `interruptible`
+/// is only needed on Windows.
+pub(crate) fn configure_command(_cmd: &mut Command, hidden: bool, _interruptible: bool) {
+    if hidden {
+        // TODO: Check if this is a problem in PS Core on Unix platforms
+        // See: https://github.com/cfsamson/powershell-script/pull/9
+    }
+}
+
+/// Adds an argument which is passed to PowerShell as is. Only Windows quotes
+/// arguments when building a command line, so this is a regular argument
+/// here.
+pub(crate) fn raw_arg(cmd: &mut Command, arg: &OsStr) {
+    cmd.arg(arg);
+}
+
+/// Paths have no length limit worth working around here.
+pub(crate) fn long_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+/// There are no short names on Unix, so `path` is returned as it is if it
+/// exists.
+pub(crate) fn short_path_name(path: &Path) -> io::Result<PathBuf> {
+    path.metadata()?;
+    Ok(path.to_path_buf())
+}
+
+/// See `short_path_name`.
+pub(crate) fn long_path_name(path: &Path) -> io::Result<PathBuf> {
+    short_path_name(path)
+}
+
+/// Looks for PowerShell on `PATH`, then in the user supplied `probe_paths`
+/// and finally in the default install locations for snap, `dotnet tool` and
+/// the Microsoft packages.
+pub(crate) fn get_powershell_path(probe_paths: &[PathBuf]) -> Result<String> {
+    if is_program_on_path(POWERSHELL_NAME).unwrap_or(false) {
+        return Ok(POWERSHELL_NAME.to_string());
+    }
+
+    let dotnet_tool = env::var_os("HOME").map(|home| Path::new(&home).join(".dotnet/tools/pwsh"));
+
+    let candidates = probe_paths
+        .iter()
+        .cloned()
+        .chain(DEFAULT_PROBE_PATHS.iter().map(PathBuf::from))
+        .chain(dotnet_tool);
+
+    first_existing(candidates).ok_or(PsError::PowershellNotFound)
+}
+
+/// Returns every PowerShell installation found on `PATH`, in `probe_paths`
+/// and in the default install locations, including previews.
+pub(crate) fn installations(probe_paths: &[PathBuf]) -> Vec<PathBuf> {
+    let dotnet_tool = env::var_os("HOME").map(|home| Path::new(&home).join(".dotnet/tools/pwsh"));
+    let candidates = find_on_path(POWERSHELL_NAME)
+        .into_iter()
+        .chain(find_on_path("pwsh-preview"))
+        .chain(probe_paths.iter().cloned())
+        .chain(DEFAULT_PROBE_PATHS.iter().map(PathBuf::from))
+        .chain(dotnet_tool)
+        .chain(Some(PathBuf::from(
+            "/opt/microsoft/powershell/7-preview/pwsh",
+        )));
+    all_existing(candidates)
+}
+
+/// Sends `SIGINT` to the child, which PowerShell handles like Ctrl+C.
+pub(crate) fn interrupt(child: &Child) -> io::Result<()> {
+    signal_child(child, SIGINT)
+}
+
+/// Stops the child with `SIGSTOP`.
+pub(crate) fn suspend(child: &Child) -> io::Result<()> {
+    signal_child(child, SIGSTOP)
+}
+
+/// Continues a stopped child with `SIGCONT`.
+pub(crate) fn resume(child: &Child) -> io::Result<()> {
+    signal_child(child, SIGCONT)
+}
+
+/// Sends `SIGINT` to the process `pid`.
+pub(crate) fn interrupt_pid(pid: u32) -> io::Result<()> {
+    signal_pid(pid, SIGINT)
+}
+
+/// Kills the process `pid` with `SIGKILL`.
+pub(crate) fn kill_pid(pid: u32) -> io::Result<()> {
+    signal_pid(pid, SIGKILL)
+}
+
+/// Reaps the child `pid` if it has exited, without blocking. Returns `true`
+/// if it's gone, either now or because it was reaped before.
+pub(crate) fn try_reap(pid: u32) -> bool {
+    let mut status = 0;
+    // SAFETY: `status` is a valid pointer for the duration of the call
+    unsafe { waitpid(pid as i32, &mut status, WNOHANG) != 0 }
+}
+
+/// Waits for `child` to exit like `Child::wait`, returning the resources it
+/// used as well. The child is reaped with `wait4`, so `Child::wait` and
+/// `Child::try_wait` can't be used on it anymore.
+pub(crate) fn wait(child: &mut Child) -> io::Result<(ExitStatus, Option<ResourceUsage>)> {
+    // Closing stdin like `Child::wait` does keeps the child from waiting for
+    // input forever
+    drop(child.stdin.take());
+    reap(child, 0).map(|exited| exited.expect("a blocking wait returns once the child exited"))
+}
+
+/// Returns the exit status and resource usage of `child` if it has exited,
+/// like `Child::try_wait`, see `wait`.
+pub(crate) fn try_wait(
+    child: &mut Child,
+) -> io::Result<Option<(ExitStatus, Option<ResourceUsage>)>> {
+    reap(child, WNOHANG)
+}
+
+/// Blocks until `child` has exited without reaping it, so its process id
+/// isn't reused until it's waited for.
+pub(crate) fn wait_exited(child: &Child) -> io::Result<()> {
+    // Larger than `siginfo_t` on all supported systems
+    let mut info = [0u64; 16];
+    loop {
+        // SAFETY: `info` is a valid pointer to a buffer large enough for a
+        // `siginfo_t` for the duration of the call
+        if unsafe { waitid(P_PID, child.id(), info.as_mut_ptr(), WEXITED | WNOWAIT) } == 0 {
+            return Ok(());
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
+
+fn reap(
+    child: &mut Child,
+    options: i32,
+) -> io::Result<Option<(ExitStatus, Option<ResourceUsage>)>> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut status = 0;
+    let mut rusage = Rusage::default();
+    loop {
+        // SAFETY: `status` and `rusage` are valid pointers for the duration
+        // of the call, and `Rusage` matches the layout of `struct rusage`
+        match unsafe { wait4(child.id() as i32, &mut status, options, &mut rusage) } {
+            0 => return Ok(None),
+            -1 => {
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+            _ => break,
+        }
+    }
+    let time = |t: &Timeval| Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000);
+    let usage = ResourceUsage {
+        peak_memory: rusage.ru_maxrss as u64 * MAXRSS_UNIT,
+        user_time: time(&rusage.ru_utime),
+        system_time: time(&rusage.ru_stime),
+        read_operations: rusage.ru_inblock as u64,
+        write_operations: rusage.ru_oublock as u64,
+        read_bytes: None,
+        written_bytes: None,
+    };
+    Ok(Some((ExitStatus::from_raw(status), Some(usage))))
+}
+
+/// Catches `SIGTERM` instead of letting it terminate the process, see
+/// `shutdown_requested`.
+pub(crate) fn catch_shutdown() -> io::Result<()> {
+    SHUTDOWN_SIGNAL.store(0, Ordering::SeqCst);
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    if unsafe { signal(SIGTERM, on_shutdown_signal as *const () as usize) } == SIG_ERR {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Restores the default action of `SIGTERM`.
+pub(crate) fn release_shutdown() {
+    // SAFETY: restoring the default action has no requirements
+    unsafe { signal(SIGTERM, SIG_DFL) };
+}
+
+/// Returns the exit code to exit with if a signal caught by
+/// `catch_shutdown` has arrived, following the shell convention of 128 plus
+/// the signal number.
+pub(crate) fn shutdown_requested() -> Option<i32> {
+    match SHUTDOWN_SIGNAL.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(128 + signal),
+    }
+}
+
+fn signal_child(child: &Child, signal: i32) -> io::Result<()> {
+    signal_pid(child.id(), signal)
+}
+
+fn signal_pid(pid: u32, signal: i32) -> io::Result<()> {
+    // SAFETY: `kill` has no memory safety requirements, at worst the pid has
+    // been reused, which can't happen before the child has been reaped.
+    if unsafe { kill(pid as i32, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Creates an `ExitStatus` for a process which exited with `code`.
+pub(crate) fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw(code << 8)
+}
+
+/// Returns the number of the signal which terminated a process, if it was
+/// terminated by one.
+pub(crate) fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+/// The listening end of a side channel, a Unix domain socket in the temp
+/// directory. .NET's `NamedPipeClientStream` connects to a socket when it's
+/// given its absolute path as the pipe name.
+#[derive(Debug)]
+pub(crate) struct PipeServer {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl PipeServer {
+    pub(crate) fn bind(name: &str) -> io::Result<PipeServer> {
+        let path = env::temp_dir().join(format!("{}.sock", name));
+        let listener = UnixListener::bind(&path)?;
+        Ok(PipeServer { listener, path })
+    }
+
+    /// Waits for a client to connect.
+    pub(crate) fn accept(&self) -> io::Result<UnixStream> {
+        Ok(self.listener.accept()?.0)
+    }
+
+    /// The pipe name to pass to `NamedPipeClientStream`.
+    pub(crate) fn client_name(&self) -> String {
+        self.path.to_string_lossy().into_owned()
+    }
+
+    /// Connects to the server as a client.
+    pub(crate) fn connect(&self) -> io::Result<UnixStream> {
+        UnixStream::connect(&self.path)
+    }
+}
+
+impl Drop for PipeServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,525 @@
+use std::os::windows::{
+    ffi::{OsStrExt, OsStringExt},
+    io::{AsRawHandle, FromRawHandle, RawHandle},
+    process::CommandExt,
+};
+use std::{
+    borrow::Cow,
+    env,
+    ffi::{OsStr, OsString},
+    fs::{File, OpenOptions},
+    io,
+    path::{Component, Path, PathBuf},
+    process::{Child, Command, ExitStatus},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use super::{all_existing, find_on_path, first_existing, is_program_on_path};
+use crate::{error::PsError, usage::ResourceUsage, Result, POWERSHELL_NAME};
+
+/// Paths this long only work with the extended-length prefix, unless long
+/// paths are enabled system wide and the program opts into them.
+const MAX_PATH: usize = 260;
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
+const CTRL_BREAK_EVENT: u32 = 1;
+const PROCESS_TERMINATE: u32 = 0x0001;
+const CTRL_CLOSE_EVENT: u32 = 2;
+const CTRL_SHUTDOWN_EVENT: u32 = 6;
+/// The exit code of processes ended by a console control event.
+const STATUS_CONTROL_C_EXIT: i32 = 0xC000013Au32 as i32;
+const PIPE_ACCESS_INBOUND: u32 = 0x00000001;
+const FILE_FLAG_FIRST_PIPE_INSTANCE: u32 = 0x00080000;
+const PIPE_REJECT_REMOTE_CLIENTS: u32 = 0x00000008;
+const PIPE_UNLIMITED_INSTANCES: u32 = 255;
+/// Returned by `ConnectNamedPipe` if the client connected before it was
+/// called, which isn't a failure.
+const ERROR_PIPE_CONNECTED: i32 = 535;
+const INFINITE: u32 = 0xFFFFFFFF;
+const WAIT_FAILED: u32 = 0xFFFFFFFF;
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn GenerateConsoleCtrlEvent(ctrl_event: u32, process_group_id: u32) -> i32;
+    fn OpenProcess(desired_access: u32, inherit_handle: i32, process_id: u32) -> RawHandle;
+    fn TerminateProcess(process: RawHandle, exit_code: u32) -> i32;
+    fn CloseHandle(handle: RawHandle) -> i32;
+    fn WaitForSingleObject(handle: RawHandle, milliseconds: u32) -> u32;
+    fn SetConsoleCtrlHandler(handler: Option<CtrlHandler>, add: i32) -> i32;
+    fn GetShortPathNameW(long_path: *const u16, short_path: *mut u16, len: u32) -> u32;
+    fn GetLongPathNameW(short_path: *const u16, long_path: *mut u16, len: u32) -> u32;
+    fn CreateNamedPipeW(
+        name: *const u16,
+        open_mode: u32,
+        pipe_mode: u32,
+        max_instances: u32,
+        out_buffer_size: u32,
+        in_buffer_size: u32,
+        default_timeout: u32,
+        security_attributes: *mut std::ffi::c_void,
+    ) -> RawHandle;
+    fn ConnectNamedPipe(pipe: RawHandle, overlapped: *mut std::ffi::c_void) -> i32;
+    fn K32GetProcessMemoryInfo(
+        process: RawHandle,
+        counters: *mut ProcessMemoryCounters,
+        size: u32,
+    ) -> i32;
+    fn GetProcessTimes(
+        process: RawHandle,
+        creation_time: *mut u64,
+        exit_time: *mut u64,
+        kernel_time: *mut u64,
+        user_time: *mut u64,
+    ) -> i32;
+    fn GetProcessIoCounters(process: RawHandle, counters: *mut IoCounters) -> i32;
+}
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct ProcessMemoryCounters {
+    cb: u32,
+    page_fault_count: u32,
+    peak_working_set_size: usize,
+    working_set_size: usize,
+    quota_peak_paged_pool_usage: usize,
+    quota_paged_pool_usage: usize,
+    quota_peak_non_paged_pool_usage: usize,
+    quota_non_paged_pool_usage: usize,
+    pagefile_usage: usize,
+    peak_pagefile_usage: usize,
+}
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct IoCounters {
+    read_operation_count: u64,
+    write_operation_count: u64,
+    other_operation_count: u64,
+    read_transfer_count: u64,
+    write_transfer_count: u64,
+    other_transfer_count: u64,
+}
+
+type PathConversion = unsafe extern "system" fn(*const u16, *mut u16, u32) -> u32;
+
+type CtrlHandler = unsafe extern "system" fn(ctrl_type: u32) -> i32;
+
+/// Set once the console is closing or the system shutting down.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+unsafe extern "system" fn on_shutdown_event(ctrl_type: u32) -> i32 {
+    match ctrl_type {
+        CTRL_CLOSE_EVENT | CTRL_SHUTDOWN_EVENT => {
+            SHUTDOWN.store(true, Ordering::SeqCst);
+            // The process is terminated as soon as the handler returns, so
+            // we block until whoever handles the shutdown exits. Windows
+            // ends the process anyway if that takes too long.
+            loop {
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+        _ => 0,
+    }
+}
+
+#[link(name = "ntdll")]
+extern "system" {
+    fn NtSuspendProcess(process: RawHandle) -> i32;
+    fn NtResumeProcess(process: RawHandle) -> i32;
+}
+
+/// Applies the platform specific options to the command. An `interruptible`
+/// process is started in its own process group so we can send `CTRL_BREAK`
+/// to it without affecting our own process.
+pub(crate) fn configure_command(cmd: &mut Command, hidden: bool, interruptible: bool) {
+    let mut flags = 0;
+    if hidden {
+        flags |= CREATE_NO_WINDOW;
+    }
+    if interruptible {
+        flags |= CREATE_NEW_PROCESS_GROUP;
+    }
+    cmd.creation_flags(flags);
+}
+
+/// Adds an argument which is written to the command line exactly as given,
+/// without the quoting and escaping `Command::arg` applies.
+pub(crate) fn raw_arg(cmd: &mut Command, arg: &OsStr) {
+    cmd.raw_arg(arg);
+}
+
+/// Returns `path` with the extended-length prefix (`\\?\`) if it's too long
+/// for the Win32 APIs, so deployment trees deeper than `MAX_PATH` work.
+/// Relative paths are made absolute first since the prefix requires it.
+pub(crate) fn long_path(path: &Path) -> Cow<'_, Path> {
+    let text = path.to_string_lossy();
+    if text.len() < MAX_PATH || text.starts_with(r"\\?\") || text.starts_with(r"\\.\") {
+        return Cow::Borrowed(path);
+    }
+    let absolute = match env::current_dir() {
+        Ok(dir) => dir.join(path),
+        Err(_) => return Cow::Borrowed(path),
+    };
+
+    // The prefix turns off path normalization, so `.`, `..` and forward
+    // slashes have to be dealt with here
+    let mut normalized = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other.as_os_str()),
+        }
+    }
+    let normalized = normalized.to_string_lossy().replace('/', "\\");
+    let prefixed = match normalized.strip_prefix(r"\\") {
+        Some(unc) => format!(r"\\?\UNC\{}", unc),
+        None => format!(r"\\?\{}", normalized),
+    };
+    Cow::Owned(PathBuf::from(prefixed))
+}
+
+/// Returns the 8.3 short form of the existing `path`, see
+/// `GetShortPathNameW`.
+pub(crate) fn short_path_name(path: &Path) -> io::Result<PathBuf> {
+    convert_path(path, GetShortPathNameW)
+}
+
+/// Returns the long form of the existing `path`, see `GetLongPathNameW`.
+pub(crate) fn long_path_name(path: &Path) -> io::Result<PathBuf> {
+    convert_path(path, GetLongPathNameW)
+}
+
+fn convert_path(path: &Path, convert: PathConversion) -> io::Result<PathBuf> {
+    let path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut buf = vec![0u16; MAX_PATH];
+    loop {
+        // SAFETY: `path` is nul terminated and `buf` is as long as we say.
+        let len = unsafe { convert(path.as_ptr(), buf.as_mut_ptr(), buf.len() as u32) } as usize;
+        if len == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Too small a buffer is reported with the required size, including
+        // the terminating nul, otherwise the length of the result without it
+        if len < buf.len() {
+            buf.truncate(len);
+            return Ok(PathBuf::from(OsString::from_wide(&buf)));
+        }
+        buf.resize(len, 0);
+    }
+}
+
+/// Sends `CTRL_BREAK_EVENT` to the child's process group. This only reaches
+/// the child if it shares our console, which isn't the case for hidden
+/// processes.
+pub(crate) fn interrupt(child: &Child) -> io::Result<()> {
+    interrupt_pid(child.id())
+}
+
+/// Sends `CTRL_BREAK_EVENT` to the process group of `pid`.
+pub(crate) fn interrupt_pid(pid: u32) -> io::Result<()> {
+    // SAFETY: FFI call without any pointer arguments.
+    if unsafe { GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, pid) } != 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Terminates the process `pid` with `TerminateProcess`.
+pub(crate) fn kill_pid(pid: u32) -> io::Result<()> {
+    // SAFETY: the handle is checked before use and closed exactly once
+    unsafe {
+        let process = OpenProcess(PROCESS_TERMINATE, 0, pid);
+        if process.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let terminated = TerminateProcess(process, 1);
+        let error = io::Error::last_os_error();
+        CloseHandle(process);
+        if terminated != 0 {
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+}
+
+/// Handles the console being closed and the system shutting down instead
+/// of letting them terminate the process, see `shutdown_requested`.
+pub(crate) fn catch_shutdown() -> io::Result<()> {
+    SHUTDOWN.store(false, Ordering::SeqCst);
+    // SAFETY: the handler is a valid function for the lifetime of the process
+    if unsafe { SetConsoleCtrlHandler(Some(on_shutdown_event), 1) } != 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Removes the handler added by `catch_shutdown`.
+pub(crate) fn release_shutdown() {
+    // SAFETY: removing a handler has no requirements
+    unsafe { SetConsoleCtrlHandler(Some(on_shutdown_event), 0) };
+}
+
+/// Returns the exit code to exit with if the console is closing or the
+/// system is shutting down.
+pub(crate) fn shutdown_requested() -> Option<i32> {
+    if SHUTDOWN.load(Ordering::SeqCst) {
+        Some(STATUS_CONTROL_C_EXIT)
+    } else {
+        None
+    }
+}
+
+/// Windows has no zombie processes, and once the `Child` handle is closed
+/// the pid may be reused, so a dropped child is never waited for.
+pub(crate) fn try_reap(_pid: u32) -> bool {
+    true
+}
+
+pub(crate) fn get_powershell_path(probe_paths: &[PathBuf]) -> Result<String> {
+    // Preferred option: use the powershell installation that is on path
+    if is_program_on_path(POWERSHELL_NAME).unwrap_or(false) {
+        return Ok(POWERSHELL_NAME.to_string());
+    }
+
+    // Then any location the user told us to look in
+    if let Some(path) = first_existing(probe_paths.iter().cloned()) {
+        return Ok(path);
+    }
+
+    // Backup option for windows, because cmd apparently ignores powershell on path: Try powershell's default installation path
+    let system_root = match env::var("SYSTEMROOT") {
+        Ok(x) => x,
+        Err(_e) => return Err(PsError::PowershellNotFound),
+    };
+
+    let path_candidate =
+        Path::new(&system_root).join(r#"System32\WindowsPowerShell\v1.0\powershell.exe"#);
+
+    if path_candidate.exists() {
+        Ok(path_candidate.to_string_lossy().to_string())
+    } else {
+        Err(PsError::PowershellNotFound)
+    }
+}
+
+/// Returns every PowerShell installation found on `PATH`, in `probe_paths`
+/// and in the default install locations: Windows PowerShell, and
+/// PowerShell 7 including previews.
+pub(crate) fn installations(probe_paths: &[PathBuf]) -> Vec<PathBuf> {
+    let system_root = env::var_os("SYSTEMROOT")
+        .map(|root| Path::new(&root).join(r#"System32\WindowsPowerShell\v1.0\powershell.exe"#));
+    let program_files = env::var_os("ProgramFiles").into_iter().flat_map(|dir| {
+        ["7", "7-preview"].iter().map(move |version| {
+            Path::new(&dir)
+                .join("PowerShell")
+                .join(version)
+                .join("pwsh.exe")
+        })
+    });
+    let candidates = find_on_path("PowerShell.exe")
+        .into_iter()
+        .chain(system_root)
+        .chain(find_on_path("pwsh.exe"))
+        .chain(probe_paths.iter().cloned())
+        .chain(program_files);
+    all_existing(candidates)
+}
+
+/// Waits for `child` to exit like `Child::wait`, returning the resources it
+/// used as well if they could be read.
+pub(crate) fn wait(child: &mut Child) -> io::Result<(ExitStatus, Option<ResourceUsage>)> {
+    let status = child.wait()?;
+    Ok((status, usage(child).ok()))
+}
+
+/// Returns the exit status and resource usage of `child` if it has exited,
+/// like `Child::try_wait`.
+pub(crate) fn try_wait(
+    child: &mut Child,
+) -> io::Result<Option<(ExitStatus, Option<ResourceUsage>)>> {
+    match child.try_wait()? {
+        Some(status) => Ok(Some((status, usage(child).ok()))),
+        None => Ok(None),
+    }
+}
+
+/// Blocks until `child` has exited, without reading its exit status.
+pub(crate) fn wait_exited(child: &Child) -> io::Result<()> {
+    // SAFETY: the handle stays valid for as long as `child` lives
+    match unsafe { WaitForSingleObject(child.as_raw_handle(), INFINITE) } {
+        WAIT_FAILED => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Reads the resource usage of `child`, which stays available until its
+/// handle is closed.
+fn usage(child: &Child) -> io::Result<ResourceUsage> {
+    let handle = child.as_raw_handle();
+    let mut memory = ProcessMemoryCounters {
+        cb: std::mem::size_of::<ProcessMemoryCounters>() as u32,
+        ..Default::default()
+    };
+    let (mut creation, mut exit, mut kernel, mut user) = (0, 0, 0, 0);
+    let mut io_counters = IoCounters::default();
+    // SAFETY: the handle is valid for as long as we hold a reference to
+    // `child`, and the pointers for the duration of the calls
+    let ok = unsafe {
+        K32GetProcessMemoryInfo(handle, &mut memory, memory.cb) != 0
+            && GetProcessTimes(handle, &mut creation, &mut exit, &mut kernel, &mut user) != 0
+            && GetProcessIoCounters(handle, &mut io_counters) != 0
+    };
+    if !ok {
+        return Err(io::Error::last_os_error());
+    }
+    // Process times are counted in units of 100 nanoseconds
+    let time = |t: u64| Duration::from_nanos(t * 100);
+    Ok(ResourceUsage {
+        peak_memory: memory.peak_working_set_size as u64,
+        user_time: time(user),
+        system_time: time(kernel),
+        read_operations: io_counters.read_operation_count,
+        write_operations: io_counters.write_operation_count,
+        read_bytes: Some(io_counters.read_transfer_count),
+        written_bytes: Some(io_counters.write_transfer_count),
+    })
+}
+
+/// Suspends all threads in the child with `NtSuspendProcess`.
+pub(crate) fn suspend(child: &Child) -> io::Result<()> {
+    // SAFETY: the handle is valid for as long as we hold a reference to `child`
+    nt_status(
+        unsafe { NtSuspendProcess(child.as_raw_handle()) },
+        "NtSuspendProcess",
+    )
+}
+
+/// Resumes a child suspended by `suspend`.
+pub(crate) fn resume(child: &Child) -> io::Result<()> {
+    // SAFETY: the handle is valid for as long as we hold a reference to `child`
+    nt_status(
+        unsafe { NtResumeProcess(child.as_raw_handle()) },
+        "NtResumeProcess",
+    )
+}
+
+fn nt_status(status: i32, function: &str) -> io::Result<()> {
+    if status >= 0 {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "{} failed with status {:#x}",
+            function, status
+        )))
+    }
+}
+
+/// Creates an `ExitStatus` for a process which exited with `code`.
+pub(crate) fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code as u32)
+}
+
+/// Processes aren't terminated by signals on Windows, they always have an
+/// exit code.
+pub(crate) fn exit_signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+/// The listening end of a side channel, a named pipe which only accepts
+/// local clients. Each client is served by an instance of the pipe of its
+/// own.
+#[derive(Debug)]
+pub(crate) struct PipeServer {
+    name: String,
+    path: Vec<u16>,
+    /// The instance the next client connects to. The first one is created
+    /// by `bind`, so the name is taken before the script starts.
+    pending: Mutex<Option<File>>,
+}
+
+impl PipeServer {
+    pub(crate) fn bind(name: &str) -> io::Result<PipeServer> {
+        let path: Vec<u16> = OsStr::new(&format!(r"\\.\pipe\{}", name))
+            .encode_wide()
+            .chain(Some(0))
+            .collect();
+        let first = create_pipe(&path, FILE_FLAG_FIRST_PIPE_INSTANCE)?;
+        Ok(PipeServer {
+            name: name.to_string(),
+            path,
+            pending: Mutex::new(Some(first)),
+        })
+    }
+
+    /// Waits for a client to connect.
+    pub(crate) fn accept(&self) -> io::Result<File> {
+        let pending = self
+            .pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        let pipe = match pending {
+            Some(pipe) => pipe,
+            None => create_pipe(&self.path, 0)?,
+        };
+        // SAFETY: the handle is an open pipe instance, and a null overlapped
+        // pointer makes the call block until a client connects.
+        if unsafe { ConnectNamedPipe(pipe.as_raw_handle(), ptr::null_mut()) } == 0 {
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() != Some(ERROR_PIPE_CONNECTED) {
+                return Err(error);
+            }
+        }
+        Ok(pipe)
+    }
+
+    /// The pipe name to pass to `NamedPipeClientStream`.
+    pub(crate) fn client_name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Connects to the server as a client.
+    pub(crate) fn connect(&self) -> io::Result<File> {
+        OpenOptions::new()
+            .write(true)
+            .open(format!(r"\\.\pipe\{}", self.name))
+    }
+}
+
+fn create_pipe(path: &[u16], flags: u32) -> io::Result<File> {
+    // SAFETY: `path` is nul terminated, and a null security attributes
+    // pointer gives the pipe the default security descriptor.
+    let handle = unsafe {
+        CreateNamedPipeW(
+            path.as_ptr(),
+            PIPE_ACCESS_INBOUND | flags,
+            PIPE_REJECT_REMOTE_CLIENTS,
+            PIPE_UNLIMITED_INSTANCES,
+            0,
+            4096,
+            0,
+            ptr::null_mut(),
+        )
+    };
+    // `INVALID_HANDLE_VALUE`
+    if handle as isize == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the handle was just created and nothing else owns it.
+    Ok(unsafe { File::from_raw_handle(handle) })
+}
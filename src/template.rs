@@ -0,0 +1,214 @@
+//! Scripts with named placeholders which are filled in with escaped
+//! literals, see [`ScriptTemplate`].
+
+use std::collections::HashSet;
+
+use crate::{error::PsError, source::Script, value::PsValue, Result};
+
+/// A script with `<name>` placeholders, which are replaced by the values of
+/// the parameters with the same name rendered as PowerShell literals:
+/// strings are single-quoted, so quotes, `$` and backticks in them are
+/// never interpreted, arrays become `@(...)` and so on, see
+/// [`PsValue::to_literal`].
+///
+/// A placeholder is a name made of letters, digits and underscores in angle
+/// brackets. PowerShell reserves `<` outside of strings and comments, so
+/// placeholders can't be confused with code: script blocks like `{break}`,
+/// braced variables like `${name}` and format strings like `'{0}' -f $x`
+/// are left as they are. Anything in a string or a comment is left as it
+/// is as well, so values can't be substituted into strings, concatenate
+/// them instead: `"Hello " + <name>`.
+///
+/// Rendering fails with `PsError::InvalidParameters` if a placeholder has no
+/// value, or a value no placeholder.
+///
+/// ## Example
+///
+/// ```rust
+/// use powershell_script::{PsValue, ScriptTemplate};
+///
+/// let template = ScriptTemplate::new("Get-ChildItem -Path <path> | Where-Object { $_.Length -gt <size> }");
+/// let script = template
+///     .render(vec![
+///         ("path", PsValue::from("C:\\it's $here")),
+///         ("size", PsValue::from(1024)),
+///     ])
+///     .unwrap();
+/// assert_eq!(
+///     script.source(),
+///     "Get-ChildItem -Path 'C:\\it''s $here' | Where-Object { $_.Length -gt 1024 }"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptTemplate {
+    source: String,
+}
+
+/// A piece of a parsed template.
+enum Part<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+impl ScriptTemplate {
+    /// Creates a template from its source.
+    pub fn new(source: impl Into<String>) -> Self {
+        ScriptTemplate {
+            source: source.into(),
+        }
+    }
+
+    /// The source of the template, with the placeholders.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns the names of the placeholders, in the order they first
+    /// appear.
+    pub fn placeholders(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        for part in parse(&self.source) {
+            if let Part::Placeholder(name) = part {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        names
+    }
+
+    /// Returns the script with the placeholders replaced by the values in
+    /// `params`. Names are compared case-insensitively, like PowerShell
+    /// compares variable names.
+    pub fn render<I, K, V>(&self, params: I) -> Result<Script>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Into<PsValue>,
+    {
+        let params: Vec<(String, PsValue)> = params
+            .into_iter()
+            .map(|(k, v)| (k.as_ref().to_string(), v.into()))
+            .collect();
+        let mut used = HashSet::new();
+        let mut out = String::with_capacity(self.source.len());
+        for part in parse(&self.source) {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Placeholder(name) => {
+                    let index = params
+                        .iter()
+                        .position(|(k, _)| k.eq_ignore_ascii_case(name))
+                        .ok_or_else(|| {
+                            PsError::InvalidParameters(format!(
+                                "the template: there's no value for `<{}>`",
+                                name
+                            ))
+                        })?;
+                    used.insert(index);
+                    out.push_str(&params[index].1.to_literal());
+                }
+            }
+        }
+        if let Some((name, _)) = (0..params.len())
+            .find(|i| !used.contains(i))
+            .map(|i| &params[i])
+        {
+            return Err(PsError::InvalidParameters(format!(
+                "the template: there's no placeholder for `{}`",
+                name
+            )));
+        }
+        Ok(Script::new(out))
+    }
+}
+
+impl From<&str> for ScriptTemplate {
+    fn from(source: &str) -> Self {
+        ScriptTemplate::new(source)
+    }
+}
+
+impl From<String> for ScriptTemplate {
+    fn from(source: String) -> Self {
+        ScriptTemplate::new(source)
+    }
+}
+
+/// Splits `source` into text and placeholders, skipping strings, here-strings
+/// and comments.
+fn parse(source: &str) -> Vec<Part<'_>> {
+    let bytes = source.as_bytes();
+    let mut parts = Vec::new();
+    let mut text_start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &source[i..];
+        // Here-strings, which end with the quote and `@` at the start of a
+        // line
+        if rest.starts_with("@'") || rest.starts_with("@\"") {
+            let end = if rest.starts_with("@'") {
+                "\n'@"
+            } else {
+                "\n\"@"
+            };
+            i += rest.find(end).map_or(rest.len(), |e| e + end.len());
+            continue;
+        }
+        match bytes[i] {
+            b'\'' => i += string_end(rest, b'\'', false),
+            b'"' => i += string_end(rest, b'"', true),
+            b'<' if rest.starts_with("<#") => {
+                i += rest.find("#>").map_or(rest.len(), |e| e + 2);
+            }
+            b'#' => i += rest.find('\n').unwrap_or(rest.len()),
+            b'<' => {
+                if let Some(name) = placeholder(rest) {
+                    parts.push(Part::Text(&source[text_start..i]));
+                    parts.push(Part::Placeholder(name));
+                    i += name.len() + 2;
+                    text_start = i;
+                } else {
+                    i += 1;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    parts.push(Part::Text(&source[text_start..]));
+    parts
+}
+
+/// Returns the length of the string literal at the start of `rest`,
+/// including its quotes. Quotes are escaped by doubling them, and in
+/// expandable strings with a backtick as well.
+fn string_end(rest: &str, quote: u8, expandable: bool) -> usize {
+    let bytes = rest.as_bytes();
+    let mut i = 1;
+    while i < bytes.len() {
+        if expandable && bytes[i] == b'`' {
+            i += 2;
+        } else if bytes[i] == quote {
+            if bytes.get(i + 1) == Some(&quote) {
+                i += 2;
+            } else {
+                return i + 1;
+            }
+        } else {
+            i += 1;
+        }
+    }
+    bytes.len()
+}
+
+/// Returns the name if `rest` starts with a placeholder, `<name>`.
+fn placeholder(rest: &str) -> Option<&str> {
+    let inner = rest.strip_prefix('<')?;
+    let end = inner.find('>')?;
+    let name = &inner[..end];
+    let mut chars = name.chars();
+    let first = chars.next()?;
+    let valid = (first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    Some(name).filter(|_| valid)
+}
@@ -0,0 +1,221 @@
+//! Parsers for the text PowerShell's formatting cmdlets produce, for output
+//! which can't be turned into JSON at the source, and for records separated
+//! by a sentinel.
+
+use std::collections::HashMap;
+
+/// Parses `Format-Table` style output into one map per row, keyed by column
+/// header.
+///
+/// The column boundaries are taken from the dashes underlining the headers,
+/// so values containing spaces are handled. The last column extends to the
+/// end of the line. Output containing several tables, like the grouped
+/// output of `Get-ChildItem` on several directories, returns the rows of all
+/// of them. Lines which aren't part of a table are ignored.
+///
+/// ## Example
+///
+/// ```rust
+/// let text = "
+/// Name        Id Status
+/// ----        -- ------
+/// My Service 101 Running
+/// Other        7 Stopped
+/// ";
+/// let rows = powershell_script::parse_table(text);
+/// assert_eq!(rows[0]["Name"], "My Service");
+/// assert_eq!(rows[1]["Id"], "7");
+/// ```
+pub fn parse_table(text: &str) -> Vec<HashMap<String, String>> {
+    let lines: Vec<Vec<char>> = text
+        .lines()
+        .map(|line| line.trim_end().chars().collect())
+        .collect();
+
+    let mut rows = Vec::new();
+    let mut i = 1;
+    while i < lines.len() {
+        let columns = match underline(&lines[i]) {
+            Some(columns) if !lines[i - 1].is_empty() => columns,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        let headers = cells(&lines[i - 1], &columns);
+        i += 1;
+        while i < lines.len() && !lines[i].is_empty() {
+            let values = cells(&lines[i], &columns);
+            rows.push(headers.iter().cloned().zip(values).collect());
+            i += 1;
+        }
+    }
+    rows
+}
+
+/// Returns the `(start, end)` of each run of dashes if `line` consists of
+/// nothing else.
+fn underline(line: &[char]) -> Option<Vec<(usize, usize)>> {
+    if !line.contains(&'-') || line.iter().any(|c| *c != '-' && *c != ' ') {
+        return None;
+    }
+    let mut columns = Vec::new();
+    let mut start = None;
+    for (i, c) in line.iter().enumerate() {
+        match (c, start) {
+            ('-', None) => start = Some(i),
+            (' ', Some(s)) => {
+                columns.push((s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        columns.push((s, line.len()));
+    }
+    Some(columns)
+}
+
+/// Cuts `line` into the cells given by `columns`.
+///
+/// The dashes are only as wide as the header, not the column, so a cell is
+/// the text under the dashes extended to the left (right-aligned columns) and
+/// right (left-aligned columns) until a space or the next column. The last
+/// cell extends to the end of the line.
+fn cells(line: &[char], columns: &[(usize, usize)]) -> Vec<String> {
+    columns
+        .iter()
+        .enumerate()
+        .map(|(i, &(start, end))| {
+            let min = if i == 0 { 0 } else { columns[i - 1].1 + 1 };
+            let max = match columns.get(i + 1) {
+                Some(next) => next.0.saturating_sub(1).min(line.len()),
+                None => line.len(),
+            };
+
+            let mut start = start.min(max);
+            while start > min && line[start - 1] != ' ' {
+                start -= 1;
+            }
+            let mut end = if i + 1 == columns.len() {
+                max
+            } else {
+                end.clamp(start, max)
+            };
+            while end < max && line[end] != ' ' {
+                end += 1;
+            }
+            line[start..end]
+                .iter()
+                .collect::<String>()
+                .trim()
+                .to_string()
+        })
+        .collect()
+}
+
+/// Parses `Format-List` style output (`Name : Value` lines) into one map per
+/// record. Records are separated by blank lines.
+///
+/// Lines which start with whitespace continue the value on the line before,
+/// which is how both long values wrapped to the console width and values
+/// with line breaks of their own are written. They're joined with `\n`.
+///
+/// ## Example
+///
+/// ```rust
+/// let text = "
+/// Name        : pwsh
+/// Description : A very long description
+///               which was wrapped
+///
+/// Name        : cmd
+/// Description :
+/// ";
+/// let records = powershell_script::parse_list(text);
+/// assert_eq!(records[0]["Description"], "A very long description\nwhich was wrapped");
+/// assert_eq!(records[1]["Name"], "cmd");
+/// assert_eq!(records[1]["Description"], "");
+/// ```
+pub fn parse_list(text: &str) -> Vec<HashMap<String, String>> {
+    let mut records = Vec::new();
+    let mut record: HashMap<String, String> = HashMap::new();
+    let mut key: Option<String> = None;
+
+    for line in text.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            if !record.is_empty() {
+                records.push(std::mem::take(&mut record));
+            }
+            key = None;
+            continue;
+        }
+
+        if line.starts_with(char::is_whitespace) {
+            if let Some(value) = key.as_ref().and_then(|k| record.get_mut(k)) {
+                value.push('\n');
+                value.push_str(line.trim_start());
+            }
+            continue;
+        }
+
+        let separator = line
+            .find(" : ")
+            .map(|i| (i, i + 3))
+            .or_else(|| line.strip_suffix(" :").map(|rest| (rest.len(), line.len())));
+        match separator {
+            Some((key_end, value_start)) => {
+                let k = line[..key_end].trim().to_string();
+                record.insert(k.clone(), line[value_start..].trim().to_string());
+                key = Some(k);
+            }
+            // Not part of a list, like the header of a group
+            None => key = None,
+        }
+    }
+    if !record.is_empty() {
+        records.push(record);
+    }
+    records
+}
+
+/// Splits `text` into records ending with `separator`, for scripts which
+/// write values that may contain line breaks themselves, like
+/// `Get-ChildItem | ForEach-Object { "$($_.FullName)`0" }`. Scripts usually
+/// separate records with NUL, which can't occur in PowerShell's text output
+/// otherwise.
+///
+/// A line break directly after a separator is dropped, since that's what
+/// `Write-Output` puts after every value. Whatever follows the last
+/// separator is returned as the last record unless it's empty.
+///
+/// ## Example
+///
+/// ```rust
+/// let text = "first\nvalue\0\r\nsecond\0\r\n";
+/// let records = powershell_script::split_records(text, "\0");
+/// assert_eq!(records, ["first\nvalue", "second"]);
+/// ```
+pub fn split_records(text: &str, separator: &str) -> Vec<String> {
+    if separator.is_empty() {
+        return vec![text.to_string()];
+    }
+    let mut records: Vec<String> = Vec::new();
+    for (i, record) in text.split(separator).enumerate() {
+        let record = if i == 0 {
+            record
+        } else {
+            record
+                .strip_prefix("\r\n")
+                .or_else(|| record.strip_prefix('\n'))
+                .unwrap_or(record)
+        };
+        records.push(record.to_string());
+    }
+    if records.last().map(String::is_empty).unwrap_or(false) {
+        records.pop();
+    }
+    records
+}
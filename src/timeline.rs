@@ -0,0 +1,398 @@
+use crate::{
+    child,
+    tracker::{self, Tracked},
+    usage::ResourceUsage,
+    wrap,
+};
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Read},
+    process::{self, Child},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Written to `stderr` by the instrumented script when it starts a command,
+/// followed by the command's index.
+const LINE_MARKER: &str = "##ps-line:";
+/// How the lines `instrument` inserts start.
+const MARKER_PREFIX: &str = "[Console]::Error.WriteLine('##ps-line:";
+
+/// When a command of a script started and how much output it produced,
+/// see [`Timeline`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimelineEntry {
+    /// Time from PowerShell being started until the command started.
+    pub offset: Duration,
+    /// The line of the script.
+    pub command: String,
+    /// Bytes written to `stdout` and `stderr` from the command starting
+    /// until the next one started.
+    pub output_bytes: usize,
+}
+
+/// A record of when each command of a script started, collected when
+/// `PsScriptBuilder::record_timeline` is set. The last entry of a script
+/// that hung or failed is the command which was running at the time.
+///
+/// Commands are recognized by line and the crate only marks lines where it's
+/// sure a new statement can start, so the output of a multi-line statement
+/// is attributed to its first line. Output is read from two pipes in
+/// parallel, so the byte counts around the start of a command are
+/// approximate.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Timeline {
+    entries: Vec<TimelineEntry>,
+}
+
+impl Timeline {
+    /// The recorded commands, in the order they started.
+    pub fn entries(&self) -> &[TimelineEntry] {
+        &self.entries
+    }
+
+    /// The command which started last.
+    pub fn last(&self) -> Option<&TimelineEntry> {
+        self.entries.last()
+    }
+}
+
+impl fmt::Display for Timeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(
+                f,
+                "{:>9.3}s {:>9} B  {}",
+                entry.offset.as_secs_f64(),
+                entry.output_bytes,
+                entry.command
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// What a bracket we're inside of opened.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Bracket {
+    /// A script block, where statements can start on any line.
+    Block,
+    /// Anything else: hashtables, `switch` and `class` bodies, parentheses...
+    Other,
+}
+
+/// Lexical state carried from one line to the next.
+#[derive(Debug, Default)]
+struct Scanner {
+    brackets: Vec<Bracket>,
+    string: Option<char>,
+    here_string: Option<char>,
+    block_comment: bool,
+    /// The last character of code on the previous line.
+    last: Option<char>,
+    marked_any: bool,
+}
+
+/// Inserts a line before every command of `script` that writes a marker to
+/// `stderr`, returning the new script and the commands in marker order.
+pub(crate) fn instrument(script: &str) -> (String, Vec<String>) {
+    let mut scanner = Scanner::default();
+    let mut out = String::with_capacity(script.len() * 2);
+    let mut commands = Vec::new();
+    for line in script.lines() {
+        if scanner.starts_statement(line) {
+            out.push_str(&format!("{}{}')\n", MARKER_PREFIX, commands.len()));
+            commands.push(line.trim().to_string());
+            scanner.marked_any = true;
+        }
+        scanner.scan(line);
+        out.push_str(line);
+        out.push('\n');
+    }
+    (out, commands)
+}
+
+/// Inserts a check after every top level command of `script` which ends
+/// the script if the command failed, writing the number of the command's
+/// first line in a block tagged `failed-line`. Commands inside script blocks
+/// aren't checked since exiting from the middle of a function definition
+/// would break the script.
+///
+/// `script` may already be instrumented by `instrument`, in which case the
+/// checks go in front of the markers so they see the `$?` of the command
+/// before it, and line numbers refer to the original script.
+pub(crate) fn abort_on_error(script: &str) -> String {
+    let mut scanner = Scanner::default();
+    let mut out = String::with_capacity(script.len() * 2);
+    let mut line_no = 0;
+    let mut current = None;
+    let mut checked = false;
+    for line in script.lines() {
+        if line.starts_with(MARKER_PREFIX) {
+            if scanner.brackets.is_empty() {
+                if let Some(failed) = current {
+                    out.push_str(&failure_check(failed));
+                }
+                checked = true;
+            }
+            out.push_str(line);
+            out.push('\n');
+            continue;
+        }
+
+        line_no += 1;
+        if scanner.brackets.is_empty() && scanner.starts_statement(line) {
+            if let Some(failed) = current.filter(|_| !checked) {
+                out.push_str(&failure_check(failed));
+            }
+            current = Some(line_no);
+            scanner.marked_any = true;
+        }
+        checked = false;
+        scanner.scan(line);
+        out.push_str(line);
+        out.push('\n');
+    }
+    if let Some(failed) = current {
+        out.push_str(&failure_check(failed));
+    }
+    out
+}
+
+fn failure_check(line_no: usize) -> String {
+    format!(
+        "if (-not $?) {{ {}; exit 1 }}\n",
+        wrap::emit_block("failed-line", &line_no.to_string())
+    )
+}
+
+impl Scanner {
+    /// Whether a statement can be inserted in front of `line`.
+    fn starts_statement(&self, line: &str) -> bool {
+        let trimmed = line.trim();
+        if trimmed.is_empty()
+            || trimmed.starts_with('#')
+            || trimmed.starts_with("<#")
+            || self.string.is_some()
+            || self.here_string.is_some()
+            || self.block_comment
+            || self.brackets.contains(&Bracket::Other)
+            || matches!(self.last, Some('`' | '|' | ',' | '=' | '+'))
+        {
+            return false;
+        }
+
+        if trimmed.starts_with(['{', '}', '|', '.', ')', '-']) {
+            return false;
+        }
+
+        // Statements can't be put in front of these without changing the
+        // meaning of (or breaking) the script
+        let keyword = first_word(trimmed);
+        let continues = [
+            "else",
+            "elseif",
+            "catch",
+            "finally",
+            "begin",
+            "process",
+            "end",
+            "clean",
+            "dynamicparam",
+            "param",
+            "using",
+        ];
+        if continues.contains(&keyword.as_str()) {
+            return false;
+        }
+        if (keyword == "while" || keyword == "until") && self.last == Some('}') {
+            return false;
+        }
+        // Attributes (and `param` blocks) must come first in a script block
+        if !self.marked_any && trimmed.starts_with('[') {
+            return false;
+        }
+        true
+    }
+
+    fn scan(&mut self, line: &str) {
+        if let Some(quote) = self.here_string {
+            if line.starts_with(quote) && line[1..].starts_with('@') {
+                self.here_string = None;
+                self.last = Some('@');
+            }
+            return;
+        }
+
+        let keyword = first_word(line.trim());
+        let mut chars = line.chars().peekable();
+        let mut prev = None;
+        let mut last = None;
+        while let Some(c) = chars.next() {
+            if self.block_comment {
+                if c == '#' && chars.peek() == Some(&'>') {
+                    chars.next();
+                    self.block_comment = false;
+                }
+                continue;
+            }
+            if let Some(quote) = self.string {
+                if c == '`' && quote == '"' {
+                    chars.next();
+                } else if c == quote {
+                    if chars.peek() == Some(&quote) {
+                        chars.next();
+                    } else {
+                        self.string = None;
+                        last = Some(c);
+                    }
+                }
+                continue;
+            }
+            match c {
+                '#' => break,
+                '<' if chars.peek() == Some(&'#') => {
+                    chars.next();
+                    self.block_comment = true;
+                }
+                '`' => {
+                    // A backtick at the end of the line continues it
+                    last = Some(c);
+                    chars.next();
+                }
+                '\'' | '"' if prev == Some('@') && chars.peek().is_none() => {
+                    self.here_string = Some(c);
+                    return;
+                }
+                '\'' | '"' => self.string = Some(c),
+                '{' => {
+                    let bracket = match keyword.as_str() {
+                        _ if prev == Some('@') => Bracket::Other,
+                        "switch" | "class" | "enum" | "configuration" => Bracket::Other,
+                        _ => Bracket::Block,
+                    };
+                    self.brackets.push(bracket);
+                }
+                '(' | '[' => self.brackets.push(Bracket::Other),
+                '}' | ')' | ']' => {
+                    self.brackets.pop();
+                }
+                _ => {}
+            }
+            if !c.is_whitespace() {
+                prev = Some(c);
+                if c != '`' {
+                    last = Some(c);
+                }
+            }
+        }
+        if last.is_some() {
+            self.last = last;
+        }
+    }
+}
+
+fn first_word(line: &str) -> String {
+    line.split(|c: char| c.is_whitespace() || c == '{' || c == '(')
+        .next()
+        .unwrap_or("")
+        .to_ascii_lowercase()
+}
+
+#[derive(Debug)]
+struct Recorder {
+    started: Instant,
+    commands: Vec<String>,
+    entries: Vec<TimelineEntry>,
+}
+
+impl Recorder {
+    fn add_bytes(&mut self, bytes: usize) {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.output_bytes += bytes;
+        }
+    }
+}
+
+/// Waits for a child running an instrumented script while recording the
+/// timeline. The markers are removed from `stderr`.
+pub(crate) fn collect(
+    mut child: Child,
+    mut tracked: Option<Tracked>,
+    commands: Vec<String>,
+) -> io::Result<(process::Output, Timeline, Option<ResourceUsage>)> {
+    let recorder = Arc::new(Mutex::new(Recorder {
+        started: Instant::now(),
+        commands,
+        entries: Vec::new(),
+    }));
+
+    let stdout = child.stdout.take().map(|mut pipe| {
+        let recorder = recorder.clone();
+        thread::spawn(move || {
+            let mut out = Vec::new();
+            let mut buf = [0; 8192];
+            loop {
+                let n = pipe.read(&mut buf)?;
+                if n == 0 {
+                    return Ok(out);
+                }
+                out.extend_from_slice(&buf[..n]);
+                lock(&recorder).add_bytes(n);
+            }
+        })
+    });
+
+    let stderr = child.stderr.take().map(|pipe| {
+        let recorder = recorder.clone();
+        thread::spawn(move || {
+            let mut out = Vec::new();
+            let mut pipe = BufReader::new(pipe);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                if pipe.read_until(b'\n', &mut line)? == 0 {
+                    return Ok(out);
+                }
+                let mut recorder = lock(&recorder);
+                let index = String::from_utf8_lossy(&line)
+                    .trim_end()
+                    .strip_prefix(LINE_MARKER)
+                    .and_then(|index| index.parse::<usize>().ok());
+                match index.and_then(|i| recorder.commands.get(i).cloned()) {
+                    Some(command) => {
+                        let offset = recorder.started.elapsed();
+                        recorder.entries.push(TimelineEntry {
+                            offset,
+                            command,
+                            output_bytes: 0,
+                        });
+                    }
+                    None => {
+                        recorder.add_bytes(line.len());
+                        out.extend_from_slice(&line);
+                    }
+                }
+            }
+        })
+    });
+
+    let (status, usage) = tracker::wait(&mut child, tracked.as_mut())?;
+    let stdout = child::join(stdout)?;
+    let stderr = child::join(stderr)?;
+    let entries = std::mem::take(&mut lock(&recorder).entries);
+    Ok((
+        process::Output {
+            status,
+            stdout,
+            stderr,
+        },
+        Timeline { entries },
+        usage,
+    ))
+}
+
+fn lock(recorder: &Mutex<Recorder>) -> std::sync::MutexGuard<'_, Recorder> {
+    recorder.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,236 @@
+//! Keeping track of the PowerShell processes started by a `PsScript`, so
+//! they can be stopped when the application exits.
+
+use std::{
+    collections::HashSet,
+    fmt, io,
+    process::{Child, ExitStatus},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{target, usage::ResourceUsage};
+
+/// How often `shutdown_all` checks whether the processes have exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Records the process id of every PowerShell process started by the
+/// `PsScript`s it's given to with
+/// [`PsScriptBuilder::child_tracker`](crate::PsScriptBuilder::child_tracker),
+/// until the process has exited and been waited for.
+///
+/// Clones share the same set of processes.
+///
+/// A [`PsChild`](crate::PsChild) dropped without waiting for it leaves the
+/// process running. On Unix the tracker keeps it and reaps it once it exits,
+/// so it doesn't linger as a zombie. On Windows there's nothing to reap and
+/// the process id may be reused once the handle is closed, so the process is
+/// no longer tracked.
+///
+/// ## Example
+///
+/// ```rust, no_run
+/// use std::time::Duration;
+/// use powershell_script::{ChildTracker, PsScriptBuilder};
+///
+/// let tracker = ChildTracker::new();
+/// let ps = PsScriptBuilder::new().child_tracker(tracker.clone()).build();
+/// let child = ps.spawn("Start-Sleep 600").unwrap();
+/// drop(child);
+///
+/// // On application exit
+/// let killed = tracker.shutdown_all(Duration::from_secs(5));
+/// println!("killed {} PowerShell processes", killed);
+/// ```
+#[derive(Clone, Default)]
+pub struct ChildTracker {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Processes somebody is holding the `Child` of.
+    running: HashSet<u32>,
+    /// Processes whose `Child` was dropped before they were waited for.
+    orphans: Vec<u32>,
+}
+
+impl State {
+    fn reap(&mut self) {
+        self.orphans.retain(|pid| !target::try_reap(*pid));
+    }
+
+    fn pids(&self) -> Vec<u32> {
+        self.running.iter().chain(&self.orphans).copied().collect()
+    }
+}
+
+impl ChildTracker {
+    /// Creates a tracker which doesn't track any processes yet.
+    pub fn new() -> Self {
+        ChildTracker::default()
+    }
+
+    /// Returns the process ids of the tracked processes which haven't exited
+    /// yet, or have exited but haven't been waited for.
+    pub fn pids(&self) -> Vec<u32> {
+        let mut state = self.lock();
+        state.reap();
+        state.pids()
+    }
+
+    /// Returns the number of tracked processes.
+    pub fn len(&self) -> usize {
+        self.pids().len()
+    }
+
+    /// Whether no processes are tracked.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Stops all tracked processes, for calling when the application exits.
+    /// The processes are asked to stop like with
+    /// [`PsChild::interrupt`](crate::PsChild::interrupt), and those still
+    /// running after `grace` are killed. Returns the number of processes
+    /// which had to be killed.
+    ///
+    /// Processes started while this runs are stopped as well if they're
+    /// still running once the grace period is over.
+    pub fn shutdown_all(&self, grace: Duration) -> usize {
+        // Tracked processes are only reaped with the lock held, so holding
+        // it keeps their ids from being reused while they're signalled.
+        {
+            let mut state = self.lock();
+            state.reap();
+            let pids = state.pids();
+            if pids.is_empty() {
+                return 0;
+            }
+            for pid in pids {
+                // Processes which don't get the signal are killed below
+                let _ = target::interrupt_pid(pid);
+            }
+        }
+
+        let deadline = Instant::now() + grace;
+        while Instant::now() < deadline {
+            if self.is_empty() {
+                return 0;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        let mut state = self.lock();
+        state.reap();
+        let killed = state
+            .pids()
+            .into_iter()
+            .filter(|pid| target::kill_pid(*pid).is_ok())
+            .count();
+        state.reap();
+        killed
+    }
+
+    /// Starts tracking `child`, until the returned guard is dropped.
+    pub(crate) fn track(&self, child: &Child) -> Tracked {
+        let pid = child.id();
+        let mut state = self.lock();
+        state.reap();
+        state.running.insert(pid);
+        Tracked {
+            tracker: self.clone(),
+            pid,
+            reaped: false,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // The state is consistent after every operation, so a panic while
+        // holding the lock doesn't leave anything to clean up.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for ChildTracker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ChildTracker")
+            .field("pids", &self.pids())
+            .finish()
+    }
+}
+
+/// Keeps a process tracked while it's alive. The process has to be waited
+/// for with [`wait`] or [`try_wait`], which stop tracking it as it's reaped.
+pub(crate) struct Tracked {
+    tracker: ChildTracker,
+    pid: u32,
+    /// Set once the process is reaped, after which its id may belong to
+    /// another process.
+    reaped: bool,
+}
+
+impl Tracked {
+    /// Hands the process over to the tracker to be reaped once it exits,
+    /// since nobody is going to wait for it.
+    pub(crate) fn orphan(mut self) {
+        let mut state = self.tracker.lock();
+        if !self.reaped && state.running.remove(&self.pid) {
+            state.orphans.push(self.pid);
+        }
+        self.reaped = true;
+    }
+
+    /// Calls `reap` with the tracker's lock held, and stops tracking the
+    /// process if it reaped it.
+    fn reap<T>(&mut self, reap: impl FnOnce() -> io::Result<Option<T>>) -> io::Result<Option<T>> {
+        let mut state = self.tracker.lock();
+        let exited = reap()?;
+        if exited.is_some() {
+            state.running.remove(&self.pid);
+            self.reaped = true;
+        }
+        Ok(exited)
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        if !self.reaped {
+            self.tracker.lock().running.remove(&self.pid);
+        }
+    }
+}
+
+/// Waits for `child` to exit like `target::wait`. A tracked child is reaped
+/// with the tracker's lock held, once it has exited, so `shutdown_all` can't
+/// signal another process which was given its id.
+pub(crate) fn wait(
+    child: &mut Child,
+    tracked: Option<&mut Tracked>,
+) -> io::Result<(ExitStatus, Option<ResourceUsage>)> {
+    let tracked = match tracked {
+        Some(tracked) => tracked,
+        None => return target::wait(child),
+    };
+    // Closing stdin like `target::wait` does keeps the child from waiting for
+    // input forever
+    drop(child.stdin.take());
+    target::wait_exited(child)?;
+    tracked
+        .reap(|| target::try_wait(child))
+        .map(|exited| exited.expect("the child has exited"))
+}
+
+/// Returns the exit status of `child` if it has exited, like
+/// `target::try_wait`, reaping it like `wait`.
+pub(crate) fn try_wait(
+    child: &mut Child,
+    tracked: Option<&mut Tracked>,
+) -> io::Result<Option<(ExitStatus, Option<ResourceUsage>)>> {
+    match tracked {
+        Some(tracked) => tracked.reap(|| target::try_wait(child)),
+        None => target::try_wait(child),
+    }
+}
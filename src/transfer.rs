@@ -0,0 +1,162 @@
+//! Copying files to and from contexts a script may run in which can't simply
+//! share files with the host, like another account on the same machine or a
+//! remote computer.
+//!
+//! The copies are made by running a script with the given `PsScript`, so
+//! they happen with the permissions of the account it runs as.
+//!
+//! ## Example
+//!
+//! ```rust, no_run
+//! use powershell_script::{transfer::{self, Context}, PsScriptBuilder};
+//!
+//! let ps = PsScriptBuilder::new().build();
+//! let server = Context::remote("build-01");
+//! transfer::push(&ps, "./dist/app.zip", r"C:\deploy\app.zip", &server).unwrap();
+//! ps.run(r"Invoke-Command -ComputerName build-01 { Expand-Archive C:\deploy\app.zip C:\app -Force }").unwrap();
+//! transfer::pull(&ps, r"C:\app\install.log", "./install.log", &server).unwrap();
+//! ```
+
+use std::path::Path;
+
+use crate::{credential::Credential, wrap, PsScript, Result};
+
+/// Where files are copied to or from.
+#[derive(Debug, Clone)]
+pub enum Context {
+    /// A process on this machine running as `user`, like an elevated process
+    /// or a service account, which may not have access to the files of the
+    /// account the host runs as. Pushed files are granted `Modify` access
+    /// for the user. Pulled files are copied as the account `PsScript` runs
+    /// as, so they must be readable by it.
+    User(String),
+    /// A remote computer, reached through a PowerShell remoting session.
+    Remote {
+        computer: String,
+        credential: Option<Credential>,
+    },
+}
+
+impl Context {
+    /// A process on this machine running as `user`.
+    pub fn user(user: impl Into<String>) -> Self {
+        Context::User(user.into())
+    }
+
+    /// A remote computer, connected to as the current user.
+    pub fn remote(computer: impl Into<String>) -> Self {
+        Context::Remote {
+            computer: computer.into(),
+            credential: None,
+        }
+    }
+
+    /// A remote computer, connected to with `credential`.
+    pub fn remote_with_credential(computer: impl Into<String>, credential: Credential) -> Self {
+        Context::Remote {
+            computer: computer.into(),
+            credential: Some(credential),
+        }
+    }
+}
+
+/// Copies the local file or directory `local` to `dest` in `context`.
+pub fn push<P: AsRef<Path>>(ps: &PsScript, local: P, dest: &str, context: &Context) -> Result<()> {
+    let local = wrap::quote(&local.as_ref().to_string_lossy());
+    let dest_quoted = wrap::quote(dest);
+    let script = match context {
+        Context::User(user) => (
+            String::new(),
+            format!(
+                "Copy-Item -LiteralPath {} -Destination {} -Recurse -Force -ErrorAction Stop\n{}\n",
+                local,
+                dest_quoted,
+                grant_access(dest, user)
+            ),
+        ),
+        Context::Remote { .. } => in_session(
+            context,
+            &format!(
+                "Copy-Item -LiteralPath {} -Destination {} -ToSession $__ps_session -Recurse -Force -ErrorAction Stop",
+                local, dest_quoted
+            ),
+        ),
+    };
+    run(ps, script)
+}
+
+/// Copies the file or directory `src` in `context` to the local path
+/// `local`.
+pub fn pull<P: AsRef<Path>>(ps: &PsScript, src: &str, local: P, context: &Context) -> Result<()> {
+    let local = wrap::quote(&local.as_ref().to_string_lossy());
+    let src = wrap::quote(src);
+    let script = match context {
+        Context::User(_) => (
+            String::new(),
+            format!(
+                "Copy-Item -LiteralPath {} -Destination {} -Recurse -Force -ErrorAction Stop\n",
+                src, local
+            ),
+        ),
+        Context::Remote { .. } => in_session(
+            context,
+            &format!(
+                "Copy-Item -LiteralPath {} -Destination {} -FromSession $__ps_session -Recurse -Force -ErrorAction Stop",
+                src, local
+            ),
+        ),
+    };
+    run(ps, script)
+}
+
+/// Returns a script granting `user` modify access to `path` and, if it's a
+/// directory, everything in it.
+fn grant_access(path: &str, user: &str) -> String {
+    format!(
+        "$__ps_path = {path}; $__ps_inherit = if ((Get-Item -LiteralPath $__ps_path).PSIsContainer) {{ 'ContainerInherit, ObjectInherit' }} else {{ 'None' }}; $__ps_acl = Get-Acl -LiteralPath $__ps_path; $__ps_acl.AddAccessRule((New-Object System.Security.AccessControl.FileSystemAccessRule({user}, 'Modify', $__ps_inherit, 'None', 'Allow'))); Set-Acl -LiteralPath $__ps_path -AclObject $__ps_acl -ErrorAction Stop",
+        path = wrap::quote(path),
+        user = wrap::quote(user)
+    )
+}
+
+/// Returns a script running `command` with `$__ps_session` connected to the
+/// remote computer of `context`, and disconnecting again even if it fails,
+/// along with the commands setting up the credential it needs.
+fn in_session(context: &Context, command: &str) -> (String, String) {
+    let (computer, credential) = match context {
+        Context::Remote {
+            computer,
+            credential,
+        } => (computer, credential),
+        Context::User(_) => unreachable!("only called for remote contexts"),
+    };
+    let (secrets, credential) = match credential {
+        Some(credential) => (
+            format!(
+                "$__ps_cred = New-Object System.Management.Automation.PSCredential({}, (ConvertTo-SecureString {} -AsPlainText -Force))\n",
+                wrap::quote(&credential.user_name),
+                wrap::quote(&credential.password)
+            ),
+            " -Credential $__ps_cred",
+        ),
+        None => (String::new(), ""),
+    };
+    let script = format!(
+        "$__ps_session = New-PSSession -ComputerName {}{} -ErrorAction Stop\ntry {{ {} }} finally {{ Remove-PSSession -Session $__ps_session }}\n",
+        wrap::quote(computer),
+        credential,
+        command
+    );
+    (secrets, script)
+}
+
+/// Runs `script` after `secrets`, which are kept from middleware and
+/// `print_commands`.
+fn run(ps: &PsScript, (secrets, script): (String, String)) -> Result<()> {
+    if secrets.is_empty() {
+        ps.run(&script)?;
+    } else {
+        ps.run_with_secrets(&script, &(secrets + &script))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,218 @@
+//! Rust types for the .NET types which don't have a JSON representation of
+//! their own: `DateTime`, `Guid` and `byte[]`.
+
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    base64,
+    error::PsError,
+    value::{FromPsValue, PsValue},
+    Result,
+};
+
+fn invalid<T>(expected: &str, found: &PsValue) -> Result<T> {
+    Err(PsError::Deserialize(format!(
+        "expected {}, found {}",
+        expected,
+        found.to_literal()
+    )))
+}
+
+/// Dates are serialized as `/Date(<ms since epoch>)/` by Windows PowerShell
+/// and as ISO 8601 strings by PowerShell Core. Dates without an offset are
+/// taken to be UTC.
+impl FromPsValue for SystemTime {
+    fn from_ps_value(value: PsValue) -> Result<Self> {
+        let parsed = match &value {
+            PsValue::String(s) => parse_date(s.trim()),
+            // Windows PowerShell serializes dates from cmdlets like `Get-Date`
+            // as an object with the date in `value`
+            PsValue::Object(_) => match value.get("value") {
+                Some(PsValue::String(s)) => parse_date(s.trim()),
+                _ => None,
+            },
+            _ => None,
+        };
+        parsed.map_or_else(|| invalid("a date", &value), Ok)
+    }
+}
+
+fn parse_date(s: &str) -> Option<SystemTime> {
+    if let Some(rest) = s.strip_prefix("/Date(") {
+        let end = rest.find(|c: char| c != '-' && !c.is_ascii_digit())?;
+        let ms: i64 = rest[..end].parse().ok()?;
+        return Some(from_unix(
+            ms.div_euclid(1000),
+            ms.rem_euclid(1000) as u32 * 1_000_000,
+        ));
+    }
+    parse_iso8601(s)
+}
+
+/// Parses `YYYY-MM-DDTHH:MM:SS[.fraction][Z|+HH:MM|-HH:MM]`.
+fn parse_iso8601(s: &str) -> Option<SystemTime> {
+    let num = |range: std::ops::Range<usize>| s.get(range)?.parse::<i64>().ok();
+    let (year, month, day) = (num(0..4)?, num(5..7)?, num(8..10)?);
+    if s.get(4..5)? != "-" || s.get(7..8)? != "-" {
+        return None;
+    }
+    if s.len() == 10 {
+        return Some(from_unix(days_from_civil(year, month, day) * 86400, 0));
+    }
+    if !matches!(s.get(10..11)?, "T" | " ") {
+        return None;
+    }
+    let (hour, minute, second) = (num(11..13)?, num(14..16)?, num(17..19)?);
+
+    let mut rest = s.get(19..)?;
+    let mut nanos = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.len()
+            - fraction
+                .trim_start_matches(|c: char| c.is_ascii_digit())
+                .len();
+        let padded = format!("{:0<9}", &fraction[..digits.min(9)]);
+        nanos = padded.parse().ok()?;
+        rest = &fraction[digits..];
+    }
+    let offset = match rest {
+        "" | "Z" => 0,
+        _ => {
+            let sign = match rest.get(0..1)? {
+                "+" => 1,
+                "-" => -1,
+                _ => return None,
+            };
+            let hours: i64 = rest.get(1..3)?.parse().ok()?;
+            let minutes: i64 = rest.get(rest.len() - 2..)?.parse().ok()?;
+            sign * (hours * 3600 + minutes * 60)
+        }
+    };
+
+    let secs =
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+    Some(from_unix(secs, nanos))
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar, see
+/// <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn from_unix(secs: i64, nanos: u32) -> SystemTime {
+    if secs >= 0 {
+        UNIX_EPOCH + Duration::new(secs as u64, nanos)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()) + Duration::from_nanos(nanos.into())
+    }
+}
+
+/// A .NET `Guid`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Guid(u128);
+
+impl Guid {
+    /// Creates a `Guid` from its 128-bit value, where the first group of
+    /// hex digits holds the most significant bits.
+    pub const fn from_u128(value: u128) -> Self {
+        Guid(value)
+    }
+
+    /// The 128-bit value of the `Guid`.
+    pub const fn as_u128(&self) -> u128 {
+        self.0
+    }
+}
+
+impl FromStr for Guid {
+    type Err = PsError;
+
+    /// Parses the formats `Guid.ToString()` supports: with or without
+    /// hyphens, optionally in braces or parentheses.
+    fn from_str(s: &str) -> Result<Self> {
+        let trimmed = s.trim();
+        let inner = trimmed
+            .strip_prefix('{')
+            .and_then(|s| s.strip_suffix('}'))
+            .or_else(|| trimmed.strip_prefix('(').and_then(|s| s.strip_suffix(')')))
+            .unwrap_or(trimmed);
+        let hex: String = inner.chars().filter(|c| *c != '-').collect();
+        let hyphens_ok = inner.len() == 32
+            || (inner.len() == 36 && [8, 13, 18, 23].iter().all(|i| &inner[*i..*i + 1] == "-"));
+        if hex.len() != 32 || !hyphens_ok || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(PsError::Deserialize(format!("invalid GUID: {}", s)));
+        }
+        u128::from_str_radix(&hex, 16)
+            .map(Guid)
+            .map_err(|_| PsError::Deserialize(format!("invalid GUID: {}", s)))
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex = format!("{:032x}", self.0);
+        write!(
+            f,
+            "{}-{}-{}-{}-{}",
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        )
+    }
+}
+
+impl FromPsValue for Guid {
+    fn from_ps_value(value: PsValue) -> Result<Self> {
+        match &value {
+            PsValue::String(s) => s.parse(),
+            // Windows PowerShell serializes a `Guid` as an object
+            PsValue::Object(_) => match value.get("Guid") {
+                Some(PsValue::String(s)) => s.parse(),
+                _ => invalid("a GUID", &value),
+            },
+            _ => invalid("a GUID", &value),
+        }
+    }
+}
+
+impl From<Guid> for PsValue {
+    fn from(guid: Guid) -> Self {
+        PsValue::String(guid.to_string())
+    }
+}
+
+/// The contents of a `byte[]`. PowerShell Core serializes these as a base64
+/// string while Windows PowerShell writes an array of numbers, this accepts
+/// both.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Bytes(pub Vec<u8>);
+
+impl FromPsValue for Bytes {
+    fn from_ps_value(value: PsValue) -> Result<Self> {
+        match value {
+            PsValue::String(s) => base64::decode(&s)
+                .map(Bytes)
+                .ok_or_else(|| PsError::Deserialize("invalid base64 in byte array".to_string())),
+            other => Vec::<u8>::from_ps_value(other).map(Bytes),
+        }
+    }
+}
+
+impl From<Bytes> for Vec<u8> {
+    fn from(bytes: Bytes) -> Self {
+        bytes.0
+    }
+}
@@ -0,0 +1,37 @@
+//! The resources a PowerShell process used, see `Output::resource_usage`.
+
+use std::time::Duration;
+
+/// What the PowerShell process of a run used over its lifetime, as reported
+/// by the operating system when it exited: `getrusage` on Unix, and
+/// `GetProcessMemoryInfo`, `GetProcessTimes` and `GetProcessIoCounters` on
+/// Windows.
+///
+/// Processes the script started itself aren't included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ResourceUsage {
+    /// The largest amount of memory the process had resident at once
+    /// (its peak working set on Windows), in bytes.
+    pub peak_memory: u64,
+    /// The time spent running the process' own code.
+    pub user_time: Duration,
+    /// The time spent in the kernel on behalf of the process.
+    pub system_time: Duration,
+    /// The number of read operations. These are block reads from storage on
+    /// Unix, while Windows counts all reads including those from pipes and
+    /// the network.
+    pub read_operations: u64,
+    /// The number of write operations, counted like `read_operations`.
+    pub write_operations: u64,
+    /// The number of bytes read, which is only known on Windows.
+    pub read_bytes: Option<u64>,
+    /// The number of bytes written, which is only known on Windows.
+    pub written_bytes: Option<u64>,
+}
+
+impl ResourceUsage {
+    /// Returns the total CPU time, user and system time together.
+    pub fn cpu_time(&self) -> Duration {
+        self.user_time + self.system_time
+    }
+}
@@ -0,0 +1,493 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::{error::PsError, wrap, Result};
+
+/// A value which can be passed to, or returned from, a PowerShell script.
+///
+/// Objects keep the order of their properties. Use [`PsValue::to_literal`]
+/// to turn a value into a PowerShell expression which can be safely
+/// embedded in a script.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PsValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<PsValue>),
+    Object(Vec<(String, PsValue)>),
+}
+
+impl PsValue {
+    /// Returns a PowerShell expression evaluating to this value. Strings are
+    /// rendered as single-quoted literals so no variable expansion or
+    /// subexpression evaluation takes place, arrays as `@(...)` and objects
+    /// as `[ordered]@{...}`.
+    pub fn to_literal(&self) -> String {
+        match self {
+            PsValue::Null => "$null".to_string(),
+            PsValue::Bool(true) => "$true".to_string(),
+            PsValue::Bool(false) => "$false".to_string(),
+            PsValue::Int(i) => i.to_string(),
+            PsValue::Float(f) if f.is_nan() => "[double]::NaN".to_string(),
+            PsValue::Float(f) if f.is_infinite() && *f > 0.0 => {
+                "[double]::PositiveInfinity".to_string()
+            }
+            PsValue::Float(f) if f.is_infinite() => "[double]::NegativeInfinity".to_string(),
+            PsValue::Float(f) => format!("{:?}", f),
+            PsValue::String(s) => wrap::quote(s),
+            PsValue::Array(items) => {
+                let items: Vec<String> = items.iter().map(|i| i.to_literal()).collect();
+                format!("@({})", items.join(", "))
+            }
+            PsValue::Object(props) => {
+                let props: Vec<String> = props
+                    .iter()
+                    .map(|(k, v)| format!("{} = {}", wrap::quote(k), v.to_literal()))
+                    .collect();
+                format!("[ordered]@{{{}}}", props.join("; "))
+            }
+        }
+    }
+
+    /// Returns the property called `name` if this is an object. Property
+    /// names are compared case-insensitively, like PowerShell does.
+    pub fn get(&self, name: &str) -> Option<&PsValue> {
+        match self {
+            PsValue::Object(props) => props
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Returns the string if this is a `PsValue::String`.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            PsValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PsValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_literal())
+    }
+}
+
+impl From<bool> for PsValue {
+    fn from(b: bool) -> Self {
+        PsValue::Bool(b)
+    }
+}
+
+macro_rules! from_int {
+    ($($t:ty),*) => {
+        $(impl From<$t> for PsValue {
+            fn from(i: $t) -> Self {
+                PsValue::Int(i64::from(i))
+            }
+        })*
+    };
+}
+
+from_int!(i8, i16, i32, i64, u8, u16, u32);
+
+impl From<f32> for PsValue {
+    fn from(f: f32) -> Self {
+        PsValue::Float(f64::from(f))
+    }
+}
+
+impl From<f64> for PsValue {
+    fn from(f: f64) -> Self {
+        PsValue::Float(f)
+    }
+}
+
+impl From<&str> for PsValue {
+    fn from(s: &str) -> Self {
+        PsValue::String(s.to_string())
+    }
+}
+
+impl From<String> for PsValue {
+    fn from(s: String) -> Self {
+        PsValue::String(s)
+    }
+}
+
+impl<T: Into<PsValue>> From<Vec<T>> for PsValue {
+    fn from(items: Vec<T>) -> Self {
+        PsValue::Array(items.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<PsValue>> From<Option<T>> for PsValue {
+    fn from(opt: Option<T>) -> Self {
+        opt.map(Into::into).unwrap_or(PsValue::Null)
+    }
+}
+
+impl PsValue {
+    /// Parses JSON as produced by `ConvertTo-Json` into a `PsValue`.
+    pub fn from_json(json: &str) -> Result<PsValue> {
+        let mut parser = JsonParser {
+            src: json.as_bytes(),
+            pos: 0,
+        };
+        // `ConvertTo-Json` in Windows PowerShell may emit a BOM when redirected
+        if parser.src.starts_with("\u{feff}".as_bytes()) {
+            parser.pos = 3;
+        }
+        let value = parser.value()?;
+        parser.skip_ws();
+        if parser.pos != parser.src.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+}
+
+struct JsonParser<'a> {
+    src: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> JsonParser<'a> {
+    fn error(&self, msg: &str) -> PsError {
+        PsError::Deserialize(format!("invalid JSON at position {}: {}", self.pos, msg))
+    }
+
+    fn skip_ws(&mut self) {
+        while let Some(b' ' | b'\t' | b'\r' | b'\n') = self.src.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, literal: &str) -> bool {
+        if self.src[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn value(&mut self) -> Result<PsValue> {
+        self.skip_ws();
+        match self.src.get(self.pos) {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(PsValue::String),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) if self.eat("true") => Ok(PsValue::Bool(true)),
+            Some(_) if self.eat("false") => Ok(PsValue::Bool(false)),
+            Some(_) if self.eat("null") => Ok(PsValue::Null),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn object(&mut self) -> Result<PsValue> {
+        self.pos += 1;
+        let mut props = Vec::new();
+        self.skip_ws();
+        if self.eat("}") {
+            return Ok(PsValue::Object(props));
+        }
+        loop {
+            self.skip_ws();
+            if self.src.get(self.pos) != Some(&b'"') {
+                return Err(self.error("expected property name"));
+            }
+            let key = self.string()?;
+            self.skip_ws();
+            if !self.eat(":") {
+                return Err(self.error("expected ':'"));
+            }
+            let value = self.value()?;
+            props.push((key, value));
+            self.skip_ws();
+            if self.eat(",") {
+                continue;
+            }
+            if self.eat("}") {
+                return Ok(PsValue::Object(props));
+            }
+            return Err(self.error("expected ',' or '}'"));
+        }
+    }
+
+    fn array(&mut self) -> Result<PsValue> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        self.skip_ws();
+        if self.eat("]") {
+            return Ok(PsValue::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_ws();
+            if self.eat(",") {
+                continue;
+            }
+            if self.eat("]") {
+                return Ok(PsValue::Array(items));
+            }
+            return Err(self.error("expected ',' or ']'"));
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32> {
+        let digits = self
+            .src
+            .get(self.pos..self.pos + 4)
+            .and_then(|d| std::str::from_utf8(d).ok())
+            .and_then(|d| u32::from_str_radix(d, 16).ok())
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.pos += 4;
+        Ok(digits)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.pos += 1;
+        let mut out = Vec::new();
+        loop {
+            let b = *self
+                .src
+                .get(self.pos)
+                .ok_or_else(|| self.error("unterminated string"))?;
+            self.pos += 1;
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    let esc = *self
+                        .src
+                        .get(self.pos)
+                        .ok_or_else(|| self.error("unterminated string"))?;
+                    self.pos += 1;
+                    let c = match esc {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            if (0xD800..0xDC00).contains(&code) && self.eat("\\u") {
+                                let low = self.hex4()?;
+                                code = 0x10000
+                                    + ((code - 0xD800) << 10)
+                                    + (low.wrapping_sub(0xDC00) & 0x3FF);
+                            }
+                            std::char::from_u32(code).unwrap_or('\u{fffd}')
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    let mut buf = [0; 4];
+                    out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                b => out.push(b),
+            }
+        }
+        String::from_utf8(out).map_err(|_| self.error("invalid utf-8 in string"))
+    }
+
+    fn number(&mut self) -> Result<PsValue> {
+        let start = self.pos;
+        let mut is_float = false;
+        while let Some(&b) = self.src.get(self.pos) {
+            match b {
+                b'0'..=b'9' | b'-' | b'+' => {}
+                b'.' | b'e' | b'E' => is_float = true,
+                _ => break,
+            }
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.src[start..self.pos]).unwrap_or_default();
+        if !is_float {
+            if let Ok(i) = text.parse::<i64>() {
+                return Ok(PsValue::Int(i));
+            }
+        }
+        text.parse::<f64>()
+            .map(PsValue::Float)
+            .map_err(|_| self.error("invalid number"))
+    }
+}
+
+/// Conversion from a [`PsValue`] into a Rust type. This is what's used to
+/// turn values returned from a script into typed results.
+///
+/// Conversions are lenient in the same places PowerShell is: a single value
+/// converts into a `Vec` with one element (PowerShell unrolls one-element
+/// arrays), numeric types accept strings containing a number and `bool`
+/// accepts the strings `True` and `False`.
+///
+/// With the `macros` feature, structs can derive it with `#[derive(FromPs)]`.
+/// Each field is read from the property with the PascalCase version of its
+/// name, or the name given with `#[ps(rename = "...")]`. Missing properties
+/// are treated as `$null`, so use `Option` for properties which may not be
+/// there.
+///
+/// ```rust, ignore
+/// #[derive(FromPs)]
+/// struct Service {
+///     name: String,
+///     display_name: String,
+///     #[ps(rename = "StartType")]
+///     start: String,
+///     can_stop: bool,
+/// }
+/// ```
+pub trait FromPsValue: Sized {
+    fn from_ps_value(value: PsValue) -> Result<Self>;
+}
+
+fn mismatch<T>(expected: &str, found: &PsValue) -> Result<T> {
+    Err(PsError::Deserialize(format!(
+        "expected {}, found {}",
+        expected,
+        found.to_literal()
+    )))
+}
+
+impl FromPsValue for PsValue {
+    fn from_ps_value(value: PsValue) -> Result<Self> {
+        Ok(value)
+    }
+}
+
+impl FromPsValue for () {
+    fn from_ps_value(_value: PsValue) -> Result<Self> {
+        Ok(())
+    }
+}
+
+impl FromPsValue for String {
+    fn from_ps_value(value: PsValue) -> Result<Self> {
+        match value {
+            PsValue::String(s) => Ok(s),
+            PsValue::Int(i) => Ok(i.to_string()),
+            PsValue::Float(f) => Ok(f.to_string()),
+            PsValue::Bool(b) => Ok(if b { "True" } else { "False" }.to_string()),
+            other => mismatch("a string", &other),
+        }
+    }
+}
+
+impl FromPsValue for bool {
+    fn from_ps_value(value: PsValue) -> Result<Self> {
+        match value {
+            PsValue::Bool(b) => Ok(b),
+            // What booleans turn into when they pass through text output
+            PsValue::String(s) if s.trim().eq_ignore_ascii_case("true") => Ok(true),
+            PsValue::String(s) if s.trim().eq_ignore_ascii_case("false") => Ok(false),
+            other => mismatch("a boolean", &other),
+        }
+    }
+}
+
+macro_rules! from_ps_int {
+    ($($t:ty),*) => {
+        $(impl FromPsValue for $t {
+            fn from_ps_value(value: PsValue) -> Result<Self> {
+                use std::convert::TryFrom;
+                let converted = match &value {
+                    PsValue::Int(i) => <$t>::try_from(*i).ok(),
+                    PsValue::String(s) => s.trim().parse::<$t>().ok(),
+                    _ => None,
+                };
+                converted.map_or_else(|| mismatch(stringify!($t), &value), Ok)
+            }
+        })*
+    };
+}
+
+from_ps_int!(i8, i16, i32, i64, u8, u16, u32, u64, usize);
+
+macro_rules! from_ps_float {
+    ($($t:ty),*) => {
+        $(impl FromPsValue for $t {
+            fn from_ps_value(value: PsValue) -> Result<Self> {
+                let converted = match &value {
+                    PsValue::Int(i) => Some(*i as $t),
+                    PsValue::Float(f) => Some(*f as $t),
+                    PsValue::String(s) => s.trim().parse::<$t>().ok(),
+                    _ => None,
+                };
+                converted.map_or_else(|| mismatch(stringify!($t), &value), Ok)
+            }
+        })*
+    };
+}
+
+from_ps_float!(f32, f64);
+
+impl<T: FromPsValue> FromPsValue for Option<T> {
+    fn from_ps_value(value: PsValue) -> Result<Self> {
+        match value {
+            PsValue::Null => Ok(None),
+            other => T::from_ps_value(other).map(Some),
+        }
+    }
+}
+
+impl<T: FromPsValue> FromPsValue for Vec<T> {
+    fn from_ps_value(value: PsValue) -> Result<Self> {
+        match value {
+            PsValue::Array(items) => items.into_iter().map(T::from_ps_value).collect(),
+            PsValue::Null => Ok(Vec::new()),
+            other => Ok(vec![T::from_ps_value(other)?]),
+        }
+    }
+}
+
+impl<T: FromPsValue> FromPsValue for HashMap<String, T> {
+    fn from_ps_value(value: PsValue) -> Result<Self> {
+        match value {
+            PsValue::Object(props) => props
+                .into_iter()
+                .map(|(k, v)| Ok((k, T::from_ps_value(v)?)))
+                .collect(),
+            other => mismatch("an object", &other),
+        }
+    }
+}
+
+impl<T: FromPsValue> FromPsValue for BTreeMap<String, T> {
+    fn from_ps_value(value: PsValue) -> Result<Self> {
+        match value {
+            PsValue::Object(props) => props
+                .into_iter()
+                .map(|(k, v)| Ok((k, T::from_ps_value(v)?)))
+                .collect(),
+            other => mismatch("an object", &other),
+        }
+    }
+}
+
+/// Used by `#[derive(FromPs)]` to read a struct field from `value`, trying
+/// the property `name` and then `fallback`.
+#[doc(hidden)]
+pub fn __field<T: FromPsValue>(value: &PsValue, name: &str, fallback: &str) -> Result<T> {
+    if !matches!(value, PsValue::Object(_)) {
+        return mismatch("an object", value);
+    }
+    let property = value
+        .get(name)
+        .or_else(|| value.get(fallback))
+        .cloned()
+        .unwrap_or(PsValue::Null);
+    T::from_ps_value(property).map_err(|e| match e {
+        PsError::Deserialize(msg) => PsError::Deserialize(format!("property `{}`: {}", name, msg)),
+        other => other,
+    })
+}
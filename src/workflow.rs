@@ -0,0 +1,276 @@
+use std::fmt;
+
+use crate::{output::Output, target, wrap, PsScript, Result};
+
+const STEP_BEGIN: &str = "##ps-step-begin:";
+const STEP_END: &str = "##ps-step-end:";
+
+/// What a [`PsWorkflow`] does when a step fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// Skip the remaining steps. This is the default.
+    Stop,
+    /// Run the remaining steps anyway.
+    Continue,
+}
+
+/// The outcome of a single step in a [`PsWorkflow`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepStatus {
+    Succeeded,
+    Failed,
+    /// The step didn't run because an earlier step failed.
+    Skipped,
+}
+
+/// A sequence of named steps which run one after another in the same
+/// PowerShell session, so later steps can use variables, functions and
+/// modules set up by earlier ones.
+///
+/// A step fails if it throws, or if it writes any errors. Note that a step
+/// calling `exit` ends the whole session.
+///
+/// ## Example
+///
+/// ```rust, no_run
+/// use powershell_script::{PsScriptBuilder, PsWorkflow};
+///
+/// let ps = PsScriptBuilder::new().build();
+/// let summary = PsWorkflow::new()
+///     .step("Prepare", "$target = Join-Path $env:TEMP 'my-app'")
+///     .step("Create", "New-Item -ItemType Directory -Path $target -Force")
+///     .step("Verify", "if (-not (Test-Path $target)) { throw 'not created' }")
+///     .run(&ps)
+///     .unwrap();
+///
+/// println!("{}", summary);
+/// assert!(summary.success());
+/// ```
+#[derive(Debug, Clone)]
+pub struct PsWorkflow {
+    steps: Vec<(String, String)>,
+    policy: FailurePolicy,
+}
+
+impl PsWorkflow {
+    /// Creates an empty workflow which stops on the first failing step.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a step running `script`.
+    pub fn step(mut self, name: impl Into<String>, script: impl Into<String>) -> Self {
+        self.steps.push((name.into(), script.into()));
+        self
+    }
+
+    /// Sets what to do when a step fails. Defaults to [`FailurePolicy::Stop`].
+    pub fn on_failure(mut self, policy: FailurePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Runs the workflow using the configuration of `ps` (the execution mode
+    /// and capture options don't apply since each step runs as a script
+    /// block). Failing steps are reported in the summary, an error is only
+    /// returned if PowerShell couldn't be run at all.
+    pub fn run(&self, ps: &PsScript) -> Result<WorkflowSummary> {
+        let mut lines = vec![
+            "$__ps_wf_go = $true".to_string(),
+            "$__ps_wf_failed = $false".to_string(),
+        ];
+        for (i, (_, script)) in self.steps.iter().enumerate() {
+            lines.push(self.step_line(i, script));
+        }
+        lines.push("if ($__ps_wf_failed) { exit 1 }".to_string());
+
+        let all: Vec<&str> = self.steps.iter().map(|(_, s)| s.as_str()).collect();
+        let proc_output = ps.run_program(&all.join("\n"), &lines)?;
+
+        let stdout = split_steps(&proc_output.stdout);
+        let stderr = split_steps(&proc_output.stderr);
+        let steps = self
+            .steps
+            .iter()
+            .enumerate()
+            .map(|(i, (name, _))| {
+                let (stdout, success) = match stdout.iter().find(|s| s.index == i) {
+                    Some(step) => (step.output.clone(), step.success),
+                    None => {
+                        return StepResult {
+                            name: name.clone(),
+                            status: StepStatus::Skipped,
+                            output: None,
+                        }
+                    }
+                };
+                let stderr = stderr
+                    .iter()
+                    .find(|s| s.index == i)
+                    .map(|s| s.output.clone())
+                    .unwrap_or_default();
+                let output = Output::from(std::process::Output {
+                    status: target::exit_status(if success { 0 } else { 1 }),
+                    stdout,
+                    stderr,
+                });
+                StepResult {
+                    name: name.clone(),
+                    status: if success {
+                        StepStatus::Succeeded
+                    } else {
+                        StepStatus::Failed
+                    },
+                    output: Some(output),
+                }
+            })
+            .collect();
+
+        Ok(WorkflowSummary { steps })
+    }
+
+    /// Returns a single line running step `index`. The step's output is piped
+    /// to `Out-Default` so all of it is written before the end marker.
+    fn step_line(&self, index: usize, script: &str) -> String {
+        let stop = if self.policy == FailurePolicy::Stop {
+            "$__ps_wf_go = $false; "
+        } else {
+            ""
+        };
+        format!(
+            "if ($__ps_wf_go) {{ \
+             [Console]::Out.WriteLine('{begin}{i}'); [Console]::Error.WriteLine('{begin}{i}'); \
+             $__ps_errors = $Error.Count; $__ps_step_ok = $true; \
+             try {{ & ({sb}) | Out-Default; $__ps_step_ok = $Error.Count -eq $__ps_errors }} \
+             catch {{ $__ps_step_ok = $false; Write-Error -ErrorRecord $_ }}; \
+             [Console]::Out.WriteLine(\"{end}{i}:$__ps_step_ok\"); [Console]::Error.WriteLine(\"{end}{i}:$__ps_step_ok\"); \
+             if (-not $__ps_step_ok) {{ $__ps_wf_failed = $true; {stop}}} }}",
+            begin = STEP_BEGIN,
+            end = STEP_END,
+            i = index,
+            sb = wrap::script_block(script),
+            stop = stop,
+        )
+    }
+}
+
+impl Default for PsWorkflow {
+    fn default() -> Self {
+        PsWorkflow {
+            steps: Vec::new(),
+            policy: FailurePolicy::Stop,
+        }
+    }
+}
+
+/// The output of one step, as written between its markers.
+struct StepOutput {
+    index: usize,
+    output: Vec<u8>,
+    success: bool,
+}
+
+/// Splits the output of a workflow on the step markers. Output outside of
+/// any step is dropped.
+fn split_steps(output: &[u8]) -> Vec<StepOutput> {
+    let mut steps = Vec::new();
+    let mut current: Option<StepOutput> = None;
+    for line in output.split_inclusive(|b| *b == b'\n') {
+        let text = String::from_utf8_lossy(line);
+        let trimmed = text.trim_end_matches(['\r', '\n']);
+        if let Some(index) = trimmed.strip_prefix(STEP_BEGIN) {
+            current = index.parse().ok().map(|index| StepOutput {
+                index,
+                output: Vec::new(),
+                success: false,
+            });
+        } else if let Some(end) = trimmed.strip_prefix(STEP_END) {
+            if let Some(mut step) = current.take() {
+                step.success = end.ends_with(":True");
+                steps.push(step);
+            }
+        } else if let Some(step) = current.as_mut() {
+            step.output.extend_from_slice(line);
+        }
+    }
+
+    // A step without an end marker called `exit` or was killed
+    if let Some(step) = current {
+        steps.push(step);
+    }
+    steps
+}
+
+/// The result of running one step of a [`PsWorkflow`].
+#[derive(Debug, Clone)]
+pub struct StepResult {
+    name: String,
+    status: StepStatus,
+    output: Option<Output>,
+}
+
+impl StepResult {
+    /// The name of the step.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether the step succeeded, failed or was skipped.
+    pub fn status(&self) -> StepStatus {
+        self.status
+    }
+
+    /// The output of the step, `None` if it was skipped.
+    pub fn output(&self) -> Option<&Output> {
+        self.output.as_ref()
+    }
+}
+
+/// The results of all steps of a [`PsWorkflow`], in the order they were added.
+#[derive(Debug, Clone)]
+pub struct WorkflowSummary {
+    steps: Vec<StepResult>,
+}
+
+impl WorkflowSummary {
+    /// Whether all steps succeeded.
+    pub fn success(&self) -> bool {
+        self.steps.iter().all(|s| s.status == StepStatus::Succeeded)
+    }
+
+    /// The results of all steps.
+    pub fn steps(&self) -> &[StepResult] {
+        &self.steps
+    }
+
+    /// The result of the step called `name`.
+    pub fn step(&self, name: &str) -> Option<&StepResult> {
+        self.steps.iter().find(|s| s.name == name)
+    }
+
+    /// The steps which failed.
+    pub fn failed(&self) -> impl Iterator<Item = &StepResult> {
+        self.steps.iter().filter(|s| s.status == StepStatus::Failed)
+    }
+}
+
+impl fmt::Display for WorkflowSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for step in &self.steps {
+            let status = match step.status {
+                StepStatus::Succeeded => "succeeded",
+                StepStatus::Failed => "FAILED",
+                StepStatus::Skipped => "skipped",
+            };
+            writeln!(f, "{}: {}", step.name, status)?;
+            if step.status == StepStatus::Failed {
+                if let Some(stderr) = step.output.as_ref().and_then(|o| o.stderr()) {
+                    for line in stderr.lines() {
+                        writeln!(f, "    {}", line)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+//! Temporary directories for scripts to write intermediate files to, see
+//! `PsScriptBuilder::temp_workspace`.
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process, thread,
+    time::Duration,
+};
+
+/// How often removing the directory is attempted. On Windows files can stay
+/// locked for a moment after the process using them has exited, by the
+/// process itself or by virus scanners and indexers looking at new files.
+const REMOVE_ATTEMPTS: u32 = 5;
+/// The delay before the first retry, doubled for each further one.
+const REMOVE_BACKOFF: Duration = Duration::from_millis(50);
+
+/// A directory created for a single run, removed with its contents when
+/// dropped.
+#[derive(Debug)]
+pub(crate) struct Workspace {
+    path: PathBuf,
+}
+
+impl Workspace {
+    /// Creates a new, empty directory in the system's temp directory. `id`
+    /// identifies the run, so the names don't collide within the process.
+    pub(crate) fn create(id: u64) -> io::Result<Workspace> {
+        let base = env::temp_dir();
+        // A directory with the same name can be left over from a process
+        // which had the same pid and crashed.
+        for attempt in 0u32.. {
+            let path = base.join(format!("ps-workspace-{}-{}-{}", process::id(), id, attempt));
+            match fs::create_dir(&path) {
+                Ok(()) => return Ok(Workspace { path }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        unreachable!("ran out of workspace names")
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let mut delay = REMOVE_BACKOFF;
+        for attempt in 1..=REMOVE_ATTEMPTS {
+            match fs::remove_dir_all(&self.path) {
+                Ok(()) => return,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+                Err(_) if attempt < REMOVE_ATTEMPTS => {
+                    thread::sleep(delay);
+                    delay *= 2;
+                }
+                // Leaving a directory in the temp directory behind isn't
+                // worth failing the run over.
+                Err(_) => {}
+            }
+        }
+    }
+}
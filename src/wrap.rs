@@ -0,0 +1,71 @@
+//! Helpers for rewriting a script before it's handed over to PowerShell.
+
+use crate::{
+    base64,
+    output::{BLOCK_BEGIN, BLOCK_END, RESERVED_BLOCK_TAGS},
+};
+
+/// Quotes `value` as a single-quoted PowerShell string literal. In single
+/// quoted strings the only special character is the quote itself, which
+/// PowerShell escapes by doubling it (this includes the typographic quotes
+/// PowerShell also accepts as delimiters).
+pub(crate) fn quote(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('\'');
+    for c in value.chars() {
+        if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}') {
+            out.push(c);
+        }
+        out.push(c);
+    }
+    out.push('\'');
+    out
+}
+
+/// Returns a single line expression evaluating to a `[scriptblock]` with the
+/// contents of `script`. The script is transferred as base64 so we don't have
+/// to worry about quoting, here-strings or blank lines ending a multi-line
+/// statement when reading commands from `stdin`.
+pub(crate) fn script_block(script: &str) -> String {
+    format!(
+        "[scriptblock]::Create([Text.Encoding]::UTF8.GetString([Convert]::FromBase64String('{}')))",
+        base64::encode(script.as_bytes())
+    )
+}
+
+/// Returns the value of `-EncodedCommand` running `program`, which is base64
+/// of its UTF-16LE encoding.
+pub(crate) fn encoded_command(program: &str) -> String {
+    let bytes: Vec<u8> = program.encode_utf16().flat_map(u16::to_le_bytes).collect();
+    base64::encode(&bytes)
+}
+
+/// Returns a single line invoking `script` with the call operator
+/// (`& { <script> } <args>`), passing each argument as a string literal.
+pub(crate) fn call_operator<I, S>(script: &str, args: I) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut line = format!("& ({})", script_block(script));
+    for arg in args {
+        line.push(' ');
+        line.push_str(&quote(arg.as_ref()));
+    }
+    line
+}
+
+/// Returns a line writing the result of the PowerShell expression `expr` to
+/// `stdout` as a block tagged `tag`, which `Output` separates from the rest of
+/// the output.
+pub(crate) fn emit_block(tag: &str, expr: &str) -> String {
+    debug_assert!(
+        RESERVED_BLOCK_TAGS.contains(&tag),
+        "`{}` isn't reserved",
+        tag
+    );
+    format!(
+        "[Console]::Out.WriteLine('{}{}'); [Console]::Out.WriteLine({}); [Console]::Out.WriteLine('{}{}')",
+        BLOCK_BEGIN, tag, expr, BLOCK_END, tag
+    )
+}
@@ -0,0 +1,24 @@
+extern crate powershell_script;
+
+use powershell_script::{
+    analyzer::{Diagnostic, Severity},
+    FromPsValue, PsValue,
+};
+
+#[test]
+fn parses_diagnostics() {
+    let json = r#"[
+        {"Rule":"PSAvoidUsingInvokeExpression","Severity":"Warning","Line":1,"Column":1,"Message":"Invoke-Expression is used.","File":null},
+        {"Rule":"MissingEndCurlyBrace","Severity":"ParseError","Line":3,"Column":12,"Message":"Missing closing '}'.","File":"C:\\s\\a.ps1"}
+    ]"#;
+    let diagnostics = Vec::<Diagnostic>::from_ps_value(PsValue::from_json(json).unwrap()).unwrap();
+    assert_eq!(diagnostics[0].severity, Severity::Warning);
+    assert_eq!(diagnostics[0].file, None);
+    assert_eq!(
+        diagnostics[0].to_string(),
+        "1:1: Warning PSAvoidUsingInvokeExpression: Invoke-Expression is used."
+    );
+    assert_eq!(diagnostics[1].severity, Severity::ParseError);
+    assert_eq!(diagnostics[1].line, Some(3));
+    assert!(diagnostics[1].severity > Severity::Error);
+}
@@ -0,0 +1,64 @@
+extern crate powershell_script;
+
+use powershell_script::{parse_ansi, Color, Style};
+
+#[test]
+fn error_record_colors() {
+    // How pwsh 7 renders an error with `$PSStyle.OutputRendering = 'Ansi'`
+    let text = b"\x1b[31;1mWrite-Error: \x1b[0m\x1b[31;1mboom\x1b[0m\n";
+    let spans = parse_ansi(text);
+    assert_eq!(spans.len(), 2);
+    assert_eq!(spans[0].text, "Write-Error: boom");
+    assert_eq!(spans[0].style.foreground, Some(Color::Indexed(1)));
+    assert!(spans[0].style.bold);
+    assert_eq!(spans[1].text, "\n");
+    assert_eq!(spans[1].style, Style::default());
+}
+
+#[test]
+fn extended_colors_and_resets() {
+    let text = b"\x1b[38;5;208;48;2;10;20;30morange\x1b[39mdefault\x1b[4;94m\x1b[49mlink\x1b[24m\x1b[2Kend";
+    let spans = parse_ansi(text);
+    assert_eq!(spans[0].text, "orange");
+    assert_eq!(spans[0].style.foreground, Some(Color::Indexed(208)));
+    assert_eq!(spans[0].style.background, Some(Color::Rgb(10, 20, 30)));
+    assert_eq!(spans[1].text, "default");
+    assert_eq!(spans[1].style.foreground, None);
+    assert_eq!(spans[2].text, "link");
+    assert!(spans[2].style.underline);
+    assert_eq!(spans[2].style.foreground, Some(Color::Indexed(12)));
+    assert_eq!(spans[2].style.background, None);
+    // Sequences which aren't SGR are dropped
+    assert_eq!(spans[3].text, "end");
+    assert!(!spans[3].style.underline);
+}
+
+#[cfg(unix)]
+#[test]
+fn escape_sequences_are_only_stripped_when_asked_to() {
+    use std::os::unix::fs::PermissionsExt;
+
+    use powershell_script::{ExecutionMode, PsScriptBuilder};
+
+    // A shell script standing in for PowerShell, writing colored output
+    let executable = std::env::temp_dir().join(format!("ps-ansi-{}-color", std::process::id()));
+    std::fs::write(&executable, "#!/bin/sh\nprintf '\\033[31mred\\033[0m\\n'\n").unwrap();
+    std::fs::set_permissions(&executable, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let builder = || {
+        PsScriptBuilder::new()
+            .executable(&executable)
+            .execution_mode(ExecutionMode::Encoded)
+    };
+
+    let colored = builder().build().run("'red'");
+    let stripped = builder().strip_ansi(true).build().run("'red'");
+    std::fs::remove_file(&executable).unwrap();
+
+    assert_eq!(
+        colored.unwrap().stdout().unwrap().trim(),
+        "\x1b[31mred\x1b[0m"
+    );
+    let stripped = stripped.unwrap();
+    assert_eq!(stripped.stdout().unwrap().trim(), "red");
+    assert_eq!(stripped.styled_stdout(), b"\x1b[31mred\x1b[0m\n");
+}
@@ -0,0 +1,710 @@
+extern crate powershell_script;
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use powershell_script::{
+    BuildError, ConfirmPolicy, ExecutionMode, ExecutionPolicy, Middleware, ModuleSpec, PsError,
+    PsScript, PsScriptBuilder, PsValue, RunContext, StdinEncoding,
+};
+
+#[test]
+fn rejects_empty_probe_path() {
+    let result = PsScriptBuilder::new().probe_path("").try_build();
+    assert_eq!(result.err(), Some(BuildError::EmptyProbePath));
+}
+
+#[test]
+fn rejects_empty_executable() {
+    let result = PsScriptBuilder::new().executable("").try_build();
+    assert_eq!(result.err(), Some(BuildError::EmptyExecutable));
+}
+
+#[test]
+fn executable_is_used_instead_of_searching() {
+    let missing = std::env::temp_dir().join("no-such-dir").join("pwsh");
+    let ps = PsScriptBuilder::new().executable(missing).build();
+    match ps.run("'hi'") {
+        Err(PsError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::NotFound),
+        result => panic!("expected a missing executable, got {:?}", result),
+    }
+}
+
+#[test]
+fn rejects_duplicate_raw_args() {
+    let result = PsScriptBuilder::new()
+        .raw_arg("-ExecutionPolicy Bypass")
+        .raw_arg("-ep RemoteSigned")
+        .try_build();
+    assert_eq!(
+        result.err(),
+        Some(BuildError::DuplicateArg("-ExecutionPolicy".into()))
+    );
+}
+
+#[test]
+fn rejects_raw_args_set_by_the_builder() {
+    let result = PsScriptBuilder::new()
+        .raw_arg("-file script.ps1")
+        .try_build();
+    assert_eq!(
+        result.err(),
+        Some(BuildError::ConflictingArg("-File".into()))
+    );
+
+    let result = PsScriptBuilder::new().raw_arg("-NoProf").try_build();
+    assert_eq!(
+        result.err(),
+        Some(BuildError::ConflictingArg("-NoProfile".into()))
+    );
+
+    let result = PsScriptBuilder::new()
+        .no_profile(false)
+        .raw_arg("-NoProfile")
+        .try_build();
+    assert!(result.is_ok());
+}
+
+#[test]
+fn rejects_options_which_would_be_ignored() {
+    let result = PsScriptBuilder::new()
+        .execution_mode(ExecutionMode::Raw)
+        .abort_on_error(true)
+        .try_build();
+    assert_eq!(
+        result.err(),
+        Some(BuildError::UnsupportedMode(
+            "abort_on_error",
+            ExecutionMode::Raw
+        ))
+    );
+
+    let result = PsScriptBuilder::new()
+        .execution_mode(ExecutionMode::CallOperator)
+        .abort_on_error(true)
+        .try_build();
+    assert_eq!(
+        result.err(),
+        Some(BuildError::UnsupportedMode(
+            "abort_on_error",
+            ExecutionMode::CallOperator
+        ))
+    );
+
+    let result = PsScriptBuilder::new()
+        .abort_on_error(true)
+        .capture_return_value(true)
+        .try_build();
+    assert_eq!(
+        result.err(),
+        Some(BuildError::ConflictingOptions(
+            "abort_on_error",
+            "capture_return_value"
+        ))
+    );
+
+    let result = PsScriptBuilder::new()
+        .execution_mode(ExecutionMode::Raw)
+        .record_timeline(true)
+        .try_build();
+    assert_eq!(
+        result.err(),
+        Some(BuildError::UnsupportedMode(
+            "record_timeline",
+            ExecutionMode::Raw
+        ))
+    );
+
+    let result = PsScriptBuilder::new().on_stall(|_| {}).try_build();
+    assert_eq!(
+        result.err(),
+        Some(BuildError::MissingOption("on_stall", "stall_timeout"))
+    );
+
+    let result = PsScriptBuilder::new()
+        .execution_mode(ExecutionMode::Encoded)
+        .abort_on_error(true)
+        .record_timeline(true)
+        .try_build();
+    assert!(result.is_ok());
+}
+
+#[test]
+fn rejects_stdin_options_with_encoded_commands() {
+    let result = PsScriptBuilder::new()
+        .execution_mode(ExecutionMode::Encoded)
+        .stdin_buffer_size(1024)
+        .try_build();
+    assert_eq!(
+        result.err(),
+        Some(BuildError::UnsupportedMode(
+            "stdin_buffer_size",
+            ExecutionMode::Encoded
+        ))
+    );
+
+    let result = PsScriptBuilder::new()
+        .execution_mode(ExecutionMode::Encoded)
+        .split_lines(false)
+        .try_build();
+    assert_eq!(
+        result.err(),
+        Some(BuildError::UnsupportedMode(
+            "split_lines",
+            ExecutionMode::Encoded
+        ))
+    );
+
+    let result = PsScriptBuilder::new()
+        .execution_mode(ExecutionMode::Encoded)
+        .stdin_encoding(StdinEncoding::Utf8Bom)
+        .try_build();
+    assert_eq!(
+        result.err(),
+        Some(BuildError::UnsupportedMode(
+            "stdin_encoding",
+            ExecutionMode::Encoded
+        ))
+    );
+
+    let result = PsScriptBuilder::new()
+        .execution_mode(ExecutionMode::Encoded)
+        .split_lines(true)
+        .stdin_encoding(StdinEncoding::Utf8)
+        .try_build();
+    assert!(result.is_ok());
+}
+
+#[test]
+fn invalid_sessions_are_errors() {
+    match PsScriptBuilder::new().executable("").build_session() {
+        Err(PsError::InvalidConfig(e)) => assert_eq!(e, BuildError::EmptyExecutable),
+        other => panic!("expected an invalid configuration, got {:?}", other),
+    }
+    match PsScriptBuilder::new().executable("").build_pool(2) {
+        Err(PsError::InvalidConfig(e)) => assert_eq!(e, BuildError::EmptyExecutable),
+        other => panic!("expected an invalid configuration, got {:?}", other),
+    }
+}
+
+#[test]
+fn ignores_parameters_in_quoted_values() {
+    let result = PsScriptBuilder::new()
+        .raw_arg(r#"-WorkingDirectory "C:\build -Command""#)
+        .try_build();
+    assert!(result.is_ok());
+}
+
+#[test]
+fn ps_script_can_be_shared_between_threads() {
+    fn assert_shareable<T: Clone + Send + Sync + 'static>() {}
+    assert_shareable::<PsScript>();
+
+    let ps = PsScriptBuilder::new().probe_path("/opt/pwsh").build();
+    let handles: Vec<_> = (0..2)
+        .map(|_| {
+            let ps = ps.clone();
+            std::thread::spawn(move || ps.clone())
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+// Needs PowerShell Core for `-WorkingDirectory`
+#[cfg(all(windows, feature = "core"))]
+#[test]
+fn raw_arg_reaches_powershell_unchanged() {
+    let dir = std::env::temp_dir().join("ps raw ^arg");
+    std::fs::create_dir_all(&dir).unwrap();
+    let ps = PsScriptBuilder::new()
+        .raw_arg(format!("-WorkingDirectory \"{}\"", dir.display()))
+        .build();
+    let output = ps.run("(Get-Location).Path").unwrap();
+    assert_eq!(output.stdout().unwrap().trim(), dir.display().to_string());
+}
+
+struct Prelude(Arc<Mutex<Vec<String>>>);
+
+impl Middleware for Prelude {
+    fn before(&self, ctx: &mut RunContext) -> Result<(), PsError> {
+        *self.0.lock().unwrap() = ctx.prelude().to_vec();
+        Err(PsError::Rejected("recorded".into()))
+    }
+}
+
+fn prelude(builder: PsScriptBuilder) -> Vec<String> {
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let ps = builder.middleware(Prelude(lines.clone())).build();
+    assert!(ps.run("'hi'").is_err());
+    let lines = lines.lock().unwrap().clone();
+    lines
+}
+
+#[test]
+fn what_if_and_confirm_set_preferences() {
+    let lines = prelude(
+        PsScriptBuilder::new()
+            .what_if(true)
+            .confirm(ConfirmPolicy::None),
+    );
+    assert!(lines.contains(&"$WhatIfPreference = $true".to_string()));
+    assert!(lines.contains(&"$ConfirmPreference = 'None'".to_string()));
+
+    let lines = prelude(PsScriptBuilder::new());
+    assert!(!lines.iter().any(|line| line.contains("WhatIfPreference")));
+    assert!(!lines.iter().any(|line| line.contains("ConfirmPreference")));
+}
+
+#[test]
+fn input_timeout_wraps_prompts() {
+    let wraps = |lines: &[String], name: &str| {
+        lines
+            .iter()
+            .any(|line| line.starts_with(&format!("function {} ", name)))
+    };
+    let lines = prelude(PsScriptBuilder::new().input_timeout(Duration::from_secs(5)));
+    assert!(wraps(&lines, "Read-Host"));
+    assert!(wraps(&lines, "Get-Credential"));
+
+    let lines = prelude(PsScriptBuilder::new());
+    assert!(!wraps(&lines, "Read-Host"));
+}
+
+#[test]
+fn input_timeout_turns_off_non_interactive() {
+    let result = PsScriptBuilder::new()
+        .input_timeout(Duration::from_secs(5))
+        .non_interactive(true)
+        .try_build();
+    assert_eq!(
+        result.err(),
+        Some(BuildError::ConflictingOptions(
+            "input_timeout",
+            "non_interactive"
+        ))
+    );
+
+    // `-NonInteractive` is only rejected as a raw argument while the
+    // builder passes it itself
+    let result = PsScriptBuilder::new()
+        .non_interactive(true)
+        .input_timeout(Duration::from_secs(5))
+        .raw_arg("-NonInteractive")
+        .try_build();
+    assert!(result.is_ok());
+}
+
+#[test]
+fn stall_timeout_conflicts_with_record_timeline() {
+    let result = PsScriptBuilder::new()
+        .stall_timeout(Duration::from_secs(60))
+        .on_stall(|_| {})
+        .record_timeline(true)
+        .try_build();
+    assert_eq!(
+        result.err(),
+        Some(BuildError::ConflictingOptions(
+            "stall_timeout",
+            "record_timeline"
+        ))
+    );
+}
+
+#[test]
+fn input_timeout_conflicts_with_record_timeline() {
+    let result = PsScriptBuilder::new()
+        .input_timeout(Duration::from_secs(5))
+        .record_timeline(true)
+        .try_build();
+    assert_eq!(
+        result.err(),
+        Some(BuildError::ConflictingOptions(
+            "input_timeout",
+            "record_timeline"
+        ))
+    );
+}
+
+#[test]
+fn console_codepage_sets_output_encoding() {
+    let lines = prelude(PsScriptBuilder::new().console_codepage(65001));
+    assert!(lines.iter().any(|line| {
+        line.starts_with("[Console]::OutputEncoding = [Text.Encoding]::GetEncoding(65001)")
+    }));
+
+    let lines = prelude(PsScriptBuilder::new());
+    assert!(!lines.iter().any(|line| line.contains("OutputEncoding")));
+}
+
+#[test]
+fn culture_is_set_for_the_script() {
+    let lines = prelude(PsScriptBuilder::new().culture("de-DE"));
+    assert!(lines.iter().any(|line| {
+        line.starts_with("$__ps_culture = [Globalization.CultureInfo]::GetCultureInfo('de-DE');")
+    }));
+
+    let lines = prelude(PsScriptBuilder::new().culture("it's"));
+    assert!(lines
+        .iter()
+        .any(|line| line.contains("GetCultureInfo('it''s')")));
+}
+
+#[test]
+fn deterministic_output_pins_the_culture() {
+    let lines = prelude(PsScriptBuilder::new().deterministic_output(true));
+    assert!(lines.iter().any(|line| line.contains("GetCultureInfo('')")));
+
+    let lines = prelude(
+        PsScriptBuilder::new()
+            .culture("en-US")
+            .deterministic_output(true),
+    );
+    assert!(lines
+        .iter()
+        .any(|line| line.contains("GetCultureInfo('en-US')")));
+}
+
+#[test]
+fn deterministic_output_passes_nologo() {
+    let result = PsScriptBuilder::new()
+        .deterministic_output(true)
+        .raw_arg("-nologo")
+        .try_build();
+    assert_eq!(
+        result.err(),
+        Some(BuildError::ConflictingArg("-NoLogo".into()))
+    );
+}
+
+#[test]
+fn execution_policy_is_managed_by_the_builder() {
+    let result = PsScriptBuilder::new()
+        .execution_policy(ExecutionPolicy::Bypass)
+        .raw_arg("-ep RemoteSigned")
+        .try_build();
+    assert_eq!(
+        result.err(),
+        Some(BuildError::ConflictingArg("-ExecutionPolicy".into()))
+    );
+
+    let result = PsScriptBuilder::new()
+        .raw_arg("-ep RemoteSigned")
+        .try_build();
+    assert!(result.is_ok());
+}
+
+#[test]
+fn required_modules_are_checked_first() {
+    let lines = prelude(
+        PsScriptBuilder::new()
+            .requires(ModuleSpec::new("Az.Accounts").min_version("2.10"))
+            .requires(ModuleSpec::new("Pester's")),
+    );
+    assert!(lines[0].starts_with("$__ps_missing = "));
+    assert!(lines[0].contains("@{ Name = 'Az.Accounts'; MinVersion = '2.10' }"));
+    assert!(lines[0].contains("@{ Name = 'Pester''s'; MinVersion = $null }"));
+
+    let lines = prelude(PsScriptBuilder::new());
+    assert!(!lines.iter().any(|line| line.contains("$__ps_missing")));
+}
+
+#[test]
+fn missing_modules_are_listed() {
+    let error = PsError::MissingModules(vec![
+        ModuleSpec::new("Az.Accounts").min_version("2.10"),
+        ModuleSpec::new("Pester"),
+    ]);
+    assert_eq!(
+        error.to_string(),
+        "The script requires modules which aren't installed: Az.Accounts (>= 2.10), Pester"
+    );
+}
+
+#[test]
+fn env_reaches_the_script() {
+    let ps = PsScriptBuilder::new()
+        .env("PS_TEST_GREETING", "hello")
+        .envs([("PS_TEST_NAME", "world")])
+        .build();
+    match ps.run("\"$env:PS_TEST_GREETING $env:PS_TEST_NAME\"") {
+        Err(PsError::PowershellNotFound) => {}
+        result => assert_eq!(result.unwrap().stdout().unwrap().trim(), "hello world"),
+    }
+}
+
+#[test]
+fn current_dir_is_the_starting_location() {
+    let dir = std::env::temp_dir().canonicalize().unwrap();
+    let ps = PsScriptBuilder::new().current_dir(&dir).build();
+    match ps.run("(Get-Location).ProviderPath") {
+        Err(PsError::PowershellNotFound) => {}
+        result => assert_eq!(
+            result.unwrap().stdout().unwrap().trim(),
+            dir.to_string_lossy()
+        ),
+    }
+}
+
+#[test]
+fn cancellation_sentinel_defines_test_host_cancelled() {
+    let defines = |lines: &[String]| {
+        lines
+            .iter()
+            .any(|line| line.contains("function Test-HostCancelled"))
+    };
+    assert!(defines(&prelude(
+        PsScriptBuilder::new().cancellation_sentinel(true)
+    )));
+    assert!(!defines(&prelude(PsScriptBuilder::new())));
+}
+
+#[test]
+fn cancelled_scripts_stop_on_their_own() {
+    let ps = PsScriptBuilder::new().cancellation_sentinel(true).build();
+    let script = "while (-not (Test-HostCancelled)) { Start-Sleep -Milliseconds 50 }; 'stopped'";
+    match ps.spawn(script) {
+        Err(PsError::PowershellNotFound) => {}
+        child => {
+            let mut child = child.unwrap();
+            std::thread::sleep(Duration::from_secs(1));
+            assert!(child.cancel(Duration::from_secs(30)).unwrap());
+            assert_eq!(child.wait().unwrap().stdout().unwrap().trim(), "stopped");
+        }
+    }
+}
+
+// `echo` stands in for PowerShell, writing the arguments it gets
+#[cfg(unix)]
+#[test]
+fn encoded_mode_passes_the_script_as_an_argument() {
+    fn decode_base64(text: &str) -> Vec<u8> {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut bytes = Vec::new();
+        let (mut bits, mut n) = (0u32, 0);
+        for c in text.bytes().take_while(|&c| c != b'=') {
+            bits = (bits << 6) | ALPHABET.iter().position(|&a| a == c).unwrap() as u32;
+            n += 6;
+            if n >= 8 {
+                n -= 8;
+                bytes.push((bits >> n) as u8);
+            }
+        }
+        bytes
+    }
+
+    let ps = PsScriptBuilder::new()
+        .executable("/bin/echo")
+        .execution_mode(ExecutionMode::Encoded)
+        .build();
+    let stdout = ps.run("'hi'\n\n'there'").unwrap().stdout().unwrap();
+    let args: Vec<&str> = stdout.split_whitespace().collect();
+    assert!(!args.contains(&"-Command"));
+    let position = args
+        .iter()
+        .position(|&arg| arg == "-EncodedCommand")
+        .unwrap();
+    let utf16: Vec<u16> = decode_base64(args[position + 1])
+        .chunks(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    assert!(String::from_utf16(&utf16)
+        .unwrap()
+        .ends_with("'hi'\n\n'there'"));
+}
+
+#[test]
+fn encoded_mode_runs_the_script() {
+    let ps = PsScriptBuilder::new()
+        .execution_mode(ExecutionMode::Encoded)
+        .build();
+    let script = "$text = @'\n\nkept as is\n'@\n$text.Length";
+    match ps.run(script) {
+        Err(PsError::PowershellNotFound) => {}
+        result => assert_eq!(result.unwrap().stdout().unwrap().trim(), "11"),
+    }
+}
+
+#[test]
+fn libraries_are_replaced_by_name() {
+    let lines = prelude(
+        PsScriptBuilder::new()
+            .library("logging", "function Write-AppLog { }")
+            .library("results", "function Write-AppResult { }")
+            .library("logging", "function Write-AppLog2 { }"),
+    );
+    let libraries: Vec<&String> = lines
+        .iter()
+        .filter(|line| line.starts_with(". ([scriptblock]::Create("))
+        .collect();
+    assert_eq!(libraries.len(), 2);
+}
+
+#[test]
+fn libraries_are_available_to_scripts() {
+    let ps = PsScriptBuilder::new()
+        .library(
+            "greeting",
+            "function Get-Greeting {\n    'hello from the library'\n}",
+        )
+        .build();
+    match ps.run("Get-Greeting") {
+        Err(PsError::PowershellNotFound) => {}
+        result => assert_eq!(
+            result.unwrap().stdout().unwrap().trim(),
+            "hello from the library"
+        ),
+    }
+}
+
+#[test]
+fn run_json_converts_the_output() {
+    let ps = PsScriptBuilder::new().build();
+    let script = "Write-Host 'not part of it'; [pscustomobject]@{ Name = 'a'; Size = 1 }; [pscustomobject]@{ Name = 'b'; Size = 2 }";
+    match ps.run_json::<Vec<BTreeMap<String, PsValue>>>(script) {
+        Err(PsError::PowershellNotFound) => {}
+        result => {
+            let item = |name: &str, size: i64| {
+                BTreeMap::from([
+                    ("Name".to_string(), PsValue::from(name)),
+                    ("Size".to_string(), PsValue::Int(size)),
+                ])
+            };
+            assert_eq!(result.unwrap(), [item("a", 1), item("b", 2)]);
+        }
+    }
+}
+
+// `echo` stands in for PowerShell and never converts anything
+#[cfg(unix)]
+#[test]
+fn run_json_fails_without_converted_output() {
+    // Encoded mode passes the script as an argument, as `echo` doesn't read
+    // its input
+    let ps = PsScriptBuilder::new()
+        .executable("/bin/echo")
+        .execution_mode(ExecutionMode::Encoded)
+        .build();
+    match ps.run_json::<Option<String>>("'hi'") {
+        Err(PsError::Deserialize(_)) => {}
+        result => panic!("expected a deserialize error, got {:?}", result),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn resource_usage_is_reported() {
+    let ps = PsScriptBuilder::new()
+        .executable("/bin/echo")
+        .execution_mode(ExecutionMode::Encoded)
+        .build();
+    let output = ps.run("'hi'").unwrap();
+    let usage = output.resource_usage().expect("the usage is known");
+    assert!(usage.peak_memory > 0);
+    assert_eq!(usage.read_bytes, None);
+
+    let output = ps.spawn("'hi'").unwrap().wait().unwrap();
+    assert!(output.resource_usage().is_some());
+}
+
+// `echo` stands in for PowerShell, finishing right away
+#[cfg(unix)]
+#[test]
+fn children_can_be_polled_before_waiting() {
+    let ps = PsScriptBuilder::new()
+        .executable("/bin/echo")
+        .execution_mode(ExecutionMode::Encoded)
+        .build();
+
+    let mut child = ps.spawn("'hi'").unwrap();
+    while child.try_wait().unwrap().is_none() {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let output = child.wait().unwrap();
+    assert!(output.stdout().unwrap().contains("-EncodedCommand"));
+    assert!(output.resource_usage().is_some());
+
+    let mut child = ps.spawn("'hi'").unwrap();
+    while child.is_running() {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(child.cancel(Duration::from_secs(1)).unwrap());
+    assert!(child.wait().is_ok());
+}
+
+#[cfg(unix)]
+#[test]
+fn exited_children_are_not_suspended() {
+    let ps = PsScriptBuilder::new()
+        .executable("/bin/echo")
+        .execution_mode(ExecutionMode::Encoded)
+        .build();
+
+    let mut child = ps.spawn("'hi'").unwrap();
+    while child.is_running() {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    // The child has been reaped, so signaling its pid would fail or reach
+    // another process
+    child.suspend().unwrap();
+    child.resume().unwrap();
+    assert!(child.wait().is_ok());
+}
+
+#[test]
+fn read_psd1_imports_the_data_file() {
+    /// Records the script instead of running it.
+    struct Script(Arc<Mutex<String>>);
+
+    impl Middleware for Script {
+        fn before(&self, ctx: &mut RunContext) -> Result<(), PsError> {
+            *self.0.lock().unwrap() = ctx.script().to_string();
+            Err(PsError::Rejected("recorded".into()))
+        }
+    }
+
+    let script = Arc::new(Mutex::new(String::new()));
+    let ps = PsScriptBuilder::new()
+        .middleware(Script(script.clone()))
+        .build();
+    assert!(ps.read_psd1("/configs/it's.psd1").is_err());
+    assert_eq!(
+        *script.lock().unwrap(),
+        "Import-PowerShellDataFile -LiteralPath '/configs/it''s.psd1'"
+    );
+}
+
+#[test]
+fn read_psd1_returns_the_data() {
+    let path = std::env::temp_dir().join(format!("ps-data-{}.psd1", std::process::id()));
+    std::fs::write(&path, "@{\n    Name = 'app'\n    Ports = @(80, 443)\n}\n").unwrap();
+    let result = PsScriptBuilder::new().build().read_psd1(&path);
+    std::fs::remove_file(&path).unwrap();
+    match result {
+        Err(PsError::PowershellNotFound) => {}
+        result => {
+            let data = result.unwrap();
+            assert_eq!(data.get("Name"), Some(&PsValue::from("app")));
+            assert_eq!(data.get("Ports"), Some(&PsValue::from(vec![80, 443])));
+        }
+    }
+}
+
+#[test]
+fn capture_streams_separates_warnings() {
+    let ps = PsScriptBuilder::new().capture_streams(true).build();
+    let script = "Write-Verbose 'checking' -Verbose; Write-Warning 'low disk'; 'data'";
+    match ps.run(script) {
+        Err(PsError::PowershellNotFound) => {}
+        result => {
+            let output = result.unwrap();
+            assert_eq!(output.verbose(), ["checking"]);
+            assert_eq!(output.warnings(), ["low disk"]);
+            assert_eq!(output.stdout().unwrap().trim(), "data");
+        }
+    }
+}
@@ -0,0 +1,45 @@
+extern crate powershell_script;
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use powershell_script::{Middleware, PsError, PsScriptBuilder, RunCache, RunContext};
+
+#[derive(Default, Clone)]
+struct Reject(Arc<AtomicUsize>);
+
+impl Middleware for Reject {
+    fn before(&self, _ctx: &mut RunContext) -> Result<(), PsError> {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        Err(PsError::Rejected("not now".into()))
+    }
+}
+
+#[test]
+fn failed_runs_are_retried() {
+    let reject = Reject::default();
+    let ps = PsScriptBuilder::new().middleware(reject.clone()).build();
+    let cache = RunCache::new(Duration::from_secs(60));
+
+    assert!(cache.run(&ps, "param($a) $a", ["x"], "web01").is_err());
+    assert!(cache.run(&ps, "param($a) $a", ["x"], "web01").is_err());
+    assert!(cache
+        .force_run(&ps, "param($a) $a", ["x"], "web01")
+        .is_err());
+    assert_eq!(reject.0.load(Ordering::SeqCst), 3);
+    assert!(!cache.contains("param($a) $a", ["x"], "web01"));
+}
+
+#[test]
+fn clones_share_records() {
+    let cache = RunCache::new(Duration::from_secs(60));
+    let clone = cache.clone();
+    clone.invalidate("'hi'", [""; 0], "localhost");
+    clone.clear();
+    assert!(!cache.contains("'hi'", [""; 0], "localhost"));
+}
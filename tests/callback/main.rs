@@ -0,0 +1,108 @@
+extern crate powershell_script;
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpStream,
+    sync::{Arc, Mutex},
+};
+
+use powershell_script::{Middleware, PsError, PsScriptBuilder, PsValue, RunContext};
+
+/// Makes the calls `Invoke-HostCallback` would make while the run is about
+/// to start, recording the raw replies.
+struct Caller {
+    calls: Vec<&'static str>,
+    replies: Arc<Mutex<Vec<String>>>,
+}
+
+impl Middleware for Caller {
+    fn before(&self, ctx: &mut RunContext) -> Result<(), PsError> {
+        let function = ctx
+            .prelude()
+            .iter()
+            .find(|line| line.starts_with("function Invoke-HostCallback"))
+            .expect("Invoke-HostCallback is defined");
+        let port = between(function, "TcpClient('127.0.0.1', ", ")");
+        let token = between(function, "Token = '", "'");
+        for call in &self.calls {
+            let mut stream = TcpStream::connect(("127.0.0.1", port.parse().unwrap())).unwrap();
+            let call = call.replace("TOKEN", token);
+            stream.write_all(format!("{}\n", call).as_bytes()).unwrap();
+            let mut reply = String::new();
+            BufReader::new(&stream).read_line(&mut reply).unwrap();
+            self.replies.lock().unwrap().push(reply);
+        }
+        Err(PsError::Rejected("called".into()))
+    }
+}
+
+fn between<'a>(text: &'a str, start: &str, end: &str) -> &'a str {
+    let rest = &text[text.find(start).unwrap() + start.len()..];
+    &rest[..rest.find(end).unwrap()]
+}
+
+fn calls(calls: Vec<&'static str>) -> Vec<String> {
+    let replies = Arc::new(Mutex::new(Vec::new()));
+    let ps = PsScriptBuilder::new()
+        .host_callback("Add", |data| {
+            let number = |key| match data.get(key) {
+                Some(PsValue::Int(n)) => Ok(*n),
+                _ => Err(format!("{} must be a number", key)),
+            };
+            Ok(PsValue::Int(number("a")? + number("b")?))
+        })
+        .host_callback("Names", |_| Ok(vec!["a", "b"].into()))
+        .middleware(Caller {
+            calls,
+            replies: replies.clone(),
+        })
+        .build();
+    assert!(ps.run("'hi'").is_err());
+    let replies = replies.lock().unwrap().clone();
+    replies
+}
+
+#[test]
+fn callbacks_are_dispatched_by_name() {
+    let replies = calls(vec![
+        r#"{"Token":"TOKEN","Name":"add","Data":"{\"a\":1,\"b\":2}"}"#,
+        r#"{"Token":"TOKEN","Name":"Names","Data":"null"}"#,
+    ]);
+    // "3" and ",@('a', 'b')" in base64
+    assert_eq!(replies, ["ok Mw==\n", "ok LEAoJ2EnLCAnYicp\n"]);
+}
+
+#[test]
+fn failed_callbacks_report_their_error() {
+    let replies = calls(vec![
+        r#"{"Token":"TOKEN","Name":"Add","Data":"{\"a\":1}"}"#,
+        r#"{"Token":"TOKEN","Name":"Missing","Data":""}"#,
+    ]);
+    // "b must be a number" and "There's no host callback called 'Missing'"
+    assert_eq!(
+        replies,
+        [
+            "error YiBtdXN0IGJlIGEgbnVtYmVy\n",
+            "error VGhlcmUncyBubyBob3N0IGNhbGxiYWNrIGNhbGxlZCAnTWlzc2luZyc=\n"
+        ]
+    );
+}
+
+#[test]
+fn calls_without_the_token_are_refused() {
+    let replies = calls(vec![r#"{"Token":"guess","Name":"Add","Data":"{}"}"#]);
+    assert_eq!(replies, [""]);
+}
+
+#[test]
+fn scripts_receive_what_callbacks_return() {
+    let ps = PsScriptBuilder::new()
+        .host_callback("Greet", |data| {
+            Ok(format!("hello {}", data.as_str().unwrap_or("nobody")).into())
+        })
+        .build();
+    match ps.run("Invoke-HostCallback Greet -Data 'world'") {
+        Err(PsError::PowershellNotFound) => {}
+        result => assert_eq!(result.unwrap().stdout().unwrap().trim(), "hello world"),
+    }
+}
@@ -0,0 +1,25 @@
+extern crate powershell_script;
+
+use powershell_script::{compat, PsError};
+
+#[test]
+fn runs_once_per_installation() {
+    let installations = compat::installations();
+    match compat::run_on_all("'hello'") {
+        Err(PsError::PowershellNotFound) => assert!(installations.is_empty()),
+        Ok(report) => {
+            assert_eq!(report.runs().len(), installations.len());
+            assert!(report.is_consistent(), "{}", report.diff());
+            assert!(report.diff().is_empty());
+        }
+        Err(e) => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn installations_are_listed_once() {
+    let installations = compat::installations();
+    for (i, path) in installations.iter().enumerate() {
+        assert!(!installations[i + 1..].contains(path));
+    }
+}
@@ -0,0 +1,32 @@
+extern crate powershell_script;
+
+use std::sync::{Arc, Mutex};
+
+use powershell_script::{ExecutionContext, Middleware, PsError, PsScriptBuilder, RunContext};
+
+#[derive(Default, Clone)]
+struct Record(Arc<Mutex<Vec<ExecutionContext>>>);
+
+impl Middleware for Record {
+    fn before(&self, ctx: &mut RunContext) -> Result<(), PsError> {
+        self.0.lock().unwrap().push(ctx.execution().clone());
+        Err(PsError::Rejected("recorded".into()))
+    }
+}
+
+#[test]
+fn every_run_gets_its_own_context() {
+    let record = Record::default();
+    let ps = PsScriptBuilder::new().middleware(record.clone()).build();
+    assert!(ps.run_with_args("param($name) $name", ["a"]).is_err());
+    assert!(ps.run_with_args("param($name) $name", ["b"]).is_err());
+    assert!(ps.run("'other'").is_err());
+
+    let contexts = record.0.lock().unwrap();
+    assert_eq!(contexts[0].args, ["a"]);
+    assert_eq!(contexts[1].args, ["b"]);
+    assert!(contexts[2].args.is_empty());
+    assert_ne!(contexts[0].id, contexts[1].id);
+    assert_eq!(contexts[0].script_hash, contexts[1].script_hash);
+    assert_ne!(contexts[0].script_hash, contexts[2].script_hash);
+}
@@ -0,0 +1,22 @@
+extern crate powershell_script;
+
+use powershell_script::{EnvDelta, FromPsValue, PsValue};
+
+#[test]
+fn delta_from_snapshots() {
+    let json = r#"{"Before":{"PATH":"C:\\bin","VIRTUAL_ENV":"","TEMP":"C:\\tmp"},"After":{"PATH":"C:\\venv\\Scripts;C:\\bin","VIRTUAL_ENV":"","VIRTUAL_ENV_PROMPT":"venv"}}"#;
+    let delta = EnvDelta::from_ps_value(PsValue::from_json(json).unwrap()).unwrap();
+    assert_eq!(delta.changed.len(), 1);
+    assert_eq!(delta.changed["PATH"], "C:\\venv\\Scripts;C:\\bin");
+    assert_eq!(delta.added.len(), 1);
+    assert_eq!(delta.added["VIRTUAL_ENV_PROMPT"], "venv");
+    assert_eq!(delta.removed.iter().collect::<Vec<_>>(), ["TEMP"]);
+    assert!(!delta.is_empty());
+}
+
+#[test]
+fn unchanged_environment() {
+    let json = r#"{"Before":{"A":"1"},"After":{"A":"1"}}"#;
+    let delta = EnvDelta::from_ps_value(PsValue::from_json(json).unwrap()).unwrap();
+    assert!(delta.is_empty());
+}
@@ -0,0 +1,33 @@
+extern crate powershell_script;
+
+use powershell_script::{
+    environment::{ExecutionPolicy, LanguageMode, PolicyReport, Scope},
+    FromPsValue, PsValue,
+};
+
+#[test]
+fn parses_policy_report() {
+    let json = r#"{"Effective":"AllSigned","Scopes":[
+        {"Scope":"MachinePolicy","ExecutionPolicy":"Undefined"},
+        {"Scope":"UserPolicy","ExecutionPolicy":"AllSigned"},
+        {"Scope":"Process","ExecutionPolicy":"Undefined"},
+        {"Scope":"CurrentUser","ExecutionPolicy":"RemoteSigned"},
+        {"Scope":"LocalMachine","ExecutionPolicy":"Undefined"}
+    ],"LanguageMode":"ConstrainedLanguage"}"#;
+    let report = PolicyReport::from_ps_value(PsValue::from_json(json).unwrap()).unwrap();
+    assert_eq!(report.effective, ExecutionPolicy::AllSigned);
+    assert_eq!(report.scopes.len(), 5);
+    assert_eq!(report.deciding_scope(), Some(Scope::UserPolicy));
+    assert_eq!(report.language_mode, LanguageMode::ConstrainedLanguage);
+    assert!(report.blocks_unsigned_scripts());
+    assert_eq!(
+        report.to_string(),
+        "The execution policy is AllSigned, set in the UserPolicy scope. The language mode is ConstrainedLanguage."
+    );
+}
+
+#[test]
+fn rejects_unknown_policies() {
+    let json = r#"{"Effective":"Lenient","Scopes":[],"LanguageMode":"FullLanguage"}"#;
+    assert!(PolicyReport::from_ps_value(PsValue::from_json(json).unwrap()).is_err());
+}
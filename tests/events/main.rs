@@ -0,0 +1,75 @@
+extern crate powershell_script;
+
+use std::sync::mpsc;
+
+use powershell_script::{
+    EventSink, ExecutionMode, FromPsValue, Middleware, OutputEvent, ProgressRecord, PsError,
+    PsScriptBuilder, PsValue, RunContext, TimedEvent, Timestamp,
+};
+
+#[test]
+fn progress_record_from_json() {
+    let json = r#"{"Activity":"Copy","Status":"","Id":1,"PercentComplete":40,"SecondsRemaining":-1,"CurrentOperation":null,"ParentId":-1,"Completed":false}"#;
+    let record = ProgressRecord::from_ps_value(PsValue::from_json(json).unwrap()).unwrap();
+    assert_eq!(
+        record,
+        ProgressRecord {
+            activity: "Copy".to_string(),
+            id: 1,
+            percent_complete: Some(40),
+            ..ProgressRecord::default()
+        }
+    );
+}
+
+#[test]
+fn timed_channels_keep_the_timestamp() {
+    let (tx, rx) = mpsc::channel::<TimedEvent>();
+    let at = Timestamp::now();
+    assert!(EventSink::send_at(
+        &tx,
+        OutputEvent::Stdout("one".into()),
+        at
+    ));
+    assert!(EventSink::send(&tx, OutputEvent::Stderr("two".into())));
+    drop(tx);
+
+    let events: Vec<TimedEvent> = rx.iter().collect();
+    assert_eq!(events[0].at, at);
+    assert_eq!(events[0].event, OutputEvent::Stdout("one".into()));
+    assert!(events[1].at.instant >= at.instant);
+}
+
+struct Reject;
+
+impl Middleware for Reject {
+    fn before(&self, _ctx: &mut RunContext) -> Result<(), PsError> {
+        Err(PsError::Rejected("no".into()))
+    }
+}
+
+#[test]
+fn rejected_streaming_runs_report_no_lines() {
+    let ps = PsScriptBuilder::new().middleware(Reject).build();
+    let (mut stdout, mut stderr) = (0, 0);
+    let result = ps.run_streaming("'hello'", |_| stdout += 1, |_| stderr += 1);
+    assert!(matches!(result, Err(PsError::Rejected(_))));
+    assert_eq!((stdout, stderr), (0, 0));
+}
+
+#[cfg(unix)]
+#[test]
+fn event_receivers_end_with_the_exit_status() {
+    let ps = PsScriptBuilder::new()
+        .executable("/bin/echo")
+        .execution_mode(ExecutionMode::Encoded)
+        .build();
+    let events: Vec<OutputEvent> = ps.run_events("'hello'", 1).unwrap().into_iter().collect();
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, OutputEvent::Stdout(_))));
+    match events.last() {
+        Some(OutputEvent::Exited(status)) => assert!(status.success()),
+        other => panic!("expected the exit status, got {:?}", other),
+    }
+}
@@ -0,0 +1,54 @@
+extern crate powershell_script;
+
+use std::{
+    future::Future,
+    pin::pin,
+    sync::Arc,
+    task::{Context, Poll, Wake},
+    thread::{self, Thread},
+};
+
+use powershell_script::{Middleware, PsError, PsScriptBuilder, RunContext, ThreadedRunFuture};
+
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Arc::new(Unpark(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+struct Reject;
+
+impl Middleware for Reject {
+    fn before(&self, _ctx: &mut RunContext) -> Result<(), PsError> {
+        Err(PsError::Rejected("not today".into()))
+    }
+}
+
+#[test]
+fn run_async_resolves_to_the_result_of_run() {
+    let ps = PsScriptBuilder::new().middleware(Reject).build();
+    match block_on(ps.run_async("'hello'")) {
+        Err(PsError::Rejected(msg)) => assert_eq!(msg, "not today"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn run_future_can_be_sent_between_threads() {
+    fn assert_send<T: Send + 'static>() {}
+    assert_send::<ThreadedRunFuture>();
+}
@@ -0,0 +1,109 @@
+extern crate powershell_script;
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use powershell_script::{ExecutionMode, PsError, PsHandle, PsScriptBuilder};
+
+#[test]
+fn handles_can_be_shared_between_threads() {
+    fn assert_shareable<T: Clone + Send + Sync + 'static>() {}
+    assert_shareable::<PsHandle>();
+}
+
+#[cfg(unix)]
+#[test]
+fn scripts_are_cancelled_from_another_thread() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // A shell script standing in for PowerShell, which ignores cancellation
+    let executable = std::env::temp_dir().join(format!("ps-handle-{}-sleep", std::process::id()));
+    std::fs::write(&executable, "#!/bin/sh\nexec sleep 30\n").unwrap();
+    std::fs::set_permissions(&executable, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let ps = PsScriptBuilder::new().executable(&executable).build();
+
+    let started = Instant::now();
+    let handle = ps.spawn_handle("'hi'").unwrap();
+    let cancel = handle.clone();
+    let canceller = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        cancel.cancel(Duration::from_millis(100)).unwrap()
+    });
+    let result = handle.wait();
+    std::fs::remove_file(&executable).unwrap();
+
+    assert!(!canceller.join().unwrap());
+    assert!(started.elapsed() < Duration::from_secs(10));
+    match result {
+        Err(PsError::Powershell(output)) => assert_eq!(output.exit_code(), None),
+        result => panic!("expected the script to be killed, got {:?}", result),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn handles_are_only_waited_for_once() {
+    let ps = PsScriptBuilder::new()
+        .executable("/bin/echo")
+        .execution_mode(ExecutionMode::Encoded)
+        .build();
+    let handle = ps.spawn("'hi'").unwrap().into_handle();
+    let other = handle.clone();
+    assert!(handle.wait().is_ok());
+    assert!(!other.is_running());
+    other.kill().unwrap();
+    match other.wait() {
+        Err(PsError::Io(_)) => {}
+        result => panic!("expected an error, got {:?}", result),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn clones_can_be_used_during_the_grace_period() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let executable = std::env::temp_dir().join(format!("ps-handle-{}-grace", std::process::id()));
+    std::fs::write(&executable, "#!/bin/sh\nexec sleep 30\n").unwrap();
+    std::fs::set_permissions(&executable, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let ps = PsScriptBuilder::new().executable(&executable).build();
+
+    let handle = ps.spawn_handle("'hi'").unwrap();
+    let cancel = handle.clone();
+    let canceller = thread::spawn(move || cancel.cancel(Duration::from_secs(5)).unwrap());
+    thread::sleep(Duration::from_millis(200));
+
+    let started = Instant::now();
+    assert!(handle.is_running());
+    assert!(started.elapsed() < Duration::from_secs(1));
+    handle.kill().unwrap();
+
+    assert!(canceller.join().unwrap());
+    assert!(handle.wait().is_err());
+    std::fs::remove_file(&executable).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn clones_are_not_blocked_while_the_output_is_collected() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // Exits right away, but the background process keeps `stdout` open
+    let executable = std::env::temp_dir().join(format!("ps-handle-{}-pipe", std::process::id()));
+    std::fs::write(&executable, "#!/bin/sh\n(sleep 2; echo done) &\nexit 0\n").unwrap();
+    std::fs::set_permissions(&executable, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let ps = PsScriptBuilder::new().executable(&executable).build();
+
+    let handle = ps.spawn_handle("'hi'").unwrap();
+    let other = handle.clone();
+    let waiter = thread::spawn(move || handle.wait());
+    thread::sleep(Duration::from_millis(500));
+    let started = Instant::now();
+    assert!(!other.is_running());
+    assert!(started.elapsed() < Duration::from_millis(500));
+    let output = waiter.join().unwrap().unwrap();
+    std::fs::remove_file(&executable).unwrap();
+    assert_eq!(output.stdout().unwrap(), "done\n");
+}
@@ -0,0 +1,71 @@
+extern crate powershell_script;
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use powershell_script::{PsError, PsScriptBuilder};
+
+/// Writes a shell script standing in for PowerShell, which hangs for
+/// `seconds` without writing anything or sending heartbeats.
+#[cfg(unix)]
+fn hanging_executable(name: &str, seconds: u32) -> std::path::PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("ps-heartbeat-{}-{}", std::process::id(), name));
+    std::fs::write(&path, format!("#!/bin/sh\nexec sleep {}\n", seconds)).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+#[cfg(unix)]
+#[test]
+fn stalled_scripts_are_killed() {
+    let executable = hanging_executable("killed", 30);
+    let ps = PsScriptBuilder::new()
+        .executable(&executable)
+        .stall_timeout(Duration::from_millis(500))
+        .build();
+    let started = Instant::now();
+    let result = ps.run("'hi'");
+    std::fs::remove_file(&executable).unwrap();
+
+    match result {
+        Err(PsError::Stalled(silent)) => assert!(silent >= Duration::from_millis(500)),
+        result => panic!("expected a stall, got {:?}", result),
+    }
+    assert!(started.elapsed() < Duration::from_secs(10));
+}
+
+#[cfg(unix)]
+#[test]
+fn on_stall_is_notified_instead() {
+    let executable = hanging_executable("notified", 2);
+    let stalls = Arc::new(Mutex::new(Vec::new()));
+    let ps = {
+        let stalls = stalls.clone();
+        PsScriptBuilder::new()
+            .executable(&executable)
+            .stall_timeout(Duration::from_millis(500))
+            .on_stall(move |silent| stalls.lock().unwrap().push(silent))
+            .build()
+    };
+    let result = ps.run("'hi'");
+    std::fs::remove_file(&executable).unwrap();
+
+    assert!(result.is_ok());
+    assert_eq!(stalls.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn busy_scripts_keep_running() {
+    let ps = PsScriptBuilder::new()
+        .stall_timeout(Duration::from_secs(2))
+        .build();
+    let script = "$end = (Get-Date).AddSeconds(5); while ((Get-Date) -lt $end) { $null = 1..1000 | Measure-Object }; 'done'";
+    match ps.run(script) {
+        Err(PsError::PowershellNotFound) => {}
+        result => assert_eq!(result.unwrap().stdout().unwrap().trim(), "done"),
+    }
+}
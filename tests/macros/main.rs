@@ -0,0 +1,50 @@
+extern crate powershell_script;
+
+use powershell_script::Script;
+use powershell_script_macros::ps;
+
+#[test]
+fn ps_yields_script() {
+    let script: Script = ps!(r#"
+$items = @{ Name = 'it''s'; Count = 2 }
+"{0} items named $($items["Name"])" -f $items.Count
+$text = @"
+unbalanced { in a here-string
+"@
+"#);
+    assert!(script.contains("here-string"));
+    assert_eq!(script.lines().count(), 6);
+}
+
+#[derive(Debug, PartialEq, powershell_script_macros::FromPs)]
+struct Service {
+    name: String,
+    display_name: String,
+    #[ps(rename = "StartType")]
+    pub start: String,
+    can_stop: bool,
+    pid: Option<u32>,
+    dependencies: Vec<String>,
+}
+
+#[test]
+fn derive_from_ps() {
+    use powershell_script::{FromPsValue, PsValue};
+
+    let json = r#"{"Name":"wuauserv","DisplayName":"Windows Update","StartType":"Manual","CanStop":"True","Pid":"1234","Dependencies":"rpcss"}"#;
+    let service = Service::from_ps_value(PsValue::from_json(json).unwrap()).unwrap();
+    assert_eq!(
+        service,
+        Service {
+            name: "wuauserv".to_string(),
+            display_name: "Windows Update".to_string(),
+            start: "Manual".to_string(),
+            can_stop: true,
+            pid: Some(1234),
+            dependencies: vec!["rpcss".to_string()],
+        }
+    );
+
+    let err = Service::from_ps_value(PsValue::from_json(r#"{"Name":"x"}"#).unwrap()).unwrap_err();
+    assert!(err.to_string().contains("DisplayName"));
+}
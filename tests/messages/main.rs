@@ -0,0 +1,96 @@
+extern crate powershell_script;
+
+use std::sync::{Arc, Mutex};
+
+use powershell_script::{HostMessage, PsError, PsScriptBuilder, PsValue};
+
+#[cfg(unix)]
+#[test]
+fn messages_are_handled_before_the_run_returns() {
+    use std::{io::Write, os::unix::net::UnixStream};
+
+    use powershell_script::{Middleware, RunContext};
+
+    /// Sends messages like `Send-HostMessage` would while the run is about
+    /// to start.
+    struct Sender;
+
+    impl Middleware for Sender {
+        fn before(&self, ctx: &mut RunContext) -> Result<(), PsError> {
+            let function = ctx
+                .prelude()
+                .iter()
+                .find(|line| line.starts_with("function Send-HostMessage"))
+                .expect("Send-HostMessage is defined");
+            let path = between(function, "NamedPipeClientStream('.', '", "'");
+            let token = between(function, "Token = '", "'");
+            let mut stream = UnixStream::connect(path).unwrap();
+            for message in [
+                r#"{"Token":"TOKEN","Type":"started","Data":null}"#,
+                r#"{"Token":"guess","Type":"forged","Data":null}"#,
+                "not a message",
+                r#"{"Token":"TOKEN","Type":"deployed","Data":{"Site":"a","Files":3}}"#,
+            ] {
+                writeln!(stream, "{}", message.replace("TOKEN", token)).unwrap();
+            }
+            Err(PsError::Rejected("sent".into()))
+        }
+    }
+
+    fn between<'a>(text: &'a str, start: &str, end: &str) -> &'a str {
+        let rest = &text[text.find(start).unwrap() + start.len()..];
+        &rest[..rest.find(end).unwrap()]
+    }
+
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let ps = {
+        let messages = messages.clone();
+        PsScriptBuilder::new()
+            .on_message(move |message| messages.lock().unwrap().push(message.clone()))
+            .middleware(Sender)
+            .build()
+    };
+    assert!(ps.run("'hi'").is_err());
+
+    let messages = messages.lock().unwrap();
+    assert_eq!(
+        *messages,
+        [
+            HostMessage {
+                kind: "started".into(),
+                data: PsValue::Null,
+            },
+            HostMessage {
+                kind: "deployed".into(),
+                data: PsValue::Object(vec![
+                    ("Site".into(), PsValue::String("a".into())),
+                    ("Files".into(), PsValue::Int(3)),
+                ]),
+            },
+        ]
+    );
+}
+
+#[test]
+fn scripts_send_messages() {
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let ps = {
+        let messages = messages.clone();
+        PsScriptBuilder::new()
+            .on_message(move |message| messages.lock().unwrap().push(message.clone()))
+            .build()
+    };
+    match ps.run("Send-HostMessage progress -Data 50; 'done'") {
+        Err(PsError::PowershellNotFound) => {}
+        result => {
+            assert_eq!(result.unwrap().stdout().unwrap().trim(), "done");
+            assert_eq!(
+                *messages.lock().unwrap(),
+                [HostMessage {
+                    kind: "progress".into(),
+                    data: PsValue::Int(50),
+                }]
+            );
+        }
+    }
+}
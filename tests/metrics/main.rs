@@ -0,0 +1,30 @@
+extern crate powershell_script;
+
+use powershell_script::{InMemoryMetrics, Metrics};
+use std::time::Duration;
+
+#[test]
+fn records_counts_and_histogram() {
+    let metrics = InMemoryMetrics::with_buckets(&[1.0, 0.1]);
+    metrics.record(Duration::from_millis(50), true);
+    metrics.record(Duration::from_millis(500), false);
+    metrics.record(Duration::from_secs(5), true);
+
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.invocations, 3);
+    assert_eq!(snapshot.failures, 1);
+    assert_eq!(snapshot.buckets, vec![(0.1, 1), (1.0, 2)]);
+    assert!((snapshot.duration_sum - 5.55).abs() < 1e-9);
+}
+
+#[test]
+fn records_queue_waits_apart_from_runs() {
+    let metrics = InMemoryMetrics::default();
+    metrics.record_queue_wait(Duration::from_millis(250));
+    metrics.record_queue_wait(Duration::from_millis(750));
+
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.invocations, 0);
+    assert_eq!(snapshot.queued, 2);
+    assert!((snapshot.queue_wait_sum - 1.0).abs() < 1e-9);
+}
@@ -0,0 +1,343 @@
+extern crate powershell_script;
+
+use std::{
+    process::{self, ExitCode, ExitStatus},
+    time::Duration,
+};
+
+use powershell_script::{
+    ErrorOrigin, Output, PsError, PsValue, StderrMessage, StderrSeverity, RESERVED_BLOCK_TAGS,
+};
+
+#[cfg(unix)]
+fn success() -> ExitStatus {
+    std::os::unix::process::ExitStatusExt::from_raw(0)
+}
+
+#[cfg(windows)]
+fn success() -> ExitStatus {
+    std::os::windows::process::ExitStatusExt::from_raw(0)
+}
+
+fn output(stdout: &str) -> Output {
+    Output::from(process::Output {
+        status: success(),
+        stdout: stdout.as_bytes().to_vec(),
+        stderr: Vec::new(),
+    })
+}
+
+#[test]
+fn base64_block() {
+    let output = output(
+        "before\r\n##ps-block-begin:blob\r\nAAEC/f7/\r\nAA==\r\n##ps-block-end:blob\r\nafter\r\n",
+    );
+    assert_eq!(
+        output.decode_base64_block("blob").unwrap(),
+        [0, 1, 2, 253, 254, 255, 0]
+    );
+    assert_eq!(output.stdout().unwrap(), "before\r\nafter\r\n");
+    assert!(output.decode_base64_block("other").is_err());
+}
+
+#[test]
+fn into_lines() {
+    let lines: Vec<String> = output("one\r\ntwo\n\nthree").into_lines().collect();
+    assert_eq!(lines, ["one", "two", "", "three"]);
+    assert_eq!(output("").into_lines().count(), 0);
+}
+
+#[test]
+fn error_records() {
+    let output = output(concat!(
+        "##ps-block-begin:errors\n",
+        r#"[{"Message":"item 3 failed","Origin":"WriteError","ErrorId":"Microsoft.PowerShell.Commands.WriteErrorException","Category":"NotSpecified","ExceptionType":"Microsoft.PowerShell.Commands.WriteErrorException","Command":"","Line":4},"#,
+        r#"{"Message":"boom","Origin":"Throw","ErrorId":"boom","Category":"OperationStopped","ExceptionType":"System.Management.Automation.RuntimeException","Command":"","Line":0}]"#,
+        "\n##ps-block-end:errors\n"
+    ));
+    let errors = output.errors();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].origin, ErrorOrigin::WriteError);
+    assert_eq!(errors[0].line, Some(4));
+    assert_eq!(errors[0].command, None);
+    assert_eq!(errors[1].origin, ErrorOrigin::Throw);
+    assert_eq!(errors[1].message, "boom");
+    assert_eq!(errors[1].line, None);
+}
+
+#[test]
+fn envelope() {
+    let ok = output(
+        "noise\n##ps-block-begin:envelope\n{\"ok\":true,\"data\":1,\"error\":null}\n##ps-block-end:envelope\n##ps-block-begin:envelope\n{\"ok\":true,\"data\":42,\"error\":null}\n##ps-block-end:envelope\n",
+    );
+    assert_eq!(ok.envelope::<i32>().unwrap(), Ok(42));
+    assert_eq!(ok.stdout().unwrap(), "noise\n");
+
+    let failed = output(
+        "##ps-block-begin:envelope\n{\"ok\":false,\"data\":null,\"error\":{\"message\":\"disk full\",\"details\":{\"free\":12}}}\n##ps-block-end:envelope\n",
+    );
+    let failure = failed.envelope::<i32>().unwrap().unwrap_err();
+    assert_eq!(failure.to_string(), "disk full");
+    assert_eq!(failure.details.get("free"), Some(&PsValue::Int(12)));
+
+    assert!(output("no envelope\n").envelope::<i32>().is_err());
+}
+
+#[test]
+fn exit_code() {
+    let output = output("done\n");
+    assert_eq!(output.exit_code(), Some(0));
+    assert_eq!(output.exit_meaning(), None);
+    assert_eq!(output.to_string(), "done\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn custom_exit_codes() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let output = Output::from(process::Output {
+        status: ExitStatus::from_raw(3 << 8),
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+    });
+    assert!(!output.success());
+    assert_eq!(output.code(), Some(3));
+    assert_eq!(output.status().code(), Some(3));
+}
+
+fn with_status(status: ExitStatus) -> Output {
+    Output::from(process::Output {
+        status,
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+    })
+}
+
+#[test]
+fn to_exit_code() {
+    assert_eq!(output("done\n").to_exit_code(), ExitCode::SUCCESS);
+    assert_eq!(PsError::PowershellNotFound.exit_code(), ExitCode::from(127));
+    assert_eq!(
+        PsError::Stalled(Duration::from_secs(60)).exit_code(),
+        ExitCode::from(124)
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn to_exit_code_on_unix() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let failed = with_status(ExitStatus::from_raw(3 << 8));
+    assert_eq!(failed.to_exit_code(), ExitCode::from(3));
+    assert_eq!(PsError::Powershell(failed).exit_code(), ExitCode::from(3));
+    // Killed by SIGKILL
+    let killed = with_status(ExitStatus::from_raw(9));
+    assert_eq!(killed.to_exit_code(), ExitCode::from(137));
+}
+
+#[cfg(windows)]
+#[test]
+fn to_exit_code_on_windows() {
+    use std::os::windows::process::ExitStatusExt;
+
+    let negative = with_status(ExitStatus::from_raw(0xFFFF_FFFD));
+    assert_eq!(negative.to_exit_code(), ExitCode::from(253));
+    let wrapped = with_status(ExitStatus::from_raw(256));
+    assert_eq!(wrapped.to_exit_code(), ExitCode::FAILURE);
+}
+
+#[test]
+fn changes() {
+    let output = output(
+        "##ps-block-begin:change\n{\"Action\":\"Created\",\"Target\":\"C:\\\\app\",\"Details\":null}\n##ps-block-end:change\nok\n##ps-block-begin:change\n{\"Action\":\"Started\",\"Target\":\"W3SVC\",\"Details\":{\"Pid\":42}}\n##ps-block-end:change\n",
+    );
+    let changes = output.changes();
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0].to_string(), "Created C:\\app");
+    assert_eq!(changes[1].details.get("Pid"), Some(&PsValue::Int(42)));
+    assert_eq!(output.stdout().unwrap(), "ok\n");
+    assert!(self::output("nothing\n").changes().is_empty());
+}
+
+fn with_stderr(stderr: &str) -> Output {
+    Output::from(process::Output {
+        status: success(),
+        stdout: Vec::new(),
+        stderr: stderr.as_bytes().to_vec(),
+    })
+}
+
+#[test]
+fn error_records_from_formatted_errors() {
+    let output = with_stderr(concat!(
+        "#< CLIXML\r\n",
+        r#"<Objs Version="1.1.0.1" xmlns="http://schemas.microsoft.com/powershell/2004/04">"#,
+        r#"<Obj S="progress" RefId="0"><TN RefId="0"><T>System.Management.Automation.PSCustomObject</T><T>System.Object</T></TN><MS><I64 N="SourceId">1</I64><PR N="Record"><AV>Preparing modules for first use.</AV><AI>0</AI><Nil /><PI>-1</PI><PC>-1</PC><T>Completed</T><SR>-1</SR><SD> </SD></PR></MS></Obj>"#,
+        r#"<S S="Warning">disk is almost full_x000D__x000A_</S>"#,
+        r#"<S S="Error">Get-Item : Cannot find path 'C:\nope' because it does not exist._x000D__x000A_</S>"#,
+        r#"<S S="Error">At line:1 char:1_x000D__x000A_</S>"#,
+        r#"<S S="Error">+ Get-Item C:\nope_x000D__x000A_</S>"#,
+        r#"<S S="Error">+ ~~~~~~~~~~~~~~~~_x000D__x000A_</S>"#,
+        r#"<S S="Error">    + CategoryInfo          : ObjectNotFound: (C:\nope:String) [Get-Item], ItemNotFoundException_x000D__x000A_</S>"#,
+        r#"<S S="Error">    + FullyQualifiedErrorId : PathNotFound,Microsoft.PowerShell.Commands.GetItemCommand_x000D__x000A_</S>"#,
+        r#"<S S="Error"> _x000D__x000A_</S>"#,
+        r#"<S S="Error">second &amp; last_x000D__x000A_</S>"#,
+        "</Objs>"
+    ));
+    let errors = output.error_records();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(
+        errors[0].message,
+        "Get-Item : Cannot find path 'C:\\nope' because it does not exist."
+    );
+    assert_eq!(errors[0].category.as_deref(), Some("ObjectNotFound"));
+    assert_eq!(
+        errors[0].error_id.as_deref(),
+        Some("PathNotFound,Microsoft.PowerShell.Commands.GetItemCommand")
+    );
+    assert_eq!(
+        errors[0].stack_trace.as_deref(),
+        Some("At line:1 char:1\n+ Get-Item C:\\nope\n+ ~~~~~~~~~~~~~~~~")
+    );
+    assert_eq!(errors[1].message, "second & last");
+    assert_eq!(errors[1].category, None);
+
+    let records = powershell_script::parse_clixml(&output.stderr().unwrap()).unwrap();
+    let streams: Vec<&str> = records.iter().map(|r| r.stream.as_str()).collect();
+    assert_eq!(streams, ["progress", "Warning", "Error", "Error"]);
+    assert_eq!(records[0].object.get("SourceId"), Some(&PsValue::Int(1)));
+    assert_eq!(records[1].message, "disk is almost full");
+}
+
+#[test]
+fn error_records_from_serialized_errors() {
+    let output = with_stderr(concat!(
+        "#< CLIXML\n",
+        r#"<Objs Version="1.1.0.1" xmlns="http://schemas.microsoft.com/powershell/2004/04">"#,
+        r#"<Obj S="Error" RefId="0"><TN RefId="0"><T>System.Management.Automation.ErrorRecord</T><T>System.Object</T></TN><ToString>deploy failed</ToString>"#,
+        r#"<MS><Obj N="Exception" RefId="1"><TN RefId="1"><T>System.Management.Automation.RuntimeException</T></TN><ToString>deploy failed</ToString><Props><S N="Message">deploy failed</S></Props></Obj>"#,
+        r#"<S N="FullyQualifiedErrorId">deploy failed</S><I32 N="ErrorCategory_Category">0</I32><S N="ErrorCategory_Message">OperationStopped: (:) [], RuntimeException</S>"#,
+        r#"<S N="ErrorDetails_ScriptStackTrace">at Deploy, C:\deploy.ps1: line 3_x000A_at &lt;ScriptBlock&gt;, &lt;No file&gt;: line 1</S><B N="SerializeExtendedInfo">false</B></MS></Obj>"#,
+        r#"<Obj S="Error" RefId="2"><TNRef RefId="0" /><ToString>deploy failed</ToString><MS><Ref N="Exception" RefId="1" /></MS></Obj>"#,
+        "</Objs>"
+    ));
+    let errors = output.error_records();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].message, "deploy failed");
+    assert_eq!(errors[0].category.as_deref(), Some("OperationStopped"));
+    assert_eq!(errors[0].error_id.as_deref(), Some("deploy failed"));
+    assert_eq!(
+        errors[0].stack_trace.as_deref(),
+        Some("at Deploy, C:\\deploy.ps1: line 3\nat <ScriptBlock>, <No file>: line 1")
+    );
+    assert_eq!(
+        errors[1]
+            .object
+            .get("Exception")
+            .and_then(|e| e.get("Message")),
+        Some(&PsValue::from("deploy failed"))
+    );
+}
+
+#[test]
+fn error_records_need_clixml() {
+    assert!(with_stderr("plain error text\n").error_records().is_empty());
+    assert!(powershell_script::parse_clixml("#< CLIXML\n<Objs><S>unclosed</Objs>").is_err());
+}
+
+#[test]
+fn streams() {
+    let output = output(concat!(
+        "data\n",
+        "##ps-block-begin:streams\n",
+        r#"{"Verbose":["connecting","connected"],"Warning":["disk almost full"],"Debug":[],"Information":["done"]}"#,
+        "\n##ps-block-end:streams\n"
+    ));
+    assert_eq!(output.verbose(), ["connecting", "connected"]);
+    assert_eq!(output.warnings(), ["disk almost full"]);
+    assert!(output.debug().is_empty());
+    assert_eq!(output.information(), ["done"]);
+    assert_eq!(output.stdout().unwrap(), "data\n");
+
+    assert!(self::output("data\n").warnings().is_empty());
+}
+
+#[test]
+fn stderr_messages_from_text() {
+    let output = with_stderr(concat!(
+        "WARNING: certificate expires in 3 days\n",
+        "WARNING: retrying\n",
+        "    after 5 seconds\n",
+        "Copy-Item : Access to the path 'C:\\data' is denied.\n",
+        "At line:1 char:1\n",
+        "    + CategoryInfo          : PermissionDenied: (:) [Copy-Item], UnauthorizedAccessException\n",
+        "\n",
+        "VERBOSE: done\n",
+    ));
+    let messages = output.stderr_messages();
+    let severities: Vec<StderrSeverity> = messages.iter().map(|m| m.severity).collect();
+    assert_eq!(
+        severities,
+        [
+            StderrSeverity::Warning,
+            StderrSeverity::Warning,
+            StderrSeverity::Error,
+            StderrSeverity::Verbose
+        ]
+    );
+    assert_eq!(messages[1].message, "retrying\n    after 5 seconds");
+    assert!(messages[2].message.starts_with("Copy-Item : Access"));
+    assert!(messages[2].message.ends_with("UnauthorizedAccessException"));
+
+    assert!(output.success());
+    assert!(output.warnings().is_empty());
+    assert_eq!(
+        output.stderr_warnings(),
+        [
+            "certificate expires in 3 days",
+            "retrying\n    after 5 seconds"
+        ]
+    );
+}
+
+#[test]
+fn stderr_messages_from_clixml() {
+    let output = with_stderr(concat!(
+        "#< CLIXML\n",
+        r#"<Objs Version="1.1.0.1" xmlns="http://schemas.microsoft.com/powershell/2004/04">"#,
+        r#"<S S="Warning">low disk_x000D__x000A_</S><S S="Error">boom_x000D__x000A_</S>"#,
+        "</Objs>"
+    ));
+    assert_eq!(
+        output.stderr_messages(),
+        [
+            StderrMessage {
+                severity: StderrSeverity::Warning,
+                message: "low disk".to_string()
+            },
+            StderrMessage {
+                severity: StderrSeverity::Error,
+                message: "boom".to_string()
+            },
+        ]
+    );
+    assert_eq!(output.stderr_warnings(), ["low disk"]);
+}
+
+#[test]
+fn scripts_cannot_shadow_wrapper_blocks() {
+    // The script's own block comes first, the wrapper writes its block after
+    // the script has run
+    let output = output(concat!(
+        "##ps-block-begin:return\n",
+        "\"spoofed\"\n",
+        "##ps-block-end:return\n",
+        "##ps-block-begin:return\n",
+        "\"returned\"\n",
+        "##ps-block-end:return\n",
+    ));
+    assert_eq!(output.return_value::<String>().unwrap(), "returned");
+    assert!(RESERVED_BLOCK_TAGS.contains(&"return"));
+}
@@ -0,0 +1,29 @@
+extern crate powershell_script;
+
+use std::env;
+
+use powershell_script::paths;
+
+#[test]
+fn round_trip() {
+    let dir = env::temp_dir();
+    let short = paths::to_short(&dir).unwrap();
+    assert!(short.exists());
+    assert_eq!(
+        paths::to_long(&short).unwrap().canonicalize().unwrap(),
+        dir.canonicalize().unwrap()
+    );
+}
+
+#[test]
+fn missing_paths_are_an_error() {
+    let missing = env::temp_dir().join("no such directory, hopefully");
+    assert!(paths::to_short(&missing).is_err());
+    assert!(paths::to_long(&missing).is_err());
+}
+
+#[test]
+fn spaces() {
+    assert!(paths::has_spaces("C:\\Program Files\\tool.exe"));
+    assert!(!paths::has_spaces("C:\\PROGRA~1\\tool.exe"));
+}
@@ -0,0 +1,40 @@
+extern crate powershell_script;
+
+use std::time::Duration;
+
+use powershell_script::{
+    pester::{Outcome, TestResult, TestRun},
+    FromPsValue, PsValue,
+};
+
+fn run() -> TestRun {
+    let json = r#"[
+        {"Name":"adds","FullName":"Math.adds","Result":"Passed","DurationMs":12.5,"File":"C:\\t\\Math.Tests.ps1","Line":3,"Errors":[]},
+        {"Name":"divides","FullName":"Math.divides","Result":"Failed","DurationMs":4,"File":"C:\\t\\Math.Tests.ps1","Line":7,"Errors":["Expected 2, but got 3."]},
+        {"Name":"slow","FullName":"Math.slow","Result":"NotRun","DurationMs":0,"File":null,"Line":0,"Errors":[]}
+    ]"#;
+    TestRun {
+        tests: Vec::<TestResult>::from_ps_value(PsValue::from_json(json).unwrap()).unwrap(),
+    }
+}
+
+#[test]
+fn parses_test_results() {
+    let run = run();
+    assert_eq!(run.tests.len(), 3);
+    assert_eq!(run.tests[0].outcome, Outcome::Passed);
+    assert_eq!(run.tests[0].duration, Duration::from_micros(12_500));
+    assert_eq!(run.tests[1].errors, ["Expected 2, but got 3."]);
+    assert_eq!(run.tests[1].line, Some(7));
+    assert_eq!(run.tests[2].outcome, Outcome::NotRun);
+    assert_eq!(run.tests[2].file, None);
+    assert_eq!(run.tests[2].line, None);
+    assert_eq!(run.passed().count(), 1);
+    assert!(!run.is_success());
+}
+
+#[test]
+#[should_panic(expected = "1 of 3 Pester tests failed")]
+fn assert_passed_reports_failures() {
+    run().assert_passed();
+}
@@ -0,0 +1,189 @@
+extern crate powershell_script;
+
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use powershell_script::{
+    BuildError, InMemoryMetrics, JobOptions, PsError, PsPool, PsScriptBuilder,
+};
+
+#[test]
+fn pools_can_be_shared_between_threads() {
+    fn assert_shareable<T: Clone + Send + Sync + 'static>() {}
+    assert_shareable::<PsPool>();
+}
+
+/// A shell script standing in for PowerShell, which answers each run with
+/// the markers a session waits for. Scripts are recognized by the start of
+/// their base64 encoding: asked for its working set it reports 2 GiB,
+/// scripts starting with `fail` fail, those starting with `exit` end it and
+/// those starting with `sleep` take half a second.
+#[cfg(unix)]
+fn fake_session_executable(name: &str) -> std::path::PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("ps-pool-{}-{}", std::process::id(), name));
+    let script = r###"#!/bin/sh
+while IFS= read -r line; do
+    id=$(printf '%s\n' "$line" | sed -n "s/.*##ps-session-begin:\([0-9]*\)'.*/\1/p")
+    [ -z "$id" ] && continue
+    echo "##ps-session-begin:$id"; echo "##ps-session-begin:$id" >&2
+    ok=True
+    case "$line" in
+        *W1N5c3RlbS5EaWFnbm9zdGljcy5Qcm9jZXNz*) echo 2147483648 ;;
+        *"String('ZmFp"*) ok=False ;;
+        *"String('ZXhp"*) exit 3 ;;
+        *"String('c2xl"*) sleep 0.5; echo "ran $id" ;;
+        *) echo "ran $id" ;;
+    esac
+    echo "##ps-session-end:$id:$ok"; echo "##ps-session-end:$id:$ok" >&2
+done
+"###;
+    std::fs::write(&path, script).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+#[cfg(unix)]
+#[test]
+fn scripts_run_on_idle_workers() {
+    let executable = fake_session_executable("idle");
+    let pool = PsScriptBuilder::new()
+        .executable(&executable)
+        .build_pool(2)
+        .unwrap();
+    assert_eq!((pool.size(), pool.idle()), (2, 2));
+
+    let threads: Vec<_> = (0..8)
+        .map(|i| {
+            let pool = pool.clone();
+            thread::spawn(move || pool.run(&i.to_string()).map(|output| output.success()))
+        })
+        .collect();
+    let results: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+    std::fs::remove_file(&executable).unwrap();
+
+    assert!(results.into_iter().all(|result| result.unwrap()));
+    assert_eq!(pool.idle(), 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn workers_ended_by_scripts_are_replaced() {
+    let executable = fake_session_executable("ended");
+    let pool = PsScriptBuilder::new()
+        .executable(&executable)
+        .warm_up("Import-Module Pester")
+        .build_pool(1)
+        .unwrap();
+    assert!(matches!(pool.run("exit 3"), Err(PsError::Powershell(_))));
+    let output = pool.run("'again'");
+    std::fs::remove_file(&executable).unwrap();
+    // The warm-up ran first on the new worker
+    assert_eq!(output.unwrap().stdout().unwrap().trim(), "ran 1");
+}
+
+#[cfg(unix)]
+#[test]
+fn pools_have_at_least_one_worker() {
+    let executable = fake_session_executable("empty");
+    let pool = PsScriptBuilder::new().executable(&executable).build_pool(0);
+    std::fs::remove_file(&executable).unwrap();
+    assert_eq!(pool.unwrap().size(), 1);
+}
+
+#[test]
+fn options_pools_ignore_are_rejected() {
+    let result = PsScriptBuilder::new().record_timeline(true).build_pool(2);
+    match result {
+        Err(PsError::InvalidConfig(e)) => assert_eq!(
+            e,
+            BuildError::ConflictingOptions("record_timeline", "build_pool")
+        ),
+        _ => panic!("expected the pool to be rejected"),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn keyed_scripts_run_on_the_same_worker() {
+    let executable = fake_session_executable("keyed");
+    let pool = PsScriptBuilder::new()
+        .executable(&executable)
+        .build_pool(2)
+        .unwrap();
+    // The fake numbers the scripts each worker ran
+    let run = |key: &str| {
+        let output = pool.run_keyed(key, "'hi'").unwrap();
+        output.stdout().unwrap().trim().to_string()
+    };
+    let results = [run("a"), run("a"), run("b"), run("a"), run("b")];
+    std::fs::remove_file(&executable).unwrap();
+
+    assert_eq!(results, ["ran 0", "ran 1", "ran 0", "ran 2", "ran 1"]);
+    assert!(pool.release_key("a"));
+    assert!(!pool.release_key("a"));
+}
+
+#[cfg(unix)]
+#[test]
+fn higher_priorities_get_a_worker_first() {
+    let executable = fake_session_executable("priority");
+    let pool = PsScriptBuilder::new()
+        .executable(&executable)
+        .build_pool(1)
+        .unwrap();
+    let finished = Arc::new(Mutex::new(Vec::new()));
+    let start = |name: &'static str, priority: i32| {
+        let (pool, finished) = (pool.clone(), finished.clone());
+        let options = JobOptions::new().priority(priority);
+        let thread = thread::spawn(move || {
+            pool.run_with("sleep", &options).unwrap();
+            finished.lock().unwrap().push(name);
+        });
+        thread::sleep(Duration::from_millis(100));
+        thread
+    };
+    let threads = [
+        start("busy", 0),
+        start("batch", 0),
+        start("interactive", 10),
+    ];
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    std::fs::remove_file(&executable).unwrap();
+    assert_eq!(*finished.lock().unwrap(), ["busy", "interactive", "batch"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn scripts_missing_their_deadline_are_not_run() {
+    let executable = fake_session_executable("deadline");
+    let metrics = Arc::new(InMemoryMetrics::default());
+    let pool = PsScriptBuilder::new()
+        .executable(&executable)
+        .metrics(metrics.clone())
+        .build_pool(1)
+        .unwrap();
+    let busy = {
+        let pool = pool.clone();
+        thread::spawn(move || pool.run("sleep"))
+    };
+    thread::sleep(Duration::from_millis(100));
+    let options = JobOptions::new().deadline(Instant::now() + Duration::from_millis(100));
+    let result = pool.run_with("'late'", &options);
+    busy.join().unwrap().unwrap();
+    std::fs::remove_file(&executable).unwrap();
+
+    match result {
+        Err(PsError::DeadlineExceeded(waited)) => assert!(waited >= Duration::from_millis(100)),
+        other => panic!("expected a missed deadline, got {:?}", other),
+    }
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.queued, 2);
+    assert!(snapshot.queue_wait_sum >= 0.1);
+}
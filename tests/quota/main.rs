@@ -0,0 +1,119 @@
+extern crate powershell_script;
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use powershell_script::{ExecutionMode, ExecutionQuota, PsError, PsScriptBuilder, QuotaPolicy};
+
+/// Writes a shell script standing in for PowerShell, which sleeps for
+/// `seconds`.
+#[cfg(unix)]
+fn sleeping_executable(name: &str, seconds: u32) -> std::path::PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("ps-quota-{}-{}", std::process::id(), name));
+    std::fs::write(&path, format!("#!/bin/sh\nexec sleep {}\n", seconds)).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+// `echo` stands in for PowerShell, finishing right away
+#[cfg(unix)]
+fn echo(quota: &ExecutionQuota) -> powershell_script::PsScript {
+    PsScriptBuilder::new()
+        .executable("/bin/echo")
+        .execution_mode(ExecutionMode::Encoded)
+        .execution_quota(quota.clone())
+        .build()
+}
+
+#[cfg(unix)]
+#[test]
+fn runs_over_the_quota_are_rejected() {
+    let executable = sleeping_executable("rejected", 30);
+    let quota = ExecutionQuota::new()
+        .max_processes(1)
+        .policy(QuotaPolicy::Reject);
+    let sleeper = PsScriptBuilder::new()
+        .executable(&executable)
+        .execution_quota(quota.clone())
+        .build();
+
+    let mut child = sleeper.spawn("'hi'").unwrap();
+    assert_eq!(quota.running(), 1);
+    match echo(&quota).run("'hi'") {
+        Err(PsError::QuotaExceeded(_)) => {}
+        result => panic!("expected the quota to be exceeded, got {:?}", result),
+    }
+    child.kill().unwrap();
+    drop(child);
+    std::fs::remove_file(&executable).unwrap();
+
+    assert_eq!(quota.running(), 0);
+    assert!(echo(&quota).run("'hi'").is_ok());
+    assert_eq!(quota.running(), 0);
+}
+
+#[cfg(unix)]
+#[test]
+fn runs_wait_for_room() {
+    let executable = sleeping_executable("waiting", 1);
+    let quota = ExecutionQuota::new().max_memory(100).memory_per_process(60);
+    let sleeper = PsScriptBuilder::new()
+        .executable(&executable)
+        .execution_quota(quota.clone())
+        .build();
+
+    let started = Instant::now();
+    let background = thread::spawn(move || sleeper.run("'hi'"));
+    while quota.running() == 0 {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(quota.reserved_memory(), 60);
+    assert!(echo(&quota).run("'hi'").is_ok());
+    assert!(started.elapsed() >= Duration::from_millis(900));
+    background.join().unwrap().unwrap();
+    std::fs::remove_file(&executable).unwrap();
+    assert_eq!(quota.reserved_memory(), 0);
+}
+
+#[cfg(unix)]
+#[test]
+fn waiting_gives_up_after_the_timeout() {
+    let executable = sleeping_executable("timeout", 30);
+    let quota = ExecutionQuota::new()
+        .max_processes(1)
+        .policy(QuotaPolicy::WaitFor(Duration::from_millis(200)));
+    let sleeper = PsScriptBuilder::new()
+        .executable(&executable)
+        .execution_quota(quota.clone())
+        .build();
+
+    let mut child = sleeper.spawn("'hi'").unwrap();
+    let started = Instant::now();
+    let result = echo(&quota).run("'hi'");
+    child.kill().unwrap();
+    std::fs::remove_file(&executable).unwrap();
+
+    match result {
+        Err(PsError::QuotaExceeded(_)) => assert!(started.elapsed() >= Duration::from_millis(200)),
+        result => panic!("expected the quota to be exceeded, got {:?}", result),
+    }
+}
+
+#[test]
+fn runs_which_never_fit_fail_right_away() {
+    let quota = ExecutionQuota::new()
+        .max_memory(100)
+        .memory_per_process(200);
+    let ps = PsScriptBuilder::new()
+        .execution_quota(quota.clone())
+        .build();
+    match ps.run("'hi'") {
+        Err(PsError::QuotaExceeded(_)) => {}
+        result => panic!("expected the quota to be exceeded, got {:?}", result),
+    }
+    assert_eq!(quota.running(), 0);
+}
@@ -0,0 +1,112 @@
+extern crate powershell_script;
+
+use powershell_script::{
+    FromPsValue, ParamSchema, ParamSpec, ParamType, PsError, PsValue, ScriptRegistry,
+};
+
+fn registry() -> ScriptRegistry {
+    let mut registry = ScriptRegistry::new();
+    registry.register(
+        "greet",
+        "param($Name, $Times = 1) 1..$Times | ForEach-Object { \"Hello $Name\" }",
+        ParamSchema::new()
+            .required("Name", ParamType::String)
+            .optional("Times", ParamType::Int),
+    );
+    registry
+}
+
+fn invalid(result: Result<(), PsError>) -> String {
+    match result {
+        Err(PsError::InvalidParameters(msg)) => msg,
+        other => panic!("expected invalid parameters, got {:?}", other),
+    }
+}
+
+#[test]
+fn accepts_matching_parameters() {
+    let registry = registry();
+    let params = vec![
+        ("name", PsValue::from("world")),
+        ("Times", PsValue::from(2)),
+    ];
+    assert!(registry.validate("greet", params).is_ok());
+}
+
+#[test]
+fn rejects_parameters_not_matching_the_schema() {
+    let registry = registry();
+    let msg = invalid(registry.validate("greet", vec![("Times", PsValue::from(2))]));
+    assert_eq!(msg, "`greet`: mandatory parameter `Name` is missing");
+
+    let msg = invalid(registry.validate("greet", vec![("Name", PsValue::from(1))]));
+    assert_eq!(msg, "`greet`: parameter `Name` expects String, got `1`");
+
+    let params = vec![
+        ("Name", PsValue::from("a")),
+        ("Color", PsValue::from("red")),
+    ];
+    let msg = invalid(registry.validate("greet", params));
+    assert_eq!(msg, "`greet`: unknown parameter `Color`");
+}
+
+#[test]
+fn rejects_unknown_scripts() {
+    let result = registry().validate("deploy", Vec::<(&str, PsValue)>::new());
+    assert!(matches!(result, Err(PsError::UnknownScript(name)) if name == "deploy"));
+}
+
+#[test]
+fn registers_files() {
+    let path = std::env::temp_dir().join(format!(
+        "powershell-script-registry-{}.ps1",
+        std::process::id()
+    ));
+    std::fs::write(&path, b"\xEF\xBB\xBFparam($Path)\r\nGet-Item $Path").unwrap();
+    let mut registry = ScriptRegistry::new();
+    let result = registry.register_file(
+        "item",
+        &path,
+        ParamSchema::new().required("Path", ParamType::String),
+    );
+    std::fs::remove_file(&path).unwrap();
+    result.unwrap();
+    assert!(registry.contains("item"));
+    assert_eq!(registry.schema("item").unwrap().params()[0].name, "Path");
+}
+
+#[test]
+fn checks_allowed_values() {
+    let mut registry = ScriptRegistry::new();
+    let schema = ParamSchema::new().param(ParamSpec {
+        name: "Level".into(),
+        kind: ParamType::String,
+        mandatory: true,
+        allowed: vec!["Low".into(), "High".into()],
+    });
+    registry.register("level", "param($Level) $Level", schema);
+
+    assert!(registry.validate("level", vec![("Level", "high")]).is_ok());
+    let msg = invalid(registry.validate("level", vec![("Level", "medium")]));
+    assert_eq!(
+        msg,
+        "`level`: parameter `Level` must be one of Low, High, got `medium`"
+    );
+}
+
+#[test]
+fn reads_parsed_parameters() {
+    // What `ParamSchema::from_script` reads for
+    // `param([Parameter(Mandatory)][ValidateSet('a', 'b')][string]$Mode, [int[]]$Ids, [switch]$Force)`
+    let json = r#"[
+        {"Name":"Mode","Type":"System.String","Mandatory":true,"ValidateSet":["a","b"]},
+        {"Name":"Ids","Type":"System.Int32[]","Mandatory":false,"ValidateSet":[]},
+        {"Name":"Force","Type":"System.Management.Automation.SwitchParameter","Mandatory":false,"ValidateSet":[]}
+    ]"#;
+    let params = Vec::<ParamSpec>::from_ps_value(PsValue::from_json(json).unwrap()).unwrap();
+    assert_eq!(params[0].kind, ParamType::String);
+    assert!(params[0].mandatory);
+    assert_eq!(params[0].allowed, ["a", "b"]);
+    assert_eq!(params[1].kind, ParamType::Array);
+    assert_eq!(params[2].kind, ParamType::Bool);
+}
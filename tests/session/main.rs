@@ -0,0 +1,243 @@
+extern crate powershell_script;
+
+use std::time::Duration;
+
+use powershell_script::{
+    BuildError, ExecutionMode, ExecutionQuota, PsError, PsScriptBuilder, PsSession, QuotaPolicy,
+    RecyclePolicy,
+};
+
+/// Starts a session, or returns `None` if PowerShell isn't installed.
+fn session() -> Option<PsSession> {
+    match PsScriptBuilder::new().build_session() {
+        Ok(session) => Some(session),
+        Err(PsError::PowershellNotFound) => None,
+        Err(e) => panic!("failed to start the session: {}", e),
+    }
+}
+
+#[test]
+fn variables_survive_between_runs() {
+    let mut session = match session() {
+        Some(session) => session,
+        None => return,
+    };
+    session.run("$greeting = 'hello'").unwrap();
+    session
+        .run("function Get-Greeting { \"$greeting world\" }")
+        .unwrap();
+    let output = session.run("Get-Greeting").unwrap();
+    assert_eq!(output.stdout().unwrap().trim(), "hello world");
+    assert!(session.close().unwrap().success());
+}
+
+#[test]
+fn failed_runs_keep_the_session_alive() {
+    let mut session = match session() {
+        Some(session) => session,
+        None => return,
+    };
+    match session.run("Write-Error 'broken'") {
+        Err(PsError::Powershell(output)) => assert!(output.stderr().unwrap().contains("broken")),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let output = session.run("1 + 1").unwrap();
+    assert_eq!(output.stdout().unwrap().trim(), "2");
+}
+
+#[test]
+fn exit_ends_the_session() {
+    let mut session = match session() {
+        Some(session) => session,
+        None => return,
+    };
+    assert!(session.run("exit 3").is_err());
+    match session.run("'still there?'") {
+        Err(PsError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::BrokenPipe),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+/// A shell script standing in for PowerShell, which answers each run with
+/// the markers a session waits for. Scripts are recognized by the start of
+/// their base64 encoding: asked for its working set it reports 2 GiB,
+/// scripts starting with `fail` fail and those starting with `exit` end it.
+#[cfg(unix)]
+fn fake_session_executable(name: &str) -> std::path::PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("ps-session-{}-{}", std::process::id(), name));
+    let script = r###"#!/bin/sh
+while IFS= read -r line; do
+    id=$(printf '%s\n' "$line" | sed -n "s/.*##ps-session-begin:\([0-9]*\)'.*/\1/p")
+    [ -z "$id" ] && continue
+    echo "##ps-session-begin:$id"; echo "##ps-session-begin:$id" >&2
+    ok=True
+    case "$line" in
+        *W1N5c3RlbS5EaWFnbm9zdGljcy5Qcm9jZXNz*) echo 2147483648 ;;
+        *"String('ZmFp"*) ok=False ;;
+        *"String('ZXhp"*) exit 3 ;;
+        *) echo "ran $id" ;;
+    esac
+    echo "##ps-session-end:$id:$ok"; echo "##ps-session-end:$id:$ok" >&2
+done
+"###;
+    std::fs::write(&path, script).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+#[cfg(unix)]
+#[test]
+fn sessions_are_recycled_after_max_runs() {
+    let executable = fake_session_executable("runs");
+    let mut session = PsScriptBuilder::new()
+        .executable(&executable)
+        .recycle(RecyclePolicy::new().max_runs(2))
+        .build_session()
+        .unwrap();
+    let first = session.id();
+    session.run("1").unwrap();
+    session.run("2").unwrap();
+    assert_eq!((session.id(), session.recycled()), (first, 0));
+
+    let output = session.run("3").unwrap();
+    std::fs::remove_file(&executable).unwrap();
+    assert_eq!(output.stdout().unwrap().trim(), "ran 0");
+    assert_ne!(session.id(), first);
+    assert_eq!(session.recycled(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn idle_sessions_are_recycled() {
+    let executable = fake_session_executable("idle");
+    let mut session = PsScriptBuilder::new()
+        .executable(&executable)
+        .recycle(RecyclePolicy::new().idle_timeout(Duration::from_millis(100)))
+        .build_session()
+        .unwrap();
+    session.run("1").unwrap();
+    assert!(!session.recycle_if_due().unwrap());
+    std::thread::sleep(Duration::from_millis(200));
+    let recycled = session.recycle_if_due();
+    std::fs::remove_file(&executable).unwrap();
+    assert!(recycled.unwrap());
+    assert_eq!(session.recycled(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn sessions_using_too_much_memory_are_recycled() {
+    let executable = fake_session_executable("memory");
+    let mut session = PsScriptBuilder::new()
+        .executable(&executable)
+        .recycle(RecyclePolicy::new().max_memory(1024 * 1024 * 1024))
+        .build_session()
+        .unwrap();
+    session.run("1").unwrap();
+    assert_eq!(session.recycled(), 0);
+    session.run("2").unwrap();
+    std::fs::remove_file(&executable).unwrap();
+    assert_eq!(session.recycled(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn recycled_sessions_release_their_quota() {
+    let executable = fake_session_executable("quota");
+    let quota = ExecutionQuota::new()
+        .max_processes(1)
+        .policy(QuotaPolicy::Reject);
+    let mut session = PsScriptBuilder::new()
+        .executable(&executable)
+        .execution_quota(quota)
+        .build_session()
+        .unwrap();
+    session.recycle().unwrap();
+    let result = session.run("1");
+    std::fs::remove_file(&executable).unwrap();
+    assert_eq!(result.unwrap().stdout().unwrap().trim(), "ran 0");
+}
+
+#[cfg(unix)]
+#[test]
+fn the_warm_up_runs_in_every_process() {
+    let executable = fake_session_executable("warm-up");
+    let mut session = PsScriptBuilder::new()
+        .executable(&executable)
+        .warm_up("Import-Module Pester")
+        .recycle(RecyclePolicy::new().max_runs(1))
+        .build_session()
+        .unwrap();
+    // The warm-up is the first script of each process
+    let first = session.run("1").unwrap();
+    let second = session.run("2").unwrap();
+    std::fs::remove_file(&executable).unwrap();
+    assert_eq!(first.stdout().unwrap().trim(), "ran 1");
+    assert_eq!(second.stdout().unwrap().trim(), "ran 1");
+    assert_eq!(session.recycled(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn failed_warm_ups_fail_the_session() {
+    let executable = fake_session_executable("failed-warm-up");
+    let result = PsScriptBuilder::new()
+        .executable(&executable)
+        .warm_up("fail")
+        .build_session();
+    std::fs::remove_file(&executable).unwrap();
+    assert!(matches!(result, Err(PsError::Powershell(_))));
+}
+
+#[cfg(unix)]
+#[test]
+fn unhealthy_sessions_are_recycled() {
+    let executable = fake_session_executable("health");
+    let mut session = PsScriptBuilder::new()
+        .executable(&executable)
+        .health_check("fail")
+        .build_session()
+        .unwrap();
+    // The first script runs on a fresh process, which isn't checked
+    session.run("1").unwrap();
+    assert_eq!(session.recycled(), 0);
+    session.run("2").unwrap();
+    std::fs::remove_file(&executable).unwrap();
+    assert_eq!(session.recycled(), 1);
+}
+
+#[test]
+fn options_sessions_ignore_are_rejected() {
+    let result = PsScriptBuilder::new().capture_streams(true).build_session();
+    match result {
+        Err(PsError::InvalidConfig(e)) => assert_eq!(
+            e,
+            BuildError::ConflictingOptions("capture_streams", "build_session")
+        ),
+        _ => panic!("expected the session to be rejected"),
+    }
+
+    let result = PsScriptBuilder::new().on_failure(|_| {}).build_session();
+    match result {
+        Err(PsError::InvalidConfig(e)) => {
+            assert_eq!(
+                e,
+                BuildError::ConflictingOptions("on_failure", "build_session")
+            )
+        }
+        _ => panic!("expected the session to be rejected"),
+    }
+
+    let result = PsScriptBuilder::new()
+        .execution_mode(ExecutionMode::Encoded)
+        .build_session();
+    match result {
+        Err(PsError::InvalidConfig(e)) => assert_eq!(
+            e,
+            BuildError::UnsupportedMode("build_session", ExecutionMode::Encoded)
+        ),
+        _ => panic!("expected the session to be rejected"),
+    }
+}
@@ -0,0 +1,11 @@
+extern crate powershell_script;
+
+use powershell_script::NetworkShare;
+
+#[test]
+fn debug_hides_password() {
+    let share = NetworkShare::new(r"\\server\share").with_credentials("svc", "hunter2");
+    let debug = format!("{:?}", share);
+    assert!(debug.contains("svc"));
+    assert!(!debug.contains("hunter2"));
+}
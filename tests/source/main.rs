@@ -0,0 +1,61 @@
+extern crate powershell_script;
+
+use std::fs;
+
+use powershell_script::{ParamSchema, ParamType, PsVersion, Script};
+
+fn read(name: &str, bytes: &[u8]) -> std::io::Result<Script> {
+    let file = format!("powershell-script-{}-{}.ps1", name, std::process::id());
+    let path = std::env::temp_dir().join(file);
+    fs::write(&path, bytes).unwrap();
+    let script = Script::from_file(&path);
+    fs::remove_file(&path).unwrap();
+    script
+}
+
+#[test]
+fn from_file_decodes_byte_order_marks() {
+    let utf8 = read("utf8", b"\xEF\xBB\xBFecho 'a'\r\necho 'b'").unwrap();
+    assert_eq!(utf8.source(), "echo 'a'\necho 'b'");
+
+    let mut utf16 = vec![0xFF, 0xFE];
+    utf16.extend("echo 'ä'\r\n".encode_utf16().flat_map(u16::to_le_bytes));
+    assert_eq!(read("utf16", &utf16).unwrap().source(), "echo 'ä'\n");
+
+    let plain = read("plain", "echo 'ö'".as_bytes()).unwrap();
+    assert_eq!(plain.source(), "echo 'ö'");
+}
+
+#[test]
+fn from_file_rejects_unknown_encodings() {
+    let err = read("latin1", b"echo '\xE4'").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn metadata() {
+    let script = read("named", b"#Requires -Version 7.2 -PSEdition Core\n'hi'").unwrap();
+    assert_eq!(
+        script.name(),
+        Some(format!("powershell-script-named-{}", std::process::id()).as_str())
+    );
+    assert!(script.path().is_some());
+    assert_eq!(
+        script.required_version(),
+        Some(PsVersion { major: 7, minor: 2 })
+    );
+    assert_eq!(script.hash(), Script::new(script.source()).hash());
+
+    let script = Script::from_static("#requires -version 5\n'hi'").with_name("greet");
+    assert_eq!(script.name(), Some("greet"));
+    assert_eq!(script.required_version().unwrap().to_string(), "5.0");
+    assert_eq!(Script::new("'hi'").required_version(), None);
+}
+
+#[test]
+fn params() {
+    let schema = ParamSchema::new().required("Name", ParamType::String);
+    let script = Script::new("param($Name) $Name").with_params(schema.clone());
+    assert_eq!(script.params(), Some(&schema));
+    assert_ne!(script, Script::new("param($Name) $Name"));
+}
@@ -0,0 +1,79 @@
+extern crate powershell_script;
+
+use powershell_script::{PsError, PsValue, ScriptTemplate};
+
+fn render(template: &str, params: Vec<(&str, PsValue)>) -> Result<String, PsError> {
+    ScriptTemplate::new(template)
+        .render(params)
+        .map(|script| script.source().to_string())
+}
+
+#[test]
+fn values_are_rendered_as_literals() {
+    let rendered = render(
+        "Set-Content -Path <Path> -Value <value>; Get-Item <items> -Force:<force>",
+        vec![
+            ("path", PsValue::from("C:\\$env:TEMP\\`it's")),
+            ("value", PsValue::from("‘curly’ \"double\"")),
+            ("items", PsValue::from(vec!["a", "b"])),
+            ("force", PsValue::from(true)),
+        ],
+    )
+    .unwrap();
+    assert_eq!(
+        rendered,
+        "Set-Content -Path 'C:\\$env:TEMP\\`it''s' -Value '‘‘curly’’ \"double\"'; Get-Item @('a', 'b') -Force:$true"
+    );
+}
+
+#[test]
+fn strings_comments_and_script_blocks_are_left_alone() {
+    let template = concat!(
+        "# <name> in a comment\n",
+        "<# <name> in a block comment #>\n",
+        "'<name> and {0}' -f 1\n",
+        "\"<name> and `\"<name>`\"\"\n",
+        "@'\n<name>\n'@\n",
+        "Where-Object { $_.Name -eq <name> } | ForEach-Object {name}\n",
+    );
+    assert_eq!(
+        render(template, vec![("name", PsValue::from("x"))]).unwrap(),
+        concat!(
+            "# <name> in a comment\n",
+            "<# <name> in a block comment #>\n",
+            "'<name> and {0}' -f 1\n",
+            "\"<name> and `\"<name>`\"\"\n",
+            "@'\n<name>\n'@\n",
+            "Where-Object { $_.Name -eq 'x' } | ForEach-Object {name}\n",
+        )
+    );
+    assert_eq!(ScriptTemplate::new(template).placeholders(), vec!["name"]);
+}
+
+#[test]
+fn braces_are_never_placeholders() {
+    let template = concat!(
+        "foreach ($i in 1..3) { if ($i -eq <stop>) {break} else {continue} }\n",
+        "${name} = 1; ${env:Path}; Invoke-Command {ls}; trap {exit}\n",
+    );
+    assert_eq!(
+        render(template, vec![("stop", PsValue::from(2))]).unwrap(),
+        concat!(
+            "foreach ($i in 1..3) { if ($i -eq 2) {break} else {continue} }\n",
+            "${name} = 1; ${env:Path}; Invoke-Command {ls}; trap {exit}\n",
+        )
+    );
+    assert_eq!(ScriptTemplate::new(template).placeholders(), vec!["stop"]);
+}
+
+#[test]
+fn placeholders_and_params_have_to_match() {
+    match render("Get-Item <path>", vec![]) {
+        Err(PsError::InvalidParameters(msg)) => assert!(msg.contains("<path>")),
+        result => panic!("expected a missing value, got {:?}", result),
+    }
+    match render("Get-Date", vec![("path", PsValue::from("x"))]) {
+        Err(PsError::InvalidParameters(msg)) => assert!(msg.contains("`path`")),
+        result => panic!("expected an unused value, got {:?}", result),
+    }
+}
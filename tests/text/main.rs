@@ -0,0 +1,79 @@
+extern crate powershell_script;
+
+use powershell_script::{parse_list, parse_table, split_records};
+
+#[test]
+fn table_with_aligned_columns() {
+    // Output of `Get-Process | Format-Table Handles, Id, ProcessName, Path`
+    let text = "
+Handles    Id ProcessName Path
+-------    -- ----------- ----
+    231 10452 pwsh        C:\\Program Files\\PowerShell\\7\\pwsh.exe
+     54     4 System
+";
+    let rows = parse_table(text);
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0]["Handles"], "231");
+    assert_eq!(rows[0]["Id"], "10452");
+    assert_eq!(rows[0]["ProcessName"], "pwsh");
+    assert_eq!(
+        rows[0]["Path"],
+        "C:\\Program Files\\PowerShell\\7\\pwsh.exe"
+    );
+    assert_eq!(rows[1]["Id"], "4");
+    assert_eq!(rows[1]["Path"], "");
+}
+
+#[test]
+fn several_tables() {
+    let text = "
+    Directory: C:\\a
+
+Mode   Name
+----   ----
+d----  one
+
+    Directory: C:\\b
+
+Mode   Name
+----   ----
+-a---  two words
+";
+    let rows = parse_table(text);
+    let names: Vec<&str> = rows.iter().map(|r| r["Name"].as_str()).collect();
+    assert_eq!(names, ["one", "two words"]);
+}
+
+#[test]
+fn list_records() {
+    let text = "
+
+Name       : wuauserv
+Status     : Running
+Dependents : {one, two,
+             three}
+Path       : C:\\Windows\\system32\\svchost.exe -k netsvcs : extra
+
+
+Name       : bits
+Status     :
+";
+    let records = parse_list(text);
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0]["Dependents"], "{one, two,\nthree}");
+    assert_eq!(
+        records[0]["Path"],
+        "C:\\Windows\\system32\\svchost.exe -k netsvcs : extra"
+    );
+    assert_eq!(records[1]["Name"], "bits");
+    assert_eq!(records[1]["Status"], "");
+}
+
+#[test]
+fn records_with_line_breaks() {
+    // Output of `"a`nb`0"; "c`0"; [Console]::Out.Write("d`0e")`
+    let text = "a\r\nb\0\r\nc\0\r\nd\0e";
+    assert_eq!(split_records(text, "\0"), ["a\r\nb", "c", "d", "e"]);
+    assert_eq!(split_records("x||y||\n", "||"), ["x", "y"]);
+    assert!(split_records("", "\0").is_empty());
+}
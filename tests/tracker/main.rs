@@ -0,0 +1,86 @@
+extern crate powershell_script;
+
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use powershell_script::{ChildTracker, PsError, PsScriptBuilder, ShutdownGuard};
+
+#[test]
+fn shutdown_without_children_returns_immediately() {
+    let tracker = ChildTracker::new();
+    let start = Instant::now();
+    assert_eq!(tracker.shutdown_all(Duration::from_secs(10)), 0);
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert!(tracker.is_empty());
+}
+
+#[test]
+fn failed_spawns_are_not_tracked() {
+    let tracker = ChildTracker::new();
+    let ps = PsScriptBuilder::new()
+        .child_tracker(tracker.clone())
+        .probe_path("/nonexistent/pwsh")
+        .build();
+    if let Err(PsError::PowershellNotFound) = ps.spawn("'hello'") {
+        assert!(tracker.pids().is_empty());
+    }
+}
+
+#[test]
+fn tracker_can_be_shared_between_threads() {
+    fn assert_send_sync<T: Send + Sync + Clone>() {}
+    assert_send_sync::<ChildTracker>();
+}
+
+#[test]
+fn only_one_shutdown_guard_at_a_time() {
+    let tracker = ChildTracker::new();
+    let guard = ShutdownGuard::install(&tracker, Duration::from_millis(10)).unwrap();
+    match ShutdownGuard::install(&tracker, Duration::from_millis(10)) {
+        Err(e) => assert_eq!(e.kind(), io::ErrorKind::AlreadyExists),
+        Ok(_) => panic!("installed a second guard"),
+    }
+    drop(guard);
+    assert!(ShutdownGuard::install(&tracker, Duration::from_millis(10)).is_ok());
+}
+
+#[cfg(unix)]
+#[test]
+fn processes_are_tracked_until_they_are_waited_for() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let executable = std::env::temp_dir().join(format!("ps-tracker-{}-sleep", std::process::id()));
+    std::fs::write(&executable, "#!/bin/sh\nexec sleep 30\n").unwrap();
+    std::fs::set_permissions(&executable, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let tracker = ChildTracker::new();
+    let ps = PsScriptBuilder::new()
+        .executable(&executable)
+        .child_tracker(tracker.clone())
+        .build();
+
+    let mut child = ps.spawn("'hi'").unwrap();
+    assert_eq!(tracker.pids(), vec![child.id()]);
+    child.kill().unwrap();
+    assert!(child.wait().is_err());
+    std::fs::remove_file(&executable).unwrap();
+    assert!(tracker.is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn finished_runs_are_not_tracked() {
+    use powershell_script::ExecutionMode;
+
+    let tracker = ChildTracker::new();
+    let ps = PsScriptBuilder::new()
+        .executable("/bin/echo")
+        .execution_mode(ExecutionMode::Encoded)
+        .child_tracker(tracker.clone())
+        .build();
+    ps.run("'hi'").unwrap();
+    // The channel closes once the process has been waited for
+    for _ in ps.run_events("'hi'", 16).unwrap() {}
+    assert!(tracker.is_empty());
+}
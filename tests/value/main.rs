@@ -0,0 +1,77 @@
+extern crate powershell_script;
+
+use powershell_script::PsValue;
+
+#[test]
+fn literals() {
+    assert_eq!(PsValue::from("it's $HOME").to_literal(), "'it''s $HOME'");
+    assert_eq!(PsValue::from(-3).to_literal(), "-3");
+    assert_eq!(PsValue::from(true).to_literal(), "$true");
+    assert_eq!(PsValue::from(None::<i32>).to_literal(), "$null");
+    assert_eq!(PsValue::from(vec![1, 2]).to_literal(), "@(1, 2)");
+
+    let obj = PsValue::Object(vec![
+        ("Name".to_string(), "a".into()),
+        ("Size".to_string(), 1.5.into()),
+    ]);
+    assert_eq!(obj.to_literal(), "[ordered]@{'Name' = 'a'; 'Size' = 1.5}");
+    assert_eq!(obj.get("name"), Some(&PsValue::from("a")));
+}
+
+#[test]
+fn from_json() {
+    let json = r#"{"Name":"a \"b\" æ","Size":12,"Ratio":0.5,"Tags":["x"],"Owner":null,"On":true}"#;
+    let value = PsValue::from_json(json).unwrap();
+    assert_eq!(
+        value.get("name").and_then(PsValue::as_str),
+        Some("a \"b\" æ")
+    );
+    assert_eq!(value.get("Size"), Some(&PsValue::Int(12)));
+    assert_eq!(value.get("Ratio"), Some(&PsValue::Float(0.5)));
+    assert_eq!(value.get("Owner"), Some(&PsValue::Null));
+
+    assert!(PsValue::from_json("{\"a\":").is_err());
+    assert!(PsValue::from_json("[1] x").is_err());
+}
+
+#[test]
+fn typed_conversion() {
+    use powershell_script::FromPsValue;
+    use std::collections::HashMap;
+
+    let tags = Vec::<String>::from_ps_value(PsValue::from_json(r#""single""#).unwrap()).unwrap();
+    assert_eq!(tags, vec!["single".to_string()]);
+
+    let count = u32::from_ps_value(PsValue::from("42")).unwrap();
+    assert_eq!(count, 42);
+    assert!(u8::from_ps_value(PsValue::from(300)).is_err());
+
+    let map =
+        HashMap::<String, i64>::from_ps_value(PsValue::from_json(r#"{"a":1}"#).unwrap()).unwrap();
+    assert_eq!(map["a"], 1);
+}
+
+#[test]
+fn special_types() {
+    use powershell_script::{Bytes, FromPsValue, Guid};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    let date = |json: &str| SystemTime::from_ps_value(PsValue::from_json(json).unwrap()).unwrap();
+    let expected = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+    assert_eq!(date(r#""\/Date(1700000000123)\/""#), expected);
+    assert_eq!(
+        date(r#"{"value":"\/Date(1700000000123)\/","DisplayHint":2}"#),
+        expected
+    );
+    assert_eq!(date(r#""2023-11-14T22:13:20.123Z""#), expected);
+    assert_eq!(date(r#""2023-11-15T00:13:20.1230000+02:00""#), expected);
+
+    let guid: Guid = "{0F8FAD5B-D9CB-469F-A165-70867728950E}".parse().unwrap();
+    assert_eq!(guid.to_string(), "0f8fad5b-d9cb-469f-a165-70867728950e");
+    assert_eq!(Guid::from_ps_value(PsValue::from(guid)).unwrap(), guid);
+    assert!("0f8fad5b-d9cb-469f-a165".parse::<Guid>().is_err());
+
+    let bytes = |json: &str| Bytes::from_ps_value(PsValue::from_json(json).unwrap()).unwrap();
+    assert_eq!(bytes(r#""AQID/w==""#), Bytes(vec![1, 2, 3, 255]));
+    assert_eq!(bytes("[1,2,3,255]"), Bytes(vec![1, 2, 3, 255]));
+}
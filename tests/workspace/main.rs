@@ -0,0 +1,56 @@
+extern crate powershell_script;
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use powershell_script::{Middleware, PsError, PsScriptBuilder, RunContext};
+
+#[derive(Default, Clone)]
+struct Record(Arc<Mutex<Vec<(PathBuf, bool, bool)>>>);
+
+impl Middleware for Record {
+    fn before(&self, ctx: &mut RunContext) -> Result<(), PsError> {
+        let path = ctx.workspace().unwrap().to_path_buf();
+        let exported = ctx
+            .prelude()
+            .iter()
+            .any(|line| line.contains("PS_WORKSPACE"));
+        let exists = path.is_dir();
+        self.0.lock().unwrap().push((path, exists, exported));
+        Err(PsError::Rejected("recorded".into()))
+    }
+}
+
+#[test]
+fn every_run_gets_its_own_workspace() {
+    let record = Record::default();
+    let ps = PsScriptBuilder::new()
+        .temp_workspace(true)
+        .middleware(record.clone())
+        .build();
+    assert!(ps.run("'one'").is_err());
+    assert!(ps.run("'two'").is_err());
+
+    let runs = record.0.lock().unwrap();
+    assert_ne!(runs[0].0, runs[1].0);
+    for (path, existed, exported) in runs.iter() {
+        assert!(existed, "{} wasn't created", path.display());
+        assert!(exported);
+        assert!(!path.exists(), "{} wasn't removed", path.display());
+    }
+}
+
+#[test]
+fn no_workspace_by_default() {
+    struct Check;
+    impl Middleware for Check {
+        fn before(&self, ctx: &mut RunContext) -> Result<(), PsError> {
+            assert!(ctx.workspace().is_none());
+            Err(PsError::Rejected("checked".into()))
+        }
+    }
+    let ps = PsScriptBuilder::new().middleware(Check).build();
+    assert!(matches!(ps.run("'one'"), Err(PsError::Rejected(_))));
+}